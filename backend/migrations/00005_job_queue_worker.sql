@@ -0,0 +1,74 @@
+-- Fixes for the queue functions, needed by the backtest workers
+
+-- `retry_count` is also an output column of dequeue_job, so it has to be qualified in the UPDATE,
+-- otherwise every call fails with "column reference is ambiguous"
+CREATE OR REPLACE FUNCTION dequeue_job(
+    p_worker_id VARCHAR,
+    p_job_types VARCHAR[] DEFAULT NULL -- Filter by job types if provided
+)
+RETURNS TABLE (
+    id BIGINT,
+    job_type VARCHAR,
+    payload BYTEA,
+    retry_count INTEGER,
+    timeout_seconds INTEGER
+) AS $$
+DECLARE
+    job_record RECORD;
+BEGIN
+    -- Lock and claim the next available job
+    SELECT j.id, j.job_type, j.payload, j.retry_count, j.timeout_seconds
+    INTO job_record
+    FROM jobs j
+    WHERE j.status IN ('pending', 'failed')
+        AND j.scheduled_for <= NOW()
+        AND j.retry_count < j.max_retries
+        AND (p_job_types IS NULL OR j.job_type = ANY(p_job_types))
+    ORDER BY j.priority DESC, j.id
+    LIMIT 1
+    FOR UPDATE SKIP LOCKED;
+    
+    IF NOT FOUND THEN
+        RETURN;
+    END IF;
+    
+    -- Update job status
+    UPDATE jobs
+    SET 
+        status = 'running',
+        started_at = NOW(),
+        worker_id = p_worker_id,
+        retry_count = jobs.retry_count + 1
+    WHERE jobs.id = job_record.id;
+    
+    -- Return job details
+    RETURN QUERY
+    SELECT 
+        job_record.id,
+        job_record.job_type,
+        job_record.payload,
+        job_record.retry_count,
+        job_record.timeout_seconds;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Function to hand a running job back to the queue without counting it as an attempt
+-- This is used when a worker shuts down while it is still processing a job
+CREATE OR REPLACE FUNCTION release_job(p_job_id BIGINT)
+RETURNS BOOLEAN AS $$
+DECLARE
+    rows_updated INTEGER;
+BEGIN
+    UPDATE jobs
+    SET 
+        status = 'pending',
+        started_at = NULL,
+        worker_id = NULL,
+        retry_count = GREATEST(retry_count - 1, 0)
+    WHERE id = p_job_id
+        AND status = 'running';
+    
+    GET DIAGNOSTICS rows_updated = ROW_COUNT;
+    RETURN rows_updated > 0;
+END;
+$$ LANGUAGE plpgsql;
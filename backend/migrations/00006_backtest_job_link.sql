@@ -0,0 +1,34 @@
+-- backtests.job_id was a BIGSERIAL, so it got a value from its own sequence and never pointed to
+-- the job that actually runs the backtest. Make it a real (nullable) reference to jobs.
+ALTER TABLE backtests ALTER COLUMN job_id DROP DEFAULT;
+ALTER TABLE backtests ALTER COLUMN job_id DROP NOT NULL;
+DROP SEQUENCE IF EXISTS backtests_job_id_seq;
+
+-- None of the existing values are job ids
+UPDATE backtests SET job_id = NULL;
+
+-- Old jobs get cleaned up (see cleanup_old_jobs), the backtest keeps its last status
+ALTER TABLE backtests
+    ADD CONSTRAINT backtests_job_id_fkey FOREIGN KEY (job_id) REFERENCES jobs(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_backtests_job_id ON backtests (job_id);
+
+-- Mirror the status of a job on the backtests pointing to it.
+-- job_status and backtest_status have the same values, so the cast is safe.
+CREATE OR REPLACE FUNCTION sync_backtest_status()
+RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE backtests
+    SET status = NEW.status::TEXT::backtest_status
+    WHERE job_id = NEW.id
+        AND status IS DISTINCT FROM NEW.status::TEXT::backtest_status;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER jobs_sync_backtest_status
+    AFTER UPDATE OF status ON jobs
+    FOR EACH ROW
+    WHEN (OLD.status IS DISTINCT FROM NEW.status)
+    EXECUTE FUNCTION sync_backtest_status();
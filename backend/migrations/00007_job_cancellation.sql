@@ -0,0 +1,20 @@
+-- Function to cancel a job that is not finished yet
+-- A pending job will never be picked up, a running one is noticed by its worker (which polls the
+-- status of its job) and stopped. complete_job and fail_job only touch running jobs, so a worker
+-- finishing at the same time cannot overwrite the cancellation.
+CREATE OR REPLACE FUNCTION cancel_job(p_job_id BIGINT)
+RETURNS BOOLEAN AS $$
+DECLARE
+    rows_updated INTEGER;
+BEGIN
+    UPDATE jobs
+    SET 
+        status = 'cancelled',
+        completed_at = NOW()
+    WHERE id = p_job_id
+        AND status IN ('pending', 'running');
+    
+    GET DIAGNOSTICS rows_updated = ROW_COUNT;
+    RETURN rows_updated > 0;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,4 @@
+-- The artifact columns hold object storage keys, download URLs are presigned on demand since
+-- they expire.
+ALTER TABLE backtests RENAME COLUMN trades_url TO trades_path;
+ALTER TABLE backtests RENAME COLUMN equity_curve_url TO equity_curve_path;
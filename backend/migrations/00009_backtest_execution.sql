@@ -0,0 +1,3 @@
+-- Trading costs (fees, slippage, spread) the backtest was run with, see backtester::ExecutionConfig.
+-- An empty object means no costs at all.
+ALTER TABLE backtests ADD COLUMN execution JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,2 @@
+-- Portfolio backtests store their assets as a comma separated list, e.g. 'BTCUSDT,ETHUSDT'.
+ALTER TABLE backtests ALTER COLUMN dataset TYPE TEXT;
@@ -0,0 +1,11 @@
+-- Hash of the canonical form of the content, see validators::strategy_validator::content_hash.
+-- Strategies saved before it existed get one when they are next modified.
+ALTER TABLE strategies ADD COLUMN content_hash TEXT;
+CREATE INDEX IF NOT EXISTS idx_strategies_content_hash ON strategies (user_id, content_hash);
+
+-- Hash of the strategy the backtest ran. A backtest of the same content, dataset, dates and
+-- execution reuses the results of a finished one instead of being computed again.
+ALTER TABLE backtests ADD COLUMN content_hash TEXT;
+CREATE INDEX IF NOT EXISTS idx_backtests_cache
+    ON backtests (content_hash, dataset, timeframe, date_start, date_end)
+    WHERE status = 'done';
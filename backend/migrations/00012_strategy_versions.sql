@@ -0,0 +1,37 @@
+-- Every content a strategy had, strategies.content being a copy of the latest one. A version is
+-- never changed once written, restoring an old version writes a new one with its content.
+CREATE TABLE IF NOT EXISTS strategy_versions (
+  strategy_id UUID NOT NULL REFERENCES strategies(id) ON DELETE CASCADE,
+  -- 1 for the content the strategy was created with, incremented on each modification
+  version INTEGER NOT NULL,
+  content JSONB NOT NULL,
+  content_hash TEXT,
+  created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (strategy_id, version)
+);
+
+CREATE OR REPLACE FUNCTION reject_strategy_version_update()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'strategy versions cannot be modified';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER strategy_versions_immutable
+    BEFORE UPDATE ON strategy_versions
+    FOR EACH ROW
+    EXECUTE FUNCTION reject_strategy_version_update();
+
+-- The latest version of the strategy, the one strategies.content holds.
+ALTER TABLE strategies ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+-- The content of the existing strategies is their first version, the earlier ones are lost.
+INSERT INTO strategy_versions (strategy_id, version, content, content_hash, created_at)
+SELECT id, 1, content, content_hash, updated_at FROM strategies;
+
+-- Version of the strategy a backtest ran. NULL for the backtests run before versions existed,
+-- the content they ran may have been modified since.
+ALTER TABLE backtests ADD COLUMN strategy_version INTEGER;
+ALTER TABLE backtests
+    ADD CONSTRAINT backtests_strategy_version_fkey FOREIGN KEY (strategy_id, strategy_version)
+    REFERENCES strategy_versions(strategy_id, version);
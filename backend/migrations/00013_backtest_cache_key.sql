@@ -0,0 +1,26 @@
+-- What a finished backtest can be reused for, besides the content, dataset, dates and execution
+-- (see 00011_content_hash.sql): the version of the engine that computed it and the version of
+-- the datasets it ran on, which change when dataset-manager reloads them. NULL for the backtests
+-- computed before, they are never reused.
+ALTER TABLE backtests ADD COLUMN engine_version INTEGER;
+ALTER TABLE backtests ADD COLUMN dataset_version TEXT;
+
+DROP INDEX IF EXISTS idx_backtests_cache;
+CREATE INDEX IF NOT EXISTS idx_backtests_cache
+    ON backtests (content_hash, dataset, timeframe, date_start, date_end, engine_version, dataset_version)
+    WHERE status = 'done';
+
+-- Versions saved before content_hash existed get theirs at startup, the only change allowed to a
+-- version.
+CREATE OR REPLACE FUNCTION reject_strategy_version_update()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF OLD.content_hash IS NULL
+        AND (NEW.strategy_id, NEW.version, NEW.content, NEW.created_at)
+            IS NOT DISTINCT FROM (OLD.strategy_id, OLD.version, OLD.content, OLD.created_at)
+    THEN
+        RETURN NEW;
+    END IF;
+    RAISE EXCEPTION 'strategy versions cannot be modified';
+END;
+$$ LANGUAGE plpgsql;
@@ -1,8 +1,10 @@
-use aws_sdk_s3::Client;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
 
 use crate::{
-    auth::SessionStore, dataset_client::DatasetManagerClient, db::Database, s3_manager,
-    s3_manager::S3manager, validators::strategy_validator::StrategyValidator,
+    auth::SessionStore, dataset_client::DatasetManagerClient, db::Database,
+    s3_manager::S3Manager, validators::strategy_validator::StrategyValidator,
 };
 
 #[derive(Clone)]
@@ -10,6 +12,7 @@ pub struct AppState {
     pub db: Database,
     pub session_store: SessionStore,
     pub dataset_manager: DatasetManagerClient,
-    pub strat_validator: StrategyValidator,
-    pub s3: S3manager,
+    /// Behind a lock so the indicator catalogue can be refreshed while serving.
+    pub strat_validator: Arc<RwLock<StrategyValidator>>,
+    pub s3: S3Manager,
 }
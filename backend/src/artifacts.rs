@@ -0,0 +1,123 @@
+//! Detailed results of a backtest, stored next to it in the object storage.
+//!
+//! Each artifact is a MessagePack map of columns (one array per field) rather than an array of
+//! rows, so field names are written once and a chart can pick the columns it needs.
+
+use backtester::{BacktestReport, EquityPoint, ExitReason, OptionFill, PositionPoint, Side, Trade};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::errors::AppError;
+
+pub const CONTENT_TYPE: &str = "application/msgpack";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Trades,
+    EquityCurve,
+    Positions,
+}
+
+impl ArtifactKind {
+    /// Object key of the artifact of a backtest.
+    pub fn key(&self, backtest_id: Uuid) -> String {
+        let name = match self {
+            ArtifactKind::Trades => "trades",
+            ArtifactKind::EquityCurve => "equity_curve",
+            ArtifactKind::Positions => "positions",
+        };
+        format!("backtests/{}/{}.msgpack", backtest_id, name)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TradeColumns {
+    pub timestamp: Vec<i64>,
+    pub side: Vec<Side>,
+    pub price: Vec<f64>,
+    pub quantity: Vec<f64>,
+    pub pnl: Vec<Option<f64>>,
+    pub holding_time: Vec<Option<i64>>,
+    pub fee: Vec<f64>,
+    pub slippage: Vec<f64>,
+    pub spread: Vec<f64>,
+    pub exit_reason: Vec<Option<ExitReason>>,
+    /// Only filled by portfolio backtests.
+    pub asset: Vec<Option<String>>,
+    /// Only filled by options backtests.
+    pub option: Vec<Option<OptionFill>>,
+}
+
+impl From<&[Trade]> for TradeColumns {
+    fn from(trades: &[Trade]) -> Self {
+        Self {
+            timestamp: trades.iter().map(|t| t.timestamp).collect(),
+            side: trades.iter().map(|t| t.side).collect(),
+            price: trades.iter().map(|t| t.price).collect(),
+            quantity: trades.iter().map(|t| t.quantity).collect(),
+            pnl: trades.iter().map(|t| t.pnl).collect(),
+            holding_time: trades.iter().map(|t| t.holding_time).collect(),
+            fee: trades.iter().map(|t| t.fee).collect(),
+            slippage: trades.iter().map(|t| t.slippage).collect(),
+            spread: trades.iter().map(|t| t.spread).collect(),
+            exit_reason: trades.iter().map(|t| t.exit_reason).collect(),
+            asset: trades.iter().map(|t| t.asset.clone()).collect(),
+            option: trades.iter().map(|t| t.option).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct EquityColumns {
+    pub timestamp: Vec<i64>,
+    pub equity: Vec<f64>,
+}
+
+impl From<&[EquityPoint]> for EquityColumns {
+    fn from(points: &[EquityPoint]) -> Self {
+        Self {
+            timestamp: points.iter().map(|p| p.timestamp).collect(),
+            equity: points.iter().map(|p| p.equity).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PositionColumns {
+    pub timestamp: Vec<i64>,
+    pub quantity: Vec<f64>,
+    pub avg_entry: Vec<f64>,
+    pub cash: Vec<f64>,
+    /// Only filled by portfolio backtests.
+    pub asset: Vec<Option<String>>,
+}
+
+impl From<&[PositionPoint]> for PositionColumns {
+    fn from(points: &[PositionPoint]) -> Self {
+        Self {
+            timestamp: points.iter().map(|p| p.timestamp).collect(),
+            quantity: points.iter().map(|p| p.quantity).collect(),
+            avg_entry: points.iter().map(|p| p.avg_entry).collect(),
+            cash: points.iter().map(|p| p.cash).collect(),
+            asset: points.iter().map(|p| p.asset.clone()).collect(),
+        }
+    }
+}
+
+/// Encode the artifacts of a report, with the kind each one should be stored as.
+pub fn encode(report: &BacktestReport) -> Result<Vec<(ArtifactKind, Vec<u8>)>, AppError> {
+    Ok(vec![
+        (
+            ArtifactKind::Trades,
+            rmp_serde::to_vec_named(&TradeColumns::from(report.trades.as_slice()))?,
+        ),
+        (
+            ArtifactKind::EquityCurve,
+            rmp_serde::to_vec_named(&EquityColumns::from(report.equity_curve.as_slice()))?,
+        ),
+        (
+            ArtifactKind::Positions,
+            rmp_serde::to_vec_named(&PositionColumns::from(report.positions.as_slice()))?,
+        ),
+    ])
+}
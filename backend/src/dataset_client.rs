@@ -1,8 +1,11 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
 use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
 use reqwest::Client;
 use serde::Deserialize;
 
-use crate::errors::AppError;
+use crate::{errors::AppError, validators::strategy_validator::StrategyValidator};
 
 #[derive(Deserialize, Debug)]
 pub struct DatasetMeta {
@@ -33,6 +36,64 @@ impl DatasetManagerClient {
     pub async fn get_dataset(&self, name: String) -> Result<DatasetMeta, AppError> {
         get_dataset_metadata(&self.http, &self.base_url, &name).await
     }
+
+    pub async fn list_datasets(&self) -> Result<Vec<DatasetMeta>, AppError> {
+        let url = format!("{}/datasets", self.base_url);
+        let datasets = self
+            .http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(datasets)
+    }
+
+    /// Every indicator precomputed by at least one dataset.
+    pub async fn indicator_catalogue(&self) -> Result<HashSet<String>, AppError> {
+        Ok(self
+            .list_datasets()
+            .await?
+            .into_iter()
+            .flat_map(|dataset| dataset.ta)
+            .collect())
+    }
+
+    /// Replace the indicators `validator` accepts with the current catalogue, returning how many
+    /// there are.
+    pub async fn refresh_indicators(
+        &self,
+        validator: &RwLock<StrategyValidator>,
+    ) -> Result<usize, AppError> {
+        let indicators = self.indicator_catalogue().await?;
+        let count = indicators.len();
+        validator.write().update_valid_indicators(indicators);
+        Ok(count)
+    }
+
+    /// Refresh the validator's indicators every `period`, so datasets added to dataset-manager
+    /// become usable without a restart. Failures keep the previous catalogue. `period` must not be
+    /// zero.
+    pub fn spawn_indicator_refresh(
+        self,
+        validator: Arc<RwLock<StrategyValidator>>,
+        period: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                match self.refresh_indicators(&validator).await {
+                    Ok(count) => {
+                        tracing::debug!("Refreshed indicator catalogue, {} indicators", count)
+                    }
+                    Err(e) => tracing::warn!("Failed to refresh indicator catalogue: {}", e),
+                }
+            }
+        })
+    }
 }
 
 async fn get_dataset_metadata(
@@ -56,3 +117,86 @@ async fn get_dataset_metadata(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{Json, Router, routing::get};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::validators::strategy_validator::StrategyContent;
+
+    fn strategy_reading(indicator: &str) -> StrategyContent {
+        serde_json::from_value(serde_json::json!({
+            "meta": { "type": "spot" },
+            "actions": [
+                { "type": "buy", "w": 0.8, "cond": { "gt": { "l": indicator, "r": 0 } } }
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_indicator_refresh() {
+        // A dataset-manager with a single dataset precomputing `custom_new`
+        let app = Router::new().route(
+            "/datasets",
+            get(|| async {
+                Json(serde_json::json!([{
+                    "asset": "BTCUSDT",
+                    "timeframe": "1m",
+                    "start": "2019-01-01T00:00:00Z",
+                    "end": "2020-01-01T00:00:00Z",
+                    "count": 525600,
+                    "version": 1,
+                    "path": "BTCUSDT_1m.bin",
+                    "ta": ["custom_new"]
+                }]))
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let validator = Arc::new(RwLock::new(StrategyValidator::new(HashSet::from([
+            "custom_old".to_string(),
+        ]))));
+        assert!(
+            validator
+                .read()
+                .validate_strategy(&strategy_reading("custom_old"))
+                .is_ok()
+        );
+        assert!(
+            validator
+                .read()
+                .validate_strategy(&strategy_reading("custom_new"))
+                .is_err()
+        );
+
+        let refresh = DatasetManagerClient::new(base_url)
+            .spawn_indicator_refresh(validator.clone(), Duration::from_millis(10));
+        let mut refreshed = false;
+        for _ in 0..200 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            if validator
+                .read()
+                .validate_strategy(&strategy_reading("custom_new"))
+                .is_ok()
+            {
+                refreshed = true;
+                break;
+            }
+        }
+        refresh.abort();
+
+        // The catalogue is replaced, not extended
+        assert!(refreshed);
+        assert!(
+            validator
+                .read()
+                .validate_strategy(&strategy_reading("custom_old"))
+                .is_err()
+        );
+    }
+}
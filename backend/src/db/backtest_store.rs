@@ -1,37 +1,159 @@
-use chrono::{DateTime, Utc};
+use backtester::ENGINE_VERSION;
+use chrono::Utc;
+use sqlx::types::Json;
 use uuid::Uuid;
 
 use crate::{
+    db::job_queue::{BacktestJob, enqueue_backtest_job},
     errors::AppError,
-    models::{Backtest, BacktestStatus},
+    models::{Backtest, BacktestStatus, CreateBacktestRequest, ResultSummary, Strategy},
+    s3_manager::ArtifactPaths,
     Database
 };
 
+/// Columns of `backtests` mapped by the `Backtest` model, prefixed so they can be used in joins.
+const BACKTEST_COLUMNS: &str = r#"
+    backtests.id, backtests.strategy_id, backtests.strategy_version, backtests.job_id,
+    backtests.status, backtests.dataset, backtests.timeframe, backtests.date_start,
+    backtests.date_end, backtests.execution, backtests.created_at,
+    backtests.result_summary, backtests.trades_path, backtests.equity_curve_path,
+    backtests.positions_path
+"#;
 
 impl Database {
+    /// Create a backtest of the latest version of `strategy` and the job that will compute it, in
+    /// one transaction. `dataset_version` is the version of the datasets it runs on, see
+    /// `find_cached_backtest`.
     pub async fn create_backtest(
         &self,
-        strategy_id: Uuid,
-        dataset: &str,
-        timeframe: &str,
-        date_start: DateTime<Utc>, 
-        date_end: DateTime<Utc>,
+        request: &CreateBacktestRequest,
+        strategy: &Strategy,
+        content_hash: &str,
+        dataset_version: &str,
+        priority: i32,
     ) -> Result<Backtest, AppError> {
         let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
 
-        let backtest = sqlx::query_as::<_, Backtest>(
+        let backtest_id = sqlx::query_scalar::<_, Uuid>(
             r#"
-            INSERT INTO backtests (strategy_id, dataset, timeframe, date_start, date_end, created_at, status)
-            VALUES ($1, $2, $3, $4, $5, $6, 'pending')
-            RETURNING id, strategy_id, status, dataset, timeframe, date_start, date_end, created_at
+            INSERT INTO backtests (strategy_id, strategy_version, dataset, timeframe, date_start, date_end, execution, content_hash, dataset_version, created_at, status)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, 'pending')
+            RETURNING id
             "#,
         )
-        .bind(strategy_id)
-        .bind(dataset)
-        .bind(timeframe)
-        .bind(date_start)
-        .bind(date_end)
+        .bind(strategy.id)
+        .bind(strategy.version)
+        .bind(&request.dataset)
+        .bind(&request.timeframe)
+        .bind(request.date_start)
+        .bind(request.date_end)
+        .bind(Json(&request.execution))
+        .bind(content_hash)
+        .bind(dataset_version)
         .bind(now)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let job = BacktestJob {
+            backtest_id,
+            strategy: strategy.content.0.clone(),
+            dataset: request.dataset.clone(),
+            timeframe: request.timeframe.clone(),
+            date_start: request.date_start,
+            date_end: request.date_end,
+            execution: request.execution.clone(),
+        };
+        let job_id = enqueue_backtest_job(&mut *tx, &job, priority).await?;
+
+        let backtest = sqlx::query_as::<_, Backtest>(&format!(
+            "UPDATE backtests SET job_id = $1 WHERE id = $2 RETURNING {}",
+            BACKTEST_COLUMNS
+        ))
+        .bind(job_id)
+        .bind(backtest_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(backtest)
+    }
+
+    /// A finished backtest of one of the user's strategies with the same content hash, on the same
+    /// dataset, dates and execution, computed by this version of the engine on the same version of
+    /// the datasets. The artifacts of a backtest are only ever shared between backtests of its
+    /// user.
+    pub async fn find_cached_backtest(
+        &self,
+        request: &CreateBacktestRequest,
+        user_id: Uuid,
+        content_hash: &str,
+        dataset_version: &str,
+    ) -> Result<Option<Backtest>, AppError> {
+        let backtest = sqlx::query_as::<_, Backtest>(&format!(
+            r#"
+            SELECT {}
+            FROM backtests
+            JOIN strategies ON backtests.strategy_id = strategies.id
+            WHERE strategies.user_id = $1 AND backtests.content_hash = $2 AND dataset = $3
+                AND timeframe = $4 AND date_start = $5 AND date_end = $6 AND execution = $7
+                AND engine_version = $8 AND dataset_version = $9 AND status = 'done'
+                AND result_summary IS NOT NULL
+            ORDER BY backtests.created_at DESC
+            LIMIT 1
+            "#,
+            BACKTEST_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(content_hash)
+        .bind(&request.dataset)
+        .bind(&request.timeframe)
+        .bind(request.date_start)
+        .bind(request.date_end)
+        .bind(Json(&request.execution))
+        .bind(ENGINE_VERSION)
+        .bind(dataset_version)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(backtest)
+    }
+
+    /// Create a backtest of the latest version of `strategy` already done, with the results and
+    /// artifacts of `cached` (see `find_cached_backtest`). No job is queued.
+    pub async fn create_cached_backtest(
+        &self,
+        request: &CreateBacktestRequest,
+        strategy: &Strategy,
+        content_hash: &str,
+        dataset_version: &str,
+        cached: &Backtest,
+    ) -> Result<Backtest, AppError> {
+        let backtest = sqlx::query_as::<_, Backtest>(&format!(
+            r#"
+            INSERT INTO backtests (strategy_id, strategy_version, dataset, timeframe, date_start, date_end, execution, content_hash, created_at, status,
+                result_summary, trades_path, equity_curve_path, positions_path, engine_version, dataset_version)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, 'done', $10, $11, $12, $13, $14, $15)
+            RETURNING {}
+            "#,
+            BACKTEST_COLUMNS
+        ))
+        .bind(strategy.id)
+        .bind(strategy.version)
+        .bind(&request.dataset)
+        .bind(&request.timeframe)
+        .bind(request.date_start)
+        .bind(request.date_end)
+        .bind(Json(&request.execution))
+        .bind(content_hash)
+        .bind(Utc::now())
+        .bind(&cached.result_summary)
+        .bind(&cached.trades_path)
+        .bind(&cached.equity_curve_path)
+        .bind(&cached.positions_path)
+        .bind(ENGINE_VERSION)
+        .bind(dataset_version)
         .fetch_one(&self.pool)
         .await?;
 
@@ -43,19 +165,21 @@ impl Database {
         backtest_id: Uuid,
         user_id: Uuid,
     ) -> Result<Backtest, AppError> {
-        let backtest = sqlx::query_as::<_, Backtest>(
+        let backtest = sqlx::query_as::<_, Backtest>(&format!(
             r#"
-            SELECT * FROM backtests
+            SELECT {}
+            FROM backtests
             JOIN strategies ON backtests.strategy_id = strategies.id
-            WHERE id = $1 AND strategies.user_id = $2
+            WHERE backtests.id = $1 AND strategies.user_id = $2
             "#,
-        )
+            BACKTEST_COLUMNS
+        ))
         .bind(backtest_id)
         .bind(user_id)
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(backtest)
+        backtest.ok_or(AppError::BacktestNotFound)
     }
 
     pub async fn get_backtest_status(
@@ -65,16 +189,113 @@ impl Database {
     ) -> Result<BacktestStatus, AppError> {
         let status = sqlx::query_scalar::<_, BacktestStatus>(
             r#"
-            SELECT status FROM backtests
+            SELECT backtests.status FROM backtests
             JOIN strategies ON backtests.strategy_id = strategies.id
-            WHERE id = $1 AND strategies.user_id = $2
+            WHERE backtests.id = $1 AND strategies.user_id = $2
             "#,
         )
         .bind(backtest_id)
         .bind(user_id)
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(status)
+        status.ok_or(AppError::BacktestNotFound)
+    }
+
+    /// Backtests of a strategy, most recent first.
+    ///
+    /// The caller is expected to have checked that the strategy belongs to the user.
+    pub async fn get_strategy_backtests(
+        &self,
+        strategy_id: Uuid,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Backtest>, AppError> {
+        let backtests = sqlx::query_as::<_, Backtest>(&format!(
+            r#"
+            SELECT {}
+            FROM backtests
+            WHERE backtests.strategy_id = $1
+            ORDER BY backtests.created_at DESC, backtests.id
+            LIMIT $2 OFFSET $3
+            "#,
+            BACKTEST_COLUMNS
+        ))
+        .bind(strategy_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(backtests)
+    }
+
+    pub async fn count_strategy_backtests(&self, strategy_id: Uuid) -> Result<i64, AppError> {
+        let count =
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM backtests WHERE strategy_id = $1")
+                .bind(strategy_id)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(count)
+    }
+
+    /// Save the results of a backtest and mark its job as done, in one transaction. Returns false
+    /// and saves nothing if the job is no longer running, e.g. it was cancelled while its results
+    /// were being computed.
+    pub async fn complete_backtest(
+        &self,
+        job_id: i64,
+        backtest_id: Uuid,
+        summary: &ResultSummary,
+        artifacts: &ArtifactPaths,
+    ) -> Result<bool, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        // Locked first, a cancellation waits for the transaction and then finds the job done.
+        let running = sqlx::query_scalar::<_, i64>(
+            "SELECT id FROM jobs WHERE id = $1 AND status = 'running' FOR UPDATE",
+        )
+        .bind(job_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if running.is_none() {
+            return Ok(false);
+        }
+
+        let saved = sqlx::query(
+            r#"
+            UPDATE backtests
+            SET result_summary = $1, trades_path = $2, equity_curve_path = $3, positions_path = $4,
+                engine_version = $5
+            WHERE id = $6 AND job_id = $7 AND status = 'running'
+            "#,
+        )
+        .bind(Json(summary))
+        .bind(&artifacts.trades)
+        .bind(&artifacts.equity_curve)
+        .bind(&artifacts.positions)
+        .bind(ENGINE_VERSION)
+        .bind(backtest_id)
+        .bind(job_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if saved == 0 {
+            return Ok(false);
+        }
+
+        // Mirrored on the backtest, see sync_backtest_status.
+        let (completed,): (bool,) = sqlx::query_as("SELECT complete_job($1)")
+            .bind(job_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if !completed {
+            return Ok(false);
+        }
+
+        tx.commit().await?;
+
+        Ok(true)
     }
 }
@@ -1,6 +1,10 @@
 use crate::{Database, models::BacktestStatus};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sqlx::{PgExecutor, Type};
+use uuid::Uuid;
+
+use backtester::ExecutionConfig;
 
 use crate::{errors::AppError, validators::strategy_validator::StrategyContent};
 
@@ -10,14 +14,36 @@ pub enum JobType {
 }
 
 impl JobType {
-    fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> &str {
         match self {
             JobType::ProcessBacktest => "process_backtest",
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "job_status", rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl From<JobStatus> for BacktestStatus {
+    fn from(status: JobStatus) -> Self {
+        match status {
+            JobStatus::Pending => BacktestStatus::Pending,
+            JobStatus::Running => BacktestStatus::Running,
+            JobStatus::Done => BacktestStatus::Done,
+            JobStatus::Failed => BacktestStatus::Failed,
+            JobStatus::Cancelled => BacktestStatus::Cancelled,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
 pub struct Job {
     pub id: i64,
     pub job_type: String,
@@ -26,6 +52,78 @@ pub struct Job {
     pub timeout_seconds: i32,
 }
 
+/// Payload of a `process_backtest` job, everything a worker needs to run the backtest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestJob {
+    pub backtest_id: Uuid,
+    pub strategy: StrategyContent,
+    pub dataset: String,
+    pub timeframe: String,
+    pub date_start: DateTime<Utc>,
+    pub date_end: DateTime<Utc>,
+    #[serde(default)]
+    pub execution: ExecutionConfig,
+}
+
+impl BacktestJob {
+    /// Read the payload of a `process_backtest` job.
+    ///
+    /// Payloads are written as MessagePack maps (see `enqueue_job`). Jobs queued before were
+    /// compact arrays of the fields in declaration order, which `rmp_serde` still reads: the
+    /// fields added to the job and the strategy since then all come last and have defaults.
+    pub fn decode(payload: &[u8]) -> Result<Self, rmp_serde::decode::Error> {
+        rmp_serde::from_slice(payload)
+    }
+}
+
+/// Enqueue a job using any executor, so it can be part of a bigger transaction.
+///
+/// The payload is written as a MessagePack map, with the field names: optional fields are left
+/// out when not set, which would shift the fields that follow in a compact array.
+pub(crate) async fn enqueue_job<'e, E, T>(
+    executor: E,
+    job_type: JobType,
+    payload: &T,
+    priority: i32,
+    max_retries: i32,
+    delay_seconds: i32,
+    timeout_seconds: i32,
+) -> Result<i64, AppError>
+where
+    E: PgExecutor<'e>,
+    T: Serialize,
+{
+    let payload_bytes = rmp_serde::to_vec_named(payload)?;
+
+    let job_id: (i64,) = sqlx::query_as(
+        r#"
+        SELECT enqueue_job($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(job_type.as_str())
+    .bind(&payload_bytes)
+    .bind(priority)
+    .bind(max_retries)
+    .bind(delay_seconds)
+    .bind(timeout_seconds)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(job_id.0)
+}
+
+pub(crate) async fn enqueue_backtest_job<'e, E>(
+    executor: E,
+    job: &BacktestJob,
+    priority: i32,
+) -> Result<i64, AppError>
+where
+    E: PgExecutor<'e>,
+{
+    // WARN: Hard coded some values, but it's probably not the right aproach
+    enqueue_job(executor, JobType::ProcessBacktest, job, priority, 3, 0, 600).await
+}
+
 impl Database {
     pub async fn enqueue<T: Serialize>(
         &self,
@@ -36,58 +134,149 @@ impl Database {
         delay_seconds: i32,
         timeout_seconds: i32,
     ) -> Result<i64, AppError> {
-        let payload_bytes = rmp_serde::to_vec(payload)?;
+        enqueue_job(
+            &self.pool,
+            job_type,
+            payload,
+            priority,
+            max_retries,
+            delay_seconds,
+            timeout_seconds,
+        )
+        .await
+    }
+
+    /// Claim the next available job of one of the given types, if any.
+    pub async fn dequeue(
+        &self,
+        worker_id: &str,
+        job_types: &[JobType],
+    ) -> Result<Option<Job>, AppError> {
+        let job_types: Vec<&str> = job_types.iter().map(|t| t.as_str()).collect();
 
-        let job_id: (i64,) = sqlx::query_as(
+        let job = sqlx::query_as::<_, Job>(
             r#"
-        SELECT enqueue_job($1, $2, $3, $4, $5, $6)
-        "#,
+            SELECT * FROM dequeue_job($1, $2)
+            "#,
         )
-        .bind(job_type.as_str())
-        .bind(&payload_bytes)
-        .bind(priority)
-        .bind(max_retries)
-        .bind(delay_seconds)
-        .bind(timeout_seconds)
-        .fetch_one(&self.pool)
+        .bind(worker_id)
+        .bind(&job_types)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(job_id.0)
+        Ok(job)
     }
 
-    pub async fn enqueue_backtest(
-        &self,
-        strategy: &StrategyContent,
-        priority: i32,
-    ) -> Result<i64, AppError> {
-        let mut payload = HashMap::new();
+    pub async fn complete_job(&self, job_id: i64) -> Result<bool, AppError> {
+        let updated: (bool,) = sqlx::query_as("SELECT complete_job($1)")
+            .bind(job_id)
+            .fetch_one(&self.pool)
+            .await?;
 
-        let strategy_json = serde_json::to_value(strategy)?;
-        payload.insert("strategy", strategy_json.to_string());
+        Ok(updated.0)
+    }
 
-        // WARN: Hard coded some values, but it's probably not the right aproach
-        self.enqueue(JobType::ProcessBacktest, &payload, priority, 3, 0, 600)
-            .await
+    /// Mark the job as failed, it is rescheduled if it still has retries left.
+    pub async fn fail_job(&self, job_id: i64, error: &str) -> Result<bool, AppError> {
+        let updated: (bool,) = sqlx::query_as("SELECT fail_job($1, $2)")
+            .bind(job_id)
+            .bind(error)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(updated.0)
+    }
+
+    /// Hand a running job back to the queue, without counting it as an attempt.
+    pub async fn release_job(&self, job_id: i64) -> Result<bool, AppError> {
+        let updated: (bool,) = sqlx::query_as("SELECT release_job($1)")
+            .bind(job_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(updated.0)
     }
 
-    pub async fn get_job_status(&self, job_id: i64) -> Result<BacktestStatus, AppError> {
-        let status = sqlx::query(
+    /// Cancel a pending or running job. Returns false if it was already finished.
+    pub async fn cancel_job(&self, job_id: i64) -> Result<bool, AppError> {
+        let updated: (bool,) = sqlx::query_as("SELECT cancel_job($1)")
+            .bind(job_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(updated.0)
+    }
+
+    /// Status of a job, `None` if it does not exist (anymore, old jobs get cleaned up).
+    pub async fn get_job_status(&self, job_id: i64) -> Result<Option<JobStatus>, AppError> {
+        let status = sqlx::query_scalar::<_, JobStatus>(
             r#"
             SELECT status FROM jobs WHERE id = $1
             "#,
         )
         .bind(job_id)
-        .fetch_one(&self.pool)
-        .await;
+        .fetch_optional(&self.pool)
+        .await?;
 
-        match status {
-            Ok(s) => {}
-            Err(sqlx::Error::RowNotFound) => {
-                // Job was done to long ago...
-            }
-            Err(e) => {}
-        }
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `process_backtest` payload as queued before the execution settings existed.
+    #[derive(Serialize)]
+    struct LegacyBacktestJob {
+        backtest_id: Uuid,
+        strategy: StrategyContent,
+        dataset: String,
+        timeframe: String,
+        date_start: DateTime<Utc>,
+        date_end: DateTime<Utc>,
+    }
+
+    #[test]
+    fn test_decode_backtest_job() {
+        let strategy: StrategyContent = serde_json::from_str(
+            r#"{
+                "meta": { "type": "spot" },
+                "actions": [
+                    { "type": "buy", "w": 0.8, "cond": { "gt": { "l": "sma_10", "r": "sma_50" } } }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let legacy = LegacyBacktestJob {
+            backtest_id: Uuid::new_v4(),
+            strategy,
+            dataset: "BTCUSDT".to_string(),
+            timeframe: "1m".to_string(),
+            date_start: DateTime::from_timestamp(1546300800, 0).unwrap(),
+            date_end: DateTime::from_timestamp(1577836800, 0).unwrap(),
+        };
+
+        // Queued before, as a compact array
+        let job = BacktestJob::decode(&rmp_serde::to_vec(&legacy).unwrap()).unwrap();
+        assert_eq!(job.backtest_id, legacy.backtest_id);
+        assert_eq!(job.dataset, "BTCUSDT");
+        assert_eq!(job.date_end, legacy.date_end);
+        assert_eq!(job.strategy.actions[0].w, 0.8);
+        assert!(job.strategy.risk.is_none());
+        assert_eq!(job.execution, ExecutionConfig::default());
 
-        Ok(BacktestStatus::Pending)
+        // Queued now, as a map
+        let job = BacktestJob {
+            execution: serde_json::from_str(
+                r#"{ "taker_fee_bps": 10, "slippage": { "model": "fixed", "bps": 2 } }"#,
+            )
+            .unwrap(),
+            ..job
+        };
+        let decoded = BacktestJob::decode(&rmp_serde::to_vec_named(&job).unwrap()).unwrap();
+        assert_eq!(decoded.backtest_id, job.backtest_id);
+        assert_eq!(decoded.date_start, job.date_start);
+        assert_eq!(decoded.execution, job.execution);
     }
 }
@@ -3,33 +3,55 @@ use sqlx::types::Json;
 use uuid::Uuid;
 
 use crate::{
-    errors::AppError, models::{Strategy, StrategyResumed}, validators::strategy_validator::StrategyContent, Database
+    Database,
+    errors::AppError,
+    models::{Strategy, StrategyResumed, StrategyVersion, StrategyVersionResumed},
+    validators::strategy_validator::{StrategyContent, content_hash},
 };
 
 impl Database {
+    /// Create a strategy and its first version, in one transaction.
     pub async fn create_strategy(
         &self,
         user_id: Uuid,
         title: &str,
         content: &StrategyContent,
+        content_hash: &str,
     ) -> Result<Strategy, AppError> {
         let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
 
         let strategy = sqlx::query_as::<_, Strategy>(
             r#"
-            INSERT INTO strategies (user_id, title, content, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO strategies (user_id, title, content, content_hash, version, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, 1, $5, $6)
             RETURNING *
             "#,
         )
         .bind(user_id)
         .bind(title)
         .bind(Json(content))
+        .bind(content_hash)
         .bind(now)
         .bind(now)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
+        sqlx::query(
+            r#"
+            INSERT INTO strategy_versions (strategy_id, version, content, content_hash, created_at)
+            VALUES ($1, 1, $2, $3, $4)
+            "#,
+        )
+        .bind(strategy.id)
+        .bind(Json(content))
+        .bind(content_hash)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
         Ok(strategy)
     }
 
@@ -47,22 +69,56 @@ impl Database {
         Ok(result.rows_affected())
     }
 
+    /// Save `content` as a new version of the strategy, in one transaction. Returns the number of
+    /// the version, `None` if the user has no such strategy.
     pub async fn modify_strategy(
         &self,
         strat_id: Uuid,
         user_id: Uuid,
         title: &str,
         content: &StrategyContent,
-    ) -> Result<u64, AppError> {
-        let result = sqlx::query("UPDATE strategies SET title = $1, content = $2, updated_at = NOW() WHERE id = $3 AND user_id = $4")
-            .bind(title)
-            .bind(Json(content))
-            .bind(strat_id)
-            .bind(user_id)
-            .execute(&self.pool)
-            .await?;
+        content_hash: &str,
+    ) -> Result<Option<i32>, AppError> {
+        let mut tx = self.pool.begin().await?;
 
-        Ok(result.rows_affected())
+        // Locks the strategy until the version is saved, concurrent modifications get the next
+        // numbers.
+        let version = sqlx::query_scalar::<_, i32>(
+            r#"
+            UPDATE strategies
+            SET title = $1, content = $2, content_hash = $3, version = version + 1,
+                updated_at = NOW()
+            WHERE id = $4 AND user_id = $5
+            RETURNING version
+            "#,
+        )
+        .bind(title)
+        .bind(Json(content))
+        .bind(content_hash)
+        .bind(strat_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(version) = version else {
+            return Ok(None);
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO strategy_versions (strategy_id, version, content, content_hash, created_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            "#,
+        )
+        .bind(strat_id)
+        .bind(version)
+        .bind(Json(content))
+        .bind(content_hash)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(version))
     }
 
     pub async fn get_strategy_by_id(&self, strat_id: Uuid, user_id: Uuid) -> Result<Option<Strategy>, AppError> {
@@ -101,4 +157,127 @@ impl Database {
         Ok(strategies)
     }
 
+    /// Strategies of the user with the given content hash, the ones only written differently
+    /// from a strategy being saved.
+    pub async fn get_strategies_by_hash(
+        &self,
+        user_id: Uuid,
+        content_hash: &str,
+    ) -> Result<Vec<StrategyResumed>, AppError> {
+        let strategies = sqlx::query_as::<_, StrategyResumed>(
+            r#"
+            SELECT id, title
+            FROM strategies
+            WHERE user_id = $1 AND content_hash = $2
+            ORDER BY created_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(content_hash)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(strategies)
+    }
+
+    /// Hash the strategies and versions saved before content_hash existed, so they are found as
+    /// duplicates and their backtests can be reused. Returns the number of rows hashed.
+    pub async fn backfill_content_hashes(&self) -> Result<u64, AppError> {
+        let mut hashed = 0;
+
+        let strategies = sqlx::query_as::<_, (Uuid, Json<StrategyContent>)>(
+            r#"
+            SELECT id, content
+            FROM strategies
+            WHERE content_hash IS NULL
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        for (id, content) in strategies {
+            hashed += sqlx::query(
+                r#"
+                UPDATE strategies SET content_hash = $1
+                WHERE id = $2 AND content_hash IS NULL
+                "#,
+            )
+            .bind(content_hash(&content))
+            .bind(id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        }
+
+        let versions = sqlx::query_as::<_, (Uuid, i32, Json<StrategyContent>)>(
+            r#"
+            SELECT strategy_id, version, content
+            FROM strategy_versions
+            WHERE content_hash IS NULL
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        for (strat_id, version, content) in versions {
+            hashed += sqlx::query(
+                r#"
+                UPDATE strategy_versions SET content_hash = $1
+                WHERE strategy_id = $2 AND version = $3 AND content_hash IS NULL
+                "#,
+            )
+            .bind(content_hash(&content))
+            .bind(strat_id)
+            .bind(version)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        }
+
+        Ok(hashed)
+    }
+
+    /// Versions of a strategy, most recent first.
+    ///
+    /// The caller is expected to have checked that the strategy belongs to the user.
+    pub async fn get_strategy_versions(
+        &self,
+        strat_id: Uuid,
+    ) -> Result<Vec<StrategyVersionResumed>, AppError> {
+        let versions = sqlx::query_as::<_, StrategyVersionResumed>(
+            r#"
+            SELECT version, content_hash, created_at
+            FROM strategy_versions
+            WHERE strategy_id = $1
+            ORDER BY version DESC
+            "#,
+        )
+        .bind(strat_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+
+    pub async fn get_strategy_version(
+        &self,
+        strat_id: Uuid,
+        user_id: Uuid,
+        version: i32,
+    ) -> Result<StrategyVersion, AppError> {
+        let version = sqlx::query_as::<_, StrategyVersion>(
+            r#"
+            SELECT strategy_versions.*
+            FROM strategy_versions
+            JOIN strategies ON strategy_versions.strategy_id = strategies.id
+            WHERE strategy_versions.strategy_id = $1 AND strategies.user_id = $2
+                AND strategy_versions.version = $3
+            "#,
+        )
+        .bind(strat_id)
+        .bind(user_id)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        version.ok_or(AppError::VersionNotFound)
+    }
 }
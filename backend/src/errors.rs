@@ -44,6 +44,9 @@ pub enum AppError {
     #[error("Strategy already exists")]
     StratExists,
 
+    #[error("Strategy version not found")]
+    VersionNotFound,
+
     #[error("Invalid input: {0}")]
     BadRequest(String),
 
@@ -56,8 +59,8 @@ pub enum AppError {
     #[error("Backtest not found")]
     BacktestNotFound,
 
-    #[error("Backtest is in process")]
-    BacktestProcessing,
+    #[error("Backtest is already finished")]
+    BacktestFinished,
 
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
@@ -65,6 +68,12 @@ pub enum AppError {
     #[error("Strategy error {0}")]
     StratError(#[from] ValidationError),
 
+    #[error("Object not found")]
+    ObjectNotFound,
+
+    #[error("Storage error: {0}")]
+    Storage(String),
+
     #[error("MessagePack error {0}")]
     MessagePackError(#[from] rmp_serde::encode::Error),
 }
@@ -124,6 +133,10 @@ impl IntoResponse for AppError {
             AppError::UsernameTaken => (StatusCode::CONFLICT, "Username already taken".to_string()),
             AppError::StratNotFound => (StatusCode::NOT_FOUND, "Strategy not found".to_string()),
             AppError::StratExists => (StatusCode::CONFLICT, "Strategy already exists".to_string()),
+            AppError::VersionNotFound => (
+                StatusCode::NOT_FOUND,
+                "Strategy version not found".to_string(),
+            ),
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::Internal => {
                 tracing::error!("Internal server error");
@@ -141,12 +154,21 @@ impl IntoResponse for AppError {
                 )
             }
             AppError::BacktestNotFound => (StatusCode::NOT_FOUND, "Backtest not found".to_string()),
-            AppError::BacktestProcessing => {
-                (StatusCode::PROCESSING, "Backtest is in process".to_string())
-            }
+            AppError::BacktestFinished => (
+                StatusCode::CONFLICT,
+                "Backtest is already finished".to_string(),
+            ),
             AppError::StratError(ref e) => {
                 (StatusCode::BAD_REQUEST, format!("Strategy error: {}", e))
             }
+            AppError::ObjectNotFound => (StatusCode::NOT_FOUND, "Object not found".to_string()),
+            AppError::Storage(ref e) => {
+                tracing::error!("Storage error: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal server error".to_string(),
+                )
+            }
             AppError::MessagePackError(ref e) => {
                 tracing::error!("MessagePack encoding error: {:?}", e);
                 (
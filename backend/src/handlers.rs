@@ -1,6 +1,7 @@
 pub mod users;
 pub mod strategies;
 pub mod backtests;
+pub mod storage;
 
 use axum::{
     response::Json,
@@ -1,22 +1,28 @@
+use std::collections::{HashMap, HashSet};
+
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    http::StatusCode,
     response::Json,
 };
 use uuid::Uuid;
 
 use crate::{
     AppState,
+    dataset_client::DatasetMeta,
     errors::AppError,
     extractors::AuthenticatedUser,
-    models::{Backtest, BacktestStatus, CreateBacktestRequest},
-    validators::strategy_validator::StrategyValidator,
+    models::{
+        BacktestPage, BacktestResponse, BacktestStatus, CreateBacktestRequest, PaginationParams,
+    },
+    validators::strategy_validator::{StrategyValidator, Value, content_hash},
 };
 
 pub async fn request_backtest(
     State(state): State<AppState>,
     AuthenticatedUser(user_id): AuthenticatedUser,
-    Json(payload): Json<CreateBacktestRequest>,
-) -> Result<Json<BacktestStatus>, AppError> {
+    Json(mut payload): Json<CreateBacktestRequest>,
+) -> Result<Json<BacktestResponse>, AppError> {
     let strat = state
         .db
         .get_strategy_by_id(payload.strategy_id, user_id)
@@ -26,80 +32,226 @@ pub async fn request_backtest(
     // TODO: maybe check if the user already has pending/running strategies and check how many he
     // is allowed to have.
 
-    // Check if dataset with given timeframe exists
-    let dataset_meta = state
-        .dataset_manager
-        .get_dataset(format!("{}-{}", payload.dataset, payload.timeframe))
-        .await?;
+    payload
+        .execution
+        .validate()
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
 
-    if payload.date_start < dataset_meta.start || payload.date_end > dataset_meta.end {
-        return Err(AppError::BadRequest(format!(
-            "start and end date must be between {} and {}",
-            dataset_meta.start, dataset_meta.end
-        )));
+    // Portfolio strategies run on their own assets, stored as the dataset of the backtest.
+    if strat.content.portfolio.is_none() && payload.dataset.is_empty() {
+        return Err(AppError::BadRequest("dataset is required".to_string()));
+    }
+    let assets = StrategyValidator::get_assets(&strat.content, &payload.dataset);
+    if strat.content.portfolio.is_some() {
+        payload.dataset = assets.join(",");
     }
 
-    let indicators = StrategyValidator::get_indicators(&strat.content);
-    if !indicators.iter().all(|i| dataset_meta.ta.contains(i)) {
-        return Err(AppError::BadRequest(
-            "Using indicators not present in dataset".to_string(),
-        ));
+    let timeframes = StrategyValidator::get_timeframes(&strat.content);
+    let mut available = HashMap::new();
+    let mut dataset_versions = Vec::new();
+    for asset in &assets {
+        let indicators =
+            dataset_indicators(&state, asset, &payload, &timeframes, &mut dataset_versions).await?;
+        available.insert(asset.as_str(), indicators);
+    }
+    dataset_versions.sort();
+    let dataset_version = dataset_versions.join(",");
+
+    // Indicators the datasets do not precompute are computed by the backtester. The ones without
+    // an asset prefix are read on every asset.
+    let mut missing: Vec<String> = StrategyValidator::get_indicators(&strat.content)
+        .into_iter()
+        .filter(|ta| {
+            let (read_on, reference) = match Value::split_asset(ta) {
+                Some((asset, rest)) => (vec![asset], rest),
+                None => (assets.iter().map(String::as_str).collect(), ta.as_str()),
+            };
+            let reference = Value::parse_indicator(reference);
+            !StrategyValidator::is_computable(reference.name)
+                && read_on.iter().any(|asset| {
+                    !available
+                        .get(asset)
+                        .and_then(|indicators| indicators.get(&reference.timeframe))
+                        .is_some_and(|ta| ta.iter().any(|a| a == reference.name))
+                })
+        })
+        .collect();
+    if !missing.is_empty() {
+        missing.sort();
+        return Err(AppError::BadRequest(format!(
+            "Using indicators not present in dataset: {}",
+            missing.join(", ")
+        )));
     }
 
     // TODO: Check if users can still run backtest based on subscription
 
+    // The same strategy content was already backtested with the same settings, reuse its results.
+    let hash = content_hash(&strat.content);
+    if let Some(cached) = state
+        .db
+        .find_cached_backtest(&payload, user_id, &hash, &dataset_version)
+        .await?
+    {
+        let backtest = state
+            .db
+            .create_cached_backtest(&payload, &strat, &hash, &dataset_version, &cached)
+            .await?;
+        return Ok(Json(backtest.into()));
+    }
+
+    // Creates the backtest and adds it to the job queue
     let backtest = state
         .db
-        .create_backtest(
-            payload.strategy_id,
-            &payload.dataset,
-            &payload.timeframe,
-            payload.date_start,
-            payload.date_end,
-        )
+        .create_backtest(&payload, &strat, &hash, &dataset_version, 1) // TODO: Make the priority system
         .await?;
 
     // TODO: Log the dataset and start/end time for metrics
 
-    // Add backtest to job queue or whatever
-    let job_id = state.db.enqueue_backtest(&strat.content.0, 1).await?; // TODO: Make the priority system
+    Ok(Json(backtest.into()))
+}
+
+/// Indicators precomputed by the datasets of `asset`: the one of the backtest's timeframe (under
+/// `None`) and the ones of the other `timeframes` the strategy reads. Fails if one is missing or
+/// does not cover the backtest's dates. The version of each dataset is added to `versions`.
+async fn dataset_indicators<'a>(
+    state: &AppState,
+    asset: &str,
+    request: &CreateBacktestRequest,
+    timeframes: &'a HashSet<String>,
+    versions: &mut Vec<String>,
+) -> Result<HashMap<Option<&'a str>, Vec<String>>, AppError> {
+    // Check if dataset with given timeframe exists
+    let dataset_meta = state
+        .dataset_manager
+        .get_dataset(format!("{}-{}", asset, request.timeframe))
+        .await?;
+
+    if request.date_start < dataset_meta.start || request.date_end > dataset_meta.end {
+        return Err(AppError::BadRequest(format!(
+            "start and end date must be between {} and {} for {}",
+            dataset_meta.start, dataset_meta.end, asset
+        )));
+    }
 
-    // Returning only the backtest's initial status
-    Ok(Json(backtest.status))
+    versions.push(dataset_version(&dataset_meta));
+    let mut indicators = HashMap::from([(None, dataset_meta.ta)]);
+    // Indicators read on another timeframe need that timeframe's dataset of the same asset.
+    for tf in timeframes {
+        let meta = match state
+            .dataset_manager
+            .get_dataset(format!("{}-{}", asset, tf))
+            .await
+        {
+            Err(AppError::DatasetNotFound) => {
+                return Err(AppError::BadRequest(format!(
+                    "Timeframe {} is not available for {}",
+                    tf, asset
+                )));
+            }
+            meta => meta?,
+        };
+        versions.push(dataset_version(&meta));
+        indicators.insert(Some(tf.as_str()), meta.ta);
+    }
+
+    Ok(indicators)
+}
+
+/// What a backtest's results depend on in a dataset, dataset-manager can reload it with other
+/// candles.
+fn dataset_version(meta: &DatasetMeta) -> String {
+    format!(
+        "{}-{}@{}:{}:{}:{}",
+        meta.asset,
+        meta.timeframe,
+        meta.version,
+        meta.count,
+        meta.start.timestamp(),
+        meta.end.timestamp()
+    )
 }
 
-// NOTE: This handler is possibly not needed anymore
-pub async fn backtest_status(
+pub async fn get_backtest(
     State(state): State<AppState>,
     AuthenticatedUser(user_id): AuthenticatedUser,
     Path(backtest_id): Path<Uuid>,
-) -> Result<Json<BacktestStatus>, AppError> {
-    let status = state.db.get_backtest_status(backtest_id, user_id).await?;
-    Ok(Json(status))
+) -> Result<Json<BacktestResponse>, AppError> {
+    let backtest = state.db.get_backtest_by_id(backtest_id, user_id).await?;
+    Ok(Json(backtest.into()))
 }
 
+/// The results of a finished backtest. One still waiting or being computed is sent back as it is
+/// with `202 Accepted`, its status telling which.
 pub async fn backtest_results(
     State(state): State<AppState>,
     AuthenticatedUser(user_id): AuthenticatedUser,
     Path(backtest_id): Path<Uuid>,
-) -> Result<Json<Backtest>, AppError> {
-    let status = state.db.get_backtest_status(backtest_id, user_id).await?;
-    match status {
-        BacktestStatus::Done => {
-            let backtest = state.db.get_backtest_by_id(backtest_id, user_id).await?;
-            // TODO: Send the results rather than the backtest.
-            Ok(Json(backtest))
-        }
-        BacktestStatus::Failed | BacktestStatus::Cancelled => {
-            let backtest = state.db.get_backtest_by_id(backtest_id, user_id).await?;
-            // HACK: Just sending the backtest here cause the failed status is in it
-            Ok(Json(backtest))
+) -> Result<(StatusCode, Json<BacktestResponse>), AppError> {
+    let backtest = state.db.get_backtest_by_id(backtest_id, user_id).await?;
+    match backtest.status {
+        BacktestStatus::Done | BacktestStatus::Failed | BacktestStatus::Cancelled => {
+            let artifacts = state.s3.presign_artifacts(&backtest).await?;
+            let mut response = BacktestResponse::from(backtest);
+            response.artifacts = artifacts;
+            Ok((StatusCode::OK, Json(response)))
         }
         BacktestStatus::Pending | BacktestStatus::Running => {
-            // TODO: Get the job's status
-            // And basically do the same as above again
-            let backtest = state.db.get_backtest_by_id(backtest_id, user_id).await?;
-            Ok(Json(backtest))
+            Ok((StatusCode::ACCEPTED, Json(backtest.into())))
+        }
+    }
+}
+
+/// Cancel a backtest that is still waiting in the queue or being computed.
+pub async fn cancel_backtest(
+    State(state): State<AppState>,
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Path(backtest_id): Path<Uuid>,
+) -> Result<Json<BacktestResponse>, AppError> {
+    let backtest = state.db.get_backtest_by_id(backtest_id, user_id).await?;
+
+    let cancelled = match (&backtest.status, backtest.job_id) {
+        (BacktestStatus::Pending | BacktestStatus::Running, Some(job_id)) => {
+            state.db.cancel_job(job_id).await?
         }
+        _ => false,
+    };
+    // The job may have finished between the two queries.
+    if !cancelled {
+        return Err(AppError::BacktestFinished);
     }
+
+    // The status was mirrored from the job, read the backtest again to send it back.
+    let backtest = state.db.get_backtest_by_id(backtest_id, user_id).await?;
+    Ok(Json(backtest.into()))
+}
+
+pub async fn list_strategy_backtests(
+    State(state): State<AppState>,
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Path(strategy_id): Path<Uuid>,
+    Query(pagination): Query<PaginationParams>,
+) -> Result<Json<BacktestPage>, AppError> {
+    state
+        .db
+        .get_strategy_by_id(strategy_id, user_id)
+        .await?
+        .ok_or(AppError::StratNotFound)?;
+
+    let backtests = state
+        .db
+        .get_strategy_backtests(
+            strategy_id,
+            pagination.per_page() as i64,
+            pagination.offset(),
+        )
+        .await?;
+    let total = state.db.count_strategy_backtests(strategy_id).await?;
+
+    Ok(Json(BacktestPage {
+        items: backtests.into_iter().map(Into::into).collect(),
+        page: pagination.page(),
+        per_page: pagination.per_page(),
+        total,
+    }))
 }
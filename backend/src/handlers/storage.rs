@@ -0,0 +1,21 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::IntoResponse,
+};
+
+use crate::{AppState, errors::AppError, models::PresignedParams};
+
+/// Serve an object of the local object store through a link it signed.
+pub async fn download_object(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(params): Query<PresignedParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let bytes = state
+        .s3
+        .download_presigned(&key, params.expires, &params.signature)
+        .await?;
+
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], bytes))
+}
@@ -1,4 +1,8 @@
-use axum::{extract::State, response::Json};
+use axum::{
+    extract::{Path, Query, State},
+    response::Json,
+};
+use backtester::diff::{Change, diff};
 use rmp_serde::from_slice;
 use uuid::Uuid;
 
@@ -11,15 +15,21 @@ use crate::{
     AppState,
     errors::AppError,
     extractors::AuthenticatedUser,
-    models::{CreateStrategyRequest, GetStrategyRequest, Strategy, StrategyResumed},
-    validators::strategy_validator::{StrategyContent, validate_strategy_title},
+    models::{
+        CreateStrategyRequest, DiffParams, GetStrategyRequest, Strategy, StrategyCreated,
+        StrategyResumed, StrategyText, StrategyVersion, StrategyVersionResumed,
+    },
+    validators::{
+        strategy_lints::{Lint, LintKind, lint_strategy},
+        strategy_validator::{StrategyContent, content_hash, validate_strategy_title},
+    },
 };
 
 pub async fn create_strategy(
     State(state): State<AppState>,
     AuthenticatedUser(user_id): AuthenticatedUser,
     Json(payload): Json<CreateStrategyRequest>,
-) -> Result<Json<Strategy>, AppError> {
+) -> Result<Json<StrategyCreated>, AppError> {
     // Checking if user already has a strategy named <title>
     if state
         .db
@@ -42,14 +52,17 @@ pub async fn create_strategy(
     };
 
     validate_strategy_title(&payload.title)?;
-    state.strat_validator.validate_strategy(&content)?;
+    state.strat_validator.read().validate_strategy(&content)?;
+    let hash = content_hash(&content);
+    let mut warnings = lint_strategy(&content);
+    warnings.extend(duplicate_warnings(&state, user_id, &hash, None).await?);
 
     let strategy = state
         .db
-        .create_strategy(user_id, &payload.title, &content)
+        .create_strategy(user_id, &payload.title, &content, &hash)
         .await?;
 
-    Ok(Json(strategy))
+    Ok(Json(StrategyCreated { strategy, warnings }))
 }
 
 pub async fn delete_strategy(
@@ -57,7 +70,7 @@ pub async fn delete_strategy(
     AuthenticatedUser(user_id): AuthenticatedUser,
     Json(strat_id): Json<Uuid>,
 ) -> Result<Json<Value>, AppError> {
-    let res = state.db.delete_strategy(user_id, strat_id).await?;
+    let res = state.db.delete_strategy(strat_id, user_id).await?;
 
     Ok(Json(json!({
         "message": "sucessfuly deleted strategy",
@@ -71,26 +84,93 @@ pub async fn modify_strategy(
     Json(payload): Json<Strategy>,
 ) -> Result<Json<Value>, AppError> {
     // Checking if user has the strategy to modify
-    let strat = state
+    state
         .db
         .get_strategy_by_id(payload.id, user_id)
         .await?
         .ok_or(AppError::StratNotFound)?;
 
-    validate_strategy_title(&payload.title)?;
-    state.strat_validator.validate_strategy(&strat.content)?;
+    save_version(
+        &state,
+        user_id,
+        payload.id,
+        &payload.title,
+        &payload.content,
+    )
+    .await
+}
 
-    let res = state
+/// Save the content of an old version as a new version, the history is kept as it is.
+pub async fn restore_strategy_version(
+    State(state): State<AppState>,
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Path((strategy_id, version)): Path<(Uuid, i32)>,
+) -> Result<Json<Value>, AppError> {
+    let strat = state
         .db
-        .modify_strategy(payload.id, user_id, &payload.title, &payload.content)
+        .get_strategy_by_id(strategy_id, user_id)
+        .await?
+        .ok_or(AppError::StratNotFound)?;
+    let old = state
+        .db
+        .get_strategy_version(strategy_id, user_id, version)
         .await?;
 
+    // Validated again, the indicators available may have changed since it was saved.
+    save_version(&state, user_id, strategy_id, &strat.title, &old.content).await
+}
+
+/// Validate `content` and save it as the next version of the strategy.
+async fn save_version(
+    state: &AppState,
+    user_id: Uuid,
+    strategy_id: Uuid,
+    title: &str,
+    content: &StrategyContent,
+) -> Result<Json<Value>, AppError> {
+    validate_strategy_title(title)?;
+    state.strat_validator.read().validate_strategy(content)?;
+    let hash = content_hash(content);
+    let mut warnings = lint_strategy(content);
+    warnings.extend(duplicate_warnings(state, user_id, &hash, Some(strategy_id)).await?);
+
+    let version = state
+        .db
+        .modify_strategy(strategy_id, user_id, title, content, &hash)
+        .await?
+        .ok_or(AppError::StratNotFound)?;
+
     Ok(Json(json!({
         "message": "sucessfuly modified strategy",
-        "num_del": res,
+        "num_del": 1,
+        "version": version,
+        "warnings": warnings,
     })))
 }
 
+/// A warning for each other strategy of the user with the same content as the one being saved,
+/// `id` if it already exists.
+async fn duplicate_warnings(
+    state: &AppState,
+    user_id: Uuid,
+    content_hash: &str,
+    id: Option<Uuid>,
+) -> Result<Vec<Lint>, AppError> {
+    let duplicates = state
+        .db
+        .get_strategies_by_hash(user_id, content_hash)
+        .await?;
+    Ok(duplicates
+        .into_iter()
+        .filter(|strategy| Some(strategy.id) != id)
+        .map(|strategy| Lint {
+            kind: LintKind::DuplicateStrategy,
+            action: None,
+            message: format!("same content as your strategy '{}'", strategy.title),
+        })
+        .collect())
+}
+
 pub async fn get_strategy(
     State(state): State<AppState>,
     AuthenticatedUser(user_id): AuthenticatedUser,
@@ -111,3 +191,69 @@ pub async fn get_strategies(
     let strats = state.db.get_user_strategies(user_id).await?;
     Ok(Json(strats))
 }
+
+/// Versions of a strategy, most recent first.
+pub async fn list_strategy_versions(
+    State(state): State<AppState>,
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Path(strategy_id): Path<Uuid>,
+) -> Result<Json<Vec<StrategyVersionResumed>>, AppError> {
+    state
+        .db
+        .get_strategy_by_id(strategy_id, user_id)
+        .await?
+        .ok_or(AppError::StratNotFound)?;
+
+    let versions = state.db.get_strategy_versions(strategy_id).await?;
+    Ok(Json(versions))
+}
+
+pub async fn get_strategy_version(
+    State(state): State<AppState>,
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Path((strategy_id, version)): Path<(Uuid, i32)>,
+) -> Result<Json<StrategyVersion>, AppError> {
+    let version = state
+        .db
+        .get_strategy_version(strategy_id, user_id, version)
+        .await?;
+    Ok(Json(version))
+}
+
+/// What changed in the logic of a strategy between two of its versions, see `backtester::diff`.
+pub async fn diff_strategy_versions(
+    State(state): State<AppState>,
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Path(strategy_id): Path<Uuid>,
+    Query(params): Query<DiffParams>,
+) -> Result<Json<Vec<Change>>, AppError> {
+    let from = state
+        .db
+        .get_strategy_version(strategy_id, user_id, params.from)
+        .await?;
+    let to = state
+        .db
+        .get_strategy_version(strategy_id, user_id, params.to)
+        .await?;
+    Ok(Json(diff(&from.content, &to.content)))
+}
+
+/// Compile a strategy written in its text form, for the editor.
+pub async fn parse_strategy(
+    State(state): State<AppState>,
+    AuthenticatedUser(_): AuthenticatedUser,
+    Json(payload): Json<StrategyText>,
+) -> Result<Json<StrategyContent>, AppError> {
+    let strategy = state.strat_validator.read().validate_text(&payload.text)?;
+    Ok(Json(strategy))
+}
+
+/// Write a strategy in its text form, for the editor.
+pub async fn format_strategy(
+    AuthenticatedUser(_): AuthenticatedUser,
+    Json(content): Json<StrategyContent>,
+) -> Result<Json<StrategyText>, AppError> {
+    Ok(Json(StrategyText {
+        text: backtester::text::format(&content),
+    }))
+}
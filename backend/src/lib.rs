@@ -1,9 +1,11 @@
 pub mod app;
+pub mod artifacts;
 pub mod db;
 pub mod models;
 //mod routes;
 pub mod handlers;
 pub mod middleware;
+pub mod object_store;
 //mod store;
 pub mod auth;
 pub mod dataset_client;
@@ -11,6 +13,7 @@ pub mod errors;
 pub mod extractors;
 pub mod s3_manager;
 pub mod validators;
+pub mod worker;
 
 use axum::{
     Router,
@@ -21,7 +24,7 @@ use tower_http::cors::{Any, CorsLayer};
 
 use crate::handlers::protected_route;
 use crate::handlers::strategies::*;
-use crate::handlers::{backtests::request_backtest, users::*}; // TODO: delete
+use crate::handlers::{backtests::*, storage::*, users::*}; // TODO: delete
 
 // Making those public because they are needed for integration testing.
 pub use crate::app::AppState;
@@ -54,15 +57,28 @@ pub fn create_app(app_state: AppState) -> Router {
         // HACK: Changed it to a post because frontend didn't like get with body (here the strat id)
         .route("/api/strategy", post(get_strategy))
         .route("/api/strategy/all", get(get_strategies))
+        .route("/api/strategy/parse", post(parse_strategy))
+        .route("/api/strategy/format", post(format_strategy))
         .route("/api/backtest", post(request_backtest))
-        //.route("/api/backtest/:id", get(backtest_status))
-        //.route("/api/backtest/:id/results", get(backtest_results))
+        .route("/api/backtest/:id", get(get_backtest))
+        .route("/api/backtest/:id/results", get(backtest_results))
+        .route("/api/backtest/:id/cancel", post(cancel_backtest))
+        .route("/api/strategy/:id/backtests", get(list_strategy_backtests))
+        .route("/api/strategy/:id/versions", get(list_strategy_versions))
+        .route("/api/strategy/:id/versions/:version", get(get_strategy_version))
+        .route(
+            "/api/strategy/:id/versions/:version/restore",
+            post(restore_strategy_version),
+        )
+        .route("/api/strategy/:id/diff", get(diff_strategy_versions))
         .layer(axum::middleware::from_fn_with_state(
             app_state.clone(),
             crate::middleware::auth_middleware,
         ))
         .route("/api/register", post(register))
         .route("/api/login", post(login))
+        // Links handed out by the local object store, they carry their own signature
+        .route("/api/storage/*key", get(download_object))
         .layer(cors)
         //.layer(TraceLayer::new_for_http())
         .with_state(app_state)
@@ -3,7 +3,9 @@ use serde::{Deserialize, Serialize};
 use sqlx::{Type, types::Json};
 use uuid::Uuid;
 
-use crate::validators::strategy_validator::StrategyContent;
+use backtester::{AssetAttribution, ExecutionConfig, Summary};
+
+use crate::validators::{strategy_lints::Lint, strategy_validator::StrategyContent};
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct User {
@@ -66,10 +68,48 @@ pub struct Strategy {
     pub user_id: Uuid,
     pub title: String,
     pub content: Json<StrategyContent>,
+    /// See `strategy_validator::content_hash`, `None` for strategies not saved since it exists.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// Latest version of the content, see `StrategyVersion`.
+    #[serde(default)]
+    pub version: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// A content a strategy had. Versions are numbered from 1 and never change once saved.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct StrategyVersion {
+    pub strategy_id: Uuid,
+    pub version: i32,
+    pub content: Json<StrategyContent>,
+    pub content_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct StrategyVersionResumed {
+    pub version: i32,
+    pub content_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The two versions of a strategy to compare, see `backtester::diff`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffParams {
+    pub from: i32,
+    pub to: i32,
+}
+
+/// A saved strategy, with the lints it was saved with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrategyCreated {
+    #[serde(flatten)]
+    pub strategy: Strategy,
+    pub warnings: Vec<Lint>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct StrategyResumed {
     pub id: Uuid,
@@ -88,6 +128,12 @@ pub struct GetStrategyRequest {
     pub id: Uuid,
 }
 
+/// A strategy in its text form, see `backtester::text`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StrategyText {
+    pub text: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
 #[sqlx(type_name = "backtest_status", rename_all = "lowercase")]
 pub enum BacktestStatus {
@@ -102,20 +148,194 @@ pub enum BacktestStatus {
 pub struct Backtest {
     pub id: Uuid,
     pub strategy_id: Uuid,
+    /// Version of the strategy the backtest ran, `None` for the ones run before versions existed.
+    pub strategy_version: Option<i32>,
+    /// Job computing the backtest, `None` once the job has been cleaned up.
+    pub job_id: Option<i64>,
     pub status: BacktestStatus,
     pub dataset: String,
     pub timeframe: String,
     pub date_start: DateTime<Utc>,
     pub date_end: DateTime<Utc>,
+    pub execution: Json<ExecutionConfig>,
     pub created_at: DateTime<Utc>,
-    //    pub result_summary: Json(ResultSummary), Do i need that here ?
+    /// Filled by the worker once the backtest is done.
+    pub result_summary: Option<Json<ResultSummary>>,
+    /// Object storage keys of the artifacts, filled along with the results.
+    pub trades_path: Option<String>,
+    pub equity_curve_path: Option<String>,
+    pub positions_path: Option<String>,
+}
+
+/// Metrics of a finished backtest, stored in `backtests.result_summary`.
+///
+/// Ratios and returns are fractions (0.1 is 10%), durations are in seconds. Metrics that cannot be
+/// computed (e.g. the win rate of a run that never closed a trade) are `null`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResultSummary {
+    pub initial_capital: f64,
+    pub final_equity: f64,
+    pub net_return: f64,
+    pub profit_factor: Option<f64>,
+    pub sharpe_ratio: Option<f64>,
+    pub sortino_ratio: Option<f64>,
+    pub calmar_ratio: Option<f64>,
+    pub max_drawdown: f64,
+    pub trades_count: usize,
+    pub win_rate: Option<f64>,
+    /// Fraction of the bars spent holding a position.
+    pub exposure_time: f64,
+    pub avg_trade_duration: Option<f64>,
+    pub largest_win: Option<f64>,
+    pub largest_loss: Option<f64>,
+    /// Return of simply holding the asset over the same period, to compare against.
+    pub buy_and_hold_return: f64,
+    /// Trading costs paid over the backtest, in quote currency. Zero for the backtests computed
+    /// before costs were simulated.
+    #[serde(default)]
+    pub total_fees: f64,
+    #[serde(default)]
+    pub total_slippage: f64,
+    #[serde(default)]
+    pub total_spread: f64,
+    /// Funding paid by a perpetual strategy, negative when received.
+    #[serde(default)]
+    pub total_funding: f64,
+    /// Result of each asset of a portfolio backtest.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attribution: Vec<AssetAttribution>,
+}
+
+impl From<Summary> for ResultSummary {
+    fn from(summary: Summary) -> Self {
+        Self {
+            initial_capital: summary.initial_capital,
+            final_equity: summary.final_equity,
+            net_return: summary.net_return,
+            profit_factor: summary.profit_factor,
+            sharpe_ratio: summary.sharpe_ratio,
+            sortino_ratio: summary.sortino_ratio,
+            calmar_ratio: summary.calmar_ratio,
+            max_drawdown: summary.max_drawdown,
+            trades_count: summary.trades_count,
+            win_rate: summary.win_rate,
+            exposure_time: summary.exposure_time,
+            avg_trade_duration: summary.avg_trade_duration,
+            largest_win: summary.largest_win,
+            largest_loss: summary.largest_loss,
+            buy_and_hold_return: summary.buy_and_hold_return,
+            total_fees: summary.total_fees,
+            total_slippage: summary.total_slippage,
+            total_spread: summary.total_spread,
+            total_funding: summary.total_funding,
+            attribution: summary.attribution,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateBacktestRequest {
     pub strategy_id: Uuid,
+    /// Asset to backtest on. Portfolio strategies run on their own assets and leave it out.
+    #[serde(default)]
+    pub dataset: String,
+    pub timeframe: String,
+    pub date_start: DateTime<Utc>,
+    pub date_end: DateTime<Utc>,
+    /// Fees, slippage and spread to simulate. No costs if omitted.
+    #[serde(default)]
+    pub execution: ExecutionConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BacktestParameters {
     pub dataset: String,
     pub timeframe: String,
     pub date_start: DateTime<Utc>,
     pub date_end: DateTime<Utc>,
+    pub execution: ExecutionConfig,
+}
+
+/// Where the detailed results of a backtest can be downloaded from.
+///
+/// Presigned URLs, only sent by the results endpoint.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BacktestArtifacts {
+    pub trades: Option<String>,
+    pub equity_curve: Option<String>,
+    pub positions: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BacktestResponse {
+    pub id: Uuid,
+    pub strategy_id: Uuid,
+    pub strategy_version: Option<i32>,
+    pub status: BacktestStatus,
+    pub parameters: BacktestParameters,
+    pub result_summary: Option<ResultSummary>,
+    pub artifacts: BacktestArtifacts,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Backtest> for BacktestResponse {
+    fn from(backtest: Backtest) -> Self {
+        Self {
+            id: backtest.id,
+            strategy_id: backtest.strategy_id,
+            strategy_version: backtest.strategy_version,
+            status: backtest.status,
+            parameters: BacktestParameters {
+                dataset: backtest.dataset,
+                timeframe: backtest.timeframe,
+                date_start: backtest.date_start,
+                date_end: backtest.date_end,
+                execution: backtest.execution.0,
+            },
+            result_summary: backtest.result_summary.map(|s| s.0),
+            artifacts: BacktestArtifacts::default(),
+            created_at: backtest.created_at,
+        }
+    }
+}
+
+/// Query of a link signed by the local object store.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresignedParams {
+    pub expires: i64,
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaginationParams {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+impl PaginationParams {
+    pub const DEFAULT_PER_PAGE: u32 = 20;
+    pub const MAX_PER_PAGE: u32 = 100;
+
+    /// Page number, starting at 1.
+    pub fn page(&self) -> u32 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn per_page(&self) -> u32 {
+        self.per_page
+            .unwrap_or(Self::DEFAULT_PER_PAGE)
+            .clamp(1, Self::MAX_PER_PAGE)
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.page() as i64 - 1) * self.per_page() as i64
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BacktestPage {
+    pub items: Vec<BacktestResponse>,
+    pub page: u32,
+    pub per_page: u32,
+    pub total: i64,
 }
@@ -0,0 +1,31 @@
+mod local_store;
+mod s3_store;
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::errors::AppError;
+
+pub use local_store::LocalStore;
+pub use s3_store::S3Store;
+
+/// Where the backtest artifacts live.
+///
+/// Downloads do not go through the backend when the store can hand out links of its own
+/// (presigned S3 URLs), the local store signs links pointing to `/api/storage` instead.
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), AppError>;
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, AppError>;
+
+    /// Time-limited URL to download an object without credentials.
+    async fn presigned_url(&self, key: &str, expires_in: Duration) -> Result<String, AppError>;
+
+    /// Check a link produced by `presigned_url`, for the stores whose links are served by the
+    /// backend. `expires` is a unix timestamp.
+    fn verify_presigned(&self, _key: &str, _expires: i64, _signature: &str) -> bool {
+        false
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::ObjectStore;
+use crate::errors::AppError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Objects stored as files under a directory, for development, CI and single machine setups.
+///
+/// Download links point to the `/api/storage` route of the backend, and carry an expiry date and
+/// an HMAC of the key so they cannot be forged or reused once expired.
+#[derive(Clone)]
+pub struct LocalStore {
+    root: PathBuf,
+    /// Public URL of the backend, the links are built on top of it.
+    base_url: String,
+    signing_key: Vec<u8>,
+}
+
+impl LocalStore {
+    pub fn new(
+        root: impl Into<PathBuf>,
+        base_url: impl Into<String>,
+        signing_key: Vec<u8>,
+    ) -> Self {
+        Self {
+            root: root.into(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            signing_key,
+        }
+    }
+
+    /// Path of the file holding `key`. Keys are relative paths that must stay inside the root.
+    fn path(&self, key: &str) -> Result<PathBuf, AppError> {
+        let relative = Path::new(key);
+        let is_safe = !key.is_empty()
+            && relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        if !is_safe {
+            return Err(AppError::Storage(format!("invalid object key {}", key)));
+        }
+
+        Ok(self.root.join(relative))
+    }
+
+    fn mac(&self, key: &str, expires: i64) -> HmacSha256 {
+        // Unwrap is fine, HMAC accepts keys of any size.
+        let mut mac = HmacSha256::new_from_slice(&self.signing_key).unwrap();
+        mac.update(format!("{}\n{}", key, expires).as_bytes());
+        mac
+    }
+}
+
+#[async_trait]
+impl ObjectStore for LocalStore {
+    async fn put(&self, key: &str, bytes: Vec<u8>, _content_type: &str) -> Result<(), AppError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(|e| {
+                AppError::Storage(format!("failed to create {}: {}", parent.display(), e))
+            })?;
+        }
+
+        tokio::fs::write(&path, bytes)
+            .await
+            .map_err(|e| AppError::Storage(format!("failed to write {}: {}", path.display(), e)))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, AppError> {
+        let path = self.path(key)?;
+        tokio::fs::read(&path).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => AppError::ObjectNotFound,
+            _ => AppError::Storage(format!("failed to read {}: {}", path.display(), e)),
+        })
+    }
+
+    async fn presigned_url(&self, key: &str, expires_in: Duration) -> Result<String, AppError> {
+        self.path(key)?;
+
+        let expires = Utc::now().timestamp() + expires_in.as_secs() as i64;
+        let signature = hex::encode(self.mac(key, expires).finalize().into_bytes());
+
+        Ok(format!(
+            "{}/api/storage/{}?expires={}&signature={}",
+            self.base_url, key, expires, signature
+        ))
+    }
+
+    fn verify_presigned(&self, key: &str, expires: i64, signature: &str) -> bool {
+        if expires < Utc::now().timestamp() {
+            return false;
+        }
+
+        match hex::decode(signature) {
+            Ok(signature) => self.mac(key, expires).verify_slice(&signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query_param<'a>(url: &'a str, name: &str) -> &'a str {
+        url.split(['?', '&'])
+            .find_map(|part| part.strip_prefix(&format!("{}=", name)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_put_get_presign() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalStore::new(dir.path(), "http://localhost:3000/", b"secret".to_vec());
+
+        store
+            .put(
+                "backtests/1/trades.msgpack",
+                vec![1, 2, 3],
+                "application/msgpack",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get("backtests/1/trades.msgpack").await.unwrap(),
+            vec![1, 2, 3]
+        );
+        assert!(matches!(
+            store.get("backtests/2/trades.msgpack").await,
+            Err(AppError::ObjectNotFound)
+        ));
+        assert!(store.put("../escape", vec![], "").await.is_err());
+
+        let url = store
+            .presigned_url("backtests/1/trades.msgpack", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert!(url.starts_with("http://localhost:3000/api/storage/backtests/1/trades.msgpack?"));
+
+        let expires: i64 = query_param(&url, "expires").parse().unwrap();
+        let signature = query_param(&url, "signature");
+        assert!(store.verify_presigned("backtests/1/trades.msgpack", expires, signature));
+        assert!(!store.verify_presigned("backtests/2/trades.msgpack", expires, signature));
+        assert!(!store.verify_presigned("backtests/1/trades.msgpack", expires + 1, signature));
+        assert!(!store.verify_presigned("backtests/1/trades.msgpack", 0, signature));
+    }
+}
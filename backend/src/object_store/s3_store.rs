@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use aws_sdk_s3 as s3;
+use aws_sdk_s3::{presigning::PresigningConfig, primitives::ByteStream};
+
+use super::ObjectStore;
+use crate::errors::AppError;
+
+/// A bucket on Cloudflare R2, AWS S3 or any S3 compatible storage (MinIO, ...).
+#[derive(Clone)]
+pub struct S3Store {
+    s3_client: s3::Client,
+    bucket_name: String,
+}
+
+impl S3Store {
+    /// Cloudflare R2 bucket.
+    pub async fn r2(
+        bucket_name: String,
+        account_id: String,
+        access_key_id: String,
+        access_key_secret: String,
+    ) -> Self {
+        Self::with_endpoint(
+            format!("https://{}.r2.cloudflarestorage.com", account_id),
+            "auto".to_string(),
+            bucket_name,
+            access_key_id,
+            access_key_secret,
+        )
+        .await
+    }
+
+    /// Any S3 compatible endpoint. Buckets are addressed by path rather than by subdomain, which
+    /// is what self-hosted storages support.
+    pub async fn with_endpoint(
+        endpoint: String,
+        region: String,
+        bucket_name: String,
+        access_key_id: String,
+        access_key_secret: String,
+    ) -> Self {
+        let config = aws_config::from_env()
+            .endpoint_url(endpoint)
+            .credentials_provider(aws_sdk_s3::config::Credentials::new(
+                access_key_id,
+                access_key_secret,
+                None, // session token is not used
+                None, // doesn't expire
+                "static",
+            ))
+            .region(s3::config::Region::new(region))
+            .load()
+            .await;
+
+        let s3_config = s3::config::Builder::from(&config)
+            .force_path_style(true)
+            .build();
+
+        Self {
+            s3_client: s3::Client::from_conf(s3_config),
+            bucket_name,
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3Store {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), AppError> {
+        self.s3_client
+            .put_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(bytes))
+            .send()
+            .await
+            .map_err(|e| AppError::Storage(format!("failed to upload {}: {:?}", key, e)))?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, AppError> {
+        let object = self
+            .s3_client
+            .get_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| AppError::Storage(format!("failed to download {}: {:?}", key, e)))?;
+
+        let bytes = object
+            .body
+            .collect()
+            .await
+            .map_err(|e| AppError::Storage(format!("failed to download {}: {:?}", key, e)))?;
+
+        Ok(bytes.to_vec())
+    }
+
+    async fn presigned_url(&self, key: &str, expires_in: Duration) -> Result<String, AppError> {
+        let presigning = PresigningConfig::expires_in(expires_in)
+            .map_err(|e| AppError::Storage(format!("invalid presigning config: {}", e)))?;
+
+        let request = self
+            .s3_client
+            .get_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .presigned(presigning)
+            .await
+            .map_err(|e| AppError::Storage(format!("failed to presign {}: {:?}", key, e)))?;
+
+        Ok(request.uri().to_string())
+    }
+}
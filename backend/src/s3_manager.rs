@@ -1,36 +1,150 @@
-use aws_sdk_s3 as s3;
+use std::{sync::Arc, time::Duration};
 
+use backtester::BacktestReport;
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::{
+    artifacts::{self, ArtifactKind},
+    errors::AppError,
+    models::{Backtest, BacktestArtifacts},
+    object_store::{LocalStore, ObjectStore, S3Store},
+};
+
+/// How long the download links handed to the frontend stay valid.
+pub const PRESIGNED_URL_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Object keys of the artifacts of a backtest.
+#[derive(Debug, Clone)]
+pub struct ArtifactPaths {
+    pub trades: String,
+    pub equity_curve: String,
+    pub positions: String,
+}
+
+/// Artifacts storage, on top of whichever `ObjectStore` the configuration selected.
 #[derive(Clone)]
 pub struct S3Manager {
-    s3_client: s3::Client,
-    bucket_name: String,
+    store: Arc<dyn ObjectStore>,
 }
 
 impl S3Manager {
-    pub async fn new(
-        bucket_name: String,
-        account_id: String,
-        access_key_id: String,
-        access_key_secret: String,
-    ) -> Self {
-        let config = aws_config::from_env()
-            .endpoint_url(format!("https://{}.r2.cloudfarestorage.com", account_id))
-            .credentials_provider(aws_sdk_s3::config::Credentials::new(
-                access_key_id,
-                access_key_secret,
-                None, // session token is not used with R2
-                None, // doesn't expire
-                "R2",
-            ))
-            .region("auto")
-            .load()
-            .await;
-
-        let client = s3::Client::new(&config);
-
+    pub fn new(store: impl ObjectStore + 'static) -> Self {
         Self {
-            s3_client: client,
-            bucket_name,
+            store: Arc::new(store),
         }
     }
+
+    /// Build the manager from the environment.
+    ///
+    /// `STORAGE_BACKEND` picks the store:
+    /// - `local` (default): files under `STORAGE_DIR`, downloaded through `PUBLIC_URL`. Links are
+    ///   signed with `STORAGE_SIGNING_KEY`, or a random key that does not survive restarts.
+    /// - `r2`: Cloudflare R2, with the `R2_ACCOUNT_ID`, `R2_ACCESS_KEY_ID` and
+    ///   `R2_ACCESS_KEY_SECRET` credentials.
+    /// - `s3`: any S3 compatible storage (AWS, MinIO, ...) at `S3_ENDPOINT`, in `S3_REGION`, with
+    ///   the `S3_ACCESS_KEY_ID` and `S3_ACCESS_KEY_SECRET` credentials.
+    ///
+    /// `BUCKET_NAME` is used by both `r2` and `s3`.
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "local".to_string());
+        let bucket_name =
+            || std::env::var("BUCKET_NAME").unwrap_or_else(|_| "datasets".to_string());
+
+        let manager = match backend.as_str() {
+            "local" => {
+                let root = std::env::var("STORAGE_DIR").unwrap_or_else(|_| "./storage".to_string());
+                let public_url = std::env::var("PUBLIC_URL")
+                    .unwrap_or_else(|_| "http://localhost:3000".to_string());
+                let signing_key = match std::env::var("STORAGE_SIGNING_KEY") {
+                    Ok(key) => key.into_bytes(),
+                    Err(_) => rand::thread_rng().r#gen::<[u8; 32]>().to_vec(),
+                };
+                Self::new(LocalStore::new(root, public_url, signing_key))
+            }
+            "r2" => Self::new(
+                S3Store::r2(
+                    bucket_name(),
+                    std::env::var("R2_ACCOUNT_ID")?,
+                    std::env::var("R2_ACCESS_KEY_ID")?,
+                    std::env::var("R2_ACCESS_KEY_SECRET")?,
+                )
+                .await,
+            ),
+            "s3" => Self::new(
+                S3Store::with_endpoint(
+                    std::env::var("S3_ENDPOINT")?,
+                    std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+                    bucket_name(),
+                    std::env::var("S3_ACCESS_KEY_ID")?,
+                    std::env::var("S3_ACCESS_KEY_SECRET")?,
+                )
+                .await,
+            ),
+            other => anyhow::bail!(
+                "Unknown STORAGE_BACKEND {}, expected local, r2 or s3",
+                other
+            ),
+        };
+
+        Ok(manager)
+    }
+
+    pub async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), AppError> {
+        self.store.put(key, bytes, content_type).await
+    }
+
+    /// Time-limited URL to download an object without credentials.
+    pub async fn presigned_url(&self, key: &str, expires_in: Duration) -> Result<String, AppError> {
+        self.store.presigned_url(key, expires_in).await
+    }
+
+    /// Read an object through a link signed by the store.
+    pub async fn download_presigned(
+        &self,
+        key: &str,
+        expires: i64,
+        signature: &str,
+    ) -> Result<Vec<u8>, AppError> {
+        if !self.store.verify_presigned(key, expires, signature) {
+            return Err(AppError::Unauthorized);
+        }
+
+        self.store.get(key).await
+    }
+
+    /// Store the trades, equity curve and positions of a backtest.
+    pub async fn upload_artifacts(
+        &self,
+        backtest_id: Uuid,
+        report: &BacktestReport,
+    ) -> Result<ArtifactPaths, AppError> {
+        for (kind, bytes) in artifacts::encode(report)? {
+            self.put(&kind.key(backtest_id), bytes, artifacts::CONTENT_TYPE)
+                .await?;
+        }
+
+        Ok(ArtifactPaths {
+            trades: ArtifactKind::Trades.key(backtest_id),
+            equity_curve: ArtifactKind::EquityCurve.key(backtest_id),
+            positions: ArtifactKind::Positions.key(backtest_id),
+        })
+    }
+
+    /// Download links of the artifacts a backtest has.
+    pub async fn presign_artifacts(
+        &self,
+        backtest: &Backtest,
+    ) -> Result<BacktestArtifacts, AppError> {
+        let presign = async |path: &Option<String>| match path {
+            Some(key) => self.presigned_url(key, PRESIGNED_URL_TTL).await.map(Some),
+            None => Ok(None),
+        };
+
+        Ok(BacktestArtifacts {
+            trades: presign(&backtest.trades_path).await?,
+            equity_curve: presign(&backtest.equity_curve_path).await?,
+            positions: presign(&backtest.positions_path).await?,
+        })
+    }
 }
@@ -2,3 +2,4 @@ pub mod email_validator;
 pub mod password_validator;
 pub mod username_validator;
 pub mod strategy_validator;
+pub mod strategy_lints;
@@ -0,0 +1,602 @@
+//! Lints: strategies that pass validation but most likely do not do what their author meant.
+//!
+//! They are reported as warnings, the strategy is still saved.
+
+use std::collections::HashSet;
+
+use backtester::{
+    strategy::StateVar,
+    text::{format_cond, format_value},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::validators::strategy_validator::{Cond, Expr, StrategyContent, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintKind {
+    /// A condition that can never be met.
+    Contradiction,
+    /// A condition that is always met.
+    Tautology,
+    /// A `between` whose bounds are the wrong way round.
+    EmptyRange,
+    /// An exact comparison of values that are rarely exactly equal.
+    FloatEquality,
+    /// A value crossing itself, which never happens.
+    SelfCross,
+    /// An action closing positions that cannot be open when it fires.
+    UnreachableAction,
+    /// An action firing on the same condition as another one of the same type.
+    DuplicateAction,
+    /// A definition no action uses.
+    UnusedDefinition,
+    /// Another strategy of the user with the same content, only written differently. Found by
+    /// the handlers, see `strategy_validator::content_hash`.
+    DuplicateStrategy,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lint {
+    pub kind: LintKind,
+    /// Index of the action the lint is about, `None` for the definitions and the whole strategy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<usize>,
+    pub message: String,
+}
+
+/// Lint a strategy that passed `StrategyValidator::validate_strategy`. The conditions of the
+/// actions are linted with their definitions expanded.
+pub fn lint_strategy(strategy: &StrategyContent) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let conds: Vec<Cond> = strategy
+        .actions
+        .iter()
+        .map(|action| {
+            strategy
+                .expand(&action.cond)
+                .unwrap_or_else(|_| action.cond.clone())
+        })
+        .collect();
+
+    for (i, cond) in conds.iter().enumerate() {
+        let mut nodes = Vec::new();
+        collect_nodes(cond, &mut nodes);
+        for node in nodes {
+            if let Some((kind, message)) = lint_cond(node) {
+                lints.push(Lint {
+                    kind,
+                    action: Some(i),
+                    message,
+                });
+            }
+        }
+    }
+
+    for (i, action) in strategy.actions.iter().enumerate() {
+        let action_type = action.action_type.as_str();
+        if let Some(opening) = opening_types(action_type)
+            && !strategy
+                .actions
+                .iter()
+                .any(|other| opening.contains(&other.action_type.as_str()))
+        {
+            lints.push(Lint {
+                kind: LintKind::UnreachableAction,
+                action: Some(i),
+                message: format!(
+                    "'{}' closes positions that no action opens, it needs a '{}' action",
+                    action_type,
+                    opening.join("' or '")
+                ),
+            });
+        } else if opening_types(action_type).is_some() && requires_flat(&conds[i]) {
+            lints.push(Lint {
+                kind: LintKind::UnreachableAction,
+                action: Some(i),
+                message: format!(
+                    "'{}' only fires when there is no position to close",
+                    action_type
+                ),
+            });
+        }
+
+        if let Some(first) = (0..i).find(|&j| {
+            let other = &strategy.actions[j];
+            other.action_type == action.action_type
+                && other.asset == action.asset
+                && conds[j] == conds[i]
+        }) {
+            lints.push(Lint {
+                kind: LintKind::DuplicateAction,
+                action: Some(i),
+                message: format!(
+                    "same type and condition as action {}, both fire together",
+                    first
+                ),
+            });
+        }
+    }
+
+    let used = used_definitions(strategy);
+    for name in strategy.definitions.keys() {
+        if !used.contains(name.as_str()) {
+            lints.push(Lint {
+                kind: LintKind::UnusedDefinition,
+                action: None,
+                message: format!("'{}' is defined but never used", name),
+            });
+        }
+    }
+
+    lints
+}
+
+/// The action types opening the positions `action_type` closes, `None` for an opening action.
+fn opening_types(action_type: &str) -> Option<&'static [&'static str]> {
+    match action_type {
+        "sell" => Some(&["buy"]),
+        "close_long" => Some(&["long"]),
+        "close_short" => Some(&["short"]),
+        "close" => Some(&["long", "short"]),
+        _ => None,
+    }
+}
+
+/// Whether `cond` can only be met without a position.
+fn requires_flat(cond: &Cond) -> bool {
+    match cond {
+        Cond::Not { cond } => matches!(cond.as_ref(), Cond::InPosition),
+        Cond::And { conds } => conds.iter().any(requires_flat),
+        _ => false,
+    }
+}
+
+/// Every node of `cond`, the conditions of `bars_since` included.
+fn collect_nodes<'a>(cond: &'a Cond, nodes: &mut Vec<&'a Cond>) {
+    nodes.push(cond);
+    let values: Vec<&Value> = match cond {
+        Cond::And { conds } | Cond::Or { conds } => {
+            for cond in conds {
+                collect_nodes(cond, nodes);
+            }
+            return;
+        }
+        Cond::Not { cond } => return collect_nodes(cond, nodes),
+        Cond::LessThan { l, r }
+        | Cond::GreaterThan { l, r }
+        | Cond::LessThanOrEqual { l, r }
+        | Cond::GreaterThanOrEqual { l, r }
+        | Cond::Equal { l, r }
+        | Cond::NotEqual { l, r }
+        | Cond::CrossesAbove { l, r }
+        | Cond::CrossesBelow { l, r } => vec![l, r],
+        Cond::Between { val, min, max } => vec![val, min, max],
+        Cond::InPosition | Cond::Ref(_) => return,
+    };
+    for value in values {
+        collect_value_nodes(value, nodes);
+    }
+}
+
+fn collect_value_nodes<'a>(value: &'a Value, nodes: &mut Vec<&'a Cond>) {
+    let Value::Expr(expr) = value else {
+        return;
+    };
+    match expr.as_ref() {
+        Expr::Add { l, r }
+        | Expr::Sub { l, r }
+        | Expr::Mul { l, r }
+        | Expr::Div { l, r }
+        | Expr::Min { l, r }
+        | Expr::Max { l, r } => {
+            collect_value_nodes(l, nodes);
+            collect_value_nodes(r, nodes);
+        }
+        Expr::Abs { val } | Expr::Lag { val, .. } => collect_value_nodes(val, nodes),
+        Expr::State(_) => {}
+        Expr::BarsSince { cond } => collect_nodes(cond, nodes),
+    }
+}
+
+/// The lint of a single node, its children being linted on their own.
+fn lint_cond(cond: &Cond) -> Option<(LintKind, String)> {
+    let text = format_cond(cond);
+    match cond {
+        Cond::And { conds } => {
+            let mut constraints = Vec::new();
+            collect_constraints(conds, true, &mut constraints);
+            let (value, _) = constraints.iter().find(|(value, _)| {
+                constraints
+                    .iter()
+                    .filter(|(other, _)| other == value)
+                    .fold(Interval::ALL, |acc, (_, interval)| acc.intersect(interval))
+                    .is_empty()
+            })?;
+            Some((
+                LintKind::Contradiction,
+                format!(
+                    "'{}' can never be met, {} cannot satisfy all of it",
+                    text,
+                    format_value(value)
+                ),
+            ))
+        }
+        Cond::Or { conds } => {
+            let mut constraints = Vec::new();
+            collect_constraints(conds, false, &mut constraints);
+            let covers = constraints.iter().any(|(value, a)| {
+                constraints
+                    .iter()
+                    .any(|(other, b)| other == value && a.union_is_all(b))
+            });
+            covers.then(|| {
+                (
+                    LintKind::Tautology,
+                    format!("'{}' is always met, whatever the value", text),
+                )
+            })
+        }
+        Cond::LessThan { l, r }
+        | Cond::GreaterThan { l, r }
+        | Cond::LessThanOrEqual { l, r }
+        | Cond::GreaterThanOrEqual { l, r }
+        | Cond::Equal { l, r }
+        | Cond::NotEqual { l, r } => {
+            let always = match (constant(l), constant(r)) {
+                (Some(l), Some(r)) => Some(compare(cond, l, r)),
+                _ if l == r => Some(matches!(
+                    cond,
+                    Cond::LessThanOrEqual { .. }
+                        | Cond::GreaterThanOrEqual { .. }
+                        | Cond::Equal { .. }
+                )),
+                _ => None,
+            };
+            match always {
+                Some(true) => Some((LintKind::Tautology, format!("'{}' is always met", text))),
+                Some(false) => Some((
+                    LintKind::Contradiction,
+                    format!("'{}' can never be met", text),
+                )),
+                None if matches!(cond, Cond::Equal { .. } | Cond::NotEqual { .. })
+                    && (is_float(l) || is_float(r))
+                    && !matches!(l.as_ref(), Value::Boolean(_))
+                    && !matches!(r.as_ref(), Value::Boolean(_)) =>
+                {
+                    Some((
+                        LintKind::FloatEquality,
+                        format!(
+                            "'{}' compares decimal values exactly, which rarely matches; use \
+                             'between' or '<' and '>' instead",
+                            text
+                        ),
+                    ))
+                }
+                None => None,
+            }
+        }
+        Cond::Between { min, max, .. } => match (constant(min), constant(max)) {
+            (Some(min), Some(max)) if min > max => Some((
+                LintKind::EmptyRange,
+                format!(
+                    "'{}' can never be met, its minimum is above its maximum",
+                    text
+                ),
+            )),
+            _ => None,
+        },
+        Cond::CrossesAbove { l, r } | Cond::CrossesBelow { l, r } if l == r => Some((
+            LintKind::SelfCross,
+            format!("'{}' never fires, a value cannot cross itself", text),
+        )),
+        _ => None,
+    }
+}
+
+fn compare(cond: &Cond, l: f64, r: f64) -> bool {
+    match cond {
+        Cond::LessThan { .. } => l < r,
+        Cond::GreaterThan { .. } => l > r,
+        Cond::LessThanOrEqual { .. } => l <= r,
+        Cond::GreaterThanOrEqual { .. } => l >= r,
+        Cond::Equal { .. } => l == r,
+        _ => l != r,
+    }
+}
+
+fn constant(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => Some(*n),
+        Value::Boolean(b) => Some(if *b { 1.0 } else { 0.0 }),
+        _ => None,
+    }
+}
+
+/// Whether `value` takes decimal values, unlike the counts of the trading state.
+fn is_float(value: &Value) -> bool {
+    match value {
+        Value::Number(_) | Value::Boolean(_) => false,
+        Value::Indicator(_) | Value::Spec(_) => true,
+        Value::Expr(expr) => match expr.as_ref() {
+            Expr::Add { l, r }
+            | Expr::Sub { l, r }
+            | Expr::Mul { l, r }
+            | Expr::Min { l, r }
+            | Expr::Max { l, r } => is_float(l) || is_float(r),
+            Expr::Div { .. } => true,
+            Expr::Abs { val } | Expr::Lag { val, .. } => is_float(val),
+            Expr::State(var) => *var == StateVar::PnlPct,
+            Expr::BarsSince { .. } => false,
+        },
+    }
+}
+
+/// The comparisons of `conds` between a value and a constant, as the interval they keep the
+/// value in. The conditions of nested nodes of the same kind (`and` in `and`, `or` in `or`) are
+/// included.
+fn collect_constraints<'a>(conds: &'a [Cond], and: bool, out: &mut Vec<(&'a Value, Interval)>) {
+    for cond in conds {
+        match cond {
+            Cond::And { conds } if and => collect_constraints(conds, and, out),
+            Cond::Or { conds } if !and => collect_constraints(conds, and, out),
+            _ => out.extend(constraint(cond)),
+        }
+    }
+}
+
+fn constraint(cond: &Cond) -> Option<(&Value, Interval)> {
+    if let Cond::Between { val, min, max } = cond {
+        return constant(val).is_none().then_some((
+            val.as_ref(),
+            Interval {
+                lower: Some(Bound::inclusive(constant(min)?)),
+                upper: Some(Bound::inclusive(constant(max)?)),
+            },
+        ));
+    }
+    let (l, r) = match cond {
+        Cond::LessThan { l, r }
+        | Cond::GreaterThan { l, r }
+        | Cond::LessThanOrEqual { l, r }
+        | Cond::GreaterThanOrEqual { l, r }
+        | Cond::Equal { l, r } => (l, r),
+        _ => return None,
+    };
+    // Written as `value op c`, flipping `c op value`.
+    let (value, c, flipped) = match (constant(l), constant(r)) {
+        (None, Some(c)) => (l.as_ref(), c, false),
+        (Some(c), None) => (r.as_ref(), c, true),
+        _ => return None,
+    };
+    let (below, inclusive) = match cond {
+        Cond::LessThan { .. } => (true, false),
+        Cond::LessThanOrEqual { .. } => (true, true),
+        Cond::GreaterThan { .. } => (false, false),
+        Cond::GreaterThanOrEqual { .. } => (false, true),
+        _ => {
+            return Some((
+                value,
+                Interval {
+                    lower: Some(Bound::inclusive(c)),
+                    upper: Some(Bound::inclusive(c)),
+                },
+            ));
+        }
+    };
+    let bound = Some(Bound {
+        value: c,
+        inclusive,
+    });
+    let interval = if below != flipped {
+        Interval {
+            lower: None,
+            upper: bound,
+        }
+    } else {
+        Interval {
+            lower: bound,
+            upper: None,
+        }
+    };
+    Some((value, interval))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bound {
+    value: f64,
+    inclusive: bool,
+}
+
+impl Bound {
+    fn inclusive(value: f64) -> Self {
+        Self {
+            value,
+            inclusive: true,
+        }
+    }
+}
+
+/// The values a comparison keeps, unbounded on the sides that are `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Interval {
+    lower: Option<Bound>,
+    upper: Option<Bound>,
+}
+
+impl Interval {
+    const ALL: Interval = Interval {
+        lower: None,
+        upper: None,
+    };
+
+    fn intersect(&self, other: &Interval) -> Interval {
+        // The tighter bound, the exclusive one on a tie.
+        let pick = |a: Option<Bound>, b: Option<Bound>, lower: bool| match (a, b) {
+            (Some(a), Some(b)) if a.value == b.value => Some(Bound {
+                value: a.value,
+                inclusive: a.inclusive && b.inclusive,
+            }),
+            (Some(a), Some(b)) => Some(if (a.value > b.value) == lower { a } else { b }),
+            (a, b) => a.or(b),
+        };
+        Interval {
+            lower: pick(self.lower, other.lower, true),
+            upper: pick(self.upper, other.upper, false),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match (self.lower, self.upper) {
+            (Some(lower), Some(upper)) => {
+                lower.value > upper.value
+                    || (lower.value == upper.value && !(lower.inclusive && upper.inclusive))
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether every value is in one of the two intervals.
+    fn union_is_all(&self, other: &Interval) -> bool {
+        let covers = |low: &Interval, high: &Interval| match (low, high) {
+            (
+                Interval {
+                    lower: None,
+                    upper: Some(upper),
+                },
+                Interval {
+                    lower: Some(lower),
+                    upper: None,
+                },
+            ) => {
+                upper.value > lower.value
+                    || (upper.value == lower.value && (upper.inclusive || lower.inclusive))
+            }
+            _ => false,
+        };
+        covers(self, other) || covers(other, self)
+    }
+}
+
+/// The definitions the actions use, directly or through other definitions.
+fn used_definitions(strategy: &StrategyContent) -> HashSet<&str> {
+    let mut used = HashSet::new();
+    let mut pending: Vec<&Cond> = strategy.actions.iter().map(|a| &a.cond).collect();
+    while let Some(cond) = pending.pop() {
+        let mut nodes = Vec::new();
+        collect_nodes(cond, &mut nodes);
+        for node in nodes {
+            if let Cond::Ref(name) = node
+                && used.insert(name.as_str())
+                && let Some(definition) = strategy.definitions.get(name)
+            {
+                pending.push(definition);
+            }
+        }
+    }
+    used
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lints(json: &str) -> Vec<(LintKind, Option<usize>)> {
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        lint_strategy(&strategy)
+            .into_iter()
+            .map(|lint| (lint.kind, lint.action))
+            .collect()
+    }
+
+    fn spot(conds: &[&str]) -> String {
+        let actions: Vec<String> = conds
+            .iter()
+            .enumerate()
+            .map(|(i, cond)| {
+                let action_type = if i == 0 { "buy" } else { "sell" };
+                format!(
+                    r#"{{ "type": "{}", "w": 1.0, "cond": {} }}"#,
+                    action_type, cond
+                )
+            })
+            .collect();
+        format!(
+            r#"{{ "meta": {{ "type": "spot" }}, "actions": [{}] }}"#,
+            actions.join(", ")
+        )
+    }
+
+    #[test]
+    fn test_clean_strategy() {
+        let json = spot(&[
+            r#"{ "and": { "conds": [{ "xab": { "l": "sma_10", "r": "sma_50" } }, { "lt": { "l": "rsi", "r": 30 } }] } }"#,
+            r#"{ "or": { "conds": [{ "gt": { "l": "rsi", "r": 70 } }, { "lt": { "l": "rsi", "r": 20 } }] } }"#,
+        ]);
+        assert_eq!(lints(&json), []);
+    }
+
+    #[test]
+    fn test_conditions() {
+        let json = spot(&[
+            r#"{ "and": { "conds": [{ "gt": { "l": "rsi", "r": 70 } }, { "and": { "conds": [{ "lt": { "l": "rsi", "r": 30 } }] } }] } }"#,
+            r#"{ "or": { "conds": [{ "ge": { "l": "rsi", "r": 50 } }, { "gt": { "l": 50, "r": "rsi" } }] } }"#,
+            r#"{ "bet": { "val": "rsi", "min": 70, "max": 30 } }"#,
+            r#"{ "eq": { "l": "close", "r": "sma_50" } }"#,
+            r#"{ "xab": { "l": "close", "r": "close" } }"#,
+            r#"{ "le": { "l": "close", "r": "close" } }"#,
+        ]);
+        assert_eq!(
+            lints(&json),
+            [
+                (LintKind::Contradiction, Some(0)),
+                (LintKind::Tautology, Some(1)),
+                (LintKind::EmptyRange, Some(2)),
+                (LintKind::FloatEquality, Some(3)),
+                (LintKind::SelfCross, Some(4)),
+                (LintKind::Tautology, Some(5)),
+            ]
+        );
+
+        // Integer state variables and boolean columns compare exactly.
+        let json = spot(&[
+            r#"{ "eq": { "l": { "state": "trades_today" }, "r": 0 } }"#,
+            r#"{ "eq": { "l": "signal", "r": true } }"#,
+        ]);
+        assert_eq!(lints(&json), []);
+    }
+
+    #[test]
+    fn test_actions() {
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "actions": [
+                { "type": "sell", "w": 1.0, "cond": { "lt": { "l": "rsi", "r": 30 } } }
+            ]
+        }"#;
+        assert_eq!(lints(json), [(LintKind::UnreachableAction, Some(0))]);
+
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "definitions": {
+                "oversold": { "lt": { "l": "rsi", "r": 30 } },
+                "unused": { "gt": { "l": "rsi", "r": 70 } }
+            },
+            "actions": [
+                { "type": "buy", "w": 0.5, "cond": { "ref": "oversold" } },
+                { "type": "buy", "w": 1.0, "cond": { "lt": { "l": "rsi", "r": 30 } } },
+                { "type": "sell", "w": 1.0, "cond": { "not": { "cond": "in_position" } } }
+            ]
+        }"#;
+        assert_eq!(
+            lints(json),
+            [
+                (LintKind::DuplicateAction, Some(1)),
+                (LintKind::UnreachableAction, Some(2)),
+                (LintKind::UnusedDefinition, None),
+            ]
+        );
+    }
+}
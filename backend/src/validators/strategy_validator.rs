@@ -1,8 +1,26 @@
 use crate::errors::AppError;
-use serde::{Deserialize, Serialize};
+use backtester::{
+    dataset::{PRICE_COLUMNS, timeframe_seconds},
+    indicator::Indicator,
+};
 use std::collections::HashSet;
 use thiserror::Error;
 
+pub use backtester::indicator::IndicatorSpec;
+pub use backtester::strategy::{
+    Action, Cond, Distance, Expr, IndicatorRef, Meta, Order, OrderType, PortfolioSpec, Rebalance,
+    Risk, Sizing, StrategyContent, StrategyType, Value,
+};
+use backtester::text::{self, ParseError};
+use sha2::{Digest, Sha256};
+
+/// Highest leverage offered by the exchanges on perpetual futures.
+const MAX_LEVERAGE: f64 = 125.0;
+/// Most legs an options action can open, enough for a condor.
+const MAX_LEGS: usize = 4;
+/// Longest expiry of an option leg, in days.
+const MAX_EXPIRY_DAYS: u32 = 3 * 365;
+
 pub fn validate_strategy_title(title: &str) -> Result<(), AppError> {
     if title.is_empty() {
         return Err(AppError::BadRequest("title is required".to_string()));
@@ -34,6 +52,13 @@ pub fn validate_strategy_title(title: &str) -> Result<(), AppError> {
     Ok(())
 }
 
+/// Hash of the canonical form of a strategy (see `StrategyContent::canonical`), the same for
+/// strategies that are only written differently.
+pub fn content_hash(content: &StrategyContent) -> String {
+    let json = serde_json::to_vec(&content.canonical()).expect("strategies serialize to json");
+    hex::encode(Sha256::digest(json))
+}
+
 #[derive(Error, Debug)]
 pub enum ValidationError {
     #[error("Invalid strategy type: {0}")]
@@ -56,122 +81,22 @@ pub enum ValidationError {
     DeserializationError(String),
     #[error("Invalid indicator: {0}")]
     InvalidIndicator(String),
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
-pub enum StrategyType {
-    Spot,
-    Options,
-}
-
-impl StrategyType {
-    fn valid_actions(&self) -> HashSet<&'static str> {
-        match self {
-            StrategyType::Spot => {
-                let mut set = HashSet::new();
-                set.insert("buy");
-                set.insert("sell");
-                set
-            }
-            StrategyType::Options => {
-                let mut set = HashSet::new();
-                set.insert("long");
-                set.insert("short");
-                set
-            }
-        }
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Meta {
-    #[serde(rename = "type")]
-    pub strategy_type: StrategyType,
-    //#[serde(flatten)]
-    //pub extra: std::collections::HashMap<String, rmpv::Value>,
-}
-
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(untagged)]
-pub enum Value {
-    Number(f64),
-    //Boolean(bool),
-    Indicator(String),
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
-pub enum Cond {
-    And {
-        conds: Vec<Cond>,
-    },
-    Or {
-        conds: Vec<Cond>,
-    },
-    Not {
-        cond: Box<Cond>,
-    },
-    #[serde(rename = "lt")]
-    LessThan {
-        l: Box<Value>,
-        r: Box<Value>,
-    },
-    #[serde(rename = "gt")]
-    GreaterThan {
-        l: Box<Value>,
-        r: Box<Value>,
-    },
-    #[serde(rename = "le")]
-    LessThanOrEqual {
-        l: Box<Value>,
-        r: Box<Value>,
-    },
-    #[serde(rename = "ge")]
-    GreaterThanOrEqual {
-        l: Box<Value>,
-        r: Box<Value>,
-    },
-    #[serde(rename = "eq")]
-    Equal {
-        l: Box<Value>,
-        r: Box<Value>,
-    },
-    #[serde(rename = "neq")]
-    NotEqual {
-        l: Box<Value>,
-        r: Box<Value>,
-    },
-    #[serde(rename = "bet")]
-    Between {
-        val: Box<Value>,
-        min: Box<Value>,
-        max: Box<Value>,
-    },
-    #[serde(rename = "xab")]
-    CrossesAbove {
-        l: Box<Value>,
-        r: Box<Value>,
-    },
-    #[serde(rename = "xbe")]
-    CrossesBelow {
-        l: Box<Value>,
-        r: Box<Value>,
-    },
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Action {
-    #[serde(rename = "type")]
-    pub action_type: String,
-    pub w: f64,
-    pub cond: Cond,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct StrategyContent {
-    pub meta: Meta,
-    pub actions: Vec<Action>,
+    #[error("Invalid order: {0}")]
+    InvalidOrder(String),
+    #[error("Invalid risk: {0}")]
+    InvalidRisk(String),
+    #[error("Invalid sizing: {0}")]
+    InvalidSizing(String),
+    #[error("Invalid portfolio: {0}")]
+    InvalidPortfolio(String),
+    #[error("Invalid margin: {0}")]
+    InvalidMargin(String),
+    #[error("Invalid options: {0}")]
+    InvalidOptions(String),
+    #[error("Invalid definition: {0}")]
+    InvalidDefinition(String),
+    #[error("Syntax error at {0}")]
+    Syntax(#[from] ParseError),
 }
 
 #[derive(Debug, Clone)]
@@ -190,16 +115,57 @@ impl StrategyValidator {
         self.valid_indicators = new_indicators;
     }
 
+    /// Indicators the strategy reads, without their lag. The ones read on another timeframe or
+    /// asset keep their `@{timeframe}` qualifier or `{asset}.` prefix.
     pub fn get_indicators(strategy: &StrategyContent) -> HashSet<String> {
         let mut indicators = HashSet::new();
 
+        for cond in strategy.definitions.values() {
+            Self::collect_indicators_from_condition(cond, &mut indicators);
+        }
         for action in &strategy.actions {
             Self::collect_indicators_from_condition(&action.cond, &mut indicators);
+            if let Some(order) = &action.order {
+                for price in Self::order_prices(order) {
+                    Self::collect_indicators_from_value(price, &mut indicators);
+                }
+            }
+        }
+        if let Some(risk) = &strategy.risk {
+            for distance in Self::risk_distances(risk) {
+                if let Distance::Atr { indicator, .. } = distance {
+                    indicators.insert(Self::unlagged(Value::parse_indicator(indicator)));
+                }
+            }
         }
 
         indicators
     }
 
+    /// Timeframes, other than the backtest's own, the strategy reads indicators on, whatever the
+    /// asset.
+    pub fn get_timeframes(strategy: &StrategyContent) -> HashSet<String> {
+        Self::get_indicators(strategy)
+            .iter()
+            .filter_map(|ta| Value::parse_indicator(ta).timeframe.map(str::to_string))
+            .collect()
+    }
+
+    /// Assets the backtests of the strategy run on, `dataset` unless it is a portfolio strategy.
+    pub fn get_assets(strategy: &StrategyContent, dataset: &str) -> Vec<String> {
+        match &strategy.portfolio {
+            Some(portfolio) => portfolio.assets.clone(),
+            None => vec![dataset.to_string()],
+        }
+    }
+
+    fn unlagged(reference: IndicatorRef) -> String {
+        match reference.timeframe {
+            Some(tf) => format!("{}@{}", reference.name, tf),
+            None => reference.name.to_string(),
+        }
+    }
+
     fn collect_indicators_from_condition(condition: &Cond, indicators: &mut HashSet<String>) {
         match condition {
             Cond::GreaterThan { l, r }
@@ -229,12 +195,95 @@ impl StrategyValidator {
                 Self::collect_indicators_from_value(min, indicators);
                 Self::collect_indicators_from_value(max, indicators);
             }
+            Cond::InPosition | Cond::Ref(_) => {}
+        }
+    }
+
+    fn order_prices(order: &Order) -> Vec<&Value> {
+        match &order.order_type {
+            OrderType::Market => vec![],
+            OrderType::Limit { price } | OrderType::Stop { price } => vec![price],
+            OrderType::StopLimit { stop, limit } => vec![stop, limit],
+        }
+    }
+
+    fn risk_distances(risk: &Risk) -> impl Iterator<Item = &Distance> {
+        [&risk.stop_loss, &risk.take_profit, &risk.trailing_stop]
+            .into_iter()
+            .flatten()
+    }
+
+    fn expr_operands(expr: &Expr) -> Vec<&Value> {
+        match expr {
+            Expr::Add { l, r }
+            | Expr::Sub { l, r }
+            | Expr::Mul { l, r }
+            | Expr::Div { l, r }
+            | Expr::Min { l, r }
+            | Expr::Max { l, r } => vec![l, r],
+            Expr::Abs { val } | Expr::Lag { val, .. } => vec![val],
+            Expr::State(_) | Expr::BarsSince { .. } => vec![],
         }
     }
 
     fn collect_indicators_from_value(val: &Value, indicators: &mut HashSet<String>) {
-        if let Value::Indicator(ta) = val {
-            indicators.insert(ta.to_string());
+        match val {
+            Value::Number(_) | Value::Boolean(_) => {}
+            Value::Indicator(ta) => {
+                indicators.insert(Self::unlagged(Value::parse_indicator(ta)));
+            }
+            Value::Expr(expr) => {
+                if let Expr::BarsSince { cond } = expr.as_ref() {
+                    Self::collect_indicators_from_condition(cond, indicators);
+                }
+                for operand in Self::expr_operands(expr) {
+                    Self::collect_indicators_from_value(operand, indicators);
+                }
+            }
+            Value::Spec(spec) => {
+                let name = Indicator::from_spec(spec).map_or(spec.ind.clone(), |i| i.to_string());
+                indicators.insert(Self::unlagged(IndicatorRef {
+                    name: &name,
+                    timeframe: spec.tf.as_deref(),
+                    lag: 0,
+                }));
+            }
+        }
+    }
+
+    /// Whether the backtester can compute `name` when a dataset does not have it.
+    pub fn is_computable(name: &str) -> bool {
+        PRICE_COLUMNS.contains(&name) || Indicator::parse(name).is_ok()
+    }
+
+    /// Check an indicator name against the registry, unless it is a known dataset column.
+    fn validate_indicator(&self, name: &str) -> Result<(), ValidationError> {
+        if PRICE_COLUMNS.contains(&name) || self.valid_indicators.contains(name) {
+            return Ok(());
+        }
+        Indicator::parse(name)
+            .map(|_| ())
+            .map_err(|e| ValidationError::InvalidIndicator(e.to_string()))
+    }
+
+    /// Check an indicator reference: its indicator, and its timeframe if it has one. Whether the
+    /// asset of a backtest has that timeframe is only known when the backtest is requested.
+    fn validate_reference(&self, reference: &str) -> Result<(), ValidationError> {
+        let reference = Value::split_asset(reference).map_or(reference, |(_, rest)| rest);
+        let IndicatorRef {
+            name, timeframe, ..
+        } = Value::parse_indicator(reference);
+        self.validate_indicator(name)?;
+        timeframe.map_or(Ok(()), Self::validate_timeframe)
+    }
+
+    fn validate_timeframe(timeframe: &str) -> Result<(), ValidationError> {
+        match timeframe_seconds(timeframe) {
+            Some(_) => Ok(()),
+            None => Err(ValidationError::InvalidIndicator(format!(
+                "invalid timeframe '{}'",
+                timeframe
+            ))),
         }
     }
 
@@ -251,7 +300,8 @@ impl StrategyValidator {
     pub fn to_msgpack(&self, strategy: &StrategyContent) -> Result<Vec<u8>, ValidationError> {
         self.validate_strategy(strategy)?;
 
-        rmp_serde::to_vec(strategy).map_err(|e| ValidationError::SerializationError(e.to_string()))
+        rmp_serde::to_vec_named(strategy)
+            .map_err(|e| ValidationError::SerializationError(e.to_string()))
     }
 
     /// Validate from JSON (for user input/debugging)
@@ -269,6 +319,13 @@ impl StrategyValidator {
         self.to_msgpack(&strategy)
     }
 
+    /// Parse and validate a strategy written in its text form.
+    pub fn validate_text(&self, text: &str) -> Result<StrategyContent, ValidationError> {
+        let strategy = text::parse(text)?;
+        self.validate_strategy(&strategy)?;
+        Ok(strategy)
+    }
+
     /// Convert MessagePack to JSON (for debugging)
     pub fn msgpack_to_json(&self, bytes: &[u8]) -> Result<String, ValidationError> {
         let strategy = self.validate_msgpack(bytes)?;
@@ -283,16 +340,310 @@ impl StrategyValidator {
 
         let valid_actions = strategy.meta.strategy_type.valid_actions();
 
+        if let Some(sizing) = &strategy.meta.sizing {
+            Self::validate_sizing(sizing)?;
+        }
+        if strategy.meta.max_entries == Some(0) {
+            return Err(ValidationError::InvalidSizing(
+                "max_entries must be at least 1".to_string(),
+            ));
+        }
+
+        Self::validate_definition_names(strategy)?;
+        for action in &strategy.actions {
+            let cond = Self::expand(strategy, &action.cond)?;
+            self.validate_action(action, &cond, &valid_actions, &strategy.meta.strategy_type)?;
+        }
+        // Also checks the definitions no action uses.
+        for cond in strategy.definitions.values() {
+            self.validate_condition(&Self::expand(strategy, cond)?)?;
+        }
+
+        if let Some(risk) = &strategy.risk {
+            self.validate_risk(risk)?;
+        }
+
+        Self::validate_portfolio(strategy)?;
+        Self::validate_margin(strategy)?;
+        Self::validate_options(strategy)?;
+
+        Ok(())
+    }
+
+    /// Check the pricing settings and the legs of an options strategy, and that it only uses
+    /// what the options engine supports.
+    fn validate_options(strategy: &StrategyContent) -> Result<(), ValidationError> {
+        if strategy.meta.strategy_type != StrategyType::Options {
+            if strategy.meta.options.is_some() {
+                return Err(ValidationError::InvalidOptions(
+                    "options only apply to options strategies".to_string(),
+                ));
+            }
+            if strategy.actions.iter().any(|a| a.legs.is_some()) {
+                return Err(ValidationError::InvalidOptions(
+                    "legs only apply to options strategies".to_string(),
+                ));
+            }
+            return Ok(());
+        }
+
+        let unsupported = [
+            ("sizing", strategy.meta.sizing.is_some()),
+            ("max_entries", strategy.meta.max_entries.is_some()),
+            ("risk", strategy.risk.is_some()),
+            ("portfolio", strategy.portfolio.is_some()),
+            ("order", strategy.actions.iter().any(|a| a.order.is_some())),
+        ];
+        if let Some((section, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(ValidationError::InvalidOptions(format!(
+                "{} is not supported for options strategies",
+                section
+            )));
+        }
+
+        if let Some(options) = &strategy.meta.options {
+            if options.iv.is_some_and(|iv| !(iv > 0.0 && iv.is_finite())) {
+                return Err(ValidationError::InvalidOptions(
+                    "iv must be positive".to_string(),
+                ));
+            }
+            if options.vol_lookback < 2 {
+                return Err(ValidationError::InvalidOptions(
+                    "vol_lookback must be at least 2".to_string(),
+                ));
+            }
+            if !options.risk_free_rate.is_finite() {
+                return Err(ValidationError::InvalidOptions(
+                    "risk_free_rate must be a number".to_string(),
+                ));
+            }
+        }
+
+        for action in &strategy.actions {
+            if action.action_type == "close" {
+                if action.legs.as_ref().is_some_and(|legs| !legs.is_empty()) {
+                    return Err(ValidationError::InvalidOptions(
+                        "close actions close every open leg and take no legs".to_string(),
+                    ));
+                }
+                continue;
+            }
+            // Strategies saved before legs existed open the default ones.
+            let legs = action.option_legs();
+            if legs.is_empty() || legs.len() > MAX_LEGS {
+                return Err(ValidationError::InvalidOptions(format!(
+                    "'{}' actions need between 1 and {} legs",
+                    action.action_type, MAX_LEGS
+                )));
+            }
+            for (i, leg) in legs.iter().enumerate() {
+                if !(leg.strike.is_finite() && leg.strike > -100.0) {
+                    return Err(ValidationError::InvalidOptions(format!(
+                        "strike offset must be above -100%, got {}",
+                        leg.strike
+                    )));
+                }
+                if leg.expiry == 0 || leg.expiry > MAX_EXPIRY_DAYS {
+                    return Err(ValidationError::InvalidOptions(format!(
+                        "expiry must be between 1 and {} days, got {}",
+                        MAX_EXPIRY_DAYS, leg.expiry
+                    )));
+                }
+                if !(leg.quantity.is_finite() && leg.quantity != 0.0) {
+                    return Err(ValidationError::InvalidOptions(format!(
+                        "quantity must be a non-zero number, got {}",
+                        leg.quantity
+                    )));
+                }
+                // The same option twice is one leg with the summed quantity.
+                if legs[..i].iter().any(|other| {
+                    other.kind == leg.kind
+                        && other.strike == leg.strike
+                        && other.expiry == leg.expiry
+                }) {
+                    return Err(ValidationError::InvalidOptions(format!(
+                        "duplicate {:?} leg at strike {} expiring in {} days",
+                        leg.kind, leg.strike, leg.expiry
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the margin settings of a perpetual strategy, and that it only uses what the perp
+    /// engine supports.
+    fn validate_margin(strategy: &StrategyContent) -> Result<(), ValidationError> {
+        if strategy.meta.strategy_type != StrategyType::Perp {
+            if strategy.meta.margin.is_some() {
+                return Err(ValidationError::InvalidMargin(
+                    "margin only applies to perp strategies".to_string(),
+                ));
+            }
+            return Ok(());
+        }
+
+        let unsupported = [
+            ("sizing", strategy.meta.sizing.is_some()),
+            ("max_entries", strategy.meta.max_entries.is_some()),
+            ("risk", strategy.risk.is_some()),
+            ("portfolio", strategy.portfolio.is_some()),
+        ];
+        if let Some((section, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(ValidationError::InvalidMargin(format!(
+                "{} is not supported for perp strategies",
+                section
+            )));
+        }
+
+        let Some(margin) = &strategy.meta.margin else {
+            return Ok(());
+        };
+        if !(margin.leverage >= 1.0 && margin.leverage <= MAX_LEVERAGE) {
+            return Err(ValidationError::InvalidMargin(format!(
+                "leverage must be between 1 and {}, got {}",
+                MAX_LEVERAGE, margin.leverage
+            )));
+        }
+        let initial_margin = 100.0 / margin.leverage;
+        if !(margin.maintenance_margin > 0.0 && margin.maintenance_margin < initial_margin) {
+            return Err(ValidationError::InvalidMargin(format!(
+                "maintenance_margin must be positive and below the initial margin ({}%), got {}",
+                initial_margin, margin.maintenance_margin
+            )));
+        }
+        if !margin.funding_rate.is_finite() {
+            return Err(ValidationError::InvalidMargin(
+                "funding_rate must be a number".to_string(),
+            ));
+        }
+        if margin.funding_interval == 0 {
+            return Err(ValidationError::InvalidMargin(
+                "funding_interval must be at least 1 hour".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check the portfolio section, and that the assets the actions trade or read belong to it.
+    fn validate_portfolio(strategy: &StrategyContent) -> Result<(), ValidationError> {
+        let assets: Vec<&str> = strategy
+            .portfolio
+            .iter()
+            .flat_map(|p| p.assets.iter().map(String::as_str))
+            .collect();
+
+        if let Some(portfolio) = &strategy.portfolio {
+            if assets.is_empty() {
+                return Err(ValidationError::InvalidPortfolio(
+                    "assets cannot be empty".to_string(),
+                ));
+            }
+            for (i, asset) in assets.iter().enumerate() {
+                if asset.is_empty()
+                    || !asset
+                        .chars()
+                        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+                {
+                    return Err(ValidationError::InvalidPortfolio(format!(
+                        "invalid asset '{}'",
+                        asset
+                    )));
+                }
+                if assets[..i].contains(asset) {
+                    return Err(ValidationError::InvalidPortfolio(format!(
+                        "duplicate asset '{}'",
+                        asset
+                    )));
+                }
+            }
+
+            if let Some(allocation) = &portfolio.allocation {
+                for (asset, weight) in allocation {
+                    if !assets.contains(&asset.as_str()) {
+                        return Err(ValidationError::InvalidPortfolio(format!(
+                            "allocation of '{}', which is not in the assets",
+                            asset
+                        )));
+                    }
+                    if !(weight.is_finite() && *weight > 0.0) {
+                        return Err(ValidationError::InvalidPortfolio(format!(
+                            "allocation of '{}' must be positive, got {}",
+                            asset, weight
+                        )));
+                    }
+                }
+                // An asset left out would be traded without capital.
+                if let Some(asset) = assets.iter().find(|a| !allocation.contains_key(**a)) {
+                    return Err(ValidationError::InvalidPortfolio(format!(
+                        "no allocation for '{}'",
+                        asset
+                    )));
+                }
+                let total: f64 = allocation.values().sum();
+                if total > 1.0 + 1e-9 {
+                    return Err(ValidationError::InvalidPortfolio(format!(
+                        "allocation adds up to {}, more than 1",
+                        total
+                    )));
+                }
+            }
+        }
+
         for action in &strategy.actions {
-            self.validate_action(action, &valid_actions, &strategy.meta.strategy_type)?;
+            if let Some(asset) = &action.asset
+                && !assets.contains(&asset.as_str())
+            {
+                return Err(ValidationError::InvalidPortfolio(format!(
+                    "action trades '{}', which is not in the portfolio",
+                    asset
+                )));
+            }
+        }
+        for reference in strategy.references() {
+            if let Some((asset, _)) = Value::split_asset(reference)
+                && !assets.contains(&asset)
+            {
+                return Err(ValidationError::InvalidPortfolio(format!(
+                    "'{}' reads '{}', which is not in the portfolio",
+                    reference, asset
+                )));
+            }
         }
 
         Ok(())
     }
 
+    /// `cond` with the definitions it references inlined, which fails on an undefined name or a
+    /// cycle.
+    fn expand(strategy: &StrategyContent, cond: &Cond) -> Result<Cond, ValidationError> {
+        strategy
+            .expand(cond)
+            .map_err(|e| ValidationError::InvalidDefinition(e.to_string()))
+    }
+
+    /// Definitions are named like identifiers, so that the text form can refer to them.
+    fn validate_definition_names(strategy: &StrategyContent) -> Result<(), ValidationError> {
+        for name in strategy.definitions.keys() {
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(ValidationError::InvalidDefinition(format!(
+                    "invalid name '{}'",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// `cond` is the action's condition, expanded.
     fn validate_action(
         &self,
         action: &Action,
+        cond: &Cond,
         valid_actions: &HashSet<&str>,
         strategy_type: &StrategyType,
     ) -> Result<(), ValidationError> {
@@ -307,7 +658,140 @@ impl StrategyValidator {
             return Err(ValidationError::InvalidWeight(action.w));
         }
 
-        self.validate_condition(&action.cond)?;
+        self.validate_condition(cond)?;
+
+        if let Some(order) = &action.order {
+            self.validate_order(order)?;
+        }
+
+        if let Some(sizing) = &action.sizing {
+            if action.action_type != "buy" {
+                return Err(ValidationError::InvalidSizing(format!(
+                    "sizing only applies to buy actions, not '{}'",
+                    action.action_type
+                )));
+            }
+            Self::validate_sizing(sizing)?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_order(&self, order: &Order) -> Result<(), ValidationError> {
+        if order.tif == Some(0) {
+            return Err(ValidationError::InvalidOrder(
+                "tif must be at least 1 bar".to_string(),
+            ));
+        }
+
+        for price in Self::order_prices(order) {
+            if let Value::Number(n) = price
+                && !(n.is_finite() && *n > 0.0)
+            {
+                return Err(ValidationError::InvalidOrder(format!(
+                    "price must be positive, got {}",
+                    n
+                )));
+            }
+            if price.uses_position() {
+                return Err(ValidationError::InvalidOrder(
+                    "price cannot depend on the position".to_string(),
+                ));
+            }
+            self.validate_value(price)?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_sizing(sizing: &Sizing) -> Result<(), ValidationError> {
+        let positive = |name: &str, value: f64| {
+            if value.is_finite() && value > 0.0 {
+                Ok(())
+            } else {
+                Err(ValidationError::InvalidSizing(format!(
+                    "{} must be positive, got {}",
+                    name, value
+                )))
+            }
+        };
+
+        match sizing {
+            Sizing::Cash | Sizing::Equity => {}
+            Sizing::Quote { amount } => positive("amount", *amount)?,
+            Sizing::Volatility { target, lookback } => {
+                positive("target", *target)?;
+                if *lookback < 2 {
+                    return Err(ValidationError::InvalidSizing(
+                        "lookback must be at least 2 bars".to_string(),
+                    ));
+                }
+            }
+            Sizing::Kelly { fraction, cap, .. } => {
+                positive("fraction", *fraction)?;
+                positive("cap", *cap)?;
+                if *fraction > 1.0 || *cap > 1.0 {
+                    return Err(ValidationError::InvalidSizing(
+                        "fraction and cap must be at most 1".to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_risk(&self, risk: &Risk) -> Result<(), ValidationError> {
+        let stops = [
+            ("stop_loss", &risk.stop_loss),
+            ("trailing_stop", &risk.trailing_stop),
+        ];
+        for (name, distance) in stops {
+            if let Some(Distance::Pct(pct)) = distance
+                && *pct >= 100.0
+            {
+                return Err(ValidationError::InvalidRisk(format!(
+                    "{} must be below 100%, got {}",
+                    name, pct
+                )));
+            }
+        }
+
+        for distance in Self::risk_distances(risk) {
+            match distance {
+                Distance::Pct(pct) if !(pct.is_finite() && *pct > 0.0) => {
+                    return Err(ValidationError::InvalidRisk(format!(
+                        "percentage must be positive, got {}",
+                        pct
+                    )));
+                }
+                Distance::Pct(_) => {}
+                Distance::Atr { indicator, mult } => {
+                    if !(mult.is_finite() && *mult > 0.0) {
+                        return Err(ValidationError::InvalidRisk(format!(
+                            "ATR multiple must be positive, got {}",
+                            mult
+                        )));
+                    }
+                    self.validate_reference(indicator)?;
+                }
+            }
+        }
+
+        if risk.max_holding_bars == Some(0) {
+            return Err(ValidationError::InvalidRisk(
+                "max_holding_bars must be at least 1 bar".to_string(),
+            ));
+        }
+
+        if let Some(pct) = risk.max_daily_loss
+            && !(pct > 0.0 && pct < 100.0)
+        {
+            return Err(ValidationError::InvalidRisk(format!(
+                "max_daily_loss must be between 0 and 100%, got {}",
+                pct
+            )));
+        }
 
         Ok(())
     }
@@ -343,15 +827,63 @@ impl StrategyValidator {
                 self.validate_value(min)?;
                 self.validate_value(max)?;
             }
+            Cond::InPosition => {}
+            // Only expanded conditions are validated.
+            Cond::Ref(name) => {
+                return Err(ValidationError::InvalidDefinition(format!(
+                    "undefined condition '{}'",
+                    name
+                )));
+            }
         }
 
         Ok(())
     }
 
     fn validate_value(&self, val: &Value) -> Result<(), ValidationError> {
-        if let Value::Indicator(ta) = val {
-            if !self.valid_indicators.contains(ta) {
-                return Err(ValidationError::InvalidIndicator(ta.to_string()));
+        match val {
+            Value::Number(_) | Value::Boolean(_) => {}
+            Value::Indicator(ta) => {
+                self.validate_reference(ta)?;
+            }
+            Value::Spec(spec) => {
+                Indicator::from_spec(spec)
+                    .map_err(|e| ValidationError::InvalidIndicator(e.to_string()))?;
+                if let Some(tf) = &spec.tf {
+                    Self::validate_timeframe(tf)?;
+                }
+            }
+            Value::Expr(expr) => {
+                if let Expr::Div {
+                    r: Value::Number(r),
+                    ..
+                } = expr.as_ref()
+                    && *r == 0.0
+                {
+                    return Err(ValidationError::InvalidCondition(
+                        "division by zero".to_string(),
+                    ));
+                }
+                match expr.as_ref() {
+                    // The past states of the position are not kept by the backtester.
+                    Expr::Lag { val, .. } if val.uses_position() => {
+                        return Err(ValidationError::InvalidCondition(
+                            "lag cannot depend on the position".to_string(),
+                        ));
+                    }
+                    Expr::BarsSince { cond } => {
+                        if cond.uses_position() {
+                            return Err(ValidationError::InvalidCondition(
+                                "bars_since cannot depend on the position".to_string(),
+                            ));
+                        }
+                        self.validate_condition(cond)?;
+                    }
+                    _ => {}
+                }
+                for operand in Self::expr_operands(expr) {
+                    self.validate_value(operand)?;
+                }
             }
         }
         Ok(())
@@ -598,7 +1130,7 @@ mod tests {
     }
 
     #[test]
-    fn test_invalid_indicator() {
+    fn test_order_round_trip() {
         let json = r#"
         {
           "meta": {
@@ -607,20 +1139,677 @@ mod tests {
           "actions": [
             {
               "type": "buy",
-              "w": 0.8,
-              "cond": {
-                "gt": {
-                  "l": "sma_10",
-                  "r": "sma_50"
-                }
-              }
+              "w": 0.5,
+              "cond": { "gt": { "l": "sma_10", "r": "sma_50" } },
+              "order": { "type": { "stop_limit": { "stop": "sma_10", "limit": 105.5 } }, "tif": 3 }
+            },
+            {
+              "type": "sell",
+              "w": 1.0,
+              "cond": { "lt": { "l": "sma_10", "r": "sma_50" } },
+              "order": { "type": "market", "tif": null }
             }
           ]
         }"#;
 
-        let hash = HashSet::new();
-        let strat_val = StrategyValidator::new(hash);
-        let result = strat_val.json_to_msgpack(json);
+        let mut hash = HashSet::new();
+        hash.insert("sma_10".to_string());
+        hash.insert("sma_50".to_string());
+        let strat_validator = StrategyValidator::new(hash);
+
+        let msgpack = strat_validator.json_to_msgpack(json).unwrap();
+        let back_to_json = strat_validator.msgpack_to_json(&msgpack).unwrap();
+
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let converted: serde_json::Value = serde_json::from_str(&back_to_json).unwrap();
+        assert_eq!(original, converted);
+
+        let invalid = json.replace("\"tif\": 3", "\"tif\": 0");
+        assert!(matches!(
+            strat_validator.json_to_msgpack(&invalid),
+            Err(ValidationError::InvalidOrder(_))
+        ));
+
+        let invalid = json.replace("105.5", "-1");
+        assert!(matches!(
+            strat_validator.json_to_msgpack(&invalid),
+            Err(ValidationError::InvalidOrder(_))
+        ));
+    }
+
+    #[test]
+    fn test_risk_round_trip() {
+        let json = r#"
+        {
+          "meta": {
+            "type": "spot"
+          },
+          "actions": [
+            {
+              "type": "buy",
+              "w": 1.0,
+              "cond": { "gt": { "l": "sma_10", "r": "sma_50" } }
+            }
+          ],
+          "risk": {
+            "take_profit": { "atr": { "indicator": "atr_14", "mult": 3.0 } },
+            "trailing_stop": { "pct": 5.0 },
+            "max_daily_loss": 2.0
+          }
+        }"#;
+
+        let mut hash = HashSet::new();
+        hash.insert("sma_10".to_string());
+        hash.insert("sma_50".to_string());
+        hash.insert("atr_14".to_string());
+        let strat_validator = StrategyValidator::new(hash);
+
+        let msgpack = strat_validator.json_to_msgpack(json).unwrap();
+        let back_to_json = strat_validator.msgpack_to_json(&msgpack).unwrap();
+
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let converted: serde_json::Value = serde_json::from_str(&back_to_json).unwrap();
+        assert_eq!(original, converted);
+
+        let strategy = strat_validator.validate_json(json).unwrap();
+        assert!(StrategyValidator::get_indicators(&strategy).contains("atr_14"));
+
+        let invalid = json.replace("\"pct\": 5.0", "\"pct\": 100.0");
+        assert!(matches!(
+            strat_validator.json_to_msgpack(&invalid),
+            Err(ValidationError::InvalidRisk(_))
+        ));
+
+        let invalid = json.replace("\"mult\": 3.0", "\"mult\": 0.0");
+        assert!(matches!(
+            strat_validator.json_to_msgpack(&invalid),
+            Err(ValidationError::InvalidRisk(_))
+        ));
+
+        let invalid = json.replace("atr_14", "atr_x");
+        assert!(matches!(
+            strat_validator.json_to_msgpack(&invalid),
+            Err(ValidationError::InvalidIndicator(_))
+        ));
+    }
+
+    #[test]
+    fn test_sizing() {
+        let json = r#"
+        {
+          "meta": {
+            "type": "spot",
+            "sizing": { "mode": "kelly", "fraction": 0.5, "cap": 0.25, "min_trades": 20 },
+            "max_entries": 3
+          },
+          "actions": [
+            {
+              "type": "buy",
+              "w": 1.0,
+              "cond": { "gt": { "l": "sma_10", "r": "sma_50" } },
+              "sizing": { "mode": "volatility", "target": 1.0, "lookback": 20 }
+            },
+            {
+              "type": "sell",
+              "w": 1.0,
+              "cond": { "lt": { "l": "sma_10", "r": "sma_50" } }
+            }
+          ]
+        }"#;
+
+        let mut hash = HashSet::new();
+        hash.insert("sma_10".to_string());
+        hash.insert("sma_50".to_string());
+        let strat_validator = StrategyValidator::new(hash);
+
+        let msgpack = strat_validator.json_to_msgpack(json).unwrap();
+        let back_to_json = strat_validator.msgpack_to_json(&msgpack).unwrap();
+
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let converted: serde_json::Value = serde_json::from_str(&back_to_json).unwrap();
+        assert_eq!(original, converted);
+
+        for invalid in [
+            json.replace("\"cap\": 0.25", "\"cap\": 1.5"),
+            json.replace("\"lookback\": 20", "\"lookback\": 1"),
+            json.replace("\"max_entries\": 3", "\"max_entries\": 0"),
+            json.replace(
+                "\"cond\": { \"lt\"",
+                "\"sizing\": { \"mode\": \"equity\" }, \"cond\": { \"lt\"",
+            ),
+        ] {
+            assert!(matches!(
+                strat_validator.json_to_msgpack(&invalid),
+                Err(ValidationError::InvalidSizing(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_expression_round_trip() {
+        let json = r#"
+        {
+          "meta": {
+            "type": "spot"
+          },
+          "actions": [
+            {
+              "type": "buy",
+              "w": 1.0,
+              "cond": {
+                "and": {
+                  "conds": [
+                    { "gt": { "l": "close", "r": { "mul": { "l": "sma_50", "r": 1.02 } } } },
+                    { "gt": { "l": { "sub": { "l": "rsi", "r": "rsi[1]" } }, "r": 5.0 } },
+                    { "lt": { "l": { "abs": { "val": { "lag": { "val": "sma_50", "n": 2 } } } }, "r": 100.0 } }
+                  ]
+                }
+              }
+            }
+          ]
+        }"#;
+
+        let mut hash = HashSet::new();
+        hash.insert("close".to_string());
+        hash.insert("sma_50".to_string());
+        hash.insert("rsi".to_string());
+        let strat_validator = StrategyValidator::new(hash);
+
+        let msgpack = strat_validator.json_to_msgpack(json).unwrap();
+        let back_to_json = strat_validator.msgpack_to_json(&msgpack).unwrap();
+
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let converted: serde_json::Value = serde_json::from_str(&back_to_json).unwrap();
+        assert_eq!(original, converted);
+
+        let strategy = strat_validator.validate_json(json).unwrap();
+        let indicators = StrategyValidator::get_indicators(&strategy);
+        assert_eq!(indicators.len(), 3);
+        assert!(indicators.contains("rsi"));
+
+        let invalid = json.replace("rsi[1]", "foo[1]");
+        assert!(matches!(
+            strat_validator.json_to_msgpack(&invalid),
+            Err(ValidationError::InvalidIndicator(_))
+        ));
+
+        let invalid = json.replace("\"mul\"", "\"div\"").replace("1.02", "0.0");
+        assert!(matches!(
+            strat_validator.json_to_msgpack(&invalid),
+            Err(ValidationError::InvalidCondition(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_indicator() {
+        let json = r#"
+        {
+          "meta": {
+            "type": "spot"
+          },
+          "actions": [
+            {
+              "type": "buy",
+              "w": 0.8,
+              "cond": {
+                "gt": {
+                  "l": "foo_10",
+                  "r": "sma_50"
+                }
+              }
+            }
+          ]
+        }"#;
+
+        let hash = HashSet::new();
+        let strat_val = StrategyValidator::new(hash);
+        let result = strat_val.json_to_msgpack(json);
+        assert!(matches!(result, Err(ValidationError::InvalidIndicator(_))));
+
+        // Registry indicators are valid without being listed, with their parameters checked.
+        assert!(
+            strat_val
+                .json_to_msgpack(&json.replace("foo_10", "ema_21"))
+                .is_ok()
+        );
+        let result = strat_val.json_to_msgpack(&json.replace("foo_10", "sma_0"));
+        assert!(matches!(
+            result,
+            Err(ValidationError::InvalidIndicator(msg)) if msg == "sma_0: period must be at least 1"
+        ));
+
+        let spec = r#"{ "ind": "bb", "period": 20, "dev": 2, "out": "upper" }"#;
+        let strategy = strat_val
+            .validate_json(&json.replace("\"foo_10\"", spec))
+            .unwrap();
+        assert!(StrategyValidator::get_indicators(&strategy).contains("bb_upper_20_2"));
+        let result =
+            strat_val.json_to_msgpack(&json.replace("\"foo_10\"", &spec.replace("20", "0")));
+        assert!(matches!(result, Err(ValidationError::InvalidIndicator(_))));
+    }
+
+    #[test]
+    fn test_portfolio_round_trip() {
+        let json = r#"
+        {
+          "meta": {
+            "type": "spot"
+          },
+          "actions": [
+            {
+              "type": "buy",
+              "w": 0.5,
+              "cond": { "gt": { "l": "close", "r": "sma_50" } }
+            },
+            {
+              "type": "sell",
+              "w": 1.0,
+              "asset": "ETHUSDT",
+              "cond": { "lt": { "l": "BTCUSDT.close", "r": "BTCUSDT.sma_200@1d[1]" } }
+            }
+          ],
+          "portfolio": {
+            "assets": ["BTCUSDT", "ETHUSDT"],
+            "allocation": { "BTCUSDT": 0.6, "ETHUSDT": 0.4 },
+            "rebalance": "weekly"
+          }
+        }"#;
+
+        let strat_validator = StrategyValidator::new(HashSet::new());
+        let msgpack = strat_validator.json_to_msgpack(json).unwrap();
+        let back_to_json = strat_validator.msgpack_to_json(&msgpack).unwrap();
+
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let converted: serde_json::Value = serde_json::from_str(&back_to_json).unwrap();
+        assert_eq!(original, converted);
+
+        let strategy = strat_validator.validate_json(json).unwrap();
+        assert!(StrategyValidator::get_indicators(&strategy).contains("BTCUSDT.sma_200@1d"));
+        assert_eq!(
+            StrategyValidator::get_assets(&strategy, "ignored"),
+            ["BTCUSDT", "ETHUSDT"]
+        );
+
+        for (from, to) in [
+            ("\"asset\": \"ETHUSDT\"", "\"asset\": \"SOLUSDT\""),
+            ("BTCUSDT.close", "SOLUSDT.close"),
+            ("0.6", "0.7"),
+            ("[\"BTCUSDT\", \"ETHUSDT\"]", "[\"BTCUSDT\", \"BTCUSDT\"]"),
+            (", \"ETHUSDT\": 0.4", ""),
+        ] {
+            assert!(matches!(
+                strat_validator.json_to_msgpack(&json.replace(from, to)),
+                Err(ValidationError::InvalidPortfolio(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_timeframe_round_trip() {
+        let json = r#"
+        {
+          "meta": {
+            "type": "spot"
+          },
+          "actions": [
+            {
+              "type": "buy",
+              "w": 0.5,
+              "cond": {
+                "and": {
+                  "conds": [
+                    { "xab": { "l": "ema_9", "r": "ema_21" } },
+                    { "gt": { "l": "close@4h", "r": "sma_50@4h[1]" } },
+                    { "gt": { "l": { "ind": "rsi", "tf": "1d" }, "r": 50.0 } }
+                  ]
+                }
+              }
+            }
+          ]
+        }"#;
+
+        let strat_validator = StrategyValidator::new(HashSet::new());
+        let msgpack = strat_validator.json_to_msgpack(json).unwrap();
+        let back_to_json = strat_validator.msgpack_to_json(&msgpack).unwrap();
+
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let converted: serde_json::Value = serde_json::from_str(&back_to_json).unwrap();
+        assert_eq!(original, converted);
+
+        let strategy = strat_validator.validate_json(json).unwrap();
+        let indicators = StrategyValidator::get_indicators(&strategy);
+        assert!(indicators.contains("sma_50@4h"));
+        assert!(indicators.contains("rsi_14@1d"));
+        let mut timeframes: Vec<String> = StrategyValidator::get_timeframes(&strategy)
+            .into_iter()
+            .collect();
+        timeframes.sort();
+        assert_eq!(timeframes, ["1d", "4h"]);
+
+        for invalid in ["sma_50@4x", "foo@4h"] {
+            assert!(matches!(
+                strat_validator.json_to_msgpack(&json.replace("sma_50@4h", invalid)),
+                Err(ValidationError::InvalidIndicator(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_perp_round_trip() {
+        let json = r#"
+        {
+          "meta": {
+            "type": "perp",
+            "margin": {
+              "leverage": 5.0,
+              "maintenance_margin": 0.5,
+              "funding_rate": 0.01,
+              "funding_interval": 8
+            }
+          },
+          "actions": [
+            { "type": "short", "w": 0.5, "cond": { "xbe": { "l": "close", "r": "sma_50" } } },
+            { "type": "close_short", "w": 1.0, "cond": { "xab": { "l": "close", "r": "sma_50" } } }
+          ]
+        }"#;
+
+        let strat_validator = StrategyValidator::new(HashSet::new());
+        let msgpack = strat_validator.json_to_msgpack(json).unwrap();
+        let back_to_json = strat_validator.msgpack_to_json(&msgpack).unwrap();
+
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let converted: serde_json::Value = serde_json::from_str(&back_to_json).unwrap();
+        assert_eq!(original, converted);
+
+        for (from, to) in [
+            ("\"leverage\": 5.0", "\"leverage\": 200.0"),
+            (
+                "\"maintenance_margin\": 0.5",
+                "\"maintenance_margin\": 20.0",
+            ),
+            ("\"funding_interval\": 8", "\"funding_interval\": 0"),
+            (
+                "\"type\": \"perp\"",
+                "\"type\": \"perp\", \"max_entries\": 2",
+            ),
+        ] {
+            assert!(matches!(
+                strat_validator.json_to_msgpack(&json.replace(from, to)),
+                Err(ValidationError::InvalidMargin(_))
+            ));
+        }
+        assert!(matches!(
+            strat_validator.json_to_msgpack(&json.replace("close_short", "sell")),
+            Err(ValidationError::InvalidActionType(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_options_round_trip() {
+        let json = r#"
+        {
+          "meta": {
+            "type": "options",
+            "options": {
+              "vol_lookback": 30,
+              "risk_free_rate": 4.0
+            }
+          },
+          "actions": [
+            {
+              "type": "long",
+              "w": 1.0,
+              "cond": { "lt": { "l": "rsi", "r": 30.0 } },
+              "legs": [
+                { "type": "call", "strike": 0.0, "expiry": 30, "quantity": 1.0 },
+                { "type": "call", "strike": 10.0, "expiry": 30, "quantity": -1.0 }
+              ]
+            },
+            { "type": "close", "w": 1.0, "cond": { "gt": { "l": "rsi", "r": 70.0 } } }
+          ]
+        }"#;
+
+        let strat_validator = StrategyValidator::new(HashSet::new());
+        let msgpack = strat_validator.json_to_msgpack(json).unwrap();
+        let back_to_json = strat_validator.msgpack_to_json(&msgpack).unwrap();
+
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let converted: serde_json::Value = serde_json::from_str(&back_to_json).unwrap();
+        assert_eq!(original, converted);
+
+        for (from, to) in [
+            ("\"strike\": 10.0", "\"strike\": 0.0"),
+            (
+                "\"expiry\": 30, \"quantity\": -1.0",
+                "\"expiry\": 0, \"quantity\": -1.0",
+            ),
+            ("\"quantity\": -1.0", "\"quantity\": 0.0"),
+            ("\"strike\": 10.0", "\"strike\": -150.0"),
+            ("\"vol_lookback\": 30", "\"vol_lookback\": 1"),
+            (
+                "\"type\": \"options\"",
+                "\"type\": \"options\", \"max_entries\": 2",
+            ),
+        ] {
+            assert!(matches!(
+                strat_validator.json_to_msgpack(&json.replace(from, to)),
+                Err(ValidationError::InvalidOptions(_))
+            ));
+        }
+        let spot = json.replace("\"type\": \"options\"", "\"type\": \"spot\"");
+        assert!(matches!(
+            strat_validator.json_to_msgpack(&spot),
+            Err(ValidationError::InvalidActionType(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_options_legs() {
+        let strat_validator = StrategyValidator::new(HashSet::new());
+        let validate = |strategy_type: &str, action_type: &str, legs: Option<&str>| {
+            let legs = legs.map_or(String::new(), |legs| format!(", \"legs\": {}", legs));
+            let json = format!(
+                r#"{{
+                    "meta": {{ "type": "{strategy_type}" }},
+                    "actions": [
+                        {{
+                            "type": "{action_type}",
+                            "w": 1.0,
+                            "cond": {{ "lt": {{ "l": "rsi", "r": 30.0 }} }}{legs}
+                        }}
+                    ]
+                }}"#
+            );
+            strat_validator.json_to_msgpack(&json)
+        };
+        let leg = |strike: f64| {
+            format!(
+                r#"{{ "type": "put", "strike": {}, "expiry": 30, "quantity": 1.0 }}"#,
+                strike
+            )
+        };
+
+        // Saved before legs existed, they open the default ones.
+        assert!(validate("options", "long", None).is_ok());
+        assert!(validate("options", "short", None).is_ok());
+        assert!(validate("options", "close", None).is_ok());
+        assert!(validate("options", "long", Some(&format!("[{}]", leg(-5.0)))).is_ok());
+
+        let condor = (0..MAX_LEGS + 1)
+            .map(|i| leg(i as f64))
+            .collect::<Vec<_>>()
+            .join(", ");
+        for (strategy_type, action_type, legs) in [
+            ("options", "long", "[]".to_string()),
+            ("options", "short", format!("[{}]", condor)),
+            ("options", "long", format!("[{}, {}]", leg(5.0), leg(5.0))),
+            ("options", "close", format!("[{}]", leg(0.0))),
+            ("spot", "buy", format!("[{}]", leg(0.0))),
+        ] {
+            assert!(matches!(
+                validate(strategy_type, action_type, Some(&legs)),
+                Err(ValidationError::InvalidOptions(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let json = r#"
+        {
+          "meta": {
+            "type": "spot"
+          },
+          "actions": [
+            {
+              "type": "buy",
+              "w": 1.0,
+              "cond": {
+                "and": {
+                  "conds": [
+                    { "not": { "cond": "in_position" } },
+                    { "lt": { "l": { "state": "trades_today" }, "r": 3.0 } },
+                    { "bet": { "val": { "state": "hour" }, "min": 9.0, "max": 17.0 } },
+                    { "le": { "l": { "bars_since": { "cond": { "gt": { "l": "rsi", "r": 70.0 } } } }, "r": 5.0 } }
+                  ]
+                }
+              }
+            },
+            {
+              "type": "sell",
+              "w": 1.0,
+              "cond": { "ge": { "l": { "state": "bars_since_entry" }, "r": 5.0 } }
+            }
+          ]
+        }"#;
+
+        let strat_validator = StrategyValidator::new(HashSet::new());
+        let msgpack = strat_validator.json_to_msgpack(json).unwrap();
+        let back_to_json = strat_validator.msgpack_to_json(&msgpack).unwrap();
+
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let converted: serde_json::Value = serde_json::from_str(&back_to_json).unwrap();
+        assert_eq!(original, converted);
+        let strategy = strat_validator.validate_json(json).unwrap();
+        assert!(StrategyValidator::get_indicators(&strategy).contains("rsi"));
+
+        for (from, to) in [
+            (r#""r": 70.0"#, r#""r": { "state": "pnl_pct" }"#),
+            (
+                r#""l": { "state": "bars_since_entry" }"#,
+                r#""l": { "lag": { "val": { "state": "bars_since_entry" }, "n": 1 } }"#,
+            ),
+        ] {
+            assert!(matches!(
+                strat_validator.json_to_msgpack(&json.replace(from, to)),
+                Err(ValidationError::InvalidCondition(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_definitions() {
+        let json = r#"
+        {
+          "meta": {
+            "type": "spot"
+          },
+          "actions": [
+            {
+              "type": "buy",
+              "w": 1.0,
+              "cond": { "and": { "conds": [{ "ref": "uptrend" }, { "lt": { "l": "rsi", "r": 30.0 } }] } }
+            },
+            {
+              "type": "sell",
+              "w": 1.0,
+              "cond": { "not": { "cond": { "ref": "uptrend" } } }
+            }
+          ],
+          "definitions": {
+            "uptrend": { "and": { "conds": [{ "ref": "above" }, { "eq": { "l": "breakout", "r": true } }] } },
+            "above": { "gt": { "l": "sma_10", "r": "sma_50" } }
+          }
+        }"#;
+
+        let mut hash = HashSet::new();
+        hash.insert("breakout".to_string());
+        let strat_validator = StrategyValidator::new(hash);
+        let msgpack = strat_validator.json_to_msgpack(json).unwrap();
+        let back_to_json = strat_validator.msgpack_to_json(&msgpack).unwrap();
+
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let converted: serde_json::Value = serde_json::from_str(&back_to_json).unwrap();
+        assert_eq!(original, converted);
+        let strategy = strat_validator.validate_json(json).unwrap();
+        assert!(StrategyValidator::get_indicators(&strategy).contains("sma_50"));
+
+        for (from, to, message) in [
+            (
+                r#""cond": { "ref": "uptrend" }"#,
+                r#""cond": { "ref": "downtrend" }"#,
+                "undefined condition 'downtrend'",
+            ),
+            (
+                r#"{ "gt": { "l": "sma_10", "r": "sma_50" } }"#,
+                r#"{ "ref": "uptrend" }"#,
+                "condition defined in terms of itself: uptrend -> above -> uptrend",
+            ),
+            (r#""above""#, r#""above!""#, "invalid name 'above!'"),
+        ] {
+            assert!(matches!(
+                strat_validator.json_to_msgpack(&json.replace(from, to)),
+                Err(ValidationError::InvalidDefinition(msg)) if msg == message
+            ));
+        }
+        // Unused definitions are validated too.
+        let unused = json.replace(
+            r#""definitions": {"#,
+            r#""definitions": { "unused": { "gt": { "l": "foo_10", "r": 1.0 } },"#,
+        );
+        assert!(matches!(
+            strat_validator.json_to_msgpack(&unused),
+            Err(ValidationError::InvalidIndicator(_))
+        ));
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let strat_validator = StrategyValidator::new(HashSet::new());
+        let hash = |text: &str| content_hash(&strat_validator.validate_text(text).unwrap());
+
+        let original = hash("type spot\n\nbuy 0.8 when sma_10 > sma_50 and rsi < 30\n");
+        for same in [
+            "type spot\n\nbuy 0.8 when 30 > rsi and sma_50 < sma_10\n",
+            "type spot\n\nbuy 0.8 when (rsi < 30 and sma_10 > sma_50) and rsi < 30\n",
+            "type spot\n\nbuy 0.8 when not not (sma_10 > sma_50 and rsi < 30)\n",
+            "type spot\n\nbuy 0.8 when oversold and sma_10 > sma_50\n\ndefine oversold = rsi < 30\n",
+        ] {
+            assert_eq!(hash(same), original, "{}", same);
+        }
+        for different in [
+            "type spot\n\nbuy 0.5 when sma_10 > sma_50 and rsi < 30\n",
+            "type spot\n\nbuy 0.8 when sma_10 > sma_50 or rsi < 30\n",
+            "type spot\n\nbuy 0.8 when sma_10 >= sma_50 and rsi < 30\n",
+        ] {
+            assert_ne!(hash(different), original, "{}", different);
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        let text = "type spot\n\nbuy 0.8 when sma_10 crosses above sma_50 and rsi < 30\n";
+
+        let strat_validator = StrategyValidator::new(HashSet::new());
+        let strategy = strat_validator.validate_text(text).unwrap();
+        assert_eq!(backtester::text::format(&strategy), text);
+
+        let result = strat_validator.validate_text("type spot\nbuy 0.8 when sma_10 >");
+        assert!(matches!(
+            result,
+            Err(ValidationError::Syntax(e)) if e.line == 2 && e.column == 22
+        ));
+        let result = strat_validator.validate_text("buy 0.8 when foo_10 > 1");
         assert!(matches!(result, Err(ValidationError::InvalidIndicator(_))));
     }
 }
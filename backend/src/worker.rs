@@ -0,0 +1,270 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use backtester::{BacktestConfig, Candles};
+use tokio::{sync::watch, time::error::Elapsed};
+
+use crate::{
+    Database,
+    dataset_client::DatasetManagerClient,
+    db::job_queue::{BacktestJob, Job, JobStatus, JobType},
+    models::ResultSummary,
+    s3_manager::{ArtifactPaths, S3Manager},
+    validators::strategy_validator::StrategyValidator,
+};
+
+enum Outcome {
+    Finished(Result<anyhow::Result<(Box<ResultSummary>, ArtifactPaths)>, Elapsed>),
+    Cancelled,
+    Shutdown,
+}
+
+/// Dataset files of an asset: its `.bin` with the indicators it precomputes, and the same for
+/// each other timeframe.
+struct DatasetFiles {
+    path: PathBuf,
+    ta: Vec<String>,
+    timeframes: Vec<(String, PathBuf, Vec<String>)>,
+}
+
+impl DatasetFiles {
+    fn load(self) -> anyhow::Result<Candles> {
+        let mut candles = Candles::load(&self.path, &self.ta)
+            .with_context(|| format!("Failed to load dataset {}", self.path.display()))?;
+        for (tf, path, ta) in self.timeframes {
+            let other = Candles::load(&path, &ta)
+                .with_context(|| format!("Failed to load dataset {}", path.display()))?;
+            candles.timeframes.insert(tf, other);
+        }
+        Ok(candles)
+    }
+}
+
+/// Consumes `process_backtest` jobs from the Postgres queue.
+///
+/// Several workers can run side by side (in one process or several), `dequeue_job` uses
+/// `SKIP LOCKED` so a job is only ever handed to one of them. The status of the backtest follows
+/// the status of its job (see the `jobs_sync_backtest_status` trigger), so the worker only has to
+/// write the results and upload the artifacts.
+///
+/// While a job runs, the worker polls its status to notice when it gets cancelled and stops the
+/// engine through a cancellation flag.
+#[derive(Clone)]
+pub struct Worker {
+    pub id: String,
+    db: Database,
+    dataset_manager: DatasetManagerClient,
+    s3: S3Manager,
+    datasets_dir: PathBuf,
+    poll_interval: Duration,
+}
+
+impl Worker {
+    pub fn new(
+        id: impl Into<String>,
+        db: Database,
+        dataset_manager: DatasetManagerClient,
+        s3: S3Manager,
+        datasets_dir: impl Into<PathBuf>,
+        poll_interval: Duration,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            db,
+            dataset_manager,
+            s3,
+            datasets_dir: datasets_dir.into(),
+            poll_interval,
+        }
+    }
+
+    /// Process jobs until `shutdown` is set to true (or its sender is dropped).
+    ///
+    /// A job still running when the shutdown is requested is handed back to the queue so another
+    /// worker can pick it up.
+    pub async fn run(self, mut shutdown: watch::Receiver<bool>) {
+        tracing::info!("worker {} started", self.id);
+
+        while !*shutdown.borrow() {
+            match self.db.dequeue(&self.id, &[JobType::ProcessBacktest]).await {
+                Ok(Some(job)) => self.handle(job, &mut shutdown).await,
+                Ok(None) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep(self.poll_interval) => {}
+                        _ = shutdown.changed() => {}
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("worker {} failed to dequeue: {:?}", self.id, e);
+                    tokio::select! {
+                        _ = tokio::time::sleep(self.poll_interval) => {}
+                        _ = shutdown.changed() => {}
+                    }
+                }
+            }
+        }
+
+        tracing::info!("worker {} stopped", self.id);
+    }
+
+    async fn handle(&self, job: Job, shutdown: &mut watch::Receiver<bool>) {
+        tracing::info!("worker {} processing job {}", self.id, job.id);
+
+        let payload = match BacktestJob::decode(&job.payload) {
+            Ok(p) => p,
+            Err(e) => {
+                self.fail(&job, &format!("Invalid payload: {}", e)).await;
+                return;
+            }
+        };
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let timeout = Duration::from_secs(job.timeout_seconds.max(1) as u64);
+        let outcome = tokio::select! {
+            res = tokio::time::timeout(timeout, self.process(&payload, cancelled.clone())) => {
+                Outcome::Finished(res)
+            }
+            _ = self.wait_for_cancellation(job.id) => Outcome::Cancelled,
+            _ = shutdown.changed() => Outcome::Shutdown,
+        };
+        // Dropping `process` does not stop the engine, it runs on a blocking thread.
+        cancelled.store(true, Ordering::Relaxed);
+
+        match outcome {
+            Outcome::Finished(Ok(Ok((summary, artifacts)))) => {
+                match self
+                    .db
+                    .complete_backtest(job.id, payload.backtest_id, &summary, &artifacts)
+                    .await
+                {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        tracing::info!("job {} was cancelled before its results were saved", job.id)
+                    }
+                    Err(e) => tracing::error!("failed to complete job {}: {:?}", job.id, e),
+                }
+            }
+            Outcome::Finished(Ok(Err(e))) => {
+                self.fail(&job, &format!("{:#}", e)).await;
+            }
+            Outcome::Finished(Err(_)) => {
+                let msg = format!("Job timed out after {} seconds", job.timeout_seconds);
+                self.fail(&job, &msg).await;
+            }
+            Outcome::Cancelled => {
+                tracing::info!("worker {} stopped cancelled job {}", self.id, job.id);
+            }
+            Outcome::Shutdown => {
+                tracing::info!("worker {} shutting down, releasing job {}", self.id, job.id);
+                if let Err(e) = self.db.release_job(job.id).await {
+                    tracing::error!("failed to release job {}: {:?}", job.id, e);
+                }
+            }
+        }
+    }
+
+    /// Resolves once the job has been cancelled.
+    async fn wait_for_cancellation(&self, job_id: i64) {
+        loop {
+            tokio::time::sleep(self.poll_interval).await;
+            match self.db.get_job_status(job_id).await {
+                Ok(Some(JobStatus::Cancelled)) => return,
+                Ok(_) => {}
+                Err(e) => tracing::error!("failed to get status of job {}: {:?}", job_id, e),
+            }
+        }
+    }
+
+    /// Run the backtest and upload its artifacts, the results are saved along with the completion
+    /// of the job.
+    async fn process(
+        &self,
+        payload: &BacktestJob,
+        cancelled: Arc<AtomicBool>,
+    ) -> anyhow::Result<(Box<ResultSummary>, ArtifactPaths)> {
+        let timeframes = StrategyValidator::get_timeframes(&payload.strategy);
+        let mut files = Vec::new();
+        for asset in StrategyValidator::get_assets(&payload.strategy, &payload.dataset) {
+            let dataset = self
+                .dataset_files(&asset, &payload.timeframe, &timeframes)
+                .await?;
+            files.push((asset, dataset));
+        }
+
+        let strategy = payload.strategy.clone();
+        let config = BacktestConfig::new(payload.date_start, payload.date_end)
+            .with_execution(payload.execution.clone());
+
+        // The engine is CPU bound, keep it away from the async runtime threads.
+        let report = tokio::task::spawn_blocking(move || {
+            let mut assets = HashMap::new();
+            for (asset, dataset) in files {
+                assets.insert(asset, dataset.load()?);
+            }
+            if strategy.portfolio.is_some() {
+                return anyhow::Ok(backtester::run_portfolio_cancellable(
+                    &strategy, assets, &config, &cancelled,
+                )?);
+            }
+            // Unwrap is fine, a single asset backtest loads exactly one asset.
+            let candles = assets.into_values().next().unwrap();
+            anyhow::Ok(backtester::run_cancellable(
+                &strategy, &candles, &config, &cancelled,
+            )?)
+        })
+        .await??;
+
+        let artifacts = self
+            .s3
+            .upload_artifacts(payload.backtest_id, &report)
+            .await?;
+
+        Ok((Box::new(report.summary.into()), artifacts))
+    }
+
+    /// Files of the dataset of `asset`, along with the ones of the other `timeframes` of the
+    /// asset the strategy reads indicators on.
+    async fn dataset_files(
+        &self,
+        asset: &str,
+        timeframe: &str,
+        timeframes: &HashSet<String>,
+    ) -> anyhow::Result<DatasetFiles> {
+        let meta = self
+            .dataset_manager
+            .get_dataset(format!("{}-{}", asset, timeframe))
+            .await?;
+
+        let mut dataset = DatasetFiles {
+            path: self.datasets_dir.join(&meta.path),
+            ta: meta.ta,
+            timeframes: Vec::new(),
+        };
+        for tf in timeframes {
+            let meta = self
+                .dataset_manager
+                .get_dataset(format!("{}-{}", asset, tf))
+                .await?;
+            dataset
+                .timeframes
+                .push((tf.clone(), self.datasets_dir.join(&meta.path), meta.ta));
+        }
+        Ok(dataset)
+    }
+
+    async fn fail(&self, job: &Job, error: &str) {
+        tracing::warn!("job {} failed: {}", job.id, error);
+
+        if let Err(e) = self.db.fail_job(job.id, error).await {
+            tracing::error!("failed to mark job {} as failed: {:?}", job.id, e);
+        }
+    }
+}
@@ -1,17 +1,20 @@
 use axum_test::TestServer;
 use backend::{
     models::{
-        BacktestStatus, CreateBacktestRequest, CreateStrategyRequest, GetStrategyRequest,
-        LoginRequest, RegisterRequest, Strategy,
+        BacktestPage, BacktestResponse, BacktestStatus, CreateBacktestRequest,
+        CreateStrategyRequest, GetStrategyRequest, LoginRequest, RegisterRequest, ResultSummary,
+        Strategy,
     },
+    s3_manager::ArtifactPaths,
     validators::strategy_validator::StrategyContent,
 };
 use chrono::DateTime;
 use cookie::Cookie;
 
 use crate::helper::{
-    TestContext, TestUser,
-    assertions::{assert_success_response, extract_cookie_value},
+    TestContext, TestStrategy, TestUser,
+    assertions::{assert_status_code, assert_success_response, extract_cookie_value},
+    encode_content,
 };
 
 #[tokio::test]
@@ -69,7 +72,7 @@ pub async fn test_submit_backtest() {
         .post("/api/strategy/create")
         .json(&CreateStrategyRequest {
             title: "myStrat".to_string(),
-            content: strat,
+            content: encode_content(&strat),
         })
         .add_cookie(Cookie::new("session_id", &session_cookie))
         .await;
@@ -78,7 +81,7 @@ pub async fn test_submit_backtest() {
     let create_strat_json: Strategy = create_strat_response.json();
 
     let get_strat_response = server
-        .get("/api/strategy")
+        .post("/api/strategy")
         .json(&GetStrategyRequest {
             id: create_strat_json.id,
         })
@@ -91,6 +94,22 @@ pub async fn test_submit_backtest() {
     assert_eq!(get_strat_json.id, create_strat_json.id);
     assert_eq!(get_strat_json.title, create_strat_json.title);
 
+    // Only portfolio strategies can leave the dataset out
+    let no_dataset_response = server
+        .post("/api/backtest")
+        .json(&CreateBacktestRequest {
+            strategy_id: get_strat_json.id,
+            dataset: String::new(),
+            timeframe: "1m".to_string(),
+            date_start: DateTime::from_timestamp_secs(1546300800).unwrap(), // Tue Jan 01 2019 00:00:00 GMT+0000
+            date_end: DateTime::from_timestamp_secs(1577836800).unwrap(), // Wed Jan 01 2020 00:00:00 GMT+0000
+            execution: Default::default(),
+        })
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_status_code(&no_dataset_response, 400);
+
     // TODO: Ask for a backtest
     // TODO: Check that the backtest is defined as a job in the queue
     // TODO: Check the status ?
@@ -102,14 +121,80 @@ pub async fn test_submit_backtest() {
             timeframe: "1m".to_string(),
             date_start: DateTime::from_timestamp_secs(1546300800).unwrap(), // Tue Jan 01 2019 00:00:00 GMT+0000
             date_end: DateTime::from_timestamp_secs(1577836800).unwrap(), // Wed Jan 01 2020 00:00:00 GMT+0000
+            execution: serde_json::from_str(
+                r#"{ "taker_fee_bps": 10, "slippage": { "model": "fixed", "bps": 2 } }"#,
+            )
+            .unwrap(),
         })
         .add_cookie(Cookie::new("session_id", &session_cookie))
         .await;
 
     assert_success_response(&request_backtest_response);
-    let request_backtest_status: BacktestStatus = request_backtest_response.json();
+    let backtest: BacktestResponse = request_backtest_response.json();
+
+    assert_eq!(backtest.status, BacktestStatus::Pending);
+    assert_eq!(backtest.strategy_id, get_strat_json.id);
+    assert_eq!(backtest.parameters.dataset, "BTCUSDT");
+    assert_eq!(backtest.parameters.execution.taker_fee_bps, 10.0);
+    assert!(backtest.result_summary.is_none());
+
+    let get_backtest_response = server
+        .get(&format!("/api/backtest/{}", backtest.id))
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&get_backtest_response);
+    let get_backtest_json: BacktestResponse = get_backtest_response.json();
+    assert_eq!(get_backtest_json.id, backtest.id);
+
+    // No worker is running, so the results are not there yet.
+    let results_response = server
+        .get(&format!("/api/backtest/{}/results", backtest.id))
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
 
-    assert_eq!(request_backtest_status, BacktestStatus::Pending);
+    assert_status_code(&results_response, 202);
+    let pending: BacktestResponse = results_response.json();
+    assert_eq!(pending.id, backtest.id);
+    assert_eq!(pending.status, BacktestStatus::Pending);
+    assert!(pending.result_summary.is_none());
+
+    let list_response = server
+        .get(&format!("/api/strategy/{}/backtests", get_strat_json.id))
+        .add_query_param("page", 1)
+        .add_query_param("per_page", 10)
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&list_response);
+    let page: BacktestPage = list_response.json();
+    assert_eq!(page.total, 1);
+    assert_eq!(page.items.len(), 1);
+    assert_eq!(page.items[0].id, backtest.id);
+
+    let unknown_response = server
+        .get(&format!("/api/backtest/{}", uuid::Uuid::new_v4()))
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_status_code(&unknown_response, 404);
+
+    let cancel_response = server
+        .post(&format!("/api/backtest/{}/cancel", backtest.id))
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&cancel_response);
+    let cancelled: BacktestResponse = cancel_response.json();
+    assert_eq!(cancelled.status, BacktestStatus::Cancelled);
+
+    // Nothing left to cancel
+    let cancel_again_response = server
+        .post(&format!("/api/backtest/{}/cancel", backtest.id))
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_status_code(&cancel_again_response, 409);
 
     let logout_response = server
         .post("/api/logout")
@@ -120,3 +205,171 @@ pub async fn test_submit_backtest() {
 
     ctx.cleanup().await;
 }
+
+#[tokio::test]
+pub async fn test_cached_backtest() {
+    let ctx = TestContext::new().await;
+    let server = TestServer::new(ctx.app.clone()).unwrap();
+    let test_user = TestUser::new();
+    let other_user = TestUser {
+        username: "other".to_string(),
+        ..TestUser::new()
+    };
+    let test_strat = TestStrategy::new();
+
+    let mut session_cookies = Vec::new();
+    for user in [&test_user, &other_user] {
+        let register_response = server
+            .post("/api/register")
+            .json(&RegisterRequest {
+                username: user.username.clone(),
+                email: user.email.clone(),
+                password: user.password.clone(),
+            })
+            .await;
+
+        assert_success_response(&register_response);
+
+        let login_response = server
+            .post("/api/login")
+            .json(&LoginRequest {
+                email: user.email.clone(),
+                password: user.password.clone(),
+            })
+            .await;
+
+        assert_success_response(&login_response);
+        session_cookies.push(extract_cookie_value(&login_response, "session_id").unwrap());
+    }
+    let (session_cookie, other_session_cookie) = (&session_cookies[0], &session_cookies[1]);
+
+    // Two strategies of the user and one of the other user, all with the same content
+    let mut strats = Vec::new();
+    for (title, cookie) in [
+        ("first", session_cookie),
+        ("second", session_cookie),
+        ("other", other_session_cookie),
+    ] {
+        let create_strat_response = server
+            .post("/api/strategy/create")
+            .json(&CreateStrategyRequest {
+                title: title.to_string(),
+                content: encode_content(&test_strat.content),
+            })
+            .add_cookie(Cookie::new("session_id", cookie))
+            .await;
+
+        assert_success_response(&create_strat_response);
+        let strat: Strategy = create_strat_response.json();
+        strats.push(strat);
+    }
+
+    let backtest_request = |strategy_id| CreateBacktestRequest {
+        strategy_id,
+        dataset: "BTCUSDT".to_string(),
+        timeframe: "1m".to_string(),
+        date_start: DateTime::from_timestamp_secs(1546300800).unwrap(), // Tue Jan 01 2019 00:00:00 GMT+0000
+        date_end: DateTime::from_timestamp_secs(1577836800).unwrap(), // Wed Jan 01 2020 00:00:00 GMT+0000
+        execution: Default::default(),
+    };
+
+    let first_response = server
+        .post("/api/backtest")
+        .json(&backtest_request(strats[0].id))
+        .add_cookie(Cookie::new("session_id", session_cookie))
+        .await;
+
+    assert_success_response(&first_response);
+    let first: BacktestResponse = first_response.json();
+    assert_eq!(first.status, BacktestStatus::Pending);
+
+    // Done as a worker would, see Database::complete_backtest
+    let summary: ResultSummary = serde_json::from_str(
+        r#"{
+            "initial_capital": 10000.0, "final_equity": 11000.0, "net_return": 0.1,
+            "max_drawdown": 0.05, "trades_count": 3, "exposure_time": 0.4,
+            "buy_and_hold_return": 0.2, "total_fees": 0.0, "total_slippage": 0.0,
+            "total_spread": 0.0
+        }"#,
+    )
+    .unwrap();
+    let artifacts = ArtifactPaths {
+        trades: format!("backtests/{}/trades.json", first.id),
+        equity_curve: format!("backtests/{}/equity_curve.json", first.id),
+        positions: format!("backtests/{}/positions.json", first.id),
+    };
+    sqlx::query(
+        r#"
+        UPDATE backtests
+        SET status = 'done', result_summary = $1, trades_path = $2, equity_curve_path = $3,
+            positions_path = $4, engine_version = $5
+        WHERE id = $6
+        "#,
+    )
+    .bind(sqlx::types::Json(&summary))
+    .bind(&artifacts.trades)
+    .bind(&artifacts.equity_curve)
+    .bind(&artifacts.positions)
+    .bind(backtester::ENGINE_VERSION)
+    .bind(first.id)
+    .execute(&ctx.db_pool)
+    .await
+    .unwrap();
+
+    // The same content with the same settings reuses the results
+    let cached_response = server
+        .post("/api/backtest")
+        .json(&backtest_request(strats[1].id))
+        .add_cookie(Cookie::new("session_id", session_cookie))
+        .await;
+
+    assert_success_response(&cached_response);
+    let cached: BacktestResponse = cached_response.json();
+    assert_ne!(cached.id, first.id);
+    assert_eq!(cached.strategy_id, strats[1].id);
+    assert_eq!(cached.status, BacktestStatus::Done);
+    assert_eq!(cached.result_summary, Some(summary));
+
+    // Not with other settings
+    let other_dates_response = server
+        .post("/api/backtest")
+        .json(&CreateBacktestRequest {
+            date_end: DateTime::from_timestamp_secs(1561939200).unwrap(), // Mon Jul 01 2019 00:00:00 GMT+0000
+            ..backtest_request(strats[1].id)
+        })
+        .add_cookie(Cookie::new("session_id", session_cookie))
+        .await;
+
+    assert_success_response(&other_dates_response);
+    let other_dates: BacktestResponse = other_dates_response.json();
+    assert_eq!(other_dates.status, BacktestStatus::Pending);
+
+    // Nor for another user
+    let other_user_response = server
+        .post("/api/backtest")
+        .json(&backtest_request(strats[2].id))
+        .add_cookie(Cookie::new("session_id", other_session_cookie))
+        .await;
+
+    assert_success_response(&other_user_response);
+    let other_user_backtest: BacktestResponse = other_user_response.json();
+    assert_eq!(other_user_backtest.status, BacktestStatus::Pending);
+
+    // Nor once the dataset was reloaded with other candles
+    sqlx::query("UPDATE backtests SET dataset_version = 'BTCUSDT-1m@0' WHERE status = 'done'")
+        .execute(&ctx.db_pool)
+        .await
+        .unwrap();
+
+    let reloaded_response = server
+        .post("/api/backtest")
+        .json(&backtest_request(strats[1].id))
+        .add_cookie(Cookie::new("session_id", session_cookie))
+        .await;
+
+    assert_success_response(&reloaded_response);
+    let reloaded: BacktestResponse = reloaded_response.json();
+    assert_eq!(reloaded.status, BacktestStatus::Pending);
+
+    ctx.cleanup().await;
+}
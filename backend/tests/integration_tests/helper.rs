@@ -4,11 +4,15 @@ use backend::{
     auth::SessionStore,
     dataset_client::DatasetManagerClient,
     db::Database,
+    object_store::LocalStore,
+    s3_manager::S3Manager,
     validators::strategy_validator::{StrategyContent, StrategyValidator},
 };
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use parking_lot::RwLock;
 use redis::Client as RedisClient;
 use sqlx::PgPool;
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
 use uuid::Uuid;
 
 pub struct TestContext {
@@ -18,7 +22,7 @@ pub struct TestContext {
     pub db_pool: PgPool,
     pub redis_client: RedisClient,
     pub dataset_manager: DatasetManagerClient,
-    pub strat_validator: StrategyValidator,
+    pub strat_validator: Arc<RwLock<StrategyValidator>>,
 }
 
 impl TestContext {
@@ -54,13 +58,20 @@ impl TestContext {
         valid_indicators.insert("rsi".to_string());
         valid_indicators.insert("macd".to_string());
         valid_indicators.insert("volume".to_string());
-        let strat_validator = StrategyValidator::new(valid_indicators);
+        let strat_validator = Arc::new(RwLock::new(StrategyValidator::new(valid_indicators)));
+
+        let s3 = S3Manager::new(LocalStore::new(
+            std::env::temp_dir().join("stratmaker_test_storage"),
+            "http://localhost:3000",
+            b"test".to_vec(),
+        ));
 
         let app_state = AppState {
             db: db.clone(),
             session_store: session_store.clone(),
             dataset_manager: dataset_manager.clone(),
             strat_validator: strat_validator.clone(),
+            s3,
         };
         let app = backend::create_app(app_state);
 
@@ -160,6 +171,11 @@ impl TestStrategy {
     }
 }
 
+/// Encode a strategy the way the frontend sends it: base64 of its MessagePack representation.
+pub fn encode_content(content: &StrategyContent) -> String {
+    BASE64.encode(rmp_serde::to_vec_named(content).unwrap())
+}
+
 // Common test assertions
 pub mod assertions {
     use axum_test::TestResponse;
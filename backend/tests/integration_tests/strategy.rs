@@ -1,15 +1,21 @@
 use axum_test::TestServer;
 use backend::{
     models::{
-        CreateStrategyRequest, GetStrategyRequest, LoginRequest, RegisterRequest, Strategy,
-        StrategyResumed,
+        BacktestResponse, CreateBacktestRequest, CreateStrategyRequest, GetStrategyRequest,
+        LoginRequest, RegisterRequest, Strategy, StrategyResumed, StrategyVersion,
+        StrategyVersionResumed,
     },
     validators::strategy_validator::StrategyContent,
 };
+use backtester::{
+    diff::{Change, ChangeKind},
+    strategy::Cond,
+};
+use chrono::DateTime;
 use cookie::Cookie;
 
 use crate::helper::{
-    TestContext, TestStrategy, TestUser,
+    TestContext, TestStrategy, TestUser, encode_content,
     assertions::{
         assert_json_contains_field, assert_json_field, assert_status_code, assert_success_response,
         extract_cookie_value,
@@ -71,7 +77,7 @@ async fn test_create_strategy_success() {
         .post("/api/strategy/create")
         .json(&CreateStrategyRequest {
             title: "myStrat".to_string(),
-            content: strat,
+            content: encode_content(&strat),
         })
         .add_cookie(Cookie::new("session_id", &session_cookie))
         .await;
@@ -141,7 +147,7 @@ async fn test_get_strategies_success() {
         .post("/api/strategy/create")
         .json(&CreateStrategyRequest {
             title: test_strat1.title.clone(),
-            content: test_strat1.content.clone(),
+            content: encode_content(&test_strat1.content),
         })
         .add_cookie(Cookie::new("session_id", &session_cookie))
         .await;
@@ -152,7 +158,7 @@ async fn test_get_strategies_success() {
         .post("/api/strategy/create")
         .json(&CreateStrategyRequest {
             title: test_strat2.title.clone(),
-            content: test_strat2.content.clone(),
+            content: encode_content(&test_strat2.content),
         })
         .add_cookie(Cookie::new("session_id", &session_cookie))
         .await;
@@ -163,7 +169,7 @@ async fn test_get_strategies_success() {
         .post("/api/strategy/create")
         .json(&CreateStrategyRequest {
             title: test_strat3.title.clone(),
-            content: test_strat3.content.clone(),
+            content: encode_content(&test_strat3.content),
         })
         .add_cookie(Cookie::new("session_id", &session_cookie))
         .await;
@@ -174,7 +180,7 @@ async fn test_get_strategies_success() {
         .post("/api/strategy/create")
         .json(&CreateStrategyRequest {
             title: test_strat4.title.clone(),
-            content: test_strat4.content.clone(),
+            content: encode_content(&test_strat4.content),
         })
         .add_cookie(Cookie::new("session_id", &session_cookie))
         .await;
@@ -297,7 +303,7 @@ async fn test_create_duplicate_title_strategy() {
         .post("/api/strategy/create")
         .json(&CreateStrategyRequest {
             title: "myStrat".to_string(),
-            content: strat.clone(),
+            content: encode_content(&strat),
         })
         .add_cookie(Cookie::new("session_id", &session_cookie))
         .await;
@@ -308,7 +314,7 @@ async fn test_create_duplicate_title_strategy() {
         .post("/api/strategy/create")
         .json(&CreateStrategyRequest {
             title: "myStrat".to_string(),
-            content: strat,
+            content: encode_content(&strat),
         })
         .add_cookie(Cookie::new("session_id", &session_cookie))
         .await;
@@ -325,7 +331,373 @@ async fn test_create_duplicate_title_strategy() {
     ctx.cleanup().await;
 }
 
-/*
+#[tokio::test]
+async fn test_strategy_warnings() {
+    let ctx = TestContext::new().await;
+    let server = TestServer::new(ctx.app.clone()).unwrap();
+    let test_user = TestUser::new();
+    let test_strat = TestStrategy::new();
+
+    // 1. Register
+    let register_response = server
+        .post("/api/register")
+        .json(&RegisterRequest {
+            username: test_user.username.clone(),
+            email: test_user.email.clone(),
+            password: test_user.password.clone(),
+        })
+        .await;
+
+    assert_success_response(&register_response);
+
+    // 2. Login
+    let login_response = server
+        .post("/api/login")
+        .json(&LoginRequest {
+            email: test_user.email.clone(),
+            password: test_user.password.clone(),
+        })
+        .await;
+
+    assert_success_response(&login_response);
+    let session_cookie = extract_cookie_value(&login_response, "session_id").unwrap();
+
+    let lints = |json: &serde_json::Value| -> Vec<(String, Option<u64>)> {
+        json["warnings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|w| (w["kind"].as_str().unwrap().to_string(), w["action"].as_u64()))
+            .collect()
+    };
+
+    // Buys when the rsi is both above 70 and below 30
+    let contradiction: StrategyContent = serde_json::from_str(
+        r#"{
+            "meta": { "type": "spot" },
+            "actions": [
+                {
+                    "type": "buy",
+                    "w": 0.8,
+                    "cond": {
+                        "and": {
+                            "conds": [
+                                { "gt": { "l": "rsi", "r": 70 } },
+                                { "lt": { "l": "rsi", "r": 30 } }
+                            ]
+                        }
+                    }
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let create_strat_response = server
+        .post("/api/strategy/create")
+        .json(&CreateStrategyRequest {
+            title: test_strat.title.clone(),
+            content: encode_content(&test_strat.content),
+        })
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&create_strat_response);
+    let create_strat_json: serde_json::Value = create_strat_response.json();
+    assert_eq!(lints(&create_strat_json), []);
+
+    let create_warned_response = server
+        .post("/api/strategy/create")
+        .json(&CreateStrategyRequest {
+            title: "contradiction".to_string(),
+            content: encode_content(&contradiction),
+        })
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&create_warned_response);
+    assert_eq!(
+        lints(&create_warned_response.json()),
+        [("contradiction".to_string(), Some(0))]
+    );
+
+    // The content sent is the one validated and linted, not the saved one
+    let mut strat: Strategy = serde_json::from_value(create_strat_json).unwrap();
+    let mut invalid = strat.clone();
+    invalid.content.actions[0].cond = serde_json::from_str(r#"{ "gt": { "l": "foo", "r": 1 } }"#)
+        .unwrap();
+    let modify_invalid_response = server
+        .post("/api/strategy/modify")
+        .json(&invalid)
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_status_code(&modify_invalid_response, 400);
+
+    strat.content.actions[0].cond = contradiction.actions[0].cond.clone();
+    let modify_response = server
+        .post("/api/strategy/modify")
+        .json(&strat)
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&modify_response);
+    assert!(
+        lints(&modify_response.json()).contains(&("contradiction".to_string(), Some(0)))
+    );
+
+    let logout_response = server
+        .post("/api/logout")
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&logout_response);
+
+    ctx.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_duplicate_content_warning() {
+    let ctx = TestContext::new().await;
+    let server = TestServer::new(ctx.app.clone()).unwrap();
+    let test_user = TestUser::new();
+    let test_strat = TestStrategy::new();
+
+    // 1. Register
+    let register_response = server
+        .post("/api/register")
+        .json(&RegisterRequest {
+            username: test_user.username.clone(),
+            email: test_user.email.clone(),
+            password: test_user.password.clone(),
+        })
+        .await;
+
+    assert_success_response(&register_response);
+
+    // 2. Login
+    let login_response = server
+        .post("/api/login")
+        .json(&LoginRequest {
+            email: test_user.email.clone(),
+            password: test_user.password.clone(),
+        })
+        .await;
+
+    assert_success_response(&login_response);
+    let session_cookie = extract_cookie_value(&login_response, "session_id").unwrap();
+
+    let duplicates = |json: &serde_json::Value| -> Vec<String> {
+        json["warnings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|w| w["kind"] == "duplicate_strategy")
+            .map(|w| w["message"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let create_strat1_response = server
+        .post("/api/strategy/create")
+        .json(&CreateStrategyRequest {
+            title: "first".to_string(),
+            content: encode_content(&test_strat.content),
+        })
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&create_strat1_response);
+    assert!(duplicates(&create_strat1_response.json()).is_empty());
+
+    // Written another way, it does the same
+    let mut same_content = test_strat.content.clone();
+    same_content.definitions.insert(
+        "trend".to_string(),
+        same_content.actions[0].cond.clone(),
+    );
+    same_content.actions[0].cond = Cond::Ref("trend".to_string());
+
+    let create_strat2_response = server
+        .post("/api/strategy/create")
+        .json(&CreateStrategyRequest {
+            title: "second".to_string(),
+            content: encode_content(&same_content),
+        })
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&create_strat2_response);
+    let create_strat2_json: serde_json::Value = create_strat2_response.json();
+    assert_eq!(
+        duplicates(&create_strat2_json),
+        ["same content as your strategy 'first'"]
+    );
+
+    // A strategy is no duplicate of itself
+    let mut strat2: Strategy = serde_json::from_value(create_strat2_json).unwrap();
+    strat2.title = "second renamed".to_string();
+    let modify_response = server
+        .post("/api/strategy/modify")
+        .json(&strat2)
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&modify_response);
+    assert_eq!(
+        duplicates(&modify_response.json()),
+        ["same content as your strategy 'first'"]
+    );
+
+    let logout_response = server
+        .post("/api/logout")
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&logout_response);
+
+    ctx.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_strategy_versions() {
+    let ctx = TestContext::new().await;
+    let server = TestServer::new(ctx.app.clone()).unwrap();
+    let test_user = TestUser::new();
+    let test_strat = TestStrategy::new();
+
+    // 1. Register
+    let register_response = server
+        .post("/api/register")
+        .json(&RegisterRequest {
+            username: test_user.username.clone(),
+            email: test_user.email.clone(),
+            password: test_user.password.clone(),
+        })
+        .await;
+
+    assert_success_response(&register_response);
+
+    // 2. Login
+    let login_response = server
+        .post("/api/login")
+        .json(&LoginRequest {
+            email: test_user.email.clone(),
+            password: test_user.password.clone(),
+        })
+        .await;
+
+    assert_success_response(&login_response);
+    let session_cookie = extract_cookie_value(&login_response, "session_id").unwrap();
+
+    let create_strat_response = server
+        .post("/api/strategy/create")
+        .json(&CreateStrategyRequest {
+            title: test_strat.title.clone(),
+            content: encode_content(&test_strat.content),
+        })
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&create_strat_response);
+    let mut strat: Strategy = create_strat_response.json();
+    assert_eq!(strat.version, 1);
+
+    // Each modification is a new version
+    strat.content.actions[0].w = 0.5;
+    let modify_response = server
+        .post("/api/strategy/modify")
+        .json(&strat)
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&modify_response);
+    let modify_json: serde_json::Value = modify_response.json();
+    assert_json_field(&modify_json, "message", "sucessfuly modified strategy");
+    assert_eq!(modify_json["version"], 2);
+    assert_eq!(modify_json["num_del"], 1);
+
+    let versions_response = server
+        .get(&format!("/api/strategy/{}/versions", strat.id))
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&versions_response);
+    let versions: Vec<StrategyVersionResumed> = versions_response.json();
+    let numbers: Vec<i32> = versions.iter().map(|v| v.version).collect();
+    assert_eq!(numbers.len(), 2);
+    assert!(numbers.contains(&1) && numbers.contains(&2));
+
+    let version_response = server
+        .get(&format!("/api/strategy/{}/versions/1", strat.id))
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&version_response);
+    let version: StrategyVersion = version_response.json();
+    assert_eq!(version.content.actions[0].w, 0.8);
+
+    let unknown_version_response = server
+        .get(&format!("/api/strategy/{}/versions/42", strat.id))
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_status_code(&unknown_version_response, 404);
+
+    let diff_response = server
+        .get(&format!("/api/strategy/{}/diff", strat.id))
+        .add_query_param("from", 1)
+        .add_query_param("to", 2)
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&diff_response);
+    let changes: Vec<Change> = diff_response.json();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].path, "actions[0].w");
+    assert_eq!(changes[0].kind, ChangeKind::Modified);
+    assert_eq!(changes[0].before.as_deref(), Some("0.8"));
+    assert_eq!(changes[0].after.as_deref(), Some("0.5"));
+
+    // Restoring writes the old content as a new version, the history is kept
+    let restore_response = server
+        .post(&format!("/api/strategy/{}/versions/1/restore", strat.id))
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&restore_response);
+    let restore_json: serde_json::Value = restore_response.json();
+    assert_eq!(restore_json["version"], 3);
+
+    let get_strat_response = server
+        .post("/api/strategy")
+        .json(&GetStrategyRequest { id: strat.id })
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&get_strat_response);
+    let restored: Strategy = get_strat_response.json();
+    assert_eq!(restored.version, 3);
+    assert_eq!(restored.content.actions[0].w, 0.8);
+
+    let versions_response = server
+        .get(&format!("/api/strategy/{}/versions", strat.id))
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&versions_response);
+    let versions: Vec<StrategyVersionResumed> = versions_response.json();
+    assert_eq!(versions.len(), 3);
+
+    let logout_response = server
+        .post("/api/logout")
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&logout_response);
+
+    ctx.cleanup().await;
+}
+
 #[tokio::test]
 async fn test_delete_strategy() {
     let ctx = TestContext::new().await;
@@ -361,15 +733,42 @@ async fn test_delete_strategy() {
         .post("/api/strategy/create")
         .json(&CreateStrategyRequest {
             title: test_strat.title.clone(),
-            content: test_strat.content.clone(),
+            content: encode_content(&test_strat.content),
         })
         .add_cookie(Cookie::new("session_id", &session_cookie))
         .await;
 
     assert_success_response(&create_strat_response);
+    let mut created_strat: Strategy = create_strat_response.json();
 
-    let created_strat: Strategy = create_strat_response.json();
+    created_strat.content.actions[0].w = 0.5;
+    let modify_response = server
+        .post("/api/strategy/modify")
+        .json(&created_strat)
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&modify_response);
+
+    // The backtest runs the latest version
+    let request_backtest_response = server
+        .post("/api/backtest")
+        .json(&CreateBacktestRequest {
+            strategy_id: created_strat.id,
+            dataset: "BTCUSDT".to_string(),
+            timeframe: "1m".to_string(),
+            date_start: DateTime::from_timestamp_secs(1546300800).unwrap(), // Tue Jan 01 2019 00:00:00 GMT+0000
+            date_end: DateTime::from_timestamp_secs(1577836800).unwrap(), // Wed Jan 01 2020 00:00:00 GMT+0000
+            execution: Default::default(),
+        })
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
 
+    assert_success_response(&request_backtest_response);
+    let backtest: BacktestResponse = request_backtest_response.json();
+    assert_eq!(backtest.strategy_version, Some(2));
+
+    // The versions and the backtests referencing them go with the strategy
     let delete_strat_response = server
         .post("/api/strategy/delete")
         .json(&created_strat.id)
@@ -378,11 +777,43 @@ async fn test_delete_strategy() {
 
     assert_success_response(&delete_strat_response);
 
-    let delete_json = &delete_strat_response.json();
+    let delete_json: serde_json::Value = delete_strat_response.json();
+
+    assert_json_contains_field(&delete_json, "num_del");
+    assert_eq!(delete_json["num_del"], 1);
 
-    assert_json_contains_field(delete_json, "num_del");
-    assert_json_field(delete_json, "num_del", "1");
+    let get_strat_response = server
+        .post("/api/strategy")
+        .json(&GetStrategyRequest {
+            id: created_strat.id,
+        })
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_status_code(&get_strat_response, 404);
+
+    let (versions_left,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM strategy_versions WHERE strategy_id = $1")
+            .bind(created_strat.id)
+            .fetch_one(&ctx.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(versions_left, 0);
+
+    let (backtests_left,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM backtests WHERE strategy_id = $1")
+            .bind(created_strat.id)
+            .fetch_one(&ctx.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(backtests_left, 0);
+
+    let logout_response = server
+        .post("/api/logout")
+        .add_cookie(Cookie::new("session_id", &session_cookie))
+        .await;
+
+    assert_success_response(&logout_response);
 
     ctx.cleanup().await;
 }
-*/
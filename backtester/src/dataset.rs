@@ -0,0 +1,225 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::error::BacktestError;
+
+pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Columns every dataset has, on top of its indicators.
+pub const PRICE_COLUMNS: [&str; 5] = ["open", "high", "low", "close", "volume"];
+
+/// Size of the fixed part of a record: u64 timestamp + open, high, low, close, volume as f32.
+pub const BASE_RECORD_SIZE: usize = 8 + 5 * 4;
+
+/// Candle series loaded from a dataset-manager `.bin` file.
+///
+/// The file is a flat array of little-endian records, one per bar, sorted by timestamp:
+/// `u64 timestamp (seconds) | f32 open | f32 high | f32 low | f32 close | f32 volume`,
+/// followed by one `f32` per precomputed indicator, in the order of the `ta` list of the
+/// dataset's `.meta.json`.
+///
+/// Values are stored column-wise so a condition can look at any bar (e.g. the previous one for
+/// crosses) without keeping extra state around.
+#[derive(Debug, Clone, Default)]
+pub struct Candles {
+    pub timestamps: Vec<i64>,
+    pub open: Vec<f64>,
+    pub high: Vec<f64>,
+    pub low: Vec<f64>,
+    pub close: Vec<f64>,
+    pub volume: Vec<f64>,
+    pub indicators: HashMap<String, Vec<f64>>,
+    /// Bars of the same asset on other timeframes, keyed by timeframe (`4h`), for the strategies
+    /// reading indicators on them.
+    pub timeframes: HashMap<String, Candles>,
+}
+
+impl Candles {
+    pub fn load(path: impl AsRef<Path>, ta: &[String]) -> Result<Self, BacktestError> {
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes, ta)
+    }
+
+    pub fn from_bytes(bytes: &[u8], ta: &[String]) -> Result<Self, BacktestError> {
+        let record_size = BASE_RECORD_SIZE + 4 * ta.len();
+        if !bytes.len().is_multiple_of(record_size) {
+            return Err(BacktestError::InvalidDataset(format!(
+                "file size {} is not a multiple of the record size {}",
+                bytes.len(),
+                record_size
+            )));
+        }
+
+        let count = bytes.len() / record_size;
+        let mut candles = Self {
+            timestamps: Vec::with_capacity(count),
+            open: Vec::with_capacity(count),
+            high: Vec::with_capacity(count),
+            low: Vec::with_capacity(count),
+            close: Vec::with_capacity(count),
+            volume: Vec::with_capacity(count),
+            indicators: ta
+                .iter()
+                .map(|name| (name.clone(), Vec::with_capacity(count)))
+                .collect(),
+            timeframes: HashMap::new(),
+        };
+
+        for record in bytes.chunks_exact(record_size) {
+            let ts = u64::from_le_bytes(record[0..8].try_into().unwrap());
+            candles.timestamps.push(ts as i64);
+            candles.open.push(read_f32(record, 0));
+            candles.high.push(read_f32(record, 1));
+            candles.low.push(read_f32(record, 2));
+            candles.close.push(read_f32(record, 3));
+            candles.volume.push(read_f32(record, 4));
+
+            for (i, name) in ta.iter().enumerate() {
+                // Unwrap is fine, every name was inserted above.
+                candles
+                    .indicators
+                    .get_mut(name)
+                    .unwrap()
+                    .push(read_f32(record, 5 + i));
+            }
+        }
+
+        if candles.timestamps.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(BacktestError::InvalidDataset(
+                "timestamps must be strictly increasing".to_string(),
+            ));
+        }
+
+        Ok(candles)
+    }
+
+    pub fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+
+    /// Get a column by the name a strategy would use to reference it.
+    pub fn column(&self, name: &str) -> Option<&[f64]> {
+        match name {
+            "open" => Some(&self.open),
+            "high" => Some(&self.high),
+            "low" => Some(&self.low),
+            "close" => Some(&self.close),
+            "volume" => Some(&self.volume),
+            _ => self.indicators.get(name).map(|v| v.as_slice()),
+        }
+    }
+
+    /// Bars of another timeframe, with the length of a bar in seconds.
+    pub fn timeframe(&self, timeframe: &str) -> Result<(&Candles, i64), BacktestError> {
+        let unknown = || BacktestError::UnknownTimeframe(timeframe.to_string());
+        let seconds = timeframe_seconds(timeframe).ok_or_else(unknown)?;
+        Ok((self.timeframes.get(timeframe).ok_or_else(unknown)?, seconds))
+    }
+
+    /// Length of a bar in seconds, taken as the shortest interval between two bars since the
+    /// series can have holes.
+    pub fn bar_seconds(&self) -> i64 {
+        self.timestamps
+            .windows(2)
+            .map(|w| w[1] - w[0])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// For each bar, the index of the last bar of `other` (bars of `seconds`) that closed by the
+    /// time this bar closes, so a bar of another timeframe or asset is only seen once it is
+    /// complete. Timestamps are the bars' open times.
+    pub fn align(&self, other: &Candles, seconds: i64) -> Vec<Option<usize>> {
+        let length = self.bar_seconds();
+        let closed = |j: usize| other.timestamps[j] + seconds;
+
+        let mut j = 0;
+        self.timestamps
+            .iter()
+            .map(|ts| {
+                let close = ts + length;
+                while j < other.len() && closed(j) <= close {
+                    j += 1;
+                }
+                j.checked_sub(1)
+            })
+            .collect()
+    }
+
+    /// Index range of the bars whose timestamp is within `[start, end]`.
+    pub fn range(&self, start: i64, end: i64) -> std::ops::Range<usize> {
+        let lo = self.timestamps.partition_point(|&t| t < start);
+        let hi = self.timestamps.partition_point(|&t| t <= end);
+        lo..hi.max(lo)
+    }
+}
+
+/// Length in seconds of a timeframe such as `15m`, `4h`, `1d` or `1w`.
+pub fn timeframe_seconds(timeframe: &str) -> Option<i64> {
+    let unit = match timeframe.chars().last()? {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => SECONDS_PER_DAY,
+        'w' => 7 * SECONDS_PER_DAY,
+        _ => return None,
+    };
+    let count: i64 = timeframe[..timeframe.len() - 1].parse().ok()?;
+    (count > 0).then_some(count * unit)
+}
+
+fn read_f32(record: &[u8], field: usize) -> f64 {
+    let offset = 8 + field * 4;
+    f32::from_le_bytes(record[offset..offset + 4].try_into().unwrap()) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_bytes() {
+        let ta = vec!["sma".to_string()];
+        let mut bytes = Vec::new();
+        for (ts, close) in [(60u64, 1.5f32), (120, 2.5)] {
+            bytes.extend_from_slice(&ts.to_le_bytes());
+            for v in [close, close + 1.0, close - 1.0, close, 100.0, close * 2.0] {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+
+        let candles = Candles::from_bytes(&bytes, &ta).unwrap();
+        assert_eq!(candles.timestamps, vec![60, 120]);
+        assert_eq!(candles.high, vec![2.5, 3.5]);
+        assert_eq!(candles.column("sma"), Some(&[3.0, 5.0][..]));
+        assert_eq!(candles.range(100, 1000), 1..2);
+
+        assert!(Candles::from_bytes(&bytes[1..], &ta).is_err());
+    }
+
+    #[test]
+    fn test_align() {
+        let hourly = Candles {
+            timestamps: (0..6).map(|h| h * 3600).collect(),
+            ..Default::default()
+        };
+        let four_hours = Candles {
+            timestamps: vec![0, 4 * 3600],
+            ..Default::default()
+        };
+
+        // The first 4h bar closes with the 4th hourly bar, the second one is never complete.
+        assert_eq!(
+            hourly.align(&four_hours, timeframe_seconds("4h").unwrap()),
+            vec![None, None, None, Some(0), Some(0), Some(0)]
+        );
+
+        assert_eq!(timeframe_seconds("15m"), Some(900));
+        assert_eq!(timeframe_seconds("1w"), Some(604_800));
+        assert_eq!(timeframe_seconds("0h"), None);
+        assert_eq!(timeframe_seconds("1M"), None);
+        assert_eq!(timeframe_seconds("h"), None);
+    }
+}
@@ -0,0 +1,306 @@
+//! Structural diff between two versions of a strategy.
+//!
+//! What changed is decided on the canonical forms (see `StrategyContent::canonical`), so only the
+//! changes to what the strategy does are reported, not the ones to how it is written: reordering
+//! the children of an `and` or moving a condition into a definition is no change. The changes
+//! themselves are shown as the versions were written. Conditions are compared node by node, a
+//! reference to a definition standing for the condition it names, the rest of the strategy
+//! field by field.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    strategy::{Action, Cond, StrategyContent},
+    text::{format_action, format_cond},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// Where the change is: a section (`meta`, `risk`, `portfolio`), an action (`actions[1]`),
+    /// a field of an action (`actions[1].w`) or a node of its condition
+    /// (`actions[0].cond.and[1]`). Indices are the ones of the new version, except for removals.
+    pub path: String,
+    pub kind: ChangeKind,
+    /// The old version, in the text form for actions and conditions (see `text`) and as json
+    /// otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+}
+
+impl Change {
+    fn new(path: String, before: Option<String>, after: Option<String>) -> Self {
+        let kind = match (&before, &after) {
+            (None, _) => ChangeKind::Added,
+            (_, None) => ChangeKind::Removed,
+            _ => ChangeKind::Modified,
+        };
+        Self {
+            path,
+            kind,
+            before,
+            after,
+        }
+    }
+}
+
+/// The changes from `old` to `new`, empty if they do the same.
+pub fn diff(old: &StrategyContent, new: &StrategyContent) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    let sections = [
+        ("meta", Some(json(&old.meta)), Some(json(&new.meta))),
+        (
+            "risk",
+            old.risk.as_ref().map(json),
+            new.risk.as_ref().map(json),
+        ),
+        (
+            "portfolio",
+            old.portfolio.as_ref().map(json),
+            new.portfolio.as_ref().map(json),
+        ),
+    ];
+    for (name, before, after) in sections {
+        if before != after {
+            changes.push(Change::new(name.to_string(), before, after));
+        }
+    }
+
+    let (old_canonical, new_canonical) = (old.canonical(), new.canonical());
+    for i in 0..old.actions.len().max(new.actions.len()) {
+        let path = format!("actions[{}]", i);
+        match (old.actions.get(i), new.actions.get(i)) {
+            (Some(before), Some(after)) => {
+                // Order prices are compared in their canonical form too.
+                let (before_canonical, after_canonical) = (
+                    fields(&old_canonical.actions[i]),
+                    fields(&new_canonical.actions[i]),
+                );
+                let (before_fields, after_fields) = (fields(before), fields(after));
+                let mut keys: Vec<&String> =
+                    before_fields.keys().chain(after_fields.keys()).collect();
+                keys.sort();
+                keys.dedup();
+                for key in keys {
+                    if before_canonical.get(key) != after_canonical.get(key) {
+                        changes.push(Change::new(
+                            format!("{}.{}", path, key),
+                            before_fields.get(key).map(json),
+                            after_fields.get(key).map(json),
+                        ));
+                    }
+                }
+                let versions = Versions { old, new };
+                versions.diff_cond(
+                    format!("{}.cond", path),
+                    &before.cond,
+                    &after.cond,
+                    &mut changes,
+                );
+            }
+            (before, after) => changes.push(Change::new(
+                path,
+                before.map(format_action),
+                after.map(format_action),
+            )),
+        }
+    }
+
+    changes
+}
+
+/// The fields of an action but its condition.
+fn fields(action: &Action) -> serde_json::Map<String, serde_json::Value> {
+    match serde_json::to_value(action) {
+        Ok(serde_json::Value::Object(mut fields)) => {
+            fields.remove("cond");
+            fields
+        }
+        _ => unreachable!("actions serialize to objects"),
+    }
+}
+
+/// The two versions compared, for the definitions their conditions reference.
+struct Versions<'a> {
+    old: &'a StrategyContent,
+    new: &'a StrategyContent,
+}
+
+impl Versions<'_> {
+    /// The changes from `old` to `new`, two conditions as written. Children of `and` and `or`
+    /// doing the same in both are unchanged, the others are paired in order and compared in turn,
+    /// the rest being added or removed.
+    fn diff_cond(&self, path: String, old: &Cond, new: &Cond, changes: &mut Vec<Change>) {
+        let (old_key, new_key) = (key(self.old, old), key(self.new, new));
+        if old_key.is_some() && old_key == new_key {
+            return;
+        }
+        // Look through the references, a definition that changed is reported where it changed.
+        // Both expanded, so there is no cycle to follow.
+        if old_key.is_some() && new_key.is_some() {
+            if let Cond::Ref(name) = old
+                && let Some(definition) = self.old.definitions.get(name)
+            {
+                return self.diff_cond(path, definition, new, changes);
+            }
+            if let Cond::Ref(name) = new
+                && let Some(definition) = self.new.definitions.get(name)
+            {
+                return self.diff_cond(path, old, definition, changes);
+            }
+        }
+
+        match (old, new) {
+            (Cond::And { conds: before }, Cond::And { conds: after })
+            | (Cond::Or { conds: before }, Cond::Or { conds: after }) => {
+                let op = if matches!(old, Cond::And { .. }) {
+                    "and"
+                } else {
+                    "or"
+                };
+                let before_keys: Vec<_> = before.iter().map(|c| key(self.old, c)).collect();
+                let after_keys: Vec<_> = after.iter().map(|c| key(self.new, c)).collect();
+                let unmatched = |keys: &[Option<Cond>], others: &[Option<Cond>]| -> Vec<usize> {
+                    (0..keys.len())
+                        .filter(|&i| keys[i].is_none() || !others.contains(&keys[i]))
+                        .collect()
+                };
+                let removed = unmatched(&before_keys, &after_keys);
+                let added = unmatched(&after_keys, &before_keys);
+                for (&i, &j) in removed.iter().zip(&added) {
+                    self.diff_cond(
+                        format!("{}.{}[{}]", path, op, j),
+                        &before[i],
+                        &after[j],
+                        changes,
+                    );
+                }
+                let paired = removed.len().min(added.len());
+                for &i in &removed[paired..] {
+                    changes.push(Change::new(
+                        format!("{}.{}[{}]", path, op, i),
+                        Some(format_cond(&before[i])),
+                        None,
+                    ));
+                }
+                for &j in &added[paired..] {
+                    changes.push(Change::new(
+                        format!("{}.{}[{}]", path, op, j),
+                        None,
+                        Some(format_cond(&after[j])),
+                    ));
+                }
+            }
+            (Cond::Not { cond: before }, Cond::Not { cond: after }) => {
+                self.diff_cond(format!("{}.not", path), before, after, changes)
+            }
+            _ => changes.push(Change::new(
+                path,
+                Some(format_cond(old)),
+                Some(format_cond(new)),
+            )),
+        }
+    }
+}
+
+/// What `cond` does: its canonical form once expanded, `None` if it cannot be expanded.
+fn key(strategy: &StrategyContent, cond: &Cond) -> Option<Cond> {
+    strategy.expand(cond).ok().map(|cond| cond.canonical())
+}
+
+fn json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("strategies serialize to json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::parse;
+
+    fn changes(old: &str, new: &str) -> Vec<Change> {
+        diff(&parse(old).unwrap(), &parse(new).unwrap())
+    }
+
+    #[test]
+    fn test_same() {
+        let old = "type spot\n\nbuy 0.8 when sma_10 > sma_50 and rsi < 30\n";
+        let new = "type spot\n\nbuy 0.8 when oversold and sma_50 < sma_10\n\n\
+                   define oversold = rsi < 30\n";
+        assert_eq!(changes(old, new), []);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = "type spot\n\n\
+                   buy 0.8 when sma_10 > sma_50 and rsi < 30 and volume > 100\n\
+                   sell 1 when rsi > 70\n";
+        let new = "type spot\nrisk {\"stop_loss\": {\"pct\": 5.0}}\n\n\
+                   buy 0.5 when sma_10 > sma_50 and rsi < 25\n";
+        let change = |path: &str, kind, before: Option<&str>, after: Option<&str>| Change {
+            path: path.to_string(),
+            kind,
+            before: before.map(str::to_string),
+            after: after.map(str::to_string),
+        };
+        assert_eq!(
+            changes(old, new),
+            [
+                change(
+                    "risk",
+                    ChangeKind::Added,
+                    None,
+                    Some(r#"{"stop_loss":{"pct":5.0}}"#)
+                ),
+                change(
+                    "actions[0].w",
+                    ChangeKind::Modified,
+                    Some("0.8"),
+                    Some("0.5")
+                ),
+                change(
+                    "actions[0].cond.and[1]",
+                    ChangeKind::Modified,
+                    Some("rsi < 30"),
+                    Some("rsi < 25")
+                ),
+                change(
+                    "actions[0].cond.and[2]",
+                    ChangeKind::Removed,
+                    Some("volume > 100"),
+                    None
+                ),
+                change(
+                    "actions[1]",
+                    ChangeKind::Removed,
+                    Some("sell 1 when rsi > 70"),
+                    None
+                ),
+            ]
+        );
+
+        // A definition that changed shows up in the actions using it.
+        let old = "type spot\n\nbuy 0.8 when oversold and sma_10 > sma_50\n\n\
+                   define oversold = rsi < 30\n";
+        let new = "type spot\n\nbuy 0.8 when oversold and sma_10 > sma_50\n\n\
+                   define oversold = rsi <= 30\n";
+        assert_eq!(
+            changes(old, new),
+            [change(
+                "actions[0].cond.and[0]",
+                ChangeKind::Modified,
+                Some("rsi < 30"),
+                Some("rsi <= 30")
+            )]
+        );
+    }
+}
@@ -0,0 +1,1267 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    dataset::Candles,
+    error::BacktestError,
+    eval::{CompiledCond, Context, Operand},
+    execution::{ExecutionConfig, FillCosts},
+    options,
+    order::{CompiledOrder, Liquidity, PendingOrder},
+    perp,
+    report::{
+        AssetAttribution, BacktestReport, EquityPoint, ExitReason, PositionPoint, RunStats, Side,
+        Summary, Trade,
+    },
+    risk::{self, CompiledRisk, Protection},
+    sizing::{self, ClosedTrades, Stake},
+    strategy::{Order, Sizing, StrategyContent, StrategyType, Value},
+};
+
+pub const DEFAULT_INITIAL_CAPITAL: f64 = 10_000.0;
+
+/// Number of bars processed between two checks of the cancellation flag.
+pub(crate) const CANCEL_CHECK_INTERVAL: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct BacktestConfig {
+    pub initial_capital: f64,
+    pub date_start: DateTime<Utc>,
+    pub date_end: DateTime<Utc>,
+    pub execution: ExecutionConfig,
+}
+
+impl BacktestConfig {
+    pub fn new(date_start: DateTime<Utc>, date_end: DateTime<Utc>) -> Self {
+        Self {
+            initial_capital: DEFAULT_INITIAL_CAPITAL,
+            date_start,
+            date_end,
+            execution: ExecutionConfig::default(),
+        }
+    }
+
+    pub fn with_execution(mut self, execution: ExecutionConfig) -> Self {
+        self.execution = execution;
+        self
+    }
+}
+
+struct CompiledAction<'a> {
+    side: Side,
+    w: f64,
+    /// `None` for sells.
+    sizing: Option<Sizing>,
+    cond: CompiledCond<'a>,
+    order: CompiledOrder<'a>,
+    tif: u32,
+}
+
+#[derive(Debug)]
+struct Portfolio {
+    cash: f64,
+    quantity: f64,
+    avg_entry: f64,
+    /// Timestamp of the buy that opened the current position.
+    opened_at: Option<i64>,
+    /// Number of buys the current position was built from.
+    entries: u32,
+}
+
+impl Portfolio {
+    fn equity(&self, price: f64) -> f64 {
+        self.cash + self.quantity * price
+    }
+
+    /// Unrealized pnl at `price` in percent of the cost of the position, fees included.
+    fn pnl_pct(&self, price: f64) -> f64 {
+        (price / self.avg_entry - 1.0) * 100.0
+    }
+
+    fn position(&self, timestamp: i64) -> PositionPoint {
+        PositionPoint {
+            timestamp,
+            quantity: self.quantity,
+            avg_entry: self.avg_entry,
+            cash: self.cash,
+            asset: None,
+        }
+    }
+
+    /// `buy` spends `w` of the available cash (fees included), `sell` sells `w` of the held
+    /// quantity.
+    ///
+    /// Slippage and spread move the fill price away from `price`, the fee is charged on top of
+    /// the notional. The average entry price includes the fees paid to enter, so the pnl of a
+    /// sell is net of every cost.
+    fn fill(
+        &mut self,
+        side: Side,
+        w: f64,
+        price: f64,
+        timestamp: i64,
+        costs: FillCosts,
+    ) -> Option<Trade> {
+        if !price.is_finite() || price <= 0.0 {
+            return None;
+        }
+        let markup = costs.slippage + costs.half_spread;
+
+        match side {
+            Side::Buy => {
+                let fill_price = price * (1.0 + markup);
+                let amount = self.cash * w;
+                let quantity = amount / (fill_price * (1.0 + costs.fee_rate));
+                if quantity <= 0.0 {
+                    return None;
+                }
+                self.avg_entry =
+                    (self.avg_entry * self.quantity + amount) / (self.quantity + quantity);
+                self.quantity += quantity;
+                self.cash -= amount;
+                self.opened_at.get_or_insert(timestamp);
+                self.entries += 1;
+
+                Some(Trade {
+                    timestamp,
+                    side,
+                    price: fill_price,
+                    quantity,
+                    pnl: None,
+                    holding_time: None,
+                    exit_reason: None,
+                    asset: None,
+                    option: None,
+                    fee: quantity * fill_price * costs.fee_rate,
+                    slippage: quantity * price * costs.slippage,
+                    spread: quantity * price * costs.half_spread,
+                })
+            }
+            Side::Sell => {
+                let fill_price = price * (1.0 - markup).max(0.0);
+                let quantity = self.quantity * w;
+                if quantity <= 0.0 {
+                    return None;
+                }
+                let fee = quantity * fill_price * costs.fee_rate;
+                let proceeds = quantity * fill_price - fee;
+                let pnl = proceeds - quantity * self.avg_entry;
+                let holding_time = self.opened_at.map(|t| timestamp - t);
+                self.quantity -= quantity;
+                self.cash += proceeds;
+                if self.quantity <= f64::EPSILON {
+                    self.quantity = 0.0;
+                    self.avg_entry = 0.0;
+                    self.opened_at = None;
+                    self.entries = 0;
+                }
+
+                Some(Trade {
+                    timestamp,
+                    side,
+                    price: fill_price,
+                    quantity,
+                    pnl: Some(pnl),
+                    holding_time,
+                    exit_reason: Some(ExitReason::Signal),
+                    asset: None,
+                    option: None,
+                    fee,
+                    slippage: quantity * price * costs.slippage,
+                    spread: quantity * price * costs.half_spread,
+                })
+            }
+        }
+    }
+}
+
+/// Trading state of one asset: its share of the capital and its position, its pending orders and
+/// the state of its risk rules. A single asset backtest is one sleeve holding all the capital.
+struct Sleeve<'a> {
+    candles: &'a Candles,
+    actions: Vec<CompiledAction<'a>>,
+    risk: CompiledRisk<'a>,
+    max_entries: Option<u32>,
+    portfolio: Portfolio,
+    /// Capital given to the sleeve, net of the cash moved by rebalancing.
+    funding: f64,
+    /// Close of the last bar processed, the position is marked at it.
+    mark: f64,
+    pending: Vec<PendingOrder>,
+    closed: ClosedTrades,
+    protection: Option<Protection<'a>>,
+    current_day: Option<i64>,
+    day_start_equity: f64,
+    halted_day: Option<i64>,
+    exposed_bars: usize,
+    trades: Vec<Trade>,
+    positions: Vec<PositionPoint>,
+}
+
+impl<'a> Sleeve<'a> {
+    /// Compile the actions of `strategy` trading `asset`, every action if `None`.
+    fn compile(
+        strategy: &StrategyContent,
+        asset: Option<&str>,
+        candles: &'a Candles,
+        capital: f64,
+    ) -> Result<Self, BacktestError> {
+        let actions = strategy
+            .actions
+            .iter()
+            .filter(|action| {
+                asset.is_none() || action.asset.is_none() || action.asset.as_deref() == asset
+            })
+            .map(|action| {
+                let side = match action.action_type.as_str() {
+                    "buy" => Side::Buy,
+                    "sell" => Side::Sell,
+                    other => return Err(BacktestError::InvalidActionType(other.to_string())),
+                };
+                let sizing = match side {
+                    Side::Buy => Some(
+                        action
+                            .sizing
+                            .clone()
+                            .or_else(|| strategy.meta.sizing.clone())
+                            .unwrap_or_default(),
+                    ),
+                    Side::Sell => None,
+                };
+                Ok(CompiledAction {
+                    side,
+                    w: action.w,
+                    sizing,
+                    cond: CompiledCond::compile(&strategy.expand(&action.cond)?, candles)?,
+                    order: CompiledOrder::compile(action.order.as_ref(), candles)?,
+                    tif: action
+                        .order
+                        .as_ref()
+                        .map_or(Order::DEFAULT_TIF, |o| o.tif()),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            candles,
+            actions,
+            risk: CompiledRisk::compile(strategy.risk.as_ref(), candles)?,
+            max_entries: strategy.meta.max_entries,
+            portfolio: Portfolio {
+                cash: capital,
+                quantity: 0.0,
+                avg_entry: 0.0,
+                opened_at: None,
+                entries: 0,
+            },
+            funding: capital,
+            mark: 0.0,
+            pending: Vec::new(),
+            closed: ClosedTrades::default(),
+            protection: None,
+            current_day: None,
+            day_start_equity: capital,
+            halted_day: None,
+            exposed_bars: 0,
+            trades: Vec::new(),
+            positions: Vec::new(),
+        })
+    }
+
+    fn equity(&self) -> f64 {
+        self.portfolio.equity(self.mark)
+    }
+
+    /// Fill the pending orders on `bar`, enforce the risk rules, then send the orders of the
+    /// actions whose condition is met on its close.
+    fn step(&mut self, bar: usize, execution: &ExecutionConfig) {
+        let candles = self.candles;
+        let timestamp = candles.timestamps[bar];
+        let (open, high, low) = (candles.open[bar], candles.high[bar], candles.low[bar]);
+
+        let today = risk::day(timestamp);
+        if self.current_day != Some(today) {
+            self.current_day = Some(today);
+            self.day_start_equity = self.portfolio.equity(open);
+        }
+        if self.halted_day == Some(today) {
+            self.pending.retain(|order| order.side != Side::Buy);
+        }
+
+        let mut waiting = Vec::with_capacity(self.pending.len());
+        for mut order in std::mem::take(&mut self.pending) {
+            if order.side == Side::Buy
+                && self
+                    .max_entries
+                    .is_some_and(|max| self.portfolio.entries >= max)
+            {
+                continue;
+            }
+            match order.try_fill(open, high, low) {
+                Some((price, liquidity)) => {
+                    let costs = match liquidity {
+                        Liquidity::Maker => execution.maker_costs(),
+                        Liquidity::Taker => execution.taker_costs(order.volatility),
+                    };
+                    let portfolio = &mut self.portfolio;
+                    let w = order.stake.weight(portfolio.cash, portfolio.equity(price));
+                    if let Some(trade) = portfolio.fill(order.side, w, price, timestamp, costs) {
+                        self.protection = match trade.side {
+                            Side::Buy => Some(self.risk.protect(
+                                portfolio.avg_entry,
+                                bar,
+                                self.protection.as_ref(),
+                            )),
+                            Side::Sell if portfolio.quantity > 0.0 => self.protection.take(),
+                            Side::Sell => None,
+                        };
+                        self.closed.record(&trade);
+                        self.trades.push(trade);
+                        self.positions.push(portfolio.position(timestamp));
+                    }
+                }
+                None if bar < order.expires => waiting.push(order),
+                None => {}
+            }
+        }
+        self.pending = waiting;
+
+        // Risk exits are market orders sent when a level is crossed, except the take-profit
+        // which rests in the book.
+        let previous_volatility = bar.checked_sub(1).map_or(0.0, |b| {
+            (candles.high[b] - candles.low[b]) / candles.close[b]
+        });
+        let mut exit = self
+            .protection
+            .as_mut()
+            .and_then(|p| self.risk.check_exit(p, bar, open, high, low));
+        let daily_floor = self
+            .risk
+            .max_daily_loss()
+            .filter(|_| self.halted_day != Some(today))
+            .map(|max_loss| self.day_start_equity * (1.0 - max_loss));
+        if let Some(floor) = daily_floor
+            && exit.is_none()
+            && self.portfolio.quantity > 0.0
+            && self.portfolio.equity(low) <= floor
+        {
+            // Exits where the equity crosses the floor, or at the open on a gap.
+            let price = ((floor - self.portfolio.cash) / self.portfolio.quantity)
+                .max(low)
+                .min(open);
+            exit = Some((price, ExitReason::MaxDailyLoss));
+        }
+        if let Some((price, reason)) = exit {
+            let costs = match reason {
+                ExitReason::TakeProfit => execution.maker_costs(),
+                _ => execution.taker_costs(previous_volatility),
+            };
+            if let Some(mut trade) = self
+                .portfolio
+                .fill(Side::Sell, 1.0, price, timestamp, costs)
+            {
+                trade.exit_reason = Some(reason);
+                self.closed.record(&trade);
+                self.trades.push(trade);
+                self.positions.push(self.portfolio.position(timestamp));
+            }
+            self.protection = None;
+        }
+        if exit.is_some_and(|(_, reason)| reason == ExitReason::MaxDailyLoss)
+            || daily_floor.is_some_and(|floor| self.portfolio.equity(candles.close[bar]) <= floor)
+        {
+            self.halted_day = Some(today);
+            self.pending.retain(|order| order.side != Side::Buy);
+        }
+
+        self.mark = candles.close[bar];
+        if self.portfolio.quantity > 0.0 {
+            self.exposed_bars += 1;
+        }
+
+        let volatility = (candles.high[bar] - candles.low[bar]) / candles.close[bar];
+        let context = Context::new(
+            candles,
+            bar,
+            self.portfolio.opened_at,
+            self.portfolio.pnl_pct(self.mark),
+            &self.trades,
+        );
+        for action in &self.actions {
+            if action.side == Side::Buy && self.halted_day == Some(today) {
+                continue;
+            }
+            if action.cond.eval(bar, &context) {
+                let stake = match &action.sizing {
+                    Some(sizing) => sizing::stake(sizing, action.w, bar, candles, &self.closed),
+                    None => Stake::Quantity(action.w),
+                };
+                self.pending.push(PendingOrder {
+                    side: action.side,
+                    stake,
+                    volatility,
+                    kind: action.order.place(bar),
+                    expires: bar + action.tif as usize,
+                });
+            }
+        }
+    }
+}
+
+fn validate(strategy: &StrategyContent, config: &BacktestConfig) -> Result<(), BacktestError> {
+    config.execution.validate()?;
+    match &strategy.meta.strategy_type {
+        StrategyType::Spot => Ok(()),
+        StrategyType::Perp => {
+            let margin = strategy.meta.margin.clone().unwrap_or_default();
+            if !(margin.leverage >= 1.0 && margin.leverage.is_finite()) {
+                return Err(BacktestError::InvalidMargin(
+                    "leverage must be at least 1".to_string(),
+                ));
+            }
+            if !(margin.maintenance_margin > 0.0
+                && margin.maintenance_margin < 100.0 / margin.leverage)
+            {
+                return Err(BacktestError::InvalidMargin(
+                    "maintenance_margin must be positive and below the initial margin".to_string(),
+                ));
+            }
+            if !margin.funding_rate.is_finite() || margin.funding_interval == 0 {
+                return Err(BacktestError::InvalidMargin(
+                    "invalid funding settings".to_string(),
+                ));
+            }
+            Ok(())
+        }
+        StrategyType::Options => {
+            let options = strategy.meta.options.clone().unwrap_or_default();
+            if options.iv.is_some_and(|iv| !(iv > 0.0 && iv.is_finite())) {
+                return Err(BacktestError::InvalidOptions(
+                    "iv must be positive".to_string(),
+                ));
+            }
+            if options.vol_lookback < 2 {
+                return Err(BacktestError::InvalidOptions(
+                    "vol_lookback must be at least 2".to_string(),
+                ));
+            }
+            if !options.risk_free_rate.is_finite() {
+                return Err(BacktestError::InvalidOptions(
+                    "risk_free_rate must be a number".to_string(),
+                ));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Run `strategy` over the bars of `candles` that fall inside the configured date range.
+///
+/// Conditions are evaluated on the close of each bar and the resulting orders are sent from the
+/// next bar on, so a signal can never trade on a price it did not know yet: market orders fill at
+/// the next open, the other order types against the next bars' high and low until their
+/// time-in-force runs out. Orders still pending at the end of the range are dropped. The equity
+/// curve is marked at each close.
+///
+/// The exits of the strategy's `risk` section are checked on every bar the position is held,
+/// against the bar's high and low. Once the max daily loss is hit, the position is closed and no
+/// new buy is filled until the next UTC day.
+///
+/// Perpetual strategies run on margin instead, see `perp::run`, and options strategies trade
+/// priced options, see `options::run`.
+pub fn run(
+    strategy: &StrategyContent,
+    candles: &Candles,
+    config: &BacktestConfig,
+) -> Result<BacktestReport, BacktestError> {
+    run_cancellable(strategy, candles, config, &AtomicBool::new(false))
+}
+
+/// Same as `run`, but gives up with `BacktestError::Cancelled` once `cancelled` is set.
+///
+/// The flag is only checked every few thousand bars, so it can be set from another thread
+/// without slowing the run down.
+pub fn run_cancellable(
+    strategy: &StrategyContent,
+    candles: &Candles,
+    config: &BacktestConfig,
+    cancelled: &AtomicBool,
+) -> Result<BacktestReport, BacktestError> {
+    validate(strategy, config)?;
+    match strategy.meta.strategy_type {
+        StrategyType::Spot => {}
+        StrategyType::Perp => return perp::run(strategy, candles, config, cancelled),
+        StrategyType::Options => return options::run(strategy, candles, config, cancelled),
+    }
+    let mut sleeve = Sleeve::compile(strategy, None, candles, config.initial_capital)?;
+
+    let range = candles.range(config.date_start.timestamp(), config.date_end.timestamp());
+    if range.is_empty() {
+        return Err(BacktestError::EmptyRange);
+    }
+    let buy_and_hold_return =
+        RunStats::buy_and_hold(candles.open[range.start], candles.close[range.end - 1]);
+
+    let mut equity_curve = Vec::with_capacity(range.len());
+    for (i, bar) in range.enumerate() {
+        if i.is_multiple_of(CANCEL_CHECK_INTERVAL) && cancelled.load(Ordering::Relaxed) {
+            return Err(BacktestError::Cancelled);
+        }
+
+        sleeve.step(bar, &config.execution);
+        equity_curve.push(EquityPoint {
+            timestamp: candles.timestamps[bar],
+            equity: sleeve.equity(),
+        });
+    }
+
+    let stats = RunStats {
+        exposed_bars: sleeve.exposed_bars,
+        buy_and_hold_return,
+    };
+    Ok(BacktestReport {
+        summary: Summary::compute(config.initial_capital, &sleeve.trades, &equity_curve, stats),
+        trades: sleeve.trades,
+        equity_curve,
+        positions: sleeve.positions,
+    })
+}
+
+/// Run a portfolio strategy over `assets`, the candles of each asset of its `portfolio`.
+///
+/// Every asset trades its share of the capital like `run` would, the assets being walked
+/// together on the merged timeline of their bars. The equity curve is the portfolio's, marked on
+/// every timestamp of the timeline with the last close of each asset. Trades and positions are
+/// tagged with their asset, and the summary attributes the result to each asset.
+pub fn run_portfolio(
+    strategy: &StrategyContent,
+    assets: HashMap<String, Candles>,
+    config: &BacktestConfig,
+) -> Result<BacktestReport, BacktestError> {
+    run_portfolio_cancellable(strategy, assets, config, &AtomicBool::new(false))
+}
+
+/// Same as `run_portfolio`, but gives up with `BacktestError::Cancelled` once `cancelled` is set.
+pub fn run_portfolio_cancellable(
+    strategy: &StrategyContent,
+    mut assets: HashMap<String, Candles>,
+    config: &BacktestConfig,
+    cancelled: &AtomicBool,
+) -> Result<BacktestReport, BacktestError> {
+    validate(strategy, config)?;
+    if strategy.meta.strategy_type != StrategyType::Spot {
+        return Err(BacktestError::UnsupportedStrategyType(format!(
+            "{:?} portfolio",
+            strategy.meta.strategy_type
+        )));
+    }
+    let spec = strategy.portfolio.as_ref().ok_or_else(|| {
+        BacktestError::InvalidPortfolio("the strategy has no portfolio".to_string())
+    })?;
+    if spec.assets.is_empty() {
+        return Err(BacktestError::InvalidPortfolio("no assets".to_string()));
+    }
+    if let Some(asset) = spec.assets.iter().find(|a| !assets.contains_key(*a)) {
+        return Err(BacktestError::UnknownAsset(asset.clone()));
+    }
+    add_cross_asset_columns(strategy, &spec.assets, &mut assets)?;
+
+    let weights = spec.weights();
+    let mut sleeves = spec
+        .assets
+        .iter()
+        .zip(&weights)
+        .map(|(asset, w)| {
+            Sleeve::compile(
+                strategy,
+                Some(asset),
+                &assets[asset],
+                config.initial_capital * w,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut idle = config.initial_capital - sleeves.iter().map(|s| s.funding).sum::<f64>();
+
+    let (start, end) = (config.date_start.timestamp(), config.date_end.timestamp());
+    let ranges: Vec<Range<usize>> = sleeves
+        .iter()
+        .map(|s| s.candles.range(start, end))
+        .collect();
+    let mut timeline: Vec<i64> = sleeves
+        .iter()
+        .zip(&ranges)
+        .flat_map(|(sleeve, range)| sleeve.candles.timestamps[range.clone()].iter().copied())
+        .collect();
+    timeline.sort_unstable();
+    timeline.dedup();
+    if timeline.is_empty() {
+        return Err(BacktestError::EmptyRange);
+    }
+    let buy_and_hold_return = sleeves
+        .iter()
+        .zip(&ranges)
+        .zip(&weights)
+        .filter(|((_, range), _)| !range.is_empty())
+        .map(|((sleeve, range), w)| {
+            let candles = sleeve.candles;
+            w * RunStats::buy_and_hold(candles.open[range.start], candles.close[range.end - 1])
+        })
+        .sum();
+
+    let mut next: Vec<usize> = ranges.iter().map(|r| r.start).collect();
+    let mut period = None;
+    let mut exposed_bars = 0;
+    let mut equity_curve = Vec::with_capacity(timeline.len());
+    for (i, &timestamp) in timeline.iter().enumerate() {
+        if i.is_multiple_of(CANCEL_CHECK_INTERVAL) && cancelled.load(Ordering::Relaxed) {
+            return Err(BacktestError::Cancelled);
+        }
+
+        if let Some(rebalance) = spec.rebalance {
+            let current = rebalance.period(timestamp);
+            if period.is_some_and(|p| p != current) {
+                rebalance_cash(&mut sleeves, &weights, &mut idle);
+            }
+            period = Some(current);
+        }
+
+        for ((sleeve, bar), range) in sleeves.iter_mut().zip(&mut next).zip(&ranges) {
+            if *bar < range.end && sleeve.candles.timestamps[*bar] == timestamp {
+                sleeve.step(*bar, &config.execution);
+                *bar += 1;
+            }
+        }
+        if sleeves.iter().any(|s| s.portfolio.quantity > 0.0) {
+            exposed_bars += 1;
+        }
+        equity_curve.push(EquityPoint {
+            timestamp,
+            equity: idle + sleeves.iter().map(Sleeve::equity).sum::<f64>(),
+        });
+    }
+
+    let mut trades = Vec::new();
+    let mut positions = Vec::new();
+    let mut attribution = Vec::with_capacity(sleeves.len());
+    for ((asset, weight), sleeve) in spec.assets.iter().zip(&weights).zip(sleeves) {
+        let final_equity = sleeve.equity();
+        let pnl = final_equity - sleeve.funding;
+        attribution.push(AssetAttribution {
+            asset: asset.clone(),
+            weight: *weight,
+            final_equity,
+            pnl,
+            contribution: pnl / config.initial_capital,
+            trades_count: sleeve.trades.len(),
+        });
+        trades.extend(sleeve.trades.into_iter().map(|trade| Trade {
+            asset: Some(asset.clone()),
+            ..trade
+        }));
+        positions.extend(sleeve.positions.into_iter().map(|point| PositionPoint {
+            asset: Some(asset.clone()),
+            ..point
+        }));
+    }
+    trades.sort_by_key(|t| t.timestamp);
+    positions.sort_by_key(|p| p.timestamp);
+
+    let stats = RunStats {
+        exposed_bars,
+        buy_and_hold_return,
+    };
+    let mut summary = Summary::compute(config.initial_capital, &trades, &equity_curve, stats);
+    summary.attribution = attribution;
+    Ok(BacktestReport {
+        summary,
+        trades,
+        equity_curve,
+        positions,
+    })
+}
+
+/// Add the indicators of other assets the strategy reads (`BTCUSDT.close`) to the columns of
+/// each asset of the portfolio, aligned on its bars.
+fn add_cross_asset_columns(
+    strategy: &StrategyContent,
+    portfolio: &[String],
+    assets: &mut HashMap<String, Candles>,
+) -> Result<(), BacktestError> {
+    let references: BTreeSet<&str> = strategy
+        .references()
+        .into_iter()
+        .filter(|r| Value::split_asset(r).is_some())
+        .collect();
+
+    let mut columns = Vec::new();
+    for reference in references {
+        let Some((asset, indicator)) = Value::split_asset(reference) else {
+            continue;
+        };
+        let other = assets
+            .get(asset)
+            .ok_or_else(|| BacktestError::UnknownAsset(asset.to_string()))?;
+        let series = Operand::reference(indicator, other)?;
+        for name in portfolio {
+            let values = assets[name]
+                .align(other, other.bar_seconds())
+                .into_iter()
+                .map(|bar| bar.map_or(f64::NAN, |bar| series.at(bar)))
+                .collect();
+            columns.push((name.clone(), reference.to_string(), values));
+        }
+    }
+
+    for (name, reference, values) in columns {
+        if let Some(candles) = assets.get_mut(&name) {
+            candles.indicators.insert(reference, values);
+        }
+    }
+    Ok(())
+}
+
+/// Move cash between the sleeves to bring them back to their weight of the portfolio equity.
+///
+/// Positions are left to the actions: a sleeve above its target hands over the cash it holds, up
+/// to its excess, and the cash collected is shared among the sleeves below theirs.
+fn rebalance_cash(sleeves: &mut [Sleeve], weights: &[f64], idle: &mut f64) {
+    let total = *idle + sleeves.iter().map(Sleeve::equity).sum::<f64>();
+    let idle_target = total * (1.0 - weights.iter().sum::<f64>());
+    let mut pool = (*idle - idle_target).max(0.0);
+    *idle -= pool;
+
+    let mut deficits = vec![0.0; sleeves.len()];
+    for ((sleeve, w), deficit) in sleeves.iter_mut().zip(weights).zip(&mut deficits) {
+        let excess = sleeve.equity() - w * total;
+        if excess > 0.0 {
+            let amount = excess.min(sleeve.portfolio.cash);
+            sleeve.portfolio.cash -= amount;
+            sleeve.funding -= amount;
+            pool += amount;
+        } else {
+            *deficit = -excess;
+        }
+    }
+
+    let needed: f64 = deficits.iter().sum();
+    if needed > 0.0 {
+        let given = pool.min(needed);
+        for (sleeve, deficit) in sleeves.iter_mut().zip(&deficits) {
+            let amount = given * deficit / needed;
+            sleeve.portfolio.cash += amount;
+            sleeve.funding += amount;
+        }
+        pool -= given;
+    }
+    *idle += pool;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dataset::SECONDS_PER_DAY, strategy::Cond};
+
+    fn candles(close: &[f64], sma: &[f64]) -> Candles {
+        let mut candles = Candles {
+            timestamps: (0..close.len() as i64).map(|i| i * 60).collect(),
+            open: close.to_vec(),
+            high: close.to_vec(),
+            low: close.to_vec(),
+            close: close.to_vec(),
+            volume: vec![1.0; close.len()],
+            ..Default::default()
+        };
+        candles.indicators.insert("sma".to_string(), sma.to_vec());
+        candles
+    }
+
+    fn config() -> BacktestConfig {
+        BacktestConfig::new(
+            DateTime::from_timestamp(0, 0).unwrap(),
+            DateTime::from_timestamp(1_000_000, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_cross_round_trip() {
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "actions": [
+                { "type": "buy", "w": 1.0, "cond": { "xab": { "l": "close", "r": "sma" } } },
+                { "type": "sell", "w": 1.0, "cond": { "xbe": { "l": "close", "r": "sma" } } }
+            ]
+        }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let candles = candles(
+            &[10.0, 9.0, 11.0, 12.0, 16.0, 14.0, 8.0, 7.0],
+            &[10.0, 10.0, 10.0, 10.0, 10.0, 15.0, 10.0, 10.0],
+        );
+
+        let report = run(&strategy, &candles, &config()).unwrap();
+
+        // Crosses above on bar 2, bought at the open of bar 3 (12).
+        // Crosses below on bar 5, sold at the open of bar 6 (8).
+        assert_eq!(report.trades.len(), 2);
+        assert_eq!(report.trades[0].side, Side::Buy);
+        assert_eq!(report.trades[0].price, 12.0);
+        assert_eq!(report.trades[1].side, Side::Sell);
+        assert_eq!(report.trades[1].price, 8.0);
+        assert_eq!(report.equity_curve.len(), 8);
+        assert_eq!(report.positions.len(), 2);
+        assert_eq!(report.positions[1].quantity, 0.0);
+
+        let expected = 10_000.0 * 8.0 / 12.0;
+        assert!((report.summary.final_equity - expected).abs() < 1e-6);
+        assert_eq!(report.summary.win_rate, Some(0.0));
+        assert!(report.summary.max_drawdown > 0.0);
+        assert_eq!(report.trades[1].holding_time, Some(180));
+        // Held from the close of bar 3 to the close of bar 5.
+        assert_eq!(report.summary.exposure_time, 3.0 / 8.0);
+        assert_eq!(report.summary.buy_and_hold_return, 7.0 / 10.0 - 1.0);
+    }
+
+    #[test]
+    fn test_execution_costs() {
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "actions": [
+                { "type": "buy", "w": 1.0, "cond": { "xab": { "l": "close", "r": "sma" } } },
+                { "type": "sell", "w": 1.0, "cond": { "xbe": { "l": "close", "r": "sma" } } }
+            ]
+        }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let candles = candles(
+            &[10.0, 9.0, 11.0, 10.0, 10.0, 9.0, 10.0],
+            &[10.0, 10.0, 10.0, 10.0, 10.0, 10.0, 10.0],
+        );
+        let execution: ExecutionConfig = serde_json::from_str(
+            r#"{
+                "taker_fee_bps": 10,
+                "slippage": { "model": "fixed", "bps": 5 },
+                "spread": { "model": "fixed", "bps": 10 }
+            }"#,
+        )
+        .unwrap();
+        let config = config().with_execution(execution);
+
+        let report = run(&strategy, &candles, &config).unwrap();
+
+        // Bought and sold at 10, every cost is lost.
+        assert_eq!(report.trades.len(), 2);
+        assert!((report.trades[0].price - 10.01).abs() < 1e-9);
+        assert!((report.trades[1].price - 9.99).abs() < 1e-9);
+        assert!(report.summary.final_equity < 10_000.0 * (1.0 - 0.003));
+        assert!(report.trades[1].pnl.unwrap() < 0.0);
+        assert!((report.summary.total_fees - 20.0).abs() < 0.1);
+        assert!((report.summary.total_slippage - 10.0).abs() < 0.1);
+        assert!((report.summary.total_spread - 10.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_limit_order() {
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "actions": [
+                {
+                    "type": "buy",
+                    "w": 1.0,
+                    "cond": { "xab": { "l": "close", "r": "sma" } },
+                    "order": { "type": { "limit": { "price": "sma" } }, "tif": 3 }
+                }
+            ]
+        }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let mut candles = candles(
+            &[10.0, 9.0, 11.0, 12.0, 11.5, 10.5, 12.0],
+            &[10.0, 10.0, 10.0, 10.0, 10.0, 10.0, 10.0],
+        );
+        candles.low = vec![9.5, 8.5, 10.5, 11.0, 11.0, 9.8, 11.5];
+        let config = config().with_execution(ExecutionConfig {
+            maker_fee_bps: 0.0,
+            taker_fee_bps: 100.0,
+            ..Default::default()
+        });
+
+        let report = run(&strategy, &candles, &config).unwrap();
+
+        // Crosses above on bar 2, the limit at 10 is reached on bar 5, within the 3 bars.
+        assert_eq!(report.trades.len(), 1);
+        assert_eq!(report.trades[0].timestamp, 5 * 60);
+        assert_eq!(report.trades[0].price, 10.0);
+        assert_eq!(report.trades[0].fee, 0.0);
+
+        // Expired before reaching the limit.
+        let strategy: StrategyContent =
+            serde_json::from_str(&json.replace("\"tif\": 3", "\"tif\": 2")).unwrap();
+        let report = run(&strategy, &candles, &config).unwrap();
+        assert!(report.trades.is_empty());
+    }
+
+    #[test]
+    fn test_risk_exits() {
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "actions": [
+                { "type": "buy", "w": 1.0, "cond": { "gt": { "l": "close", "r": "sma" } } }
+            ],
+            "risk": { "stop_loss": { "pct": 5 }, "take_profit": { "pct": 10 } }
+        }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let mut candles = candles(
+            &[10.0, 11.0, 10.0, 10.0, 10.0, 10.0],
+            &[10.0, 10.0, 20.0, 9.0, 20.0, 20.0],
+        );
+        candles.open = vec![10.0, 10.0, 10.0, 10.0, 10.0, 10.0];
+        candles.high = vec![10.0, 11.0, 10.5, 10.0, 11.5, 10.0];
+        candles.low = vec![10.0, 10.0, 9.0, 10.0, 10.0, 10.0];
+
+        let report = run(&strategy, &candles, &config()).unwrap();
+
+        // Bought at 10 on bar 2, stopped at 9.5 within the bar.
+        // Bought again at 10 on bar 4, takes profit at 11.
+        let exits: Vec<_> = report
+            .trades
+            .iter()
+            .filter(|t| t.side == Side::Sell)
+            .map(|t| (t.timestamp, t.price, t.exit_reason))
+            .collect();
+        assert_eq!(
+            exits,
+            vec![
+                (2 * 60, 9.5, Some(ExitReason::StopLoss)),
+                (4 * 60, 11.0, Some(ExitReason::TakeProfit)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_daily_loss() {
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "actions": [
+                { "type": "buy", "w": 1.0, "cond": { "gt": { "l": "close", "r": "sma" } } }
+            ],
+            "risk": { "max_daily_loss": 2 }
+        }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let mut candles = candles(&[10.0, 10.0, 9.0, 10.0, 10.0], &[0.0; 5]);
+        candles.open[2] = 10.0;
+
+        let report = run(&strategy, &candles, &config()).unwrap();
+
+        // Bought at 10 on bar 1, the floor of 9800 is crossed at 9.8 on bar 2 and no new buy is
+        // made for the rest of the day.
+        assert_eq!(report.trades.len(), 2);
+        assert_eq!(report.trades[1].price, 9.8);
+        assert_eq!(report.trades[1].exit_reason, Some(ExitReason::MaxDailyLoss));
+        assert!((report.summary.final_equity - 9_800.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_sizing_and_max_entries() {
+        let json = r#"
+        {
+            "meta": { "type": "spot", "sizing": { "mode": "equity" }, "max_entries": 2 },
+            "actions": [
+                { "type": "buy", "w": 0.25, "cond": { "gt": { "l": "close", "r": "sma" } } },
+                {
+                    "type": "buy",
+                    "w": 0.5,
+                    "cond": { "gt": { "l": "close", "r": 100 } },
+                    "sizing": { "mode": "quote", "amount": 1000 }
+                }
+            ]
+        }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let candles = candles(
+            &[10.0, 10.0, 10.0, 200.0, 200.0],
+            &[0.0, 0.0, 1000.0, 1000.0, 1000.0],
+        );
+
+        let report = run(&strategy, &candles, &config()).unwrap();
+
+        // A quarter of the equity on bars 1 and 2, then the position is full and the quote sized
+        // buy signaled on bar 3 is dropped.
+        assert_eq!(report.trades.len(), 2);
+        assert_eq!(report.trades[0].quantity, 250.0);
+        assert_eq!(report.trades[1].quantity, 250.0);
+
+        let strategy: StrategyContent =
+            serde_json::from_str(&json.replace("\"max_entries\": 2", "\"max_entries\": 3"))
+                .unwrap();
+        let report = run(&strategy, &candles, &config()).unwrap();
+        assert_eq!(report.trades.len(), 3);
+        // Half of 1000 of quote at 200.
+        assert_eq!(report.trades[2].quantity, 2.5);
+    }
+
+    #[test]
+    fn test_state_conditions() {
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "actions": [
+                { "type": "buy", "w": 1.0, "cond": { "and": { "conds": [
+                    { "not": { "cond": "in_position" } },
+                    { "lt": { "l": { "state": "trades_today" }, "r": 2 } }
+                ] } } },
+                { "type": "sell", "w": 1.0, "cond": { "ge": { "l": { "state": "bars_since_entry" }, "r": 2 } } }
+            ]
+        }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let flat = candles(&[10.0; 8], &[10.0; 8]);
+
+        // Bought at the open of bar 1, held for 2 bars, then no more trades that day.
+        let report = run(&strategy, &flat, &config()).unwrap();
+        let fills: Vec<_> = report
+            .trades
+            .iter()
+            .map(|t| (t.side, t.timestamp))
+            .collect();
+        assert_eq!(fills, [(Side::Buy, 60), (Side::Sell, 240)]);
+
+        // Out once the position is up 15%, on the close of bar 3.
+        let json = json.replace(
+            r#"{ "ge": { "l": { "state": "bars_since_entry" }, "r": 2 } }"#,
+            r#"{ "gt": { "l": { "state": "pnl_pct" }, "r": 15 } }"#,
+        );
+        let strategy: StrategyContent = serde_json::from_str(&json).unwrap();
+        let rising = candles(
+            &[10.0, 10.0, 11.0, 12.0, 13.0, 14.0],
+            &[10.0, 10.0, 10.0, 10.0, 10.0, 10.0],
+        );
+        let report = run(&strategy, &rising, &config()).unwrap();
+        assert_eq!(report.trades.len(), 2);
+        assert_eq!(report.trades[1].price, 13.0);
+
+        // The conditions of `bars_since` and the prices of orders do not see the position.
+        let json = r#"{ "meta": { "type": "spot" }, "actions": [{ "type": "buy", "w": 0.5, "cond": { "lt": { "l": { "bars_since": { "cond": "in_position" } }, "r": 3 } } }] }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let result = run(&strategy, &flat, &config());
+        assert!(matches!(result, Err(BacktestError::InvalidCondition(_))));
+        let json = r#"{ "meta": { "type": "spot" }, "actions": [{ "type": "buy", "w": 0.5, "cond": "in_position", "order": { "type": { "limit": { "price": { "state": "pnl_pct" } } } } }] }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let result = run(&strategy, &flat, &config());
+        assert!(matches!(result, Err(BacktestError::InvalidCondition(_))));
+    }
+
+    #[test]
+    fn test_definitions() {
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "definitions": {
+                "above": { "gt": { "l": "close", "r": "sma" } },
+                "entry": { "and": { "conds": [{ "ref": "above" }, { "not": { "cond": "in_position" } }] } }
+            },
+            "actions": [
+                { "type": "buy", "w": 1.0, "cond": { "ref": "entry" } },
+                { "type": "sell", "w": 1.0, "cond": { "not": { "cond": { "ref": "above" } } } }
+            ]
+        }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let candles = candles(
+            &[10.0, 9.0, 11.0, 12.0, 16.0, 14.0, 8.0, 7.0],
+            &[10.0, 10.0, 10.0, 10.0, 10.0, 15.0, 10.0, 10.0],
+        );
+
+        let report = run(&strategy, &candles, &config()).unwrap();
+        let fills: Vec<_> = report.trades.iter().map(|t| (t.side, t.price)).collect();
+        assert_eq!(fills, [(Side::Buy, 12.0), (Side::Sell, 8.0)]);
+
+        let cyclic = json.replace(
+            r#"{ "ref": "above" }, { "not""#,
+            r#"{ "ref": "entry" }, { "not""#,
+        );
+        let strategy: StrategyContent = serde_json::from_str(&cyclic).unwrap();
+        let result = run(&strategy, &candles, &config());
+        assert!(matches!(
+            result,
+            Err(BacktestError::InvalidCondition(msg)) if msg.ends_with("entry -> entry")
+        ));
+        let undefined = json.replace(r#""ref": "entry""#, r#""ref": "exit""#);
+        let strategy: StrategyContent = serde_json::from_str(&undefined).unwrap();
+        let result = run(&strategy, &candles, &config());
+        assert!(matches!(
+            result,
+            Err(BacktestError::InvalidCondition(msg)) if msg == "undefined condition 'exit'"
+        ));
+
+        // Each definition doubling the previous one, 2^40 nodes once expanded.
+        let mut strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        for i in 0..40 {
+            let next = format!("d{}", i + 1);
+            let cond =
+                serde_json::json!({ "and": { "conds": [{ "ref": next }, { "ref": next }] } });
+            strategy
+                .definitions
+                .insert(format!("d{}", i), serde_json::from_value(cond).unwrap());
+        }
+        strategy
+            .definitions
+            .insert("d40".to_string(), Cond::InPosition);
+        strategy.actions[0].cond = Cond::Ref("d0".to_string());
+        let result = run(&strategy, &candles, &config());
+        assert!(matches!(
+            result,
+            Err(BacktestError::InvalidCondition(msg)) if msg.starts_with("condition too large")
+        ));
+
+        // A chain of definitions each negating the next one, too deep once expanded.
+        strategy.definitions.clear();
+        for i in 0..9000 {
+            let cond = serde_json::json!({ "not": { "cond": { "ref": format!("d{}", i + 1) } } });
+            strategy
+                .definitions
+                .insert(format!("d{}", i), serde_json::from_value(cond).unwrap());
+        }
+        strategy
+            .definitions
+            .insert("d9000".to_string(), Cond::InPosition);
+        let result = run(&strategy, &candles, &config());
+        assert!(matches!(
+            result,
+            Err(BacktestError::InvalidCondition(msg)) if msg.starts_with("condition nested too deep")
+        ));
+    }
+
+    #[test]
+    fn test_invalid_execution() {
+        let json = r#"{ "meta": { "type": "spot" }, "actions": [] }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let candles = candles(&[1.0, 2.0], &[1.0, 1.0]);
+        let config = config().with_execution(ExecutionConfig {
+            taker_fee_bps: -1.0,
+            ..Default::default()
+        });
+
+        let result = run(&strategy, &candles, &config);
+        assert!(matches!(result, Err(BacktestError::InvalidExecution(_))));
+    }
+
+    #[test]
+    fn test_no_signal_keeps_capital() {
+        let json = r#"{ "meta": { "type": "spot" }, "actions": [{ "type": "buy", "w": 0.5, "cond": { "gt": { "l": "close", "r": 100 } } }] }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let candles = candles(&[1.0, 2.0, 3.0], &[1.0, 1.0, 1.0]);
+
+        let report = run(&strategy, &candles, &config()).unwrap();
+        assert!(report.trades.is_empty());
+        assert_eq!(report.summary.final_equity, 10_000.0);
+        assert_eq!(report.summary.net_return, 0.0);
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let json = r#"{ "meta": { "type": "spot" }, "actions": [] }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let candles = candles(&[1.0, 2.0], &[1.0, 1.0]);
+
+        let result = run_cancellable(&strategy, &candles, &config(), &AtomicBool::new(true));
+        assert!(matches!(result, Err(BacktestError::Cancelled)));
+    }
+
+    #[test]
+    fn test_unknown_indicator() {
+        let json = r#"{ "meta": { "type": "spot" }, "actions": [{ "type": "buy", "w": 0.5, "cond": { "gt": { "l": "foo", "r": 30 } } }] }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let candles = candles(&[1.0, 2.0], &[1.0, 1.0]);
+
+        let result = run(&strategy, &candles, &config());
+        assert!(matches!(result, Err(BacktestError::UnknownIndicator(_))));
+
+        let json = json.replace("foo", "sma_0");
+        let strategy: StrategyContent = serde_json::from_str(&json).unwrap();
+        let result = run(&strategy, &candles, &config());
+        assert!(matches!(result, Err(BacktestError::InvalidIndicator(_))));
+    }
+
+    #[test]
+    fn test_portfolio() {
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "actions": [
+                { "type": "buy", "w": 1.0, "cond": { "gt": { "l": "close", "r": 15 } } },
+                {
+                    "type": "buy", "w": 1.0, "asset": "ETHUSDT",
+                    "cond": { "gt": { "l": "BTCUSDT.close", "r": 15 } }
+                }
+            ],
+            "portfolio": {
+                "assets": ["BTCUSDT", "ETHUSDT"],
+                "allocation": { "BTCUSDT": 0.5, "ETHUSDT": 0.25 }
+            }
+        }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let btc = candles(&[10.0, 10.0, 10.0, 20.0, 20.0, 30.0], &[0.0; 6]);
+        // Starts one bar later.
+        let mut eth = candles(&[5.0, 5.0, 5.0, 5.0, 10.0], &[0.0; 5]);
+        eth.timestamps = (1..6).map(|i| i * 60).collect();
+        let assets = HashMap::from([("BTCUSDT".to_string(), btc), ("ETHUSDT".to_string(), eth)]);
+
+        let report = run_portfolio(&strategy, assets.clone(), &config()).unwrap();
+
+        // BTC closes above 15 on bar 3: BTC buys at 20 and ETH at 5 on the next bar (240).
+        assert_eq!(report.equity_curve.len(), 6);
+        assert_eq!(report.trades.len(), 2);
+        assert_eq!(report.trades[0].asset.as_deref(), Some("BTCUSDT"));
+        assert_eq!(report.trades[0].quantity, 250.0);
+        assert_eq!(report.trades[1].asset.as_deref(), Some("ETHUSDT"));
+        assert_eq!(report.trades[1].timestamp, 240);
+        assert_eq!(report.trades[1].quantity, 500.0);
+
+        // 2500 left unallocated, 250 BTC at 30 and 500 ETH at 10.
+        let summary = &report.summary;
+        assert_eq!(summary.final_equity, 15_000.0);
+        assert_eq!(summary.buy_and_hold_return, 0.5 * 2.0 + 0.25 * 1.0);
+        assert_eq!(summary.attribution.len(), 2);
+        for attribution in &summary.attribution {
+            assert_eq!(attribution.pnl, 2_500.0);
+            assert_eq!(attribution.contribution, 0.25);
+        }
+
+        // Other assets are only readable by portfolio backtests.
+        let result = run(&strategy, &assets["ETHUSDT"], &config());
+        assert!(matches!(result, Err(BacktestError::UnknownAsset(asset)) if asset == "BTCUSDT"));
+    }
+
+    #[test]
+    fn test_portfolio_rebalance() {
+        let json = r#"
+        {
+            "meta": { "type": "spot" },
+            "actions": [
+                { "type": "buy", "w": 0.5, "asset": "A", "cond": { "gt": { "l": "close", "r": 0 } } }
+            ],
+            "portfolio": { "assets": ["A", "B"], "rebalance": "daily" }
+        }"#;
+        let strategy: StrategyContent = serde_json::from_str(json).unwrap();
+        let mut a = candles(&[10.0, 10.0, 20.0, 20.0], &[0.0; 4]);
+        let mut b = candles(&[1.0; 4], &[0.0; 4]);
+        a.timestamps = (0..4).map(|i| i * SECONDS_PER_DAY).collect();
+        b.timestamps = a.timestamps.clone();
+        let assets = HashMap::from([("A".to_string(), a), ("B".to_string(), b)]);
+
+        let report = run_portfolio(&strategy, assets, &config()).unwrap();
+
+        // A buys 250 with half of its cash on day 1 and doubles on day 2: 7500 against 5000.
+        // On day 3, A hands 1250 of its cash over to B, at 6250 each.
+        let summary = &report.summary;
+        assert_eq!(summary.final_equity, 12_500.0);
+        assert_eq!(summary.attribution[0].final_equity, 6_250.0);
+        assert_eq!(summary.attribution[0].pnl, 2_500.0);
+        assert_eq!(summary.attribution[1].final_equity, 6_250.0);
+        assert_eq!(summary.attribution[1].pnl, 0.0);
+    }
+}
@@ -0,0 +1,66 @@
+use thiserror::Error;
+
+use crate::{indicator::IndicatorError, strategy::DefinitionError};
+
+#[derive(Error, Debug)]
+pub enum BacktestError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid dataset: {0}")]
+    InvalidDataset(String),
+
+    #[error("No candles in the requested date range")]
+    EmptyRange,
+
+    #[error("Indicator not present in dataset: {0}")]
+    UnknownIndicator(String),
+
+    #[error("Invalid indicator: {0}")]
+    InvalidIndicator(String),
+
+    #[error("Timeframe not available: {0}")]
+    UnknownTimeframe(String),
+
+    #[error("Asset not available: {0}")]
+    UnknownAsset(String),
+
+    #[error("Invalid portfolio: {0}")]
+    InvalidPortfolio(String),
+
+    #[error("Unsupported strategy type: {0}")]
+    UnsupportedStrategyType(String),
+
+    #[error("Invalid execution settings: {0}")]
+    InvalidExecution(String),
+
+    #[error("Invalid margin settings: {0}")]
+    InvalidMargin(String),
+
+    #[error("Invalid options settings: {0}")]
+    InvalidOptions(String),
+
+    #[error("Invalid condition: {0}")]
+    InvalidCondition(String),
+
+    #[error("Invalid action type: {0}")]
+    InvalidActionType(String),
+
+    #[error("Backtest was cancelled")]
+    Cancelled,
+}
+
+impl From<IndicatorError> for BacktestError {
+    fn from(error: IndicatorError) -> Self {
+        match error {
+            IndicatorError::Unknown(name) => BacktestError::UnknownIndicator(name),
+            e => BacktestError::InvalidIndicator(e.to_string()),
+        }
+    }
+}
+
+impl From<DefinitionError> for BacktestError {
+    fn from(error: DefinitionError) -> Self {
+        BacktestError::InvalidCondition(error.to_string())
+    }
+}
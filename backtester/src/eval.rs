@@ -0,0 +1,547 @@
+use std::rc::Rc;
+
+use chrono::{DateTime, Datelike, Timelike};
+
+use crate::{
+    dataset::Candles,
+    error::BacktestError,
+    indicator::Indicator,
+    report::Trade,
+    risk,
+    strategy::{Cond, DefinitionError, Expr, IndicatorRef, StateVar, Value},
+};
+
+/// The trading state on the close of a bar, for the conditions reading it. See `StateVar`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Context {
+    pub(crate) in_position: bool,
+    pub(crate) bars_since_entry: f64,
+    pub(crate) trades_today: f64,
+    pub(crate) pnl_pct: f64,
+}
+
+impl Context {
+    /// No position and no fill, for the values that do not depend on them.
+    pub(crate) const FLAT: Context = Context {
+        in_position: false,
+        bars_since_entry: f64::NAN,
+        trades_today: 0.0,
+        pnl_pct: f64::NAN,
+    };
+
+    /// The state of a position opened at `opened_at` with an unrealized pnl of `pnl_pct`, on the
+    /// close of `bar`. `trades` are the fills so far.
+    pub(crate) fn new(
+        candles: &Candles,
+        bar: usize,
+        opened_at: Option<i64>,
+        pnl_pct: f64,
+        trades: &[Trade],
+    ) -> Self {
+        let timestamps = &candles.timestamps;
+        let today = risk::day(timestamps[bar]);
+        let trades_today = trades
+            .iter()
+            .rev()
+            .take_while(|trade| risk::day(trade.timestamp) == today)
+            .count();
+        match opened_at {
+            Some(opened_at) => Self {
+                in_position: true,
+                bars_since_entry: (bar - timestamps[..bar].partition_point(|&t| t < opened_at))
+                    as f64,
+                trades_today: trades_today as f64,
+                pnl_pct,
+            },
+            None => Self {
+                in_position: false,
+                bars_since_entry: f64::NAN,
+                trades_today: trades_today as f64,
+                pnl_pct: f64::NAN,
+            },
+        }
+    }
+
+    fn get(&self, var: StateVar) -> f64 {
+        match var {
+            StateVar::BarsSinceEntry => self.bars_since_entry,
+            StateVar::TradesToday => self.trades_today,
+            StateVar::PnlPct => self.pnl_pct,
+            // Known from the candles, compiled to series.
+            StateVar::Hour | StateVar::Weekday => f64::NAN,
+        }
+    }
+}
+
+/// A `Value` with its indicator already resolved to a dataset column. Expressions are computed
+/// for the whole series when compiled, unless they depend on the position.
+#[derive(Debug, Clone)]
+pub(crate) enum Operand<'a> {
+    Const(f64),
+    Column(&'a [f64]),
+    Series(Rc<[f64]>),
+    /// A variable of the position, only known while the backtest runs.
+    State(StateVar),
+    /// Arithmetic on an operand depending on the position, computed on each evaluation.
+    Binary(fn(f64, f64) -> f64, Box<Operand<'a>>, Box<Operand<'a>>),
+}
+
+impl<'a> Operand<'a> {
+    /// Compile a value that does not depend on the position, for the prices of orders and the
+    /// risk rules.
+    pub(crate) fn compile(val: &Value, candles: &'a Candles) -> Result<Self, BacktestError> {
+        if val.uses_position() {
+            return Err(BacktestError::InvalidCondition(
+                "the position can only be read by conditions".to_string(),
+            ));
+        }
+        Self::compile_stateful(val, candles)
+    }
+
+    fn compile_stateful(val: &Value, candles: &'a Candles) -> Result<Self, BacktestError> {
+        match val {
+            Value::Number(n) => Ok(Operand::Const(*n)),
+            Value::Boolean(b) => Ok(Operand::Const(if *b { 1.0 } else { 0.0 })),
+            Value::Indicator(reference) => Self::reference(reference, candles),
+            Value::Expr(expr) => Self::compile_expr(expr, candles),
+            Value::Spec(spec) => {
+                let indicator = Indicator::from_spec(spec)?;
+                Self::on_timeframe(&indicator.to_string(), spec.tf.as_deref(), 0, candles)
+            }
+        }
+    }
+
+    /// An indicator reference, see `Value`.
+    pub(crate) fn reference(reference: &str, candles: &'a Candles) -> Result<Self, BacktestError> {
+        if let Some(column) = candles.column(reference) {
+            return Ok(Operand::Column(column));
+        }
+        // The portfolio engine adds the other assets' indicators as columns.
+        if let Some((asset, _)) = Value::split_asset(reference) {
+            return Err(BacktestError::UnknownAsset(asset.to_string()));
+        }
+        let IndicatorRef {
+            name,
+            timeframe,
+            lag,
+        } = Value::parse_indicator(reference);
+        Self::on_timeframe(name, timeframe, lag, candles)
+    }
+
+    /// The indicator `name` on the bars of `timeframe`, `lag` of them back, aligned on the bars
+    /// of `candles` without looking ahead.
+    fn on_timeframe(
+        name: &str,
+        timeframe: Option<&str>,
+        lag: u32,
+        candles: &'a Candles,
+    ) -> Result<Self, BacktestError> {
+        let Some(timeframe) = timeframe else {
+            return Ok(Self::indicator(name, candles)?.lag(lag as usize, candles.len()));
+        };
+        let (other, seconds) = candles.timeframe(timeframe)?;
+        let series = Self::indicator(name, other)?.lag(lag as usize, other.len());
+        Ok(Operand::Series(
+            candles
+                .align(other, seconds)
+                .into_iter()
+                .map(|bar| bar.map_or(f64::NAN, |bar| series.at(bar)))
+                .collect(),
+        ))
+    }
+
+    /// The dataset column `name`, or the indicator it names computed from the candles.
+    fn indicator(name: &str, candles: &'a Candles) -> Result<Self, BacktestError> {
+        match candles.column(name) {
+            Some(column) => Ok(Operand::Column(column)),
+            None => Ok(Operand::Series(
+                Indicator::parse(name)?.compute(candles).into(),
+            )),
+        }
+    }
+
+    fn compile_expr(expr: &Expr, candles: &'a Candles) -> Result<Self, BacktestError> {
+        let binary = |l: &Value, r: &Value, f: fn(f64, f64) -> f64| {
+            let l = Self::compile_stateful(l, candles)?;
+            let r = Self::compile_stateful(r, candles)?;
+            Ok(match (&l, &r) {
+                (Operand::Const(l), Operand::Const(r)) => Operand::Const(f(*l, *r)),
+                _ if l.is_stateful() || r.is_stateful() => {
+                    Operand::Binary(f, Box::new(l), Box::new(r))
+                }
+                _ => Operand::Series((0..candles.len()).map(|i| f(l.at(i), r.at(i))).collect()),
+            })
+        };
+
+        match expr {
+            Expr::Add { l, r } => binary(l, r, |l, r| l + r),
+            Expr::Sub { l, r } => binary(l, r, |l, r| l - r),
+            Expr::Mul { l, r } => binary(l, r, |l, r| l * r),
+            Expr::Div { l, r } => binary(l, r, |l, r| if r == 0.0 { f64::NAN } else { l / r }),
+            // `f64::min` and `f64::max` would ignore a missing operand.
+            Expr::Min { l, r } => binary(l, r, |l, r| {
+                if l.is_nan() || r.is_nan() {
+                    f64::NAN
+                } else {
+                    l.min(r)
+                }
+            }),
+            Expr::Max { l, r } => binary(l, r, |l, r| {
+                if l.is_nan() || r.is_nan() {
+                    f64::NAN
+                } else {
+                    l.max(r)
+                }
+            }),
+            Expr::Abs { val } => binary(val, &Value::Number(0.0), |v, _| v.abs()),
+            Expr::Lag { val, n } => {
+                // The past states of the position are not kept.
+                Ok(Self::compile(val, candles)?.lag(*n as usize, candles.len()))
+            }
+            Expr::State(StateVar::Hour) => Ok(Self::time(candles, |t| t.hour())),
+            Expr::State(StateVar::Weekday) => {
+                Ok(Self::time(candles, |t| t.weekday().num_days_from_monday()))
+            }
+            Expr::State(var) => Ok(Operand::State(*var)),
+            Expr::BarsSince { cond } => {
+                if cond.uses_position() {
+                    return Err(BacktestError::InvalidCondition(
+                        "bars_since cannot depend on the position".to_string(),
+                    ));
+                }
+                let cond = CompiledCond::compile(cond, candles)?;
+                let mut last = None;
+                Ok(Operand::Series(
+                    (0..candles.len())
+                        .map(|bar| {
+                            if cond.eval(bar, &Context::FLAT) {
+                                last = Some(bar);
+                            }
+                            last.map_or(f64::NAN, |last| (bar - last) as f64)
+                        })
+                        .collect(),
+                ))
+            }
+        }
+    }
+
+    /// A field of the bars' open time.
+    fn time(candles: &Candles, field: fn(DateTime<chrono::Utc>) -> u32) -> Self {
+        Operand::Series(
+            candles
+                .timestamps
+                .iter()
+                .map(|&t| DateTime::from_timestamp(t, 0).map_or(f64::NAN, |t| field(t) as f64))
+                .collect(),
+        )
+    }
+
+    fn is_stateful(&self) -> bool {
+        matches!(self, Operand::State(_) | Operand::Binary(..))
+    }
+
+    /// The operand `n` bars back, missing on the first `n` bars.
+    fn lag(self, n: usize, len: usize) -> Self {
+        match self {
+            Operand::Const(_) => self,
+            _ if n == 0 => self,
+            _ => Operand::Series(
+                (0..len)
+                    .map(|i| if i >= n { self.at(i - n) } else { f64::NAN })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The value on `bar` of an operand that does not depend on the position.
+    pub(crate) fn at(&self, bar: usize) -> f64 {
+        self.value(bar, &Context::FLAT)
+    }
+
+    fn value(&self, bar: usize, context: &Context) -> f64 {
+        match self {
+            Operand::Const(n) => *n,
+            Operand::Column(col) => col[bar],
+            Operand::Series(series) => series[bar],
+            Operand::State(var) => context.get(*var),
+            Operand::Binary(f, l, r) => f(l.value(bar, context), r.value(bar, context)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum CmpOp {
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Neq,
+}
+
+impl CmpOp {
+    fn apply(self, l: f64, r: f64) -> bool {
+        // Missing values (indicator warm-up, holes in the dataset) never satisfy anything.
+        if l.is_nan() || r.is_nan() {
+            return false;
+        }
+        match self {
+            CmpOp::Lt => l < r,
+            CmpOp::Gt => l > r,
+            CmpOp::Le => l <= r,
+            CmpOp::Ge => l >= r,
+            CmpOp::Eq => l == r,
+            CmpOp::Neq => l != r,
+        }
+    }
+}
+
+/// A `Cond` tree compiled against a candle series, so evaluating it on a bar does not need any
+/// lookup by indicator name.
+#[derive(Debug, Clone)]
+pub(crate) enum CompiledCond<'a> {
+    And(Vec<CompiledCond<'a>>),
+    Or(Vec<CompiledCond<'a>>),
+    Not(Box<CompiledCond<'a>>),
+    Cmp {
+        op: CmpOp,
+        l: Operand<'a>,
+        r: Operand<'a>,
+    },
+    Between {
+        val: Operand<'a>,
+        min: Operand<'a>,
+        max: Operand<'a>,
+    },
+    CrossesAbove {
+        l: Operand<'a>,
+        r: Operand<'a>,
+    },
+    CrossesBelow {
+        l: Operand<'a>,
+        r: Operand<'a>,
+    },
+    InPosition,
+}
+
+impl<'a> CompiledCond<'a> {
+    pub(crate) fn compile(cond: &Cond, candles: &'a Candles) -> Result<Self, BacktestError> {
+        let cmp = |op, l: &Value, r: &Value| -> Result<Self, BacktestError> {
+            Ok(CompiledCond::Cmp {
+                op,
+                l: Operand::compile_stateful(l, candles)?,
+                r: Operand::compile_stateful(r, candles)?,
+            })
+        };
+
+        match cond {
+            Cond::And { conds } => Ok(CompiledCond::And(
+                conds
+                    .iter()
+                    .map(|c| Self::compile(c, candles))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Cond::Or { conds } => Ok(CompiledCond::Or(
+                conds
+                    .iter()
+                    .map(|c| Self::compile(c, candles))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Cond::Not { cond } => Ok(CompiledCond::Not(Box::new(Self::compile(cond, candles)?))),
+            Cond::LessThan { l, r } => cmp(CmpOp::Lt, l, r),
+            Cond::GreaterThan { l, r } => cmp(CmpOp::Gt, l, r),
+            Cond::LessThanOrEqual { l, r } => cmp(CmpOp::Le, l, r),
+            Cond::GreaterThanOrEqual { l, r } => cmp(CmpOp::Ge, l, r),
+            Cond::Equal { l, r } => cmp(CmpOp::Eq, l, r),
+            Cond::NotEqual { l, r } => cmp(CmpOp::Neq, l, r),
+            Cond::Between { val, min, max } => Ok(CompiledCond::Between {
+                val: Operand::compile_stateful(val, candles)?,
+                min: Operand::compile_stateful(min, candles)?,
+                max: Operand::compile_stateful(max, candles)?,
+            }),
+            Cond::CrossesAbove { l, r } => Ok(CompiledCond::CrossesAbove {
+                l: Operand::compile_stateful(l, candles)?,
+                r: Operand::compile_stateful(r, candles)?,
+            }),
+            Cond::CrossesBelow { l, r } => Ok(CompiledCond::CrossesBelow {
+                l: Operand::compile_stateful(l, candles)?,
+                r: Operand::compile_stateful(r, candles)?,
+            }),
+            Cond::InPosition => Ok(CompiledCond::InPosition),
+            // The actions' conditions are expanded before being compiled.
+            Cond::Ref(name) => Err(DefinitionError::Undefined(name.clone()).into()),
+        }
+    }
+
+    /// Evaluate the condition on the close of bar `bar`, in the trading state `context`.
+    pub(crate) fn eval(&self, bar: usize, context: &Context) -> bool {
+        let at = |operand: &Operand, bar| operand.value(bar, context);
+        match self {
+            CompiledCond::And(conds) => conds.iter().all(|c| c.eval(bar, context)),
+            CompiledCond::Or(conds) => conds.iter().any(|c| c.eval(bar, context)),
+            CompiledCond::Not(cond) => !cond.eval(bar, context),
+            CompiledCond::Cmp { op, l, r } => op.apply(at(l, bar), at(r, bar)),
+            CompiledCond::Between { val, min, max } => {
+                let v = at(val, bar);
+                CmpOp::Ge.apply(v, at(min, bar)) && CmpOp::Le.apply(v, at(max, bar))
+            }
+            // A cross needs the previous bar, so it can never happen on the first one. The state
+            // of the position on the previous bar is not kept, it is taken as the current one.
+            CompiledCond::CrossesAbove { l, r } => {
+                bar > 0
+                    && CmpOp::Le.apply(at(l, bar - 1), at(r, bar - 1))
+                    && CmpOp::Gt.apply(at(l, bar), at(r, bar))
+            }
+            CompiledCond::CrossesBelow { l, r } => {
+                bar > 0
+                    && CmpOp::Ge.apply(at(l, bar - 1), at(r, bar - 1))
+                    && CmpOp::Lt.apply(at(l, bar), at(r, bar))
+            }
+            CompiledCond::InPosition => context.in_position,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candles() -> Candles {
+        let close = vec![10.0, 12.0, 11.0, 15.0];
+        let mut candles = Candles {
+            timestamps: vec![0, 60, 120, 180],
+            open: close.clone(),
+            high: close.clone(),
+            low: close.clone(),
+            close,
+            volume: vec![1.0; 4],
+            ..Default::default()
+        };
+        candles
+            .indicators
+            .insert("sma".to_string(), vec![10.0, 11.0, 11.0, 12.0]);
+        candles
+    }
+
+    #[test]
+    fn test_expressions() {
+        let candles = candles();
+        let cond: Cond = serde_json::from_str(
+            r#"{ "gt": { "l": "close", "r": { "mul": { "l": "sma", "r": 1.2 } } } }"#,
+        )
+        .unwrap();
+        let cond = CompiledCond::compile(&cond, &candles).unwrap();
+        assert_eq!(
+            (0..4)
+                .map(|bar| cond.eval(bar, &Context::FLAT))
+                .collect::<Vec<_>>(),
+            [false, false, false, true]
+        );
+
+        // Missing on the first bar, which fails the comparison.
+        let cond: Cond = serde_json::from_str(
+            r#"{ "gt": { "l": { "sub": { "l": "close", "r": "close[1]" } }, "r": 1 } }"#,
+        )
+        .unwrap();
+        let cond = CompiledCond::compile(&cond, &candles).unwrap();
+        assert_eq!(
+            (0..4)
+                .map(|bar| cond.eval(bar, &Context::FLAT))
+                .collect::<Vec<_>>(),
+            [false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn test_state() {
+        let candles = candles();
+        let value: Value = serde_json::from_str(
+            r#"{ "bars_since": { "cond": { "gt": { "l": "close", "r": "sma" } } } }"#,
+        )
+        .unwrap();
+        let bars_since = Operand::compile(&value, &candles).unwrap();
+        assert!(bars_since.at(0).is_nan());
+        assert_eq!(
+            (1..4).map(|bar| bars_since.at(bar)).collect::<Vec<_>>(),
+            [0.0, 1.0, 0.0]
+        );
+        // 1970-01-01 was a Thursday.
+        let value: Value = serde_json::from_str(r#"{ "state": "weekday" }"#).unwrap();
+        assert_eq!(Operand::compile(&value, &candles).unwrap().at(2), 3.0);
+
+        let cond: Cond = serde_json::from_str(
+            r#"{ "or": { "conds": [
+                { "ge": { "l": { "add": { "l": { "state": "bars_since_entry" }, "r": 1 } }, "r": 3 } },
+                { "lt": { "l": { "state": "pnl_pct" }, "r": -5 } }
+            ] } }"#,
+        )
+        .unwrap();
+        let cond = CompiledCond::compile(&cond, &candles).unwrap();
+        let context = |opened_at, pnl_pct| Context::new(&candles, 3, opened_at, pnl_pct, &[]);
+        assert!(!cond.eval(3, &Context::FLAT));
+        assert!(!cond.eval(3, &context(Some(120), 0.0)));
+        assert!(cond.eval(3, &context(Some(60), 0.0)));
+        assert!(cond.eval(3, &context(Some(180), -6.0)));
+    }
+
+    #[test]
+    fn test_parse_indicator() {
+        let parse = |reference| {
+            let IndicatorRef {
+                name,
+                timeframe,
+                lag,
+            } = Value::parse_indicator(reference);
+            (name, timeframe, lag)
+        };
+        assert_eq!(parse("rsi"), ("rsi", None, 0));
+        assert_eq!(parse("rsi[2]"), ("rsi", None, 2));
+        assert_eq!(parse("rsi[x]"), ("rsi[x]", None, 0));
+        assert_eq!(parse("[2]"), ("[2]", None, 0));
+        assert_eq!(parse("sma_50@4h[1]"), ("sma_50", Some("4h"), 1));
+        assert_eq!(parse("sma_50@"), ("sma_50@", None, 0));
+
+        let candles = candles();
+        let lagged =
+            Operand::compile_stateful(&Value::Indicator("sma[1]".to_string()), &candles).unwrap();
+        assert!(lagged.at(0).is_nan());
+        assert_eq!(lagged.at(3), 11.0);
+        assert!(matches!(
+            Operand::compile_stateful(&Value::Indicator("foo[1]".to_string()), &candles),
+            Err(BacktestError::UnknownIndicator(name)) if name == "foo"
+        ));
+    }
+
+    #[test]
+    fn test_timeframes() {
+        let mut candles = candles();
+        let close = vec![5.0, 7.0];
+        candles.timeframes.insert(
+            "2m".to_string(),
+            Candles {
+                timestamps: vec![0, 120],
+                close,
+                ..Default::default()
+            },
+        );
+        let values = |reference: &str| {
+            let operand =
+                Operand::compile_stateful(&Value::Indicator(reference.to_string()), &candles)?;
+            Ok::<_, BacktestError>((0..4).map(|bar| operand.at(bar)).collect::<Vec<_>>())
+        };
+
+        // A 2m bar is only seen from the 1m bar it closes with.
+        let aligned = values("close@2m").unwrap();
+        assert!(aligned[0].is_nan());
+        assert_eq!(aligned[1..], [5.0, 5.0, 7.0]);
+
+        let lagged = values("close@2m[1]").unwrap();
+        assert!(lagged[..3].iter().all(|v| v.is_nan()));
+        assert_eq!(lagged[3], 5.0);
+
+        assert!(matches!(
+            values("close@5m"),
+            Err(BacktestError::UnknownTimeframe(tf)) if tf == "5m"
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::BacktestError;
+
+/// Trading costs applied to every fill.
+///
+/// Everything defaults to zero, so a backtest without execution settings trades at the raw
+/// candle prices.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecutionConfig {
+    /// Fee of the orders resting in the book, in basis points of the notional.
+    pub maker_fee_bps: f64,
+    /// Fee of the orders taking liquidity (every market order), in basis points of the notional.
+    pub taker_fee_bps: f64,
+    pub slippage: SlippageModel,
+    pub spread: SpreadModel,
+}
+
+/// How far from the quoted price a market order gets filled, always against the trader.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "lowercase")]
+pub enum SlippageModel {
+    #[default]
+    None,
+    /// Constant, in basis points of the price.
+    Fixed { bps: f64 },
+    /// `factor` times the relative range (high - low) / close of the bar the signal fired on.
+    Volatility { factor: f64 },
+}
+
+/// Distance between the bid and the ask. Buys pay half of it above the price, sells receive half
+/// of it below.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "lowercase")]
+pub enum SpreadModel {
+    #[default]
+    None,
+    /// Constant full spread, in basis points of the price.
+    Fixed { bps: f64 },
+}
+
+/// Price adjustments of one fill, as fractions of the quoted price.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct FillCosts {
+    pub fee_rate: f64,
+    pub slippage: f64,
+    pub half_spread: f64,
+}
+
+impl ExecutionConfig {
+    pub fn validate(&self) -> Result<(), BacktestError> {
+        let check = |name: &str, value: f64| {
+            if value.is_finite() && value >= 0.0 {
+                Ok(())
+            } else {
+                Err(BacktestError::InvalidExecution(format!(
+                    "{} must be a positive number, got {}",
+                    name, value
+                )))
+            }
+        };
+
+        check("maker_fee_bps", self.maker_fee_bps)?;
+        check("taker_fee_bps", self.taker_fee_bps)?;
+        match self.slippage {
+            SlippageModel::None => {}
+            SlippageModel::Fixed { bps } => check("slippage bps", bps)?,
+            SlippageModel::Volatility { factor } => check("slippage factor", factor)?,
+        }
+        match self.spread {
+            SpreadModel::None => {}
+            SpreadModel::Fixed { bps } => check("spread bps", bps)?,
+        }
+
+        Ok(())
+    }
+
+    /// Costs of an order that was resting in the book.
+    pub(crate) fn maker_costs(&self) -> FillCosts {
+        FillCosts {
+            fee_rate: self.maker_fee_bps / 10_000.0,
+            slippage: 0.0,
+            half_spread: 0.0,
+        }
+    }
+
+    /// Costs of a market order, `volatility` being the relative range of the signal bar.
+    pub(crate) fn taker_costs(&self, volatility: f64) -> FillCosts {
+        let slippage = match self.slippage {
+            SlippageModel::None => 0.0,
+            SlippageModel::Fixed { bps } => bps / 10_000.0,
+            SlippageModel::Volatility { factor } if volatility.is_finite() => factor * volatility,
+            SlippageModel::Volatility { .. } => 0.0,
+        };
+        let half_spread = match self.spread {
+            SpreadModel::None => 0.0,
+            SpreadModel::Fixed { bps } => bps / 10_000.0 / 2.0,
+        };
+
+        FillCosts {
+            fee_rate: self.taker_fee_bps / 10_000.0,
+            slippage,
+            half_spread,
+        }
+    }
+}
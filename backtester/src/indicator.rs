@@ -0,0 +1,758 @@
+//! Registry of the technical indicators the engine can compute from the candles, for the ones a
+//! dataset does not precompute.
+//!
+//! An indicator is referenced either by name, `{kind}[_{output}][_{param}...]` with positional
+//! parameters (`ema_21`, `macd_hist_12_26_9`, `bb_upper_20_2`), or by an `IndicatorSpec` with
+//! named parameters. Parameters left out take the usual defaults (`rsi` is `rsi_14`) except the
+//! period of the moving averages, which has to be given.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::dataset::{Candles, SECONDS_PER_DAY};
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum IndicatorError {
+    #[error("unknown indicator '{0}'")]
+    Unknown(String),
+
+    #[error("{indicator}: {param} {reason}")]
+    InvalidParameter {
+        indicator: String,
+        param: String,
+        reason: String,
+    },
+}
+
+/// Longest period, or any other length, an indicator can take. Some are computed over their
+/// whole window at every bar.
+pub const MAX_PERIOD: u32 = 10_000;
+
+/// Structured reference to an indicator, e.g. `{ "ind": "bb", "period": 20, "dev": 2, "out":
+/// "upper" }`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndicatorSpec {
+    pub ind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fast: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<u32>,
+    /// Number of standard deviations of the Bollinger bands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dev: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub d: Option<u32>,
+    /// Output of the indicators that have several.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out: Option<String>,
+    /// Timeframe to compute the indicator on, the backtest's own if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tf: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacdOutput {
+    Line,
+    Signal,
+    Hist,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandOutput {
+    Upper,
+    Middle,
+    Lower,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StochOutput {
+    K,
+    D,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indicator {
+    Sma {
+        period: u32,
+    },
+    Ema {
+        period: u32,
+    },
+    Wma {
+        period: u32,
+    },
+    Rsi {
+        period: u32,
+    },
+    Macd {
+        fast: u32,
+        slow: u32,
+        signal: u32,
+        out: MacdOutput,
+    },
+    Bb {
+        period: u32,
+        dev: f64,
+        out: BandOutput,
+    },
+    Atr {
+        period: u32,
+    },
+    Adx {
+        period: u32,
+    },
+    Stoch {
+        k: u32,
+        d: u32,
+        out: StochOutput,
+    },
+    /// Volume weighted average price since the start of the (UTC) day.
+    Vwap,
+    Obv,
+}
+
+/// Names of the parameters of an indicator kind, in the order they appear in a name.
+fn positional_params(kind: &str) -> Option<&'static [&'static str]> {
+    match kind {
+        "sma" | "ema" | "wma" | "rsi" | "atr" | "adx" => Some(&["period"]),
+        "macd" => Some(&["fast", "slow", "signal"]),
+        "bb" => Some(&["period", "dev"]),
+        "stoch" => Some(&["k", "d"]),
+        "vwap" | "obv" => Some(&[]),
+        _ => None,
+    }
+}
+
+impl Indicator {
+    /// Parse a name such as `ema_21` or `bb_lower_20_2.5`.
+    pub fn parse(name: &str) -> Result<Self, IndicatorError> {
+        let mut tokens = name.split('_').peekable();
+        let kind = tokens.next().unwrap_or_default();
+        let params =
+            positional_params(kind).ok_or_else(|| IndicatorError::Unknown(name.to_string()))?;
+        let invalid = |param: &str, reason: String| IndicatorError::InvalidParameter {
+            indicator: name.to_string(),
+            param: param.to_string(),
+            reason,
+        };
+
+        let mut spec = IndicatorSpec {
+            ind: kind.to_string(),
+            ..Default::default()
+        };
+        if matches!(kind, "macd" | "bb" | "stoch")
+            && let Some(out) = tokens.next_if(|t| t.starts_with(|c: char| c.is_ascii_alphabetic()))
+        {
+            spec.out = Some(out.to_string());
+        }
+
+        let values: Vec<&str> = tokens.collect();
+        if values.len() > params.len() {
+            return Err(invalid(
+                "parameters",
+                format!("expects at most {}, got {}", params.len(), values.len()),
+            ));
+        }
+        for (param, value) in params.iter().zip(values) {
+            if *param == "dev" {
+                spec.dev =
+                    Some(value.parse().map_err(|_| {
+                        invalid(param, format!("must be a number, got '{}'", value))
+                    })?);
+                continue;
+            }
+            let value: u32 = value
+                .parse()
+                .map_err(|_| invalid(param, format!("must be a whole number, got '{}'", value)))?;
+            match *param {
+                "period" => spec.period = Some(value),
+                "fast" => spec.fast = Some(value),
+                "slow" => spec.slow = Some(value),
+                "signal" => spec.signal = Some(value),
+                "k" => spec.k = Some(value),
+                _ => spec.d = Some(value),
+            }
+        }
+
+        Self::from_spec(&spec).map_err(|e| match e {
+            IndicatorError::InvalidParameter { param, reason, .. } => invalid(&param, reason),
+            e => e,
+        })
+    }
+
+    pub fn from_spec(spec: &IndicatorSpec) -> Result<Self, IndicatorError> {
+        let kind = spec.ind.as_str();
+        let params =
+            positional_params(kind).ok_or_else(|| IndicatorError::Unknown(spec.ind.clone()))?;
+        let invalid = |param: &str, reason: String| IndicatorError::InvalidParameter {
+            indicator: spec.ind.clone(),
+            param: param.to_string(),
+            reason,
+        };
+
+        let given = [
+            ("period", spec.period.is_some()),
+            ("fast", spec.fast.is_some()),
+            ("slow", spec.slow.is_some()),
+            ("signal", spec.signal.is_some()),
+            ("dev", spec.dev.is_some()),
+            ("k", spec.k.is_some()),
+            ("d", spec.d.is_some()),
+        ];
+        if let Some((param, _)) = given
+            .iter()
+            .find(|(param, given)| *given && !params.contains(param))
+        {
+            return Err(invalid(param, format!("is not a parameter of {}", kind)));
+        }
+
+        let length = |param: &str, value: Option<u32>, default: Option<u32>| match value.or(default)
+        {
+            None => Err(invalid(param, "is required".to_string())),
+            Some(0) => Err(invalid(param, "must be at least 1".to_string())),
+            Some(n) if n > MAX_PERIOD => {
+                Err(invalid(param, format!("must be at most {}", MAX_PERIOD)))
+            }
+            Some(n) => Ok(n),
+        };
+        let out = |outputs: &[&str]| -> Result<usize, IndicatorError> {
+            match &spec.out {
+                None => Ok(0),
+                Some(out) => outputs.iter().position(|o| o == out).ok_or_else(|| {
+                    invalid(
+                        "out",
+                        format!("must be one of {}, got '{}'", outputs.join(", "), out),
+                    )
+                }),
+            }
+        };
+        if matches!(
+            kind,
+            "sma" | "ema" | "wma" | "rsi" | "atr" | "adx" | "vwap" | "obv"
+        ) {
+            out(&[]).map_err(|_| invalid("out", format!("{} has a single output", kind)))?;
+        }
+
+        Ok(match kind {
+            "sma" => Indicator::Sma {
+                period: length("period", spec.period, None)?,
+            },
+            "ema" => Indicator::Ema {
+                period: length("period", spec.period, None)?,
+            },
+            "wma" => Indicator::Wma {
+                period: length("period", spec.period, None)?,
+            },
+            "rsi" => Indicator::Rsi {
+                period: length("period", spec.period, Some(14))?,
+            },
+            "atr" => Indicator::Atr {
+                period: length("period", spec.period, Some(14))?,
+            },
+            "adx" => Indicator::Adx {
+                period: length("period", spec.period, Some(14))?,
+            },
+            "macd" => {
+                let fast = length("fast", spec.fast, Some(12))?;
+                let slow = length("slow", spec.slow, Some(26))?;
+                if fast >= slow {
+                    return Err(invalid(
+                        "fast",
+                        format!("must be below slow ({}), got {}", slow, fast),
+                    ));
+                }
+                Indicator::Macd {
+                    fast,
+                    slow,
+                    signal: length("signal", spec.signal, Some(9))?,
+                    out: [MacdOutput::Line, MacdOutput::Signal, MacdOutput::Hist]
+                        [out(&["line", "signal", "hist"])?],
+                }
+            }
+            "bb" => {
+                let dev = spec.dev.unwrap_or(2.0);
+                if !(dev.is_finite() && dev > 0.0) {
+                    return Err(invalid("dev", format!("must be positive, got {}", dev)));
+                }
+                Indicator::Bb {
+                    period: length("period", spec.period, Some(20))?,
+                    dev,
+                    out: [BandOutput::Middle, BandOutput::Upper, BandOutput::Lower]
+                        [out(&["middle", "upper", "lower"])?],
+                }
+            }
+            "stoch" => Indicator::Stoch {
+                k: length("k", spec.k, Some(14))?,
+                d: length("d", spec.d, Some(3))?,
+                out: [StochOutput::K, StochOutput::D][out(&["k", "d"])?],
+            },
+            "vwap" => Indicator::Vwap,
+            _ => Indicator::Obv,
+        })
+    }
+
+    /// Values of the indicator on every bar, `NaN` while it warms up.
+    pub fn compute(&self, candles: &Candles) -> Vec<f64> {
+        let close = &candles.close;
+        match *self {
+            Indicator::Sma { period } => sma(close, period as usize),
+            Indicator::Ema { period } => ema(close, period as usize),
+            Indicator::Wma { period } => wma(close, period as usize),
+            Indicator::Rsi { period } => rsi(close, period as usize),
+            Indicator::Macd {
+                fast,
+                slow,
+                signal,
+                out,
+            } => {
+                let line: Vec<f64> = ema(close, fast as usize)
+                    .iter()
+                    .zip(ema(close, slow as usize))
+                    .map(|(fast, slow)| fast - slow)
+                    .collect();
+                if out == MacdOutput::Line {
+                    return line;
+                }
+                let signal = ema(&line, signal as usize);
+                match out {
+                    MacdOutput::Hist => line.iter().zip(&signal).map(|(l, s)| l - s).collect(),
+                    _ => signal,
+                }
+            }
+            Indicator::Bb { period, dev, out } => {
+                let period = period as usize;
+                let middle = sma(close, period);
+                if out == BandOutput::Middle {
+                    return middle;
+                }
+                let sign = if out == BandOutput::Upper { 1.0 } else { -1.0 };
+                let mean_sq = sma(&close.iter().map(|c| c * c).collect::<Vec<_>>(), period);
+                middle
+                    .iter()
+                    .zip(mean_sq)
+                    .map(|(m, sq)| m + sign * dev * (sq - m * m).max(0.0).sqrt())
+                    .collect()
+            }
+            Indicator::Atr { period } => rma(&true_range(candles), period as usize),
+            Indicator::Adx { period } => adx(candles, period as usize),
+            Indicator::Stoch { k, d, out } => {
+                let k = stoch_k(candles, k as usize);
+                match out {
+                    StochOutput::K => k,
+                    StochOutput::D => sma(&k, d as usize),
+                }
+            }
+            Indicator::Vwap => vwap(candles),
+            Indicator::Obv => obv(candles),
+        }
+    }
+}
+
+/// Canonical name, which is what the dataset-manager names a precomputed indicator. Default
+/// outputs are left out.
+impl fmt::Display for Indicator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Indicator::Sma { period } => write!(f, "sma_{}", period),
+            Indicator::Ema { period } => write!(f, "ema_{}", period),
+            Indicator::Wma { period } => write!(f, "wma_{}", period),
+            Indicator::Rsi { period } => write!(f, "rsi_{}", period),
+            Indicator::Atr { period } => write!(f, "atr_{}", period),
+            Indicator::Adx { period } => write!(f, "adx_{}", period),
+            Indicator::Macd {
+                fast,
+                slow,
+                signal,
+                out,
+            } => match out {
+                MacdOutput::Line => write!(f, "macd_{}_{}_{}", fast, slow, signal),
+                MacdOutput::Signal => write!(f, "macd_signal_{}_{}_{}", fast, slow, signal),
+                MacdOutput::Hist => write!(f, "macd_hist_{}_{}_{}", fast, slow, signal),
+            },
+            Indicator::Bb { period, dev, out } => match out {
+                BandOutput::Middle => write!(f, "bb_{}_{}", period, dev),
+                BandOutput::Upper => write!(f, "bb_upper_{}_{}", period, dev),
+                BandOutput::Lower => write!(f, "bb_lower_{}_{}", period, dev),
+            },
+            Indicator::Stoch { k, d, out } => match out {
+                StochOutput::K => write!(f, "stoch_{}_{}", k, d),
+                StochOutput::D => write!(f, "stoch_d_{}_{}", k, d),
+            },
+            Indicator::Vwap => write!(f, "vwap"),
+            Indicator::Obv => write!(f, "obv"),
+        }
+    }
+}
+
+fn sma(values: &[f64], period: usize) -> Vec<f64> {
+    let mut out = vec![f64::NAN; values.len()];
+    let (mut sum, mut missing) = (0.0, 0);
+    for i in 0..values.len() {
+        if values[i].is_nan() {
+            missing += 1;
+        } else {
+            sum += values[i];
+        }
+        if i >= period {
+            if values[i - period].is_nan() {
+                missing -= 1;
+            } else {
+                sum -= values[i - period];
+            }
+        }
+        if i + 1 >= period && missing == 0 {
+            out[i] = sum / period as f64;
+        }
+    }
+    out
+}
+
+/// Exponential smoothing with factor `alpha`, seeded with the mean of the first `period` values.
+/// A missing value restarts it.
+fn smooth(values: &[f64], period: usize, alpha: f64) -> Vec<f64> {
+    let mut out = vec![f64::NAN; values.len()];
+    let mut run = 0;
+    let mut prev = f64::NAN;
+    for (i, &v) in values.iter().enumerate() {
+        if v.is_nan() {
+            run = 0;
+            prev = f64::NAN;
+            continue;
+        }
+        run += 1;
+        if run == period {
+            prev = values[i + 1 - period..=i].iter().sum::<f64>() / period as f64;
+        } else if run > period {
+            prev += alpha * (v - prev);
+        }
+        out[i] = prev;
+    }
+    out
+}
+
+fn ema(values: &[f64], period: usize) -> Vec<f64> {
+    smooth(values, period, 2.0 / (period as f64 + 1.0))
+}
+
+/// Wilder's moving average.
+fn rma(values: &[f64], period: usize) -> Vec<f64> {
+    smooth(values, period, 1.0 / period as f64)
+}
+
+fn wma(values: &[f64], period: usize) -> Vec<f64> {
+    let weights = (period * (period + 1) / 2) as f64;
+    let mut out = vec![f64::NAN; values.len()];
+    // Sums of the last `period` values, the weighted one giving the newest the weight `period`.
+    let (mut sum, mut weighted, mut missing) = (0.0, 0.0, 0);
+    for i in 0..values.len() {
+        // Every value loses one of weight, the oldest leaving the window with none.
+        weighted -= sum;
+        if i >= period {
+            if values[i - period].is_nan() {
+                missing -= 1;
+            } else {
+                sum -= values[i - period];
+            }
+        }
+        if values[i].is_nan() {
+            missing += 1;
+        } else {
+            sum += values[i];
+            weighted += period as f64 * values[i];
+        }
+        if i + 1 >= period && missing == 0 {
+            out[i] = weighted / weights;
+        }
+    }
+    out
+}
+
+fn rsi(close: &[f64], period: usize) -> Vec<f64> {
+    let (mut gains, mut losses) = (vec![f64::NAN; close.len()], vec![f64::NAN; close.len()]);
+    for i in 1..close.len() {
+        let change = close[i] - close[i - 1];
+        gains[i] = change.max(0.0);
+        losses[i] = (-change).max(0.0);
+    }
+
+    rma(&gains, period)
+        .iter()
+        .zip(rma(&losses, period))
+        .map(|(gain, loss)| {
+            if loss == 0.0 {
+                if gain.is_nan() { f64::NAN } else { 100.0 }
+            } else {
+                100.0 - 100.0 / (1.0 + gain / loss)
+            }
+        })
+        .collect()
+}
+
+fn true_range(candles: &Candles) -> Vec<f64> {
+    (0..candles.len())
+        .map(|i| {
+            let range = candles.high[i] - candles.low[i];
+            if i == 0 {
+                return range;
+            }
+            let prev_close = candles.close[i - 1];
+            range
+                .max((candles.high[i] - prev_close).abs())
+                .max((candles.low[i] - prev_close).abs())
+        })
+        .collect()
+}
+
+fn adx(candles: &Candles, period: usize) -> Vec<f64> {
+    let n = candles.len();
+    let (mut plus_dm, mut minus_dm) = (vec![f64::NAN; n], vec![f64::NAN; n]);
+    let mut tr = true_range(candles);
+    if let Some(first) = tr.first_mut() {
+        *first = f64::NAN;
+    }
+    for i in 1..n {
+        let up = candles.high[i] - candles.high[i - 1];
+        let down = candles.low[i - 1] - candles.low[i];
+        plus_dm[i] = if up > down && up > 0.0 { up } else { 0.0 };
+        minus_dm[i] = if down > up && down > 0.0 { down } else { 0.0 };
+    }
+
+    let (tr, plus_dm, minus_dm) = (
+        rma(&tr, period),
+        rma(&plus_dm, period),
+        rma(&minus_dm, period),
+    );
+    let dx: Vec<f64> = (0..n)
+        .map(|i| {
+            let plus_di = 100.0 * plus_dm[i] / tr[i];
+            let minus_di = 100.0 * minus_dm[i] / tr[i];
+            let sum = plus_di + minus_di;
+            if sum == 0.0 {
+                0.0
+            } else {
+                100.0 * (plus_di - minus_di).abs() / sum
+            }
+        })
+        .collect();
+    rma(&dx, period)
+}
+
+/// Position of the close in the high-low range of the last `period` bars, in percent.
+fn stoch_k(candles: &Candles, period: usize) -> Vec<f64> {
+    (0..candles.len())
+        .map(|i| {
+            if i + 1 < period {
+                return f64::NAN;
+            }
+            let window = i + 1 - period..=i;
+            let highest = candles.high[window.clone()]
+                .iter()
+                .fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+            let lowest = candles.low[window]
+                .iter()
+                .fold(f64::INFINITY, |a, &b| a.min(b));
+            if highest == lowest {
+                50.0
+            } else {
+                100.0 * (candles.close[i] - lowest) / (highest - lowest)
+            }
+        })
+        .collect()
+}
+
+fn vwap(candles: &Candles) -> Vec<f64> {
+    let mut out = Vec::with_capacity(candles.len());
+    let (mut day, mut value, mut volume) = (None, 0.0, 0.0);
+    for i in 0..candles.len() {
+        let today = candles.timestamps[i].div_euclid(SECONDS_PER_DAY);
+        if day != Some(today) {
+            day = Some(today);
+            value = 0.0;
+            volume = 0.0;
+        }
+        let typical = (candles.high[i] + candles.low[i] + candles.close[i]) / 3.0;
+        value += typical * candles.volume[i];
+        volume += candles.volume[i];
+        out.push(if volume > 0.0 {
+            value / volume
+        } else {
+            f64::NAN
+        });
+    }
+    out
+}
+
+fn obv(candles: &Candles) -> Vec<f64> {
+    let mut out = Vec::with_capacity(candles.len());
+    let mut obv = 0.0;
+    for i in 0..candles.len() {
+        if i > 0 {
+            let change = candles.close[i] - candles.close[i - 1];
+            if change > 0.0 {
+                obv += candles.volume[i];
+            } else if change < 0.0 {
+                obv -= candles.volume[i];
+            }
+        }
+        out.push(obv);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(values: &[f64], expected: &[f64]) {
+        assert_eq!(values.len(), expected.len());
+        for (v, e) in values.iter().zip(expected) {
+            assert!(
+                (v.is_nan() && e.is_nan()) || (v - e).abs() < 1e-9,
+                "{:?} != {:?}",
+                values,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Indicator::parse("ema_21"),
+            Ok(Indicator::Ema { period: 21 })
+        );
+        assert_eq!(Indicator::parse("rsi"), Ok(Indicator::Rsi { period: 14 }));
+        assert_eq!(
+            Indicator::parse("macd_hist"),
+            Ok(Indicator::Macd {
+                fast: 12,
+                slow: 26,
+                signal: 9,
+                out: MacdOutput::Hist
+            })
+        );
+        assert_eq!(
+            Indicator::parse("bb_upper_20_2.5"),
+            Ok(Indicator::Bb {
+                period: 20,
+                dev: 2.5,
+                out: BandOutput::Upper
+            })
+        );
+        assert_eq!(
+            Indicator::parse("foo_3"),
+            Err(IndicatorError::Unknown("foo_3".to_string()))
+        );
+
+        let error = |name| Indicator::parse(name).unwrap_err().to_string();
+        assert_eq!(error("sma"), "sma: period is required");
+        assert_eq!(error("sma_0"), "sma_0: period must be at least 1");
+        assert_eq!(
+            error("sma_x"),
+            "sma_x: period must be a whole number, got 'x'"
+        );
+        assert_eq!(
+            error("macd_26_12"),
+            "macd_26_12: fast must be below slow (12), got 26"
+        );
+        assert_eq!(
+            error("bb_top_20"),
+            "bb_top_20: out must be one of middle, upper, lower, got 'top'"
+        );
+
+        // Canonical names parse back to the same indicator.
+        for name in [
+            "sma_10",
+            "macd_signal_12_26_9",
+            "bb_lower_20_2",
+            "stoch_d_14_3",
+            "obv",
+        ] {
+            assert_eq!(Indicator::parse(name).unwrap().to_string(), name);
+        }
+    }
+
+    #[test]
+    fn test_from_spec() {
+        let spec: IndicatorSpec =
+            serde_json::from_str(r#"{ "ind": "bb", "period": 20, "dev": 2, "out": "upper" }"#)
+                .unwrap();
+        assert_eq!(
+            Indicator::from_spec(&spec).unwrap().to_string(),
+            "bb_upper_20_2"
+        );
+
+        let spec: IndicatorSpec =
+            serde_json::from_str(r#"{ "ind": "rsi", "period": 14, "dev": 2 }"#).unwrap();
+        assert_eq!(
+            Indicator::from_spec(&spec).unwrap_err().to_string(),
+            "rsi: dev is not a parameter of rsi"
+        );
+
+        let spec: IndicatorSpec =
+            serde_json::from_str(r#"{ "ind": "wma", "period": 10001 }"#).unwrap();
+        assert_eq!(
+            Indicator::from_spec(&spec).unwrap_err().to_string(),
+            "wma: period must be at most 10000"
+        );
+    }
+
+    #[test]
+    fn test_compute() {
+        let close = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let candles = Candles {
+            timestamps: vec![0, 60, 120, 180, 240],
+            open: close.to_vec(),
+            high: close.iter().map(|c| c + 1.0).collect(),
+            low: close.iter().map(|c| c - 1.0).collect(),
+            close: close.to_vec(),
+            volume: vec![1.0, 2.0, 1.0, 2.0, 1.0],
+            ..Default::default()
+        };
+        let nan = f64::NAN;
+
+        assert_close(
+            &Indicator::Sma { period: 3 }.compute(&candles),
+            &[nan, nan, 2.0, 3.0, 4.0],
+        );
+        // Seeded with 2, then halfway to the close.
+        assert_close(
+            &Indicator::Ema { period: 3 }.compute(&candles),
+            &[nan, nan, 2.0, 3.0, 4.0],
+        );
+        assert_close(
+            &Indicator::Wma { period: 2 }.compute(&candles),
+            &[nan, 5.0 / 3.0, 8.0 / 3.0, 11.0 / 3.0, 14.0 / 3.0],
+        );
+        assert_close(
+            &Indicator::Wma { period: 3 }.compute(&candles),
+            &[nan, nan, 14.0 / 6.0, 20.0 / 6.0, 26.0 / 6.0],
+        );
+        // Only gains.
+        assert_close(
+            &Indicator::Rsi { period: 2 }.compute(&candles),
+            &[nan, nan, 100.0, 100.0, 100.0],
+        );
+        assert_close(
+            &Indicator::Atr { period: 2 }.compute(&candles),
+            &[nan, 2.0, 2.0, 2.0, 2.0],
+        );
+        assert_close(
+            &Indicator::Obv.compute(&candles),
+            &[0.0, 2.0, 3.0, 5.0, 6.0],
+        );
+        assert_close(
+            &Indicator::Vwap.compute(&candles),
+            &[1.0, 5.0 / 3.0, 2.0, 8.0 / 3.0, 3.0],
+        );
+    }
+}
@@ -0,0 +1,34 @@
+//! Backtest engine shared by the backend and the backtest workers.
+//!
+//! It takes a `StrategyContent`, a candle series in the dataset-manager `.bin` format (one per
+//! asset for a portfolio strategy) and a date range, walks the series bar by bar and produces the
+//! trades, the equity curve and summary statistics of the run.
+
+pub mod dataset;
+pub mod diff;
+pub mod engine;
+pub mod error;
+mod eval;
+pub mod execution;
+pub mod indicator;
+mod options;
+mod order;
+mod perp;
+pub mod report;
+mod risk;
+mod sizing;
+pub mod strategy;
+pub mod text;
+
+pub use dataset::Candles;
+pub use engine::{BacktestConfig, run, run_cancellable, run_portfolio, run_portfolio_cancellable};
+pub use error::BacktestError;
+pub use execution::{ExecutionConfig, SlippageModel, SpreadModel};
+pub use report::{
+    AssetAttribution, BacktestReport, EquityPoint, ExitReason, OptionFill, PositionPoint, Side,
+    Summary, Trade,
+};
+
+/// Version of the results the engine produces. Bump it on any change that gives different results
+/// for the same strategy and candles, the backtests of an older version are not reused then.
+pub const ENGINE_VERSION: i32 = 1;
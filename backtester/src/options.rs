@@ -0,0 +1,623 @@
+//! Engine of the options strategies: structures of calls and puts on the asset of the dataset,
+//! priced with Black-Scholes and settled at their expiry.
+//!
+//! Written options are not margined, their premium is simply credited to the cash.
+
+use std::{
+    f64::consts::{PI, SQRT_2},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    dataset::{Candles, SECONDS_PER_DAY},
+    engine::{BacktestConfig, CANCEL_CHECK_INTERVAL},
+    error::BacktestError,
+    eval::{CompiledCond, Context},
+    execution::FillCosts,
+    report::{
+        BacktestReport, EquityPoint, ExitReason, OptionFill, PositionPoint, RunStats, Side,
+        Summary, Trade,
+    },
+    sizing::realized_volatility,
+    strategy::{OptionKind, OptionLeg, OptionsConfig, StrategyContent},
+};
+
+const SECONDS_PER_YEAR: f64 = 365.0 * SECONDS_PER_DAY as f64;
+
+/// Price and greeks of one unit of an option.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Greeks {
+    pub price: f64,
+    pub delta: f64,
+    pub gamma: f64,
+    /// Per point of volatility.
+    pub vega: f64,
+    /// Per day.
+    pub theta: f64,
+}
+
+/// Black-Scholes price and greeks of an option expiring in `years`, `volatility` and `rate`
+/// being annualized fractions. An expired option is worth its intrinsic value.
+pub(crate) fn black_scholes(
+    kind: OptionKind,
+    spot: f64,
+    strike: f64,
+    years: f64,
+    volatility: f64,
+    rate: f64,
+) -> Greeks {
+    if years <= 0.0 || volatility <= 0.0 {
+        let (price, delta) = match kind {
+            OptionKind::Call if spot > strike => (spot - strike, 1.0),
+            OptionKind::Put if spot < strike => (strike - spot, -1.0),
+            _ => (0.0, 0.0),
+        };
+        return Greeks {
+            price,
+            delta,
+            gamma: 0.0,
+            vega: 0.0,
+            theta: 0.0,
+        };
+    }
+
+    let deviation = volatility * years.sqrt();
+    let d1 = ((spot / strike).ln() + (rate + volatility * volatility / 2.0) * years) / deviation;
+    let d2 = d1 - deviation;
+    let discounted = strike * (-rate * years).exp();
+    let decay = -spot * norm_pdf(d1) * volatility / (2.0 * years.sqrt());
+    let gamma = norm_pdf(d1) / (spot * deviation);
+    let vega = spot * norm_pdf(d1) * years.sqrt() / 100.0;
+
+    match kind {
+        OptionKind::Call => Greeks {
+            price: spot * norm_cdf(d1) - discounted * norm_cdf(d2),
+            delta: norm_cdf(d1),
+            gamma,
+            vega,
+            theta: (decay - rate * discounted * norm_cdf(d2)) / 365.0,
+        },
+        OptionKind::Put => Greeks {
+            price: discounted * norm_cdf(-d2) - spot * norm_cdf(-d1),
+            delta: norm_cdf(d1) - 1.0,
+            gamma,
+            vega,
+            theta: (decay + rate * discounted * norm_cdf(-d2)) / 365.0,
+        },
+    }
+}
+
+fn norm_pdf(x: f64) -> f64 {
+    (-x * x / 2.0).exp() / (2.0 * PI).sqrt()
+}
+
+fn norm_cdf(x: f64) -> f64 {
+    (1.0 + erf(x / SQRT_2)) / 2.0
+}
+
+/// Abramowitz and Stegun 7.1.26, accurate to 1.5e-7.
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let y = 1.0 - poly * (-x * x).exp();
+    if x < 0.0 { -y } else { y }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OptionsAction {
+    Long,
+    Short,
+    Close,
+}
+
+struct CompiledAction<'a> {
+    action: OptionsAction,
+    w: f64,
+    legs: &'a [OptionLeg],
+    cond: CompiledCond<'a>,
+}
+
+/// Action signaled on the close of a bar, traded on the open of the next one.
+struct Signal<'a> {
+    action: OptionsAction,
+    w: f64,
+    legs: &'a [OptionLeg],
+    /// Volatility known on the signal bar, the options are priced with it.
+    volatility: f64,
+    /// Relative range of the signal bar, for the taker costs.
+    range: f64,
+}
+
+#[derive(Debug)]
+struct OpenLeg {
+    kind: OptionKind,
+    strike: f64,
+    expiry: i64,
+    /// Negative for a written option.
+    quantity: f64,
+    entry: f64,
+    entry_fee: f64,
+    opened_at: i64,
+}
+
+impl OpenLeg {
+    fn greeks(&self, spot: f64, timestamp: i64, volatility: f64, rate: f64) -> Greeks {
+        let years = (self.expiry - timestamp) as f64 / SECONDS_PER_YEAR;
+        black_scholes(self.kind, spot, self.strike, years, volatility, rate)
+    }
+
+    fn fill(&self, greeks: &Greeks, volatility: f64) -> OptionFill {
+        OptionFill {
+            kind: self.kind,
+            strike: self.strike,
+            expiry: self.expiry,
+            volatility,
+            delta: greeks.delta,
+            gamma: greeks.gamma,
+            vega: greeks.vega,
+            theta: greeks.theta,
+        }
+    }
+
+    /// Close the leg at `price`, the fill price of one unit.
+    fn close(
+        &self,
+        price: f64,
+        timestamp: i64,
+        fill: OptionFill,
+        costs: FillCosts,
+        reason: ExitReason,
+    ) -> Trade {
+        let markup = if self.quantity > 0.0 {
+            -(costs.slippage + costs.half_spread)
+        } else {
+            costs.slippage + costs.half_spread
+        };
+        let fill_price = price * (1.0 + markup);
+        let quantity = self.quantity.abs();
+        let fee = quantity * fill_price * costs.fee_rate;
+
+        Trade {
+            timestamp,
+            side: if self.quantity > 0.0 {
+                Side::Sell
+            } else {
+                Side::Buy
+            },
+            price: fill_price,
+            quantity,
+            pnl: Some(self.quantity * (fill_price - self.entry) - fee - self.entry_fee),
+            holding_time: Some(timestamp - self.opened_at),
+            fee,
+            slippage: quantity * price * costs.slippage,
+            spread: quantity * price * costs.half_spread,
+            exit_reason: Some(reason),
+            asset: None,
+            option: Some(fill),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Book {
+    cash: f64,
+    legs: Vec<OpenLeg>,
+}
+
+impl Book {
+    /// Cash moved by a trade: the premium and the fee.
+    fn settle(&mut self, trade: &Trade) {
+        let premium = trade.quantity * trade.price;
+        match trade.side {
+            Side::Buy => self.cash -= premium + trade.fee,
+            Side::Sell => self.cash += premium - trade.fee,
+        }
+    }
+
+    /// Unrealized pnl of the open legs worth `marked`, in percent of the premiums they were
+    /// traded for, fees included.
+    fn pnl_pct(&self, marked: f64) -> f64 {
+        let cost: f64 = self
+            .legs
+            .iter()
+            .map(|leg| leg.quantity * leg.entry + leg.entry_fee)
+            .sum();
+        let premiums: f64 = self
+            .legs
+            .iter()
+            .map(|leg| leg.quantity.abs() * leg.entry)
+            .sum();
+        (marked - cost) / premiums * 100.0
+    }
+
+    /// Opening of the oldest open leg.
+    fn opened_at(&self) -> Option<i64> {
+        self.legs.iter().map(|leg| leg.opened_at).min()
+    }
+
+    fn position(&self, timestamp: i64, spot: f64, volatility: f64, rate: f64) -> PositionPoint {
+        PositionPoint {
+            timestamp,
+            quantity: self
+                .legs
+                .iter()
+                .map(|leg| leg.quantity * leg.greeks(spot, timestamp, volatility, rate).delta)
+                .sum(),
+            avg_entry: 0.0,
+            cash: self.cash,
+            asset: None,
+        }
+    }
+}
+
+/// Annualized volatility known on the close of `bar`, `None` until there is enough history to
+/// measure it.
+fn volatility(
+    config: &OptionsConfig,
+    candles: &Candles,
+    bar: usize,
+    bar_seconds: i64,
+) -> Option<f64> {
+    match config.iv {
+        Some(iv) => Some(iv / 100.0),
+        None => realized_volatility(&candles.close, bar, config.vol_lookback as usize)
+            .map(|v| v * (SECONDS_PER_YEAR / bar_seconds as f64).sqrt()),
+    }
+}
+
+/// Run an options strategy over the bars of `candles` in the configured date range.
+///
+/// Signals are traded at the next open like the market orders of `engine::run`, the options being
+/// priced on that open with the volatility known on the signal bar. Their fill price bears the
+/// slippage and spread of the execution settings, and the taker fee is charged on the premium.
+/// A leg still open at the first bar at or after its expiry is settled at its intrinsic value on
+/// the bar's open, without costs. The equity curve marks the open legs at each close.
+///
+/// `positions` holds the delta of the open legs after each fill, `avg_entry` is not used.
+pub(crate) fn run(
+    strategy: &StrategyContent,
+    candles: &Candles,
+    config: &BacktestConfig,
+    cancelled: &AtomicBool,
+) -> Result<BacktestReport, BacktestError> {
+    let options = strategy.meta.options.clone().unwrap_or_default();
+    let rate = options.risk_free_rate / 100.0;
+    let actions = strategy
+        .actions
+        .iter()
+        .map(|action| {
+            let kind = match action.action_type.as_str() {
+                "long" => OptionsAction::Long,
+                "short" => OptionsAction::Short,
+                "close" => OptionsAction::Close,
+                other => return Err(BacktestError::InvalidActionType(other.to_string())),
+            };
+            Ok(CompiledAction {
+                action: kind,
+                w: action.w,
+                legs: action.option_legs(),
+                cond: CompiledCond::compile(&strategy.expand(&action.cond)?, candles)?,
+            })
+        })
+        .collect::<Result<Vec<_>, BacktestError>>()?;
+
+    let range = candles.range(config.date_start.timestamp(), config.date_end.timestamp());
+    if range.is_empty() {
+        return Err(BacktestError::EmptyRange);
+    }
+    let buy_and_hold_return =
+        RunStats::buy_and_hold(candles.open[range.start], candles.close[range.end - 1]);
+    let bar_seconds = candles.bar_seconds();
+
+    let mut book = Book {
+        cash: config.initial_capital,
+        legs: Vec::new(),
+    };
+    let mut trades = Vec::new();
+    let mut positions = Vec::new();
+    let mut equity_curve = Vec::with_capacity(range.len());
+    let mut signals: Vec<Signal> = Vec::new();
+    let mut exposed_bars = 0;
+
+    for (i, bar) in range.enumerate() {
+        if i.is_multiple_of(CANCEL_CHECK_INTERVAL) && cancelled.load(Ordering::Relaxed) {
+            return Err(BacktestError::Cancelled);
+        }
+
+        let timestamp = candles.timestamps[bar];
+        let open = candles.open[bar];
+
+        let (expired, open_legs) = std::mem::take(&mut book.legs)
+            .into_iter()
+            .partition::<Vec<_>, _>(|leg| leg.expiry <= timestamp);
+        book.legs = open_legs;
+        for leg in &expired {
+            let greeks = leg.greeks(open, timestamp, 0.0, rate);
+            let fill = leg.fill(&greeks, 0.0);
+            let trade = leg.close(
+                greeks.price,
+                timestamp,
+                fill,
+                FillCosts::default(),
+                ExitReason::Expiry,
+            );
+            book.settle(&trade);
+            trades.push(trade);
+        }
+        if !expired.is_empty() {
+            positions.push(book.position(timestamp, open, 0.0, rate));
+        }
+
+        for signal in std::mem::take(&mut signals) {
+            let costs = config.execution.taker_costs(signal.range);
+            let filled = trades.len();
+            match signal.action {
+                OptionsAction::Long | OptionsAction::Short => {
+                    let direction = if signal.action == OptionsAction::Long {
+                        1.0
+                    } else {
+                        -1.0
+                    };
+                    for leg in signal.legs {
+                        let mut open_leg = OpenLeg {
+                            kind: leg.kind,
+                            strike: open * (1.0 + leg.strike / 100.0),
+                            expiry: timestamp + leg.expiry as i64 * SECONDS_PER_DAY,
+                            quantity: direction * leg.quantity * signal.w,
+                            entry: 0.0,
+                            entry_fee: 0.0,
+                            opened_at: timestamp,
+                        };
+                        let greeks = open_leg.greeks(open, timestamp, signal.volatility, rate);
+                        if open_leg.quantity == 0.0 || !greeks.price.is_finite() {
+                            continue;
+                        }
+                        let markup = costs.slippage + costs.half_spread;
+                        let price = greeks.price;
+                        open_leg.entry = if open_leg.quantity > 0.0 {
+                            price * (1.0 + markup)
+                        } else {
+                            price * (1.0 - markup)
+                        };
+                        let quantity = open_leg.quantity.abs();
+                        open_leg.entry_fee = quantity * open_leg.entry * costs.fee_rate;
+
+                        let trade = Trade {
+                            timestamp,
+                            side: if open_leg.quantity > 0.0 {
+                                Side::Buy
+                            } else {
+                                Side::Sell
+                            },
+                            price: open_leg.entry,
+                            quantity,
+                            pnl: None,
+                            holding_time: None,
+                            fee: open_leg.entry_fee,
+                            slippage: quantity * price * costs.slippage,
+                            spread: quantity * price * costs.half_spread,
+                            exit_reason: None,
+                            asset: None,
+                            option: Some(open_leg.fill(&greeks, signal.volatility)),
+                        };
+                        book.settle(&trade);
+                        trades.push(trade);
+                        book.legs.push(open_leg);
+                    }
+                }
+                OptionsAction::Close => {
+                    for leg in std::mem::take(&mut book.legs) {
+                        let greeks = leg.greeks(open, timestamp, signal.volatility, rate);
+                        let fill = leg.fill(&greeks, signal.volatility);
+                        let trade =
+                            leg.close(greeks.price, timestamp, fill, costs, ExitReason::Signal);
+                        book.settle(&trade);
+                        trades.push(trade);
+                    }
+                }
+            }
+            if trades.len() > filled {
+                positions.push(book.position(timestamp, open, signal.volatility, rate));
+            }
+        }
+
+        let close = candles.close[bar];
+        let known_volatility = volatility(&options, candles, bar, bar_seconds);
+        let close_time = timestamp + bar_seconds;
+        let marked: f64 = book
+            .legs
+            .iter()
+            .map(|leg| {
+                let volatility = known_volatility.unwrap_or(0.0);
+                leg.quantity * leg.greeks(close, close_time, volatility, rate).price
+            })
+            .sum();
+        equity_curve.push(EquityPoint {
+            timestamp,
+            equity: book.cash + marked,
+        });
+        if !book.legs.is_empty() {
+            exposed_bars += 1;
+        }
+
+        // Nothing is traded until the volatility can be measured.
+        let Some(signal_volatility) = known_volatility else {
+            continue;
+        };
+        let bar_range = (candles.high[bar] - candles.low[bar]) / close;
+        let context = Context::new(
+            candles,
+            bar,
+            book.opened_at(),
+            book.pnl_pct(marked),
+            &trades,
+        );
+        for action in &actions {
+            if action.cond.eval(bar, &context) {
+                signals.push(Signal {
+                    action: action.action,
+                    w: action.w,
+                    legs: action.legs,
+                    volatility: signal_volatility,
+                    range: bar_range,
+                });
+            }
+        }
+    }
+
+    let stats = RunStats {
+        exposed_bars,
+        buy_and_hold_return,
+    };
+    Ok(BacktestReport {
+        summary: Summary::compute(config.initial_capital, &trades, &equity_curve, stats),
+        trades,
+        equity_curve,
+        positions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::{engine::run, strategy::DEFAULT_EXPIRY_DAYS};
+
+    fn candles(close: &[f64], signal: &[f64]) -> Candles {
+        let mut candles = Candles {
+            timestamps: (0..close.len() as i64)
+                .map(|i| i * SECONDS_PER_DAY)
+                .collect(),
+            open: close.to_vec(),
+            high: close.to_vec(),
+            low: close.to_vec(),
+            close: close.to_vec(),
+            volume: vec![1.0; close.len()],
+            ..Default::default()
+        };
+        candles
+            .indicators
+            .insert("signal".to_string(), signal.to_vec());
+        candles
+    }
+
+    fn config() -> BacktestConfig {
+        BacktestConfig::new(
+            DateTime::from_timestamp(0, 0).unwrap(),
+            DateTime::from_timestamp(100 * SECONDS_PER_DAY, 0).unwrap(),
+        )
+    }
+
+    fn strategy(action: &str, expiry: u32) -> StrategyContent {
+        let json = format!(
+            r#"{{
+                "meta": {{ "type": "options", "options": {{ "iv": 50.0 }} }},
+                "actions": [
+                    {{
+                        "type": "{action}",
+                        "w": 1.0,
+                        "cond": {{ "gt": {{ "l": "signal", "r": 0.5 }} }},
+                        "legs": [{{ "type": "call", "strike": 0.0, "expiry": {expiry}, "quantity": 2.0 }}]
+                    }},
+                    {{ "type": "close", "w": 1.0, "cond": {{ "lt": {{ "l": "signal", "r": -0.5 }} }} }}
+                ]
+            }}"#
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_black_scholes() {
+        let call = black_scholes(OptionKind::Call, 100.0, 100.0, 1.0, 0.2, 0.05);
+        let put = black_scholes(OptionKind::Put, 100.0, 100.0, 1.0, 0.2, 0.05);
+        assert!((call.price - 10.4506).abs() < 1e-4);
+        assert!((put.price - 5.5735).abs() < 1e-4);
+        assert!((call.delta - 0.6368).abs() < 1e-4);
+        assert!((put.delta + 0.3632).abs() < 1e-4);
+        assert!((call.gamma - 0.018762).abs() < 1e-6);
+        assert!((call.vega - 0.37524).abs() < 1e-5);
+        assert!((call.theta + 6.4140 / 365.0).abs() < 1e-5);
+        // Put-call parity.
+        let parity = call.price - put.price - (100.0 - 100.0 * (-0.05f64).exp());
+        assert!(parity.abs() < 1e-6);
+
+        let expired = black_scholes(OptionKind::Put, 90.0, 100.0, 0.0, 0.2, 0.05);
+        assert_eq!(expired.price, 10.0);
+        assert_eq!(expired.delta, -1.0);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let candles = candles(&[100.0, 100.0, 120.0, 120.0], &[1.0, 0.0, 0.0, 0.0]);
+        let premium = black_scholes(OptionKind::Call, 100.0, 100.0, 1.0 / 365.0, 0.5, 0.0).price;
+
+        // Bought at the open of bar 1, settled at the open of bar 2, a day later.
+        let report = run(&strategy("long", 1), &candles, &config()).unwrap();
+        assert_eq!(report.trades.len(), 2);
+        assert_eq!(report.trades[0].side, Side::Buy);
+        assert_eq!(report.trades[0].quantity, 2.0);
+        assert!((report.trades[0].price - premium).abs() < 1e-9);
+        let option = report.trades[0].option.unwrap();
+        assert_eq!(option.strike, 100.0);
+        assert_eq!(option.expiry, 2 * SECONDS_PER_DAY);
+        assert!(option.delta > 0.5 && option.delta < 0.6);
+        let settlement = &report.trades[1];
+        assert_eq!(settlement.exit_reason, Some(ExitReason::Expiry));
+        assert_eq!(settlement.price, 20.0);
+        assert!((settlement.pnl.unwrap() - 2.0 * (20.0 - premium)).abs() < 1e-9);
+        let expected = 10_000.0 + 2.0 * (20.0 - premium);
+        assert!((report.summary.final_equity - expected).abs() < 1e-9);
+
+        // Written the other way around.
+        let report = run(&strategy("short", 1), &candles, &config()).unwrap();
+        assert_eq!(report.trades[0].side, Side::Sell);
+        let expected = 10_000.0 - 2.0 * (20.0 - premium);
+        assert!((report.summary.final_equity - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_default_legs() {
+        // Saved before legs existed: one at-the-money call.
+        let candles = candles(&[100.0, 100.0, 120.0, 120.0], &[1.0, 0.0, 0.0, 0.0]);
+        for (action, side) in [("long", Side::Buy), ("short", Side::Sell)] {
+            let mut strategy = strategy(action, 1);
+            strategy.actions[0].legs = None;
+            let report = run(&strategy, &candles, &config()).unwrap();
+            assert_eq!(report.trades.len(), 1);
+            assert_eq!(report.trades[0].side, side);
+            assert_eq!(report.trades[0].quantity, 1.0);
+            let option = report.trades[0].option.unwrap();
+            assert_eq!(option.kind, OptionKind::Call);
+            assert_eq!(option.strike, 100.0);
+            assert_eq!(
+                option.expiry,
+                (1 + DEFAULT_EXPIRY_DAYS as i64) * SECONDS_PER_DAY
+            );
+        }
+    }
+
+    #[test]
+    fn test_close() {
+        let candles = candles(&[100.0, 100.0, 120.0, 120.0], &[1.0, -1.0, 0.0, 0.0]);
+        let price = |spot, days: f64| {
+            black_scholes(OptionKind::Call, spot, 100.0, days / 365.0, 0.5, 0.0).price
+        };
+
+        // Closed at the open of bar 2 with 29 days left.
+        let report = run(&strategy("long", 30), &candles, &config()).unwrap();
+        assert_eq!(report.trades.len(), 2);
+        let close = &report.trades[1];
+        assert_eq!(close.exit_reason, Some(ExitReason::Signal));
+        assert_eq!(close.holding_time, Some(SECONDS_PER_DAY));
+        let pnl = 2.0 * (price(120.0, 29.0) - price(100.0, 30.0));
+        assert!((close.pnl.unwrap() - pnl).abs() < 1e-9);
+        assert_eq!(report.positions.last().unwrap().quantity, 0.0);
+        // Marked at the close of bar 1 while open.
+        let marked = 10_000.0 + 2.0 * (price(100.0, 29.0) - price(100.0, 30.0));
+        assert!((report.equity_curve[1].equity - marked).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{
+    dataset::Candles,
+    error::BacktestError,
+    eval::Operand,
+    report::Side,
+    sizing::Stake,
+    strategy::{Order, OrderType},
+};
+
+/// An `OrderType` with its prices resolved to dataset columns.
+#[derive(Debug, Clone)]
+pub(crate) enum CompiledOrder<'a> {
+    Market,
+    Limit(Operand<'a>),
+    Stop(Operand<'a>),
+    StopLimit {
+        stop: Operand<'a>,
+        limit: Operand<'a>,
+    },
+}
+
+impl<'a> CompiledOrder<'a> {
+    pub(crate) fn compile(
+        order: Option<&Order>,
+        candles: &'a Candles,
+    ) -> Result<Self, BacktestError> {
+        let Some(order) = order else {
+            return Ok(CompiledOrder::Market);
+        };
+
+        match &order.order_type {
+            OrderType::Market => Ok(CompiledOrder::Market),
+            OrderType::Limit { price } => {
+                Ok(CompiledOrder::Limit(Operand::compile(price, candles)?))
+            }
+            OrderType::Stop { price } => Ok(CompiledOrder::Stop(Operand::compile(price, candles)?)),
+            OrderType::StopLimit { stop, limit } => Ok(CompiledOrder::StopLimit {
+                stop: Operand::compile(stop, candles)?,
+                limit: Operand::compile(limit, candles)?,
+            }),
+        }
+    }
+
+    /// The order as sent on the close of `bar`, with its prices fixed.
+    pub(crate) fn place(&self, bar: usize) -> OrderKind {
+        match self {
+            CompiledOrder::Market => OrderKind::Market,
+            CompiledOrder::Limit(price) => OrderKind::Limit {
+                price: price.at(bar),
+            },
+            CompiledOrder::Stop(price) => OrderKind::Stop {
+                price: price.at(bar),
+            },
+            CompiledOrder::StopLimit { stop, limit } => OrderKind::StopLimit {
+                stop: stop.at(bar),
+                limit: limit.at(bar),
+                triggered: false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OrderKind {
+    Market,
+    Limit {
+        price: f64,
+    },
+    Stop {
+        price: f64,
+    },
+    StopLimit {
+        stop: f64,
+        limit: f64,
+        triggered: bool,
+    },
+}
+
+/// Which fee applies to a fill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Liquidity {
+    /// The order was resting in the book.
+    Maker,
+    /// The order crossed the book, it pays the taker fee, the slippage and the spread.
+    Taker,
+}
+
+/// An order waiting to be filled.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PendingOrder {
+    pub side: Side,
+    pub stake: Stake,
+    /// Relative range of the bar the signal fired on, for the slippage.
+    pub volatility: f64,
+    pub kind: OrderKind,
+    /// Last bar the order can be filled on.
+    pub expires: usize,
+}
+
+impl PendingOrder {
+    /// Price the order fills at on a bar, if it does.
+    ///
+    /// Only the open, high and low of the bar are known, so a gap through the order price fills at
+    /// the open, otherwise it fills at its own price. When a stop-limit triggers, the trigger is
+    /// assumed to come first within the bar.
+    pub(crate) fn try_fill(&mut self, open: f64, high: f64, low: f64) -> Option<(f64, Liquidity)> {
+        // Prices are mirrored for sells, so "better" is always "lower" below.
+        let (sign, open, best, worst) = match self.side {
+            Side::Buy => (1.0, open, low, high),
+            Side::Sell => (-1.0, -open, -high, -low),
+        };
+
+        let (price, liquidity) = match &mut self.kind {
+            OrderKind::Market => (open, Liquidity::Taker),
+            OrderKind::Limit { price } => limit_fill(open, best, sign * *price)?,
+            OrderKind::Stop { price } => {
+                (stop_trigger(open, worst, sign * *price)?, Liquidity::Taker)
+            }
+            OrderKind::StopLimit {
+                stop,
+                limit,
+                triggered,
+            } => {
+                let limit = sign * *limit;
+                if *triggered {
+                    limit_fill(open, best, limit)?
+                } else {
+                    let trigger = stop_trigger(open, worst, sign * *stop)?;
+                    *triggered = true;
+                    if trigger <= limit {
+                        (trigger, Liquidity::Taker)
+                    } else if best <= limit {
+                        (limit, Liquidity::Maker)
+                    } else {
+                        return None;
+                    }
+                }
+            }
+        };
+
+        Some((sign * price, liquidity))
+    }
+}
+
+/// Fill of a resting limit order, prices mirrored so lower is better.
+fn limit_fill(open: f64, best: f64, limit: f64) -> Option<(f64, Liquidity)> {
+    if open <= limit {
+        Some((open, Liquidity::Maker))
+    } else if best <= limit {
+        Some((limit, Liquidity::Maker))
+    } else {
+        None
+    }
+}
+
+/// Price a stop triggers at, prices mirrored so the stop is above the market.
+fn stop_trigger(open: f64, worst: f64, stop: f64) -> Option<f64> {
+    if open >= stop {
+        Some(open)
+    } else if worst >= stop {
+        Some(stop)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(side: Side, kind: OrderKind) -> PendingOrder {
+        PendingOrder {
+            side,
+            stake: Stake::Quantity(1.0),
+            volatility: 0.0,
+            kind,
+            expires: 0,
+        }
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut buy = order(Side::Buy, OrderKind::Limit { price: 9.0 });
+        assert_eq!(buy.try_fill(10.0, 11.0, 9.5), None);
+        assert_eq!(buy.try_fill(10.0, 11.0, 8.0), Some((9.0, Liquidity::Maker)));
+        assert_eq!(buy.try_fill(8.5, 11.0, 8.0), Some((8.5, Liquidity::Maker)));
+
+        let mut sell = order(Side::Sell, OrderKind::Limit { price: 11.0 });
+        assert_eq!(sell.try_fill(10.0, 10.5, 9.0), None);
+        assert_eq!(
+            sell.try_fill(10.0, 12.0, 9.0),
+            Some((11.0, Liquidity::Maker))
+        );
+    }
+
+    #[test]
+    fn test_stop() {
+        let mut sell = order(Side::Sell, OrderKind::Stop { price: 9.0 });
+        assert_eq!(sell.try_fill(10.0, 11.0, 9.5), None);
+        assert_eq!(
+            sell.try_fill(10.0, 11.0, 8.0),
+            Some((9.0, Liquidity::Taker))
+        );
+        assert_eq!(sell.try_fill(8.0, 8.5, 7.0), Some((8.0, Liquidity::Taker)));
+    }
+
+    #[test]
+    fn test_stop_limit() {
+        let kind = OrderKind::StopLimit {
+            stop: 11.0,
+            limit: 10.5,
+            triggered: false,
+        };
+
+        // Triggered at 11, above the limit, waits for the price to come back.
+        let mut buy = order(Side::Buy, kind);
+        assert_eq!(buy.try_fill(10.0, 12.0, 10.8), None);
+        assert!(matches!(
+            buy.kind,
+            OrderKind::StopLimit {
+                triggered: true,
+                ..
+            }
+        ));
+        assert_eq!(
+            buy.try_fill(11.0, 11.5, 10.0),
+            Some((10.5, Liquidity::Maker))
+        );
+
+        // Not triggered.
+        let mut buy = order(Side::Buy, kind);
+        assert_eq!(buy.try_fill(10.0, 10.9, 9.0), None);
+        assert!(matches!(
+            buy.kind,
+            OrderKind::StopLimit {
+                triggered: false,
+                ..
+            }
+        ));
+    }
+}
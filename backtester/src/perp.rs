@@ -0,0 +1,501 @@
+//! Engine of the perpetual strategies: positions in either direction on margin, with funding
+//! payments and liquidations.
+//!
+//! The account is cross margin: the whole collateral backs the single net position.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    dataset::Candles,
+    engine::{BacktestConfig, CANCEL_CHECK_INTERVAL},
+    error::BacktestError,
+    eval::{CompiledCond, Context},
+    execution::FillCosts,
+    order::{CompiledOrder, Liquidity, PendingOrder},
+    report::{
+        BacktestReport, EquityPoint, ExitReason, PositionPoint, RunStats, Side, Summary, Trade,
+    },
+    sizing::Stake,
+    strategy::{Margin, Order, StrategyContent},
+};
+
+const SECONDS_PER_HOUR: i64 = 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PerpAction {
+    Long,
+    Short,
+    CloseLong,
+    CloseShort,
+}
+
+impl PerpAction {
+    fn parse(action_type: &str) -> Result<Self, BacktestError> {
+        match action_type {
+            "long" => Ok(PerpAction::Long),
+            "short" => Ok(PerpAction::Short),
+            "close_long" => Ok(PerpAction::CloseLong),
+            "close_short" => Ok(PerpAction::CloseShort),
+            other => Err(BacktestError::InvalidActionType(other.to_string())),
+        }
+    }
+
+    /// Side of the order sent, which decides how a limit or stop price fills.
+    fn side(self) -> Side {
+        match self {
+            PerpAction::Long | PerpAction::CloseShort => Side::Buy,
+            PerpAction::Short | PerpAction::CloseLong => Side::Sell,
+        }
+    }
+}
+
+struct CompiledAction<'a> {
+    action: PerpAction,
+    w: f64,
+    cond: CompiledCond<'a>,
+    order: CompiledOrder<'a>,
+    tif: u32,
+}
+
+#[derive(Debug)]
+struct Account {
+    collateral: f64,
+    /// Positive when long, negative when short.
+    quantity: f64,
+    entry: f64,
+    /// Fees paid to open the current position, charged to its pnl as it is closed.
+    entry_fees: f64,
+    opened_at: Option<i64>,
+}
+
+impl Account {
+    fn equity(&self, price: f64) -> f64 {
+        self.collateral + self.quantity * (price - self.entry)
+    }
+
+    /// Unrealized pnl at `price` in percent of the notional of the position at the entry, the
+    /// fees to open it included.
+    fn pnl_pct(&self, price: f64) -> f64 {
+        let notional = self.quantity.abs() * self.entry;
+        (self.quantity * (price - self.entry) - self.entry_fees) / notional * 100.0
+    }
+
+    fn position(&self, timestamp: i64) -> PositionPoint {
+        PositionPoint {
+            timestamp,
+            quantity: self.quantity,
+            avg_entry: self.entry,
+            cash: self.collateral,
+            asset: None,
+        }
+    }
+
+    /// Price at which the equity falls to the maintenance margin, `None` when flat.
+    fn liquidation_price(&self, maintenance: f64) -> Option<f64> {
+        let q = self.quantity;
+        if q == 0.0 {
+            return None;
+        }
+        // collateral + q * (p - entry) = maintenance * |q| * p
+        let price = (q * self.entry - self.collateral) / (q - maintenance * q.abs());
+        Some(price.max(0.0))
+    }
+
+    /// Open, or add to, a position in `direction` (1 for long, -1 for short) with `w` of the
+    /// margin available at `price`. Nothing is filled against an opposite position.
+    fn open(
+        &mut self,
+        direction: f64,
+        w: f64,
+        price: f64,
+        margin: &Margin,
+        timestamp: i64,
+        costs: FillCosts,
+    ) -> Option<Trade> {
+        if !price.is_finite() || price <= 0.0 || self.quantity * direction < 0.0 {
+            return None;
+        }
+        let fill_price = price * (1.0 + direction * (costs.slippage + costs.half_spread));
+        let used = self.quantity.abs() * self.entry / margin.leverage;
+        let available = (self.equity(price) - used).max(0.0) * w;
+        // The margin and the fee both come out of the available margin.
+        let notional = available / (1.0 / margin.leverage + costs.fee_rate);
+        let quantity = notional / fill_price;
+        if quantity <= 0.0 {
+            return None;
+        }
+
+        let fee = notional * costs.fee_rate;
+        let held = self.quantity.abs();
+        self.entry = (self.entry * held + fill_price * quantity) / (held + quantity);
+        self.quantity += direction * quantity;
+        self.collateral -= fee;
+        self.entry_fees += fee;
+        self.opened_at.get_or_insert(timestamp);
+
+        Some(Trade {
+            timestamp,
+            side: if direction > 0.0 {
+                Side::Buy
+            } else {
+                Side::Sell
+            },
+            price: fill_price,
+            quantity,
+            pnl: None,
+            holding_time: None,
+            fee,
+            slippage: quantity * price * costs.slippage,
+            spread: quantity * price * costs.half_spread,
+            exit_reason: None,
+            asset: None,
+            option: None,
+        })
+    }
+
+    /// Close `w` of the position if it is in `direction`.
+    fn close(
+        &mut self,
+        direction: f64,
+        w: f64,
+        price: f64,
+        timestamp: i64,
+        costs: FillCosts,
+    ) -> Option<Trade> {
+        if !price.is_finite() || price <= 0.0 || self.quantity * direction <= 0.0 {
+            return None;
+        }
+        let fill_price = price * (1.0 - direction * (costs.slippage + costs.half_spread));
+        let quantity = self.quantity.abs() * w;
+        if quantity <= 0.0 {
+            return None;
+        }
+
+        let fee = quantity * fill_price * costs.fee_rate;
+        let entry_fees = self.entry_fees * w;
+        let gross = direction * quantity * (fill_price - self.entry);
+        let holding_time = self.opened_at.map(|t| timestamp - t);
+        self.collateral += gross - fee;
+        self.entry_fees -= entry_fees;
+        self.quantity -= direction * quantity;
+        if self.quantity.abs() <= f64::EPSILON {
+            self.quantity = 0.0;
+            self.entry = 0.0;
+            self.entry_fees = 0.0;
+            self.opened_at = None;
+        }
+
+        Some(Trade {
+            timestamp,
+            side: if direction > 0.0 {
+                Side::Sell
+            } else {
+                Side::Buy
+            },
+            price: fill_price,
+            quantity,
+            pnl: Some(gross - fee - entry_fees),
+            holding_time,
+            fee,
+            slippage: quantity * price * costs.slippage,
+            spread: quantity * price * costs.half_spread,
+            exit_reason: Some(ExitReason::Signal),
+            asset: None,
+            option: None,
+        })
+    }
+}
+
+/// Run a perpetual strategy over the bars of `candles` in the configured date range.
+///
+/// Orders are sent and filled like the spot ones, see `engine::run`. On top of them:
+/// - every bar crossing a funding time (every `funding_interval` hours, UTC) settles the funding
+///   of the open position at the bar's open;
+/// - a position whose equity falls to the maintenance margin within a bar is liquidated at the
+///   price it happens, or at the open on a gap. Losses beyond the collateral are not charged.
+pub(crate) fn run(
+    strategy: &StrategyContent,
+    candles: &Candles,
+    config: &BacktestConfig,
+    cancelled: &AtomicBool,
+) -> Result<BacktestReport, BacktestError> {
+    let margin = strategy.meta.margin.clone().unwrap_or_default();
+    let actions = strategy
+        .actions
+        .iter()
+        .map(|action| {
+            Ok(CompiledAction {
+                action: PerpAction::parse(&action.action_type)?,
+                w: action.w,
+                cond: CompiledCond::compile(&strategy.expand(&action.cond)?, candles)?,
+                order: CompiledOrder::compile(action.order.as_ref(), candles)?,
+                tif: action
+                    .order
+                    .as_ref()
+                    .map_or(Order::DEFAULT_TIF, |o| o.tif()),
+            })
+        })
+        .collect::<Result<Vec<_>, BacktestError>>()?;
+    let funding_column = candles.column("funding_rate");
+    let funding_interval = margin.funding_interval as i64 * SECONDS_PER_HOUR;
+    let maintenance = margin.maintenance_margin / 100.0;
+
+    let range = candles.range(config.date_start.timestamp(), config.date_end.timestamp());
+    if range.is_empty() {
+        return Err(BacktestError::EmptyRange);
+    }
+    let buy_and_hold_return =
+        RunStats::buy_and_hold(candles.open[range.start], candles.close[range.end - 1]);
+
+    let mut account = Account {
+        collateral: config.initial_capital,
+        quantity: 0.0,
+        entry: 0.0,
+        entry_fees: 0.0,
+        opened_at: None,
+    };
+    let mut trades = Vec::new();
+    let mut positions = Vec::new();
+    let mut equity_curve = Vec::with_capacity(range.len());
+    let mut pending: Vec<(PerpAction, PendingOrder)> = Vec::new();
+    let mut funding_period = None;
+    let mut total_funding = 0.0;
+    let mut exposed_bars = 0;
+
+    for (i, bar) in range.enumerate() {
+        if i.is_multiple_of(CANCEL_CHECK_INTERVAL) && cancelled.load(Ordering::Relaxed) {
+            return Err(BacktestError::Cancelled);
+        }
+
+        let timestamp = candles.timestamps[bar];
+        let (open, high, low) = (candles.open[bar], candles.high[bar], candles.low[bar]);
+
+        let period = timestamp.div_euclid(funding_interval);
+        if funding_period.is_some_and(|p| p != period) && account.quantity != 0.0 {
+            // The rate of the dataset is the one of the last bar before the funding time.
+            let rate = funding_column
+                .map(|column| column[bar - 1])
+                .filter(|rate| rate.is_finite())
+                .unwrap_or(margin.funding_rate / 100.0);
+            let payment = account.quantity * open * rate;
+            account.collateral -= payment;
+            total_funding += payment;
+        }
+        funding_period = Some(period);
+
+        let mut waiting = Vec::with_capacity(pending.len());
+        for (action, mut order) in pending.drain(..) {
+            match order.try_fill(open, high, low) {
+                Some((price, liquidity)) => {
+                    let costs = match liquidity {
+                        Liquidity::Maker => config.execution.maker_costs(),
+                        Liquidity::Taker => config.execution.taker_costs(order.volatility),
+                    };
+                    let w = order.stake.weight(0.0, 0.0);
+                    let trade = match action {
+                        PerpAction::Long => account.open(1.0, w, price, &margin, timestamp, costs),
+                        PerpAction::Short => {
+                            account.open(-1.0, w, price, &margin, timestamp, costs)
+                        }
+                        PerpAction::CloseLong => account.close(1.0, w, price, timestamp, costs),
+                        PerpAction::CloseShort => account.close(-1.0, w, price, timestamp, costs),
+                    };
+                    if let Some(trade) = trade {
+                        trades.push(trade);
+                        positions.push(account.position(timestamp));
+                    }
+                }
+                None if bar < order.expires => waiting.push((action, order)),
+                None => {}
+            }
+        }
+        pending = waiting;
+
+        if let Some(liquidation) = account.liquidation_price(maintenance) {
+            let direction = account.quantity.signum();
+            // The worst price of the bar for the position, and the open on a gap.
+            let (worst, gapped) = if direction > 0.0 {
+                (low, open <= liquidation)
+            } else {
+                (high, open >= liquidation)
+            };
+            if direction * (worst - liquidation) <= 0.0 {
+                let price = if gapped { open } else { liquidation };
+                let previous_volatility = bar.checked_sub(1).map_or(0.0, |b| {
+                    (candles.high[b] - candles.low[b]) / candles.close[b]
+                });
+                let costs = config.execution.taker_costs(previous_volatility);
+                if let Some(mut trade) = account.close(direction, 1.0, price, timestamp, costs) {
+                    trade.exit_reason = Some(ExitReason::Liquidation);
+                    account.collateral = account.collateral.max(0.0);
+                    trades.push(trade);
+                    positions.push(account.position(timestamp));
+                }
+            }
+        }
+
+        equity_curve.push(EquityPoint {
+            timestamp,
+            equity: account.equity(candles.close[bar]),
+        });
+        if account.quantity != 0.0 {
+            exposed_bars += 1;
+        }
+
+        let volatility = (candles.high[bar] - candles.low[bar]) / candles.close[bar];
+        let context = Context::new(
+            candles,
+            bar,
+            account.opened_at,
+            account.pnl_pct(candles.close[bar]),
+            &trades,
+        );
+        for action in &actions {
+            if action.cond.eval(bar, &context) {
+                let stake = match action.action {
+                    PerpAction::Long | PerpAction::Short => Stake::Cash(action.w),
+                    PerpAction::CloseLong | PerpAction::CloseShort => Stake::Quantity(action.w),
+                };
+                pending.push((
+                    action.action,
+                    PendingOrder {
+                        side: action.action.side(),
+                        stake,
+                        volatility,
+                        kind: action.order.place(bar),
+                        expires: bar + action.tif as usize,
+                    },
+                ));
+            }
+        }
+    }
+
+    let stats = RunStats {
+        exposed_bars,
+        buy_and_hold_return,
+    };
+    let mut summary = Summary::compute(config.initial_capital, &trades, &equity_curve, stats);
+    summary.total_funding = total_funding;
+    Ok(BacktestReport {
+        summary,
+        trades,
+        equity_curve,
+        positions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::engine::run;
+
+    fn candles(seconds: i64, close: &[f64], signal: &[f64]) -> Candles {
+        let mut candles = Candles {
+            timestamps: (0..close.len() as i64).map(|i| i * seconds).collect(),
+            open: close.to_vec(),
+            high: close.to_vec(),
+            low: close.to_vec(),
+            close: close.to_vec(),
+            volume: vec![1.0; close.len()],
+            ..Default::default()
+        };
+        candles
+            .indicators
+            .insert("signal".to_string(), signal.to_vec());
+        candles
+    }
+
+    fn config() -> BacktestConfig {
+        BacktestConfig::new(
+            DateTime::from_timestamp(0, 0).unwrap(),
+            DateTime::from_timestamp(1_000_000, 0).unwrap(),
+        )
+    }
+
+    fn strategy(margin: &str, open: &str, close: &str) -> StrategyContent {
+        let json = format!(
+            r#"{{
+                "meta": {{ "type": "perp", "margin": {margin} }},
+                "actions": [
+                    {{ "type": "{open}", "w": 1.0, "cond": {{ "gt": {{ "l": "signal", "r": 0.5 }} }} }},
+                    {{ "type": "{close}", "w": 1.0, "cond": {{ "lt": {{ "l": "signal", "r": -0.5 }} }} }}
+                ]
+            }}"#
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_short() {
+        let strategy = strategy(
+            r#"{ "leverage": 2.0, "funding_rate": 0.0 }"#,
+            "short",
+            "close_short",
+        );
+        let candles = candles(60, &[10.0, 10.0, 8.0, 8.0], &[1.0, 0.0, -1.0, 0.0]);
+
+        let report = run(&strategy, &candles, &config()).unwrap();
+
+        // Short 2 x 10_000 of notional at 10, bought back at 8.
+        assert_eq!(report.trades.len(), 2);
+        assert_eq!(report.trades[0].side, Side::Sell);
+        assert_eq!(report.trades[0].quantity, 2_000.0);
+        assert_eq!(report.trades[1].side, Side::Buy);
+        assert_eq!(report.trades[1].pnl, Some(4_000.0));
+        assert_eq!(report.positions[0].quantity, -2_000.0);
+        assert_eq!(report.summary.final_equity, 14_000.0);
+        // Marked at the close of bar 2 while still short.
+        assert_eq!(report.equity_curve[2].equity, 14_000.0);
+    }
+
+    #[test]
+    fn test_funding() {
+        let strategy = strategy(r#"{ "funding_interval": 1 }"#, "long", "close_long");
+        let candles = candles(3600, &[10.0; 4], &[1.0, 0.0, 0.0, 0.0]);
+
+        let report = run(&strategy, &candles, &config()).unwrap();
+
+        // Long 10_000 of notional from bar 1, pays 0.01% of it at the open of bars 2 and 3.
+        assert!((report.summary.total_funding - 2.0).abs() < 1e-9);
+        assert!((report.summary.final_equity - 9_998.0).abs() < 1e-9);
+
+        // The rate of the dataset wins, the shorts paying when it is negative.
+        let mut candles = candles;
+        candles
+            .indicators
+            .insert("funding_rate".to_string(), vec![-0.001; 4]);
+        let report = run(&strategy, &candles, &config()).unwrap();
+        assert!((report.summary.total_funding + 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_liquidation() {
+        let long = strategy(r#"{ "leverage": 10.0 }"#, "long", "close_long");
+        let mut candles = candles(60, &[10.0, 10.0, 10.0, 10.0], &[1.0, 0.0, 0.0, 0.0]);
+        candles.low[2] = 8.5;
+
+        let report = run(&long, &candles, &config()).unwrap();
+
+        // 10_000 of quantity at 10, liquidated once the equity is 0.5% of the notional.
+        let liquidation = 90_000.0 / (10_000.0 * 0.995);
+        assert_eq!(report.trades.len(), 2);
+        let trade = &report.trades[1];
+        assert_eq!(trade.exit_reason, Some(ExitReason::Liquidation));
+        assert!((trade.price - liquidation).abs() < 1e-9);
+        let remaining = 0.005 * 10_000.0 * liquidation;
+        assert!((report.summary.final_equity - remaining).abs() < 1e-6);
+
+        // The maintenance margin can not exceed the initial margin.
+        let invalid = strategy(
+            r#"{ "leverage": 50.0, "maintenance_margin": 2.0 }"#,
+            "long",
+            "close_long",
+        );
+        assert!(matches!(
+            run(&invalid, &candles, &config()),
+            Err(BacktestError::InvalidMargin(_))
+        ));
+    }
+}
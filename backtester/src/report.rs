@@ -0,0 +1,366 @@
+use serde::{Deserialize, Serialize};
+
+use crate::strategy::OptionKind;
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// Why a position was (partly) closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    /// A `sell` action.
+    Signal,
+    StopLoss,
+    TakeProfit,
+    TrailingStop,
+    MaxHolding,
+    MaxDailyLoss,
+    /// The equity of a margin position fell to its maintenance margin.
+    Liquidation,
+    /// An option settled at its expiry.
+    Expiry,
+}
+
+/// One filled order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trade {
+    /// Timestamp (seconds) of the bar the order was filled on.
+    pub timestamp: i64,
+    pub side: Side,
+    pub price: f64,
+    pub quantity: f64,
+    /// Realized profit of the quantity sold, measured against the average entry price.
+    /// `None` for buys.
+    pub pnl: Option<f64>,
+    /// Seconds between the opening of the position (first buy while flat) and this sell.
+    /// `None` for buys.
+    pub holding_time: Option<i64>,
+    /// Fee paid on this fill.
+    pub fee: f64,
+    /// Cost of the slippage and of the spread on this fill, in quote currency.
+    pub slippage: f64,
+    pub spread: f64,
+    /// `None` for buys.
+    pub exit_reason: Option<ExitReason>,
+    /// Asset traded, only set by portfolio backtests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+    /// Option traded, only set by options backtests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub option: Option<OptionFill>,
+}
+
+/// Contract of an option fill, with the greeks of one unit at the fill.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OptionFill {
+    pub kind: OptionKind,
+    pub strike: f64,
+    /// Timestamp (seconds) the option expires at.
+    pub expiry: i64,
+    /// Annualized volatility the option was priced with.
+    pub volatility: f64,
+    pub delta: f64,
+    pub gamma: f64,
+    /// Change of the price for one point of volatility.
+    pub vega: f64,
+    /// Change of the price over one day.
+    pub theta: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EquityPoint {
+    pub timestamp: i64,
+    pub equity: f64,
+}
+
+/// Holdings right after a fill.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PositionPoint {
+    pub timestamp: i64,
+    pub quantity: f64,
+    pub avg_entry: f64,
+    /// Cash of the asset's share of the capital in a portfolio backtest.
+    pub cash: f64,
+    /// Asset held, only set by portfolio backtests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+}
+
+/// Share of a portfolio backtest's result coming from one of its assets.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetAttribution {
+    pub asset: String,
+    /// Fraction of the capital allocated to the asset.
+    pub weight: f64,
+    /// Final equity of the asset's share of the capital.
+    pub final_equity: f64,
+    /// Profit of the asset, net of the capital moved in and out of it by rebalancing.
+    pub pnl: f64,
+    /// Profit of the asset over the initial capital of the portfolio, the contributions add up
+    /// to the portfolio's net return.
+    pub contribution: f64,
+    pub trades_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub initial_capital: f64,
+    pub final_equity: f64,
+    /// Final equity over initial capital, minus one.
+    pub net_return: f64,
+    /// Gross profit over gross loss of the closing trades. `None` if nothing was ever lost.
+    pub profit_factor: Option<f64>,
+    /// Annualized, computed from bar to bar returns.
+    pub sharpe_ratio: Option<f64>,
+    /// Same as the Sharpe ratio, but only penalizing the returns below zero.
+    pub sortino_ratio: Option<f64>,
+    /// Annualized return over max drawdown. `None` if there was no drawdown.
+    pub calmar_ratio: Option<f64>,
+    /// Largest peak to trough drop of the equity curve, as a positive fraction.
+    pub max_drawdown: f64,
+    pub trades_count: usize,
+    /// Fraction of the closing trades that made a profit. `None` if nothing was closed.
+    pub win_rate: Option<f64>,
+    /// Fraction of the bars that closed with an open position.
+    pub exposure_time: f64,
+    /// Average holding time of the closing trades, in seconds.
+    pub avg_trade_duration: Option<f64>,
+    /// Best realized profit of a closing trade.
+    pub largest_win: Option<f64>,
+    /// Worst realized profit of a closing trade.
+    pub largest_loss: Option<f64>,
+    /// Return of buying at the open of the first bar and selling at the close of the last one.
+    /// For a portfolio, the average of its assets' weighted by their allocation.
+    pub buy_and_hold_return: f64,
+    /// Trading costs paid over the run, in quote currency.
+    pub total_fees: f64,
+    pub total_slippage: f64,
+    pub total_spread: f64,
+    /// Funding paid by the positions of a perpetual strategy, negative when received.
+    #[serde(default)]
+    pub total_funding: f64,
+    /// One entry per asset of a portfolio backtest, empty otherwise.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attribution: Vec<AssetAttribution>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BacktestReport {
+    pub summary: Summary,
+    pub trades: Vec<Trade>,
+    pub equity_curve: Vec<EquityPoint>,
+    /// One point per fill, the position stays the same until the next one.
+    pub positions: Vec<PositionPoint>,
+}
+
+/// What the engine knows about a run beyond its trades and equity curve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunStats {
+    /// Number of bars that closed with an open position.
+    pub exposed_bars: usize,
+    /// Return of holding the asset(s) over the range, see `Summary::buy_and_hold_return`.
+    pub buy_and_hold_return: f64,
+}
+
+impl RunStats {
+    /// Return of buying at `first_open` and selling at `last_close`.
+    pub fn buy_and_hold(first_open: f64, last_close: f64) -> f64 {
+        if first_open > 0.0 {
+            last_close / first_open - 1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Summary {
+    pub fn compute(
+        initial_capital: f64,
+        trades: &[Trade],
+        equity_curve: &[EquityPoint],
+        stats: RunStats,
+    ) -> Self {
+        let final_equity = equity_curve
+            .last()
+            .map(|p| p.equity)
+            .unwrap_or(initial_capital);
+
+        let closing: Vec<f64> = trades.iter().filter_map(|t| t.pnl).collect();
+        let gross_profit: f64 = closing.iter().filter(|p| **p > 0.0).sum();
+        let gross_loss: f64 = -closing.iter().filter(|p| **p < 0.0).sum::<f64>();
+        let wins = closing.iter().filter(|p| **p > 0.0).count();
+
+        let durations: Vec<i64> = trades.iter().filter_map(|t| t.holding_time).collect();
+        let max_drawdown = max_drawdown(equity_curve);
+
+        Self {
+            initial_capital,
+            final_equity,
+            net_return: final_equity / initial_capital - 1.0,
+            profit_factor: (gross_loss > 0.0).then(|| gross_profit / gross_loss),
+            sharpe_ratio: sharpe_ratio(equity_curve),
+            sortino_ratio: sortino_ratio(equity_curve),
+            calmar_ratio: annualized_return(initial_capital, equity_curve)
+                .filter(|_| max_drawdown > 0.0)
+                .map(|r| r / max_drawdown),
+            max_drawdown,
+            trades_count: trades.len(),
+            win_rate: (!closing.is_empty()).then(|| wins as f64 / closing.len() as f64),
+            exposure_time: if equity_curve.is_empty() {
+                0.0
+            } else {
+                stats.exposed_bars as f64 / equity_curve.len() as f64
+            },
+            avg_trade_duration: (!durations.is_empty())
+                .then(|| durations.iter().sum::<i64>() as f64 / durations.len() as f64),
+            largest_win: closing
+                .iter()
+                .copied()
+                .filter(|p| *p > 0.0)
+                .reduce(f64::max),
+            largest_loss: closing
+                .iter()
+                .copied()
+                .filter(|p| *p < 0.0)
+                .reduce(f64::min),
+            buy_and_hold_return: stats.buy_and_hold_return,
+            total_fees: trades.iter().map(|t| t.fee).sum(),
+            total_slippage: trades.iter().map(|t| t.slippage).sum(),
+            total_spread: trades.iter().map(|t| t.spread).sum(),
+            total_funding: 0.0,
+            attribution: Vec::new(),
+        }
+    }
+}
+
+fn bar_returns(equity_curve: &[EquityPoint]) -> Vec<f64> {
+    equity_curve
+        .windows(2)
+        .map(|w| w[1].equity / w[0].equity - 1.0)
+        .collect()
+}
+
+/// Number of bars in a year, guessed from the spacing of the first two points.
+fn periods_per_year(equity_curve: &[EquityPoint]) -> Option<f64> {
+    let step = equity_curve.get(1)?.timestamp - equity_curve.first()?.timestamp;
+    (step > 0).then(|| SECONDS_PER_YEAR / step as f64)
+}
+
+fn sharpe_ratio(equity_curve: &[EquityPoint]) -> Option<f64> {
+    let returns = bar_returns(equity_curve);
+    if returns.len() < 2 {
+        return None;
+    }
+
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let std_dev = variance.sqrt();
+    if std_dev == 0.0 {
+        return None;
+    }
+
+    Some(mean / std_dev * periods_per_year(equity_curve)?.sqrt())
+}
+
+fn sortino_ratio(equity_curve: &[EquityPoint]) -> Option<f64> {
+    let returns = bar_returns(equity_curve);
+    if returns.len() < 2 {
+        return None;
+    }
+
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let downside = returns.iter().map(|r| r.min(0.0).powi(2)).sum::<f64>() / n;
+    let downside_dev = downside.sqrt();
+    if downside_dev == 0.0 {
+        return None;
+    }
+
+    Some(mean / downside_dev * periods_per_year(equity_curve)?.sqrt())
+}
+
+/// Compound annual growth rate between the first and the last point of the curve.
+fn annualized_return(initial_capital: f64, equity_curve: &[EquityPoint]) -> Option<f64> {
+    let first = equity_curve.first()?;
+    let last = equity_curve.last()?;
+    let years = (last.timestamp - first.timestamp) as f64 / SECONDS_PER_YEAR;
+    if years <= 0.0 || initial_capital <= 0.0 || last.equity < 0.0 {
+        return None;
+    }
+
+    Some((last.equity / initial_capital).powf(1.0 / years) - 1.0)
+}
+
+fn max_drawdown(equity_curve: &[EquityPoint]) -> f64 {
+    let mut peak = f64::MIN;
+    let mut max_dd: f64 = 0.0;
+    for point in equity_curve {
+        peak = peak.max(point.equity);
+        if peak > 0.0 {
+            max_dd = max_dd.max((peak - point.equity) / peak);
+        }
+    }
+    max_dd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sell(timestamp: i64, pnl: f64, holding_time: i64) -> Trade {
+        Trade {
+            timestamp,
+            side: Side::Sell,
+            price: 1.0,
+            quantity: 1.0,
+            pnl: Some(pnl),
+            holding_time: Some(holding_time),
+            fee: 0.5,
+            slippage: 0.0,
+            spread: 0.0,
+            exit_reason: Some(ExitReason::Signal),
+            asset: None,
+            option: None,
+        }
+    }
+
+    #[test]
+    fn test_compute() {
+        let equity_curve: Vec<EquityPoint> = [100.0, 110.0, 99.0, 121.0]
+            .iter()
+            .enumerate()
+            .map(|(i, equity)| EquityPoint {
+                timestamp: i as i64 * 86_400,
+                equity: *equity,
+            })
+            .collect();
+        let trades = [
+            sell(86_400, 10.0, 60),
+            sell(172_800, -11.0, 120),
+            sell(259_200, 22.0, 180),
+        ];
+        let stats = RunStats {
+            exposed_bars: 3,
+            buy_and_hold_return: RunStats::buy_and_hold(50.0, 75.0),
+        };
+
+        let summary = Summary::compute(100.0, &trades, &equity_curve, stats);
+        assert_eq!(summary.exposure_time, 0.75);
+        assert_eq!(summary.avg_trade_duration, Some(120.0));
+        assert_eq!(summary.largest_win, Some(22.0));
+        assert_eq!(summary.largest_loss, Some(-11.0));
+        assert_eq!(summary.buy_and_hold_return, 0.5);
+        assert!((summary.max_drawdown - 0.1).abs() < 1e-9);
+        assert!(summary.sortino_ratio.unwrap() > 0.0);
+        assert!(summary.calmar_ratio.unwrap() > 0.0);
+        assert_eq!(summary.total_fees, 1.5);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{
+    dataset::{Candles, SECONDS_PER_DAY},
+    error::BacktestError,
+    eval::Operand,
+    report::ExitReason,
+    strategy::{Distance, Risk},
+};
+
+/// A `Distance` with its indicator resolved to a dataset column.
+#[derive(Debug, Clone)]
+enum CompiledDistance<'a> {
+    Pct(f64),
+    Atr { atr: Operand<'a>, mult: f64 },
+}
+
+impl<'a> CompiledDistance<'a> {
+    fn compile(distance: &Distance, candles: &'a Candles) -> Result<Self, BacktestError> {
+        match distance {
+            Distance::Pct(pct) => Ok(CompiledDistance::Pct(*pct)),
+            Distance::Atr { indicator, mult } => Ok(CompiledDistance::Atr {
+                atr: Operand::reference(indicator, candles)?,
+                mult: *mult,
+            }),
+        }
+    }
+
+    /// Distance from `price` for a position signaled on `bar`.
+    fn at(&self, price: f64, bar: usize) -> f64 {
+        match self {
+            CompiledDistance::Pct(pct) => price * pct / 100.0,
+            CompiledDistance::Atr { atr, mult } => atr.at(bar) * mult,
+        }
+    }
+}
+
+/// Exit levels of the open position.
+#[derive(Debug, Clone)]
+pub(crate) struct Protection<'a> {
+    stop_loss: Option<f64>,
+    take_profit: Option<f64>,
+    /// Measured from `highest` rather than from the entry.
+    trailing_stop: Option<CompiledDistance<'a>>,
+    signal_bar: usize,
+    /// Highest price reached since the entry, up to the previous bar.
+    highest: f64,
+    opened_bar: usize,
+}
+
+/// The `Risk` section of a strategy compiled against a candle series.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CompiledRisk<'a> {
+    stop_loss: Option<CompiledDistance<'a>>,
+    take_profit: Option<CompiledDistance<'a>>,
+    trailing_stop: Option<CompiledDistance<'a>>,
+    max_holding_bars: Option<usize>,
+    /// As a fraction of the equity at the start of the day.
+    max_daily_loss: Option<f64>,
+}
+
+impl<'a> CompiledRisk<'a> {
+    pub(crate) fn compile(
+        risk: Option<&Risk>,
+        candles: &'a Candles,
+    ) -> Result<Self, BacktestError> {
+        let Some(risk) = risk else {
+            return Ok(Self::default());
+        };
+        let distance = |d: &Option<Distance>| {
+            d.as_ref()
+                .map(|d| CompiledDistance::compile(d, candles))
+                .transpose()
+        };
+
+        Ok(Self {
+            stop_loss: distance(&risk.stop_loss)?,
+            take_profit: distance(&risk.take_profit)?,
+            trailing_stop: distance(&risk.trailing_stop)?,
+            max_holding_bars: risk.max_holding_bars.map(|n| n as usize),
+            max_daily_loss: risk.max_daily_loss.map(|pct| pct / 100.0),
+        })
+    }
+
+    /// Levels of a position entered at `entry` on `bar`. When adding to an existing position, the
+    /// levels move with the new average entry but the holding period and the trailing high are
+    /// kept.
+    pub(crate) fn protect(
+        &self,
+        entry: f64,
+        bar: usize,
+        previous: Option<&Protection<'a>>,
+    ) -> Protection<'a> {
+        // Distances are measured on the bar the entry was signaled.
+        let signal_bar = bar.saturating_sub(1);
+
+        Protection {
+            stop_loss: self
+                .stop_loss
+                .as_ref()
+                .map(|d| entry - d.at(entry, signal_bar)),
+            take_profit: self
+                .take_profit
+                .as_ref()
+                .map(|d| entry + d.at(entry, signal_bar)),
+            trailing_stop: self.trailing_stop.clone(),
+            signal_bar,
+            highest: previous.map_or(entry, |p| p.highest.max(entry)),
+            opened_bar: previous.map_or(bar, |p| p.opened_bar),
+        }
+    }
+
+    /// Exit of the position on `bar`, if one of its levels is reached.
+    ///
+    /// Within a bar the stops are assumed to be hit before the take-profit, and a gap through a
+    /// level exits at the open.
+    pub(crate) fn check_exit(
+        &self,
+        protection: &mut Protection<'a>,
+        bar: usize,
+        open: f64,
+        high: f64,
+        low: f64,
+    ) -> Option<(f64, ExitReason)> {
+        let below = |level: f64| {
+            if open <= level {
+                Some(open)
+            } else if low <= level {
+                Some(level)
+            } else {
+                None
+            }
+        };
+
+        let exit = if self
+            .max_holding_bars
+            .is_some_and(|max| bar - protection.opened_bar >= max)
+        {
+            Some((open, ExitReason::MaxHolding))
+        } else if let Some(price) = protection.stop_loss.and_then(below) {
+            Some((price, ExitReason::StopLoss))
+        } else if let Some(price) = protection.trailing_stop.as_ref().and_then(|d| {
+            below(protection.highest - d.at(protection.highest, protection.signal_bar))
+        }) {
+            Some((price, ExitReason::TrailingStop))
+        } else {
+            protection.take_profit.and_then(|level| {
+                if open >= level {
+                    Some((open, ExitReason::TakeProfit))
+                } else if high >= level {
+                    Some((level, ExitReason::TakeProfit))
+                } else {
+                    None
+                }
+            })
+        };
+
+        protection.highest = protection.highest.max(high);
+        exit
+    }
+
+    pub(crate) fn max_daily_loss(&self) -> Option<f64> {
+        self.max_daily_loss
+    }
+}
+
+/// Day (UTC) a timestamp falls in, used to reset the daily loss.
+pub(crate) fn day(timestamp: i64) -> i64 {
+    timestamp.div_euclid(SECONDS_PER_DAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn risk(json: &str) -> CompiledRisk<'static> {
+        let risk: Risk = serde_json::from_str(json).unwrap();
+        let candles = Box::leak(Box::new(Candles::default()));
+        CompiledRisk::compile(Some(&risk), candles).unwrap()
+    }
+
+    #[test]
+    fn test_stop_loss_before_take_profit() {
+        let risk = risk(r#"{ "stop_loss": { "pct": 5 }, "take_profit": { "pct": 10 } }"#);
+        let mut protection = risk.protect(100.0, 1, None);
+
+        assert_eq!(
+            risk.check_exit(&mut protection, 2, 100.0, 104.0, 96.0),
+            None
+        );
+        assert_eq!(
+            risk.check_exit(&mut protection, 3, 100.0, 111.0, 94.0),
+            Some((95.0, ExitReason::StopLoss))
+        );
+        assert_eq!(
+            risk.check_exit(&mut protection, 4, 112.0, 113.0, 111.0),
+            Some((112.0, ExitReason::TakeProfit))
+        );
+    }
+
+    #[test]
+    fn test_trailing_stop_and_holding() {
+        let risk = risk(r#"{ "trailing_stop": { "pct": 10 }, "max_holding_bars": 3 }"#);
+        let mut protection = risk.protect(100.0, 1, None);
+
+        assert_eq!(
+            risk.check_exit(&mut protection, 1, 100.0, 120.0, 99.0),
+            None
+        );
+        // Trails 10% below the high of 120.
+        assert_eq!(
+            risk.check_exit(&mut protection, 2, 115.0, 116.0, 105.0),
+            Some((108.0, ExitReason::TrailingStop))
+        );
+        assert_eq!(
+            risk.check_exit(&mut protection, 4, 115.0, 116.0, 114.0),
+            Some((115.0, ExitReason::MaxHolding))
+        );
+    }
+}
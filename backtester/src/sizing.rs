@@ -0,0 +1,203 @@
+use crate::{dataset::Candles, report::Trade, strategy::Sizing};
+
+/// Size of an order, resolved on the bar its signal fired.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Stake {
+    /// Fraction of the held quantity, for sells.
+    Quantity(f64),
+    /// Fraction of the available cash.
+    Cash(f64),
+    /// Fraction of the equity.
+    Equity(f64),
+    /// Amount of the quote currency.
+    Quote(f64),
+}
+
+impl Stake {
+    /// Weight to fill the order with: the fraction of the held quantity for a sell, the fraction
+    /// of `cash` for a buy, `equity` being marked at the fill price.
+    pub(crate) fn weight(&self, cash: f64, equity: f64) -> f64 {
+        let amount = match self {
+            Stake::Quantity(w) | Stake::Cash(w) => return *w,
+            Stake::Equity(fraction) => fraction * equity,
+            Stake::Quote(amount) => *amount,
+        };
+        // Also rules out NaN.
+        if !(amount > 0.0 && cash > 0.0) {
+            return 0.0;
+        }
+        (amount / cash).min(1.0)
+    }
+}
+
+/// Win/loss statistics of the closed trades, for the Kelly sizing.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ClosedTrades {
+    count: u32,
+    wins: u32,
+    win_sum: f64,
+    loss_sum: f64,
+}
+
+impl ClosedTrades {
+    pub(crate) fn record(&mut self, trade: &Trade) {
+        let Some(pnl) = trade.pnl else {
+            return;
+        };
+        self.count += 1;
+        if pnl > 0.0 {
+            self.wins += 1;
+            self.win_sum += pnl;
+        } else {
+            self.loss_sum -= pnl;
+        }
+    }
+
+    /// Kelly criterion `W - (1 - W) / R`, with `W` the win rate and `R` the ratio of the average
+    /// win to the average loss.
+    fn kelly(&self) -> f64 {
+        let losses = self.count - self.wins;
+        if self.wins == 0 {
+            return 0.0;
+        }
+        if losses == 0 || self.loss_sum <= 0.0 {
+            return 1.0;
+        }
+        let win_rate = self.wins as f64 / self.count as f64;
+        let payoff = (self.win_sum / self.wins as f64) / (self.loss_sum / losses as f64);
+        win_rate - (1.0 - win_rate) / payoff
+    }
+}
+
+/// Stake of a buy action with weight `w` signaled on `bar`.
+pub(crate) fn stake(
+    sizing: &Sizing,
+    w: f64,
+    bar: usize,
+    candles: &Candles,
+    closed: &ClosedTrades,
+) -> Stake {
+    match sizing {
+        Sizing::Cash => Stake::Cash(w),
+        Sizing::Equity => Stake::Equity(w),
+        Sizing::Quote { amount } => Stake::Quote(w * amount),
+        Sizing::Volatility { target, lookback } => {
+            // No buy until there is enough history to measure the volatility, nor while the price
+            // does not move: the stake would be infinite.
+            let fraction = realized_volatility(&candles.close, bar, *lookback as usize)
+                .filter(|volatility| *volatility > 0.0)
+                .map_or(0.0, |volatility| target / 100.0 / volatility);
+            Stake::Equity(w * fraction)
+        }
+        Sizing::Kelly {
+            fraction,
+            cap,
+            min_trades,
+        } => {
+            let size = if closed.count < *min_trades {
+                *cap
+            } else {
+                (fraction * closed.kelly()).clamp(0.0, *cap)
+            };
+            Stake::Equity(w * size)
+        }
+    }
+}
+
+/// Standard deviation of the close-to-close returns of the `lookback` bars up to `bar`.
+pub(crate) fn realized_volatility(close: &[f64], bar: usize, lookback: usize) -> Option<f64> {
+    if lookback < 2 || bar < lookback {
+        return None;
+    }
+    let returns: Vec<f64> = (bar + 1 - lookback..=bar)
+        .map(|i| close[i] / close[i - 1] - 1.0)
+        .collect();
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / returns.len() as f64;
+
+    Some(variance.sqrt()).filter(|v| v.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Side;
+
+    fn closed_trades(pnls: &[f64]) -> ClosedTrades {
+        let mut closed = ClosedTrades::default();
+        for pnl in pnls {
+            closed.record(&Trade {
+                timestamp: 0,
+                side: Side::Sell,
+                price: 1.0,
+                quantity: 1.0,
+                pnl: Some(*pnl),
+                holding_time: None,
+                fee: 0.0,
+                slippage: 0.0,
+                spread: 0.0,
+                exit_reason: None,
+                asset: None,
+                option: None,
+            });
+        }
+        closed
+    }
+
+    #[test]
+    fn test_kelly() {
+        let sizing = Sizing::Kelly {
+            fraction: 0.5,
+            cap: 0.25,
+            min_trades: 4,
+        };
+        let candles = Candles::default();
+
+        // Not enough trades, sized at the cap.
+        let closed = closed_trades(&[20.0, -10.0]);
+        assert_eq!(
+            stake(&sizing, 1.0, 0, &candles, &closed),
+            Stake::Equity(0.25)
+        );
+
+        // W = 0.5, R = 2, Kelly = 0.25, half of it.
+        let closed = closed_trades(&[20.0, -10.0, 20.0, -10.0]);
+        assert_eq!(
+            stake(&sizing, 1.0, 0, &candles, &closed),
+            Stake::Equity(0.125)
+        );
+    }
+
+    #[test]
+    fn test_volatility() {
+        let sizing = Sizing::Volatility {
+            target: 1.0,
+            lookback: 2,
+        };
+        let closed = ClosedTrades::default();
+        let candles = |close: &[f64]| Candles {
+            close: close.to_vec(),
+            ..Default::default()
+        };
+
+        // Returns of +2% and -2%, a volatility of 2%.
+        let moving = candles(&[100.0, 102.0, 99.96]);
+        let Stake::Equity(fraction) = stake(&sizing, 1.0, 2, &moving, &closed) else {
+            panic!("volatility sizing stakes the equity");
+        };
+        assert!((fraction - 0.5).abs() < 1e-9);
+
+        // Not enough history, then flat prices.
+        assert_eq!(stake(&sizing, 1.0, 1, &moving, &closed), Stake::Equity(0.0));
+        let flat = candles(&[100.0, 100.0, 100.0]);
+        assert_eq!(stake(&sizing, 1.0, 2, &flat, &closed), Stake::Equity(0.0));
+    }
+
+    #[test]
+    fn test_weight() {
+        assert_eq!(Stake::Equity(0.5).weight(4_000.0, 10_000.0), 1.0);
+        assert_eq!(Stake::Equity(0.2).weight(4_000.0, 10_000.0), 0.5);
+        assert_eq!(Stake::Quote(1_000.0).weight(4_000.0, 10_000.0), 0.25);
+        assert_eq!(Stake::Quote(1_000.0).weight(0.0, 10_000.0), 0.0);
+    }
+}
@@ -0,0 +1,1016 @@
+use chrono::{DateTime, Datelike};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use thiserror::Error;
+
+use crate::indicator::IndicatorSpec;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StrategyType {
+    Spot,
+    Options,
+    /// Perpetual futures, traded on margin in both directions.
+    Perp,
+}
+
+impl StrategyType {
+    pub fn valid_actions(&self) -> HashSet<&'static str> {
+        match self {
+            StrategyType::Spot => {
+                let mut set = HashSet::new();
+                set.insert("buy");
+                set.insert("sell");
+                set
+            }
+            StrategyType::Options => {
+                let mut set = HashSet::new();
+                set.insert("long");
+                set.insert("short");
+                set.insert("close");
+                set
+            }
+            StrategyType::Perp => {
+                let mut set = HashSet::new();
+                set.insert("long");
+                set.insert("short");
+                set.insert("close_long");
+                set.insert("close_short");
+                set
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Meta {
+    #[serde(rename = "type")]
+    pub strategy_type: StrategyType,
+    /// Sizing of the buy actions that do not set their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sizing: Option<Sizing>,
+    /// Number of buys a position can be built from, unlimited if not set. Buys beyond it are
+    /// dropped until the position is closed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_entries: Option<u32>,
+    /// Only for perpetual strategies, the defaults of `Margin` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margin: Option<Margin>,
+    /// Only for options strategies, the defaults of `OptionsConfig` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<OptionsConfig>,
+    //#[serde(flatten)]
+    //pub extra: std::collections::HashMap<String, rmpv::Value>,
+}
+
+/// Margin settings of a perpetual strategy.
+///
+/// `long` and `short` open (or add to) a position with `w` of the margin still available,
+/// `close_long` and `close_short` close `w` of it. The position is liquidated once the equity
+/// falls to the maintenance margin.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Margin {
+    /// Notional of a position over the margin it locks.
+    pub leverage: f64,
+    /// Equity under which the position is liquidated, in percentage of its notional.
+    pub maintenance_margin: f64,
+    /// Paid by the longs to the shorts (the other way around when negative) every
+    /// `funding_interval` hours, in percentage of the notional. A `funding_rate` column of the
+    /// dataset, a fraction as the exchanges publish it, takes precedence over it.
+    pub funding_rate: f64,
+    pub funding_interval: u32,
+}
+
+impl Default for Margin {
+    fn default() -> Self {
+        Self {
+            leverage: 1.0,
+            maintenance_margin: 0.5,
+            funding_rate: 0.01,
+            funding_interval: 8,
+        }
+    }
+}
+
+/// Pricing settings of an options strategy.
+///
+/// The options are priced with Black-Scholes on the candles of the underlying. `long` opens the
+/// legs of the action, `short` opens them the other way around, and `close` closes every open leg.
+/// Legs still open at their expiry are settled at their intrinsic value.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct OptionsConfig {
+    /// Annualized implied volatility, in percentage. The historical volatility of the last
+    /// `vol_lookback` bars if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iv: Option<f64>,
+    pub vol_lookback: u32,
+    /// Annualized risk-free rate, in percentage.
+    pub risk_free_rate: f64,
+}
+
+impl Default for OptionsConfig {
+    fn default() -> Self {
+        Self {
+            iv: None,
+            vol_lookback: 30,
+            risk_free_rate: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionKind {
+    Call,
+    Put,
+}
+
+/// One option of the structure an options action opens.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OptionLeg {
+    #[serde(rename = "type")]
+    pub kind: OptionKind,
+    /// Strike as an offset from the spot at the opening, in percentage: 5 is 5% above it.
+    pub strike: f64,
+    /// Days to expiry at the opening.
+    pub expiry: u32,
+    /// Units of the underlying, negative for a written option. Scaled by the action weight.
+    pub quantity: f64,
+}
+
+/// An operand of a condition.
+///
+/// Indicators are read from the dataset, or computed from the candles when the dataset does not
+/// have them (see `indicator`). An indicator name can end with `@{timeframe}` to read it on the
+/// bars of another timeframe of the same asset, `sma_50@4h` being the 50 bars sma of the 4h
+/// bars, and then with `[n]` to read it `n` bars back, `rsi[1]` being the previous bar's rsi.
+///
+/// In a portfolio strategy, a reference can start with `{asset}.` to read the indicator of
+/// another asset of the portfolio, `BTCUSDT.close` in a rule trading `ETHUSDT`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Number(f64),
+    /// 1 when true and 0 when false, like the boolean columns of the datasets.
+    Boolean(bool),
+    Indicator(String),
+    Expr(Box<Expr>),
+    Spec(IndicatorSpec),
+}
+
+/// The parts of an indicator reference, see `Value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndicatorRef<'a> {
+    pub name: &'a str,
+    /// Timeframe to read the indicator on, the backtest's own if not set.
+    pub timeframe: Option<&'a str>,
+    /// Lag in bars of `timeframe`.
+    pub lag: u32,
+}
+
+impl Value {
+    /// Split an indicator reference into the indicator name, its timeframe and its lag.
+    pub fn parse_indicator(reference: &str) -> IndicatorRef<'_> {
+        let (rest, lag) = reference
+            .strip_suffix(']')
+            .and_then(|rest| rest.rsplit_once('['))
+            .and_then(|(rest, lag)| Some((rest, lag.parse().ok()?)))
+            .filter(|(rest, _)| !rest.is_empty())
+            .unwrap_or((reference, 0));
+        let (name, timeframe) = match rest.rsplit_once('@') {
+            Some((name, tf)) if !name.is_empty() && !tf.is_empty() => (name, Some(tf)),
+            _ => (rest, None),
+        };
+        IndicatorRef {
+            name,
+            timeframe,
+            lag,
+        }
+    }
+
+    /// Split the asset off a reference to another asset's indicator, `BTCUSDT.close` giving
+    /// `("BTCUSDT", "close")`. Asset names are upper case, which tells them apart from the dots
+    /// of an indicator's parameters.
+    pub fn split_asset(reference: &str) -> Option<(&str, &str)> {
+        reference.split_once('.').filter(|(asset, rest)| {
+            !asset.is_empty()
+                && !rest.is_empty()
+                && asset
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        })
+    }
+
+    fn references<'a>(&'a self, references: &mut Vec<&'a str>) {
+        match self {
+            Value::Number(_) | Value::Boolean(_) | Value::Spec(_) => {}
+            Value::Indicator(reference) => references.push(reference),
+            Value::Expr(expr) => match expr.as_ref() {
+                Expr::Add { l, r }
+                | Expr::Sub { l, r }
+                | Expr::Mul { l, r }
+                | Expr::Div { l, r }
+                | Expr::Min { l, r }
+                | Expr::Max { l, r } => {
+                    l.references(references);
+                    r.references(references);
+                }
+                Expr::Abs { val } | Expr::Lag { val, .. } => val.references(references),
+                Expr::State(_) => {}
+                Expr::BarsSince { cond } => cond.references(references),
+            },
+        }
+    }
+
+    /// See `Cond::expand`, only `bars_since` holds conditions.
+    fn expand(&mut self, expansion: &mut Expansion) -> Result<(), DefinitionError> {
+        expansion.visit(|expansion| {
+            let Value::Expr(expr) = self else {
+                return Ok(());
+            };
+            match expr.as_mut() {
+                Expr::Add { l, r }
+                | Expr::Sub { l, r }
+                | Expr::Mul { l, r }
+                | Expr::Div { l, r }
+                | Expr::Min { l, r }
+                | Expr::Max { l, r } => {
+                    l.expand(expansion)?;
+                    r.expand(expansion)
+                }
+                Expr::Abs { val } | Expr::Lag { val, .. } => val.expand(expansion),
+                Expr::State(_) => Ok(()),
+                Expr::BarsSince { cond } => cond.expand(expansion),
+            }
+        })
+    }
+
+    /// Whether the value depends on the position, which is only known while the backtest runs.
+    pub fn uses_position(&self) -> bool {
+        match self {
+            Value::Number(_) | Value::Boolean(_) | Value::Indicator(_) | Value::Spec(_) => false,
+            Value::Expr(expr) => match expr.as_ref() {
+                Expr::Add { l, r }
+                | Expr::Sub { l, r }
+                | Expr::Mul { l, r }
+                | Expr::Div { l, r }
+                | Expr::Min { l, r }
+                | Expr::Max { l, r } => l.uses_position() || r.uses_position(),
+                Expr::Abs { val } | Expr::Lag { val, .. } => val.uses_position(),
+                Expr::State(var) => var.is_position(),
+                Expr::BarsSince { cond } => cond.uses_position(),
+            },
+        }
+    }
+
+    /// The canonical form of the value, see `Cond::canonical`. The operands of `add`, `mul`,
+    /// `min` and `max` are put in a fixed order.
+    pub fn canonical(&self) -> Value {
+        let expr = match self {
+            // -0 and 0 compare equal.
+            Value::Number(n) if *n == 0.0 => return Value::Number(0.0),
+            Value::Expr(expr) => expr,
+            value => return value.clone(),
+        };
+        let commutative = |l: &Value, r: &Value| {
+            let (l, r) = (l.canonical(), r.canonical());
+            if sort_key(&l) <= sort_key(&r) {
+                (l, r)
+            } else {
+                (r, l)
+            }
+        };
+        let expr = match expr.as_ref() {
+            Expr::Add { l, r } => {
+                let (l, r) = commutative(l, r);
+                Expr::Add { l, r }
+            }
+            Expr::Mul { l, r } => {
+                let (l, r) = commutative(l, r);
+                Expr::Mul { l, r }
+            }
+            Expr::Min { l, r } => {
+                let (l, r) = commutative(l, r);
+                Expr::Min { l, r }
+            }
+            Expr::Max { l, r } => {
+                let (l, r) = commutative(l, r);
+                Expr::Max { l, r }
+            }
+            Expr::Sub { l, r } => Expr::Sub {
+                l: l.canonical(),
+                r: r.canonical(),
+            },
+            Expr::Div { l, r } => Expr::Div {
+                l: l.canonical(),
+                r: r.canonical(),
+            },
+            Expr::Abs { val } => Expr::Abs {
+                val: val.canonical(),
+            },
+            Expr::Lag { val, n } => Expr::Lag {
+                val: val.canonical(),
+                n: *n,
+            },
+            Expr::State(var) => Expr::State(*var),
+            Expr::BarsSince { cond } => Expr::BarsSince {
+                cond: Box::new(cond.canonical()),
+            },
+        };
+        Value::Expr(Box::new(expr))
+    }
+}
+
+/// Order of the operands and children put in a fixed order by the canonical forms.
+fn sort_key<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("strategies serialize to json")
+}
+
+/// Arithmetic on values, evaluated bar by bar. A missing operand or a division by zero gives a
+/// missing value, which fails every comparison.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Expr {
+    Add {
+        l: Value,
+        r: Value,
+    },
+    Sub {
+        l: Value,
+        r: Value,
+    },
+    Mul {
+        l: Value,
+        r: Value,
+    },
+    Div {
+        l: Value,
+        r: Value,
+    },
+    Min {
+        l: Value,
+        r: Value,
+    },
+    Max {
+        l: Value,
+        r: Value,
+    },
+    Abs {
+        val: Value,
+    },
+    /// `val` as it was `n` bars back.
+    Lag {
+        val: Value,
+        n: u32,
+    },
+    /// A variable of the trading state, see `StateVar`.
+    State(StateVar),
+    /// Number of bars since `cond` was last met, 0 on a bar it is met and missing before it
+    /// ever is. `cond` cannot depend on the position, see `Cond::uses_position`.
+    #[serde(rename = "bars_since")]
+    BarsSince {
+        cond: Box<Cond>,
+    },
+}
+
+/// What a condition knows about the trading state and the time on the close of a bar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StateVar {
+    /// Bars since the current position was opened, 0 on the bar of its first fill and missing
+    /// when flat.
+    BarsSinceEntry,
+    /// Fills since the start of the UTC day, exits included.
+    TradesToday,
+    /// Unrealized pnl of the position in percent of its cost, marked at the close and missing
+    /// when flat.
+    PnlPct,
+    /// Hour of the bar's open, 0 to 23 UTC.
+    Hour,
+    /// Day of the week of the bar's open, 0 for Monday to 6 for Sunday, UTC.
+    Weekday,
+}
+
+impl StateVar {
+    pub const ALL: [StateVar; 5] = [
+        StateVar::BarsSinceEntry,
+        StateVar::TradesToday,
+        StateVar::PnlPct,
+        StateVar::Hour,
+        StateVar::Weekday,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StateVar::BarsSinceEntry => "bars_since_entry",
+            StateVar::TradesToday => "trades_today",
+            StateVar::PnlPct => "pnl_pct",
+            StateVar::Hour => "hour",
+            StateVar::Weekday => "weekday",
+        }
+    }
+
+    /// Whether the variable depends on the position and the fills, rather than on the time only.
+    pub fn is_position(self) -> bool {
+        !matches!(self, StateVar::Hour | StateVar::Weekday)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Cond {
+    And {
+        conds: Vec<Cond>,
+    },
+    Or {
+        conds: Vec<Cond>,
+    },
+    Not {
+        cond: Box<Cond>,
+    },
+    #[serde(rename = "lt")]
+    LessThan {
+        l: Box<Value>,
+        r: Box<Value>,
+    },
+    #[serde(rename = "gt")]
+    GreaterThan {
+        l: Box<Value>,
+        r: Box<Value>,
+    },
+    #[serde(rename = "le")]
+    LessThanOrEqual {
+        l: Box<Value>,
+        r: Box<Value>,
+    },
+    #[serde(rename = "ge")]
+    GreaterThanOrEqual {
+        l: Box<Value>,
+        r: Box<Value>,
+    },
+    #[serde(rename = "eq")]
+    Equal {
+        l: Box<Value>,
+        r: Box<Value>,
+    },
+    #[serde(rename = "neq")]
+    NotEqual {
+        l: Box<Value>,
+        r: Box<Value>,
+    },
+    #[serde(rename = "bet")]
+    Between {
+        val: Box<Value>,
+        min: Box<Value>,
+        max: Box<Value>,
+    },
+    #[serde(rename = "xab")]
+    CrossesAbove {
+        l: Box<Value>,
+        r: Box<Value>,
+    },
+    #[serde(rename = "xbe")]
+    CrossesBelow {
+        l: Box<Value>,
+        r: Box<Value>,
+    },
+    /// Met while a position is open.
+    #[serde(rename = "in_position")]
+    InPosition,
+    /// The condition named `name` in the strategy's `definitions`.
+    Ref(String),
+}
+
+impl Cond {
+    fn references<'a>(&'a self, references: &mut Vec<&'a str>) {
+        match self {
+            Cond::And { conds } | Cond::Or { conds } => {
+                for cond in conds {
+                    cond.references(references);
+                }
+            }
+            Cond::Not { cond } => cond.references(references),
+            Cond::LessThan { l, r }
+            | Cond::GreaterThan { l, r }
+            | Cond::LessThanOrEqual { l, r }
+            | Cond::GreaterThanOrEqual { l, r }
+            | Cond::Equal { l, r }
+            | Cond::NotEqual { l, r }
+            | Cond::CrossesAbove { l, r }
+            | Cond::CrossesBelow { l, r } => {
+                l.references(references);
+                r.references(references);
+            }
+            Cond::Between { val, min, max } => {
+                val.references(references);
+                min.references(references);
+                max.references(references);
+            }
+            Cond::InPosition | Cond::Ref(_) => {}
+        }
+    }
+
+    /// Whether the condition depends on the position, which is only known while the backtest
+    /// runs.
+    pub fn uses_position(&self) -> bool {
+        match self {
+            Cond::And { conds } | Cond::Or { conds } => conds.iter().any(Cond::uses_position),
+            Cond::Not { cond } => cond.uses_position(),
+            Cond::LessThan { l, r }
+            | Cond::GreaterThan { l, r }
+            | Cond::LessThanOrEqual { l, r }
+            | Cond::GreaterThanOrEqual { l, r }
+            | Cond::Equal { l, r }
+            | Cond::NotEqual { l, r }
+            | Cond::CrossesAbove { l, r }
+            | Cond::CrossesBelow { l, r } => l.uses_position() || r.uses_position(),
+            Cond::Between { val, min, max } => {
+                val.uses_position() || min.uses_position() || max.uses_position()
+            }
+            Cond::InPosition => true,
+            // Expanded before this is asked, see `StrategyContent::expand`.
+            Cond::Ref(_) => false,
+        }
+    }
+
+    /// Replace the references to the definitions by the conditions they name.
+    fn expand(&mut self, expansion: &mut Expansion) -> Result<(), DefinitionError> {
+        expansion.visit(|expansion| {
+            match self {
+                Cond::Ref(name) => {
+                    let stack = &mut expansion.stack;
+                    if let Some(start) = stack.iter().position(|n| n == name) {
+                        let mut cycle = stack[start..].to_vec();
+                        cycle.push(name.clone());
+                        return Err(DefinitionError::Cycle(cycle.join(" -> ")));
+                    }
+                    let mut cond = expansion
+                        .definitions
+                        .get(name.as_str())
+                        .cloned()
+                        .ok_or_else(|| DefinitionError::Undefined(name.clone()))?;
+                    expansion.stack.push(name.clone());
+                    cond.expand(expansion)?;
+                    expansion.stack.pop();
+                    *self = cond;
+                }
+                Cond::And { conds } | Cond::Or { conds } => {
+                    for cond in conds {
+                        cond.expand(expansion)?;
+                    }
+                }
+                Cond::Not { cond } => cond.expand(expansion)?,
+                Cond::LessThan { l, r }
+                | Cond::GreaterThan { l, r }
+                | Cond::LessThanOrEqual { l, r }
+                | Cond::GreaterThanOrEqual { l, r }
+                | Cond::Equal { l, r }
+                | Cond::NotEqual { l, r }
+                | Cond::CrossesAbove { l, r }
+                | Cond::CrossesBelow { l, r } => {
+                    l.expand(expansion)?;
+                    r.expand(expansion)?;
+                }
+                Cond::Between { val, min, max } => {
+                    val.expand(expansion)?;
+                    min.expand(expansion)?;
+                    max.expand(expansion)?;
+                }
+                Cond::InPosition => {}
+            }
+            Ok(())
+        })
+    }
+
+    /// The canonical form of the condition. Conditions written differently but met on the same
+    /// bars have the same canonical form when they only differ in:
+    /// - the order of the children of `and` and `or`, nested ones being flattened and repeated
+    ///   ones removed,
+    /// - `gt` and `ge` written as a flipped `lt` and `le`,
+    /// - the order of the operands of `eq`, `neq` and the commutative operations,
+    /// - double negations.
+    ///
+    /// References are left as they are, expand them first, see `StrategyContent::expand`.
+    pub fn canonical(&self) -> Cond {
+        let boxed = |value: &Value| Box::new(value.canonical());
+        let symmetric = |l: &Value, r: &Value| {
+            let (l, r) = (boxed(l), boxed(r));
+            if sort_key(&l) <= sort_key(&r) {
+                (l, r)
+            } else {
+                (r, l)
+            }
+        };
+        match self {
+            Cond::And { conds } | Cond::Or { conds } => {
+                let and = matches!(self, Cond::And { .. });
+                let mut children = Vec::new();
+                for cond in conds {
+                    match cond.canonical() {
+                        Cond::And { conds } if and => children.extend(conds),
+                        Cond::Or { conds } if !and => children.extend(conds),
+                        cond => children.push(cond),
+                    }
+                }
+                let mut keyed: Vec<(String, Cond)> = children
+                    .into_iter()
+                    .map(|cond| (sort_key(&cond), cond))
+                    .collect();
+                keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+                keyed.dedup_by(|(a, _), (b, _)| a == b);
+                let mut conds: Vec<Cond> = keyed.into_iter().map(|(_, cond)| cond).collect();
+                match (conds.len(), and) {
+                    (1, _) => conds.remove(0),
+                    (_, true) => Cond::And { conds },
+                    (_, false) => Cond::Or { conds },
+                }
+            }
+            Cond::Not { cond } => match cond.canonical() {
+                Cond::Not { cond } => *cond,
+                cond => Cond::Not {
+                    cond: Box::new(cond),
+                },
+            },
+            Cond::LessThan { l, r } | Cond::GreaterThan { r: l, l: r } => Cond::LessThan {
+                l: boxed(l),
+                r: boxed(r),
+            },
+            Cond::LessThanOrEqual { l, r } | Cond::GreaterThanOrEqual { r: l, l: r } => {
+                Cond::LessThanOrEqual {
+                    l: boxed(l),
+                    r: boxed(r),
+                }
+            }
+            Cond::Equal { l, r } => {
+                let (l, r) = symmetric(l, r);
+                Cond::Equal { l, r }
+            }
+            Cond::NotEqual { l, r } => {
+                let (l, r) = symmetric(l, r);
+                Cond::NotEqual { l, r }
+            }
+            Cond::Between { val, min, max } => Cond::Between {
+                val: boxed(val),
+                min: boxed(min),
+                max: boxed(max),
+            },
+            Cond::CrossesAbove { l, r } => Cond::CrossesAbove {
+                l: boxed(l),
+                r: boxed(r),
+            },
+            Cond::CrossesBelow { l, r } => Cond::CrossesBelow {
+                l: boxed(l),
+                r: boxed(r),
+            },
+            Cond::InPosition | Cond::Ref(_) => self.clone(),
+        }
+    }
+}
+
+/// Most nodes a condition can have once its definitions are expanded. Definitions referencing
+/// others several times grow exponentially, `d1 = d2 and d2`, `d2 = d3 and d3`...
+pub const MAX_EXPANDED_NODES: usize = 10_000;
+
+/// Most levels a condition can nest once its definitions are expanded, a chain of definitions
+/// each referencing the next one nests as deep as it is long.
+pub const MAX_EXPANDED_DEPTH: usize = 128;
+
+/// A reference to the definitions that can not be expanded.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum DefinitionError {
+    #[error("undefined condition '{0}'")]
+    Undefined(String),
+    #[error("condition defined in terms of itself: {0}")]
+    Cycle(String),
+    #[error(
+        "condition too large once its definitions are expanded, over {MAX_EXPANDED_NODES} nodes"
+    )]
+    TooLarge,
+    #[error(
+        "condition nested too deep once its definitions are expanded, over {MAX_EXPANDED_DEPTH} levels"
+    )]
+    TooDeep,
+}
+
+/// State of `Cond::expand`: the definitions being expanded, to detect cycles, the number of
+/// nodes that can still be expanded and how deep the current one is.
+struct Expansion<'a> {
+    definitions: &'a BTreeMap<String, Cond>,
+    stack: Vec<String>,
+    budget: usize,
+    depth: usize,
+}
+
+impl Expansion<'_> {
+    /// Count a node of the expanded condition and `expand` its children one level deeper. Every
+    /// node is visited once, so this bounds both the time and the memory the expansion takes, and
+    /// the depth bounds the stack.
+    fn visit(
+        &mut self,
+        expand: impl FnOnce(&mut Self) -> Result<(), DefinitionError>,
+    ) -> Result<(), DefinitionError> {
+        self.budget = self
+            .budget
+            .checked_sub(1)
+            .ok_or(DefinitionError::TooLarge)?;
+        if self.depth == MAX_EXPANDED_DEPTH {
+            return Err(DefinitionError::TooDeep);
+        }
+        self.depth += 1;
+        let result = expand(self);
+        self.depth -= 1;
+        result
+    }
+}
+
+/// How the order sent when an action fires gets filled. Prices are evaluated on the bar the
+/// condition was met.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType {
+    /// Filled at the open of the next bar.
+    Market,
+    /// Filled at `price` or better.
+    Limit { price: Value },
+    /// Becomes a market order once the price trades through `price`.
+    Stop { price: Value },
+    /// Becomes a limit order at `limit` once the price trades through `stop`.
+    StopLimit { stop: Value, limit: Value },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Order {
+    #[serde(rename = "type")]
+    pub order_type: OrderType,
+    /// Number of bars the order stays in the book before being cancelled. Defaults to 1, the
+    /// order only gets a chance on the bar following the signal.
+    pub tif: Option<u32>,
+}
+
+impl Order {
+    pub const DEFAULT_TIF: u32 = 1;
+
+    pub fn tif(&self) -> u32 {
+        self.tif.unwrap_or(Self::DEFAULT_TIF)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Action {
+    #[serde(rename = "type")]
+    pub action_type: String,
+    pub w: f64,
+    pub cond: Cond,
+    /// A market order if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+    /// Only for buys, overrides `Meta::sizing`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sizing: Option<Sizing>,
+    /// In a portfolio strategy, the asset the action trades. Actions without one trade every
+    /// asset of the portfolio.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+    /// Only for the `long` and `short` actions of an options strategy, the options they open.
+    /// See `Action::option_legs` for the ones saved before legs existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legs: Option<Vec<OptionLeg>>,
+}
+
+/// Days to expiry of the option opened by the `long` and `short` actions without legs.
+pub const DEFAULT_EXPIRY_DAYS: u32 = 30;
+
+/// What the `long` and `short` actions without legs open, see `Action::option_legs`.
+const DEFAULT_LEGS: &[OptionLeg] = &[OptionLeg {
+    kind: OptionKind::Call,
+    strike: 0.0,
+    expiry: DEFAULT_EXPIRY_DAYS,
+    quantity: 1.0,
+}];
+
+impl Action {
+    /// The options a `long` or `short` action of an options strategy opens. Options strategies
+    /// were saved without legs before they existed, their actions open a single at-the-money call
+    /// expiring in `DEFAULT_EXPIRY_DAYS` days, bought by a `long` and written by a `short`.
+    pub fn option_legs(&self) -> &[OptionLeg] {
+        match (&self.legs, self.action_type.as_str()) {
+            (Some(legs), _) => legs,
+            (None, "long" | "short") => DEFAULT_LEGS,
+            (None, _) => &[],
+        }
+    }
+}
+
+/// How much a buy action spends. The action weight `w` scales the size given by the mode, and
+/// a buy never spends more than the available cash.
+///
+/// Sell actions always sell `w` of the held quantity.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Sizing {
+    /// Fraction of the available cash.
+    #[default]
+    Cash,
+    /// Fraction of the equity (cash and position).
+    Equity,
+    /// Fixed amount of the quote currency.
+    Quote { amount: f64 },
+    /// Fraction of the equity such that the position's volatility matches `target`, in percent
+    /// per bar. The volatility is the standard deviation of the close-to-close returns over the
+    /// `lookback` bars up to the signal.
+    Volatility { target: f64, lookback: u32 },
+    /// `fraction` of the Kelly criterion computed from the trades closed so far, capped at
+    /// `cap` of the equity. The cap is used until `min_trades` trades are closed.
+    Kelly {
+        fraction: f64,
+        cap: f64,
+        #[serde(default = "Sizing::default_min_trades")]
+        min_trades: u32,
+    },
+}
+
+impl Sizing {
+    pub const DEFAULT_MIN_TRADES: u32 = 20;
+
+    fn default_min_trades() -> u32 {
+        Self::DEFAULT_MIN_TRADES
+    }
+}
+
+/// How far from the entry price an exit level sits.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Distance {
+    /// Percentage of the entry price (5 is 5%).
+    Pct(f64),
+    /// Multiple of an ATR indicator of the dataset, taken on the bar the entry was signaled.
+    Atr { indicator: String, mult: f64 },
+}
+
+/// Exits enforced by the engine on top of the actions, for the open position.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Risk {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_loss: Option<Distance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub take_profit: Option<Distance>,
+    /// Distance kept below the highest price reached since the entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trailing_stop: Option<Distance>,
+    /// Number of bars after which the position is closed, whatever its pnl.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_holding_bars: Option<u32>,
+    /// Loss, in percentage of the equity at the start of the (UTC) day, after which the position
+    /// is closed and no new entry is taken until the next day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_daily_loss: Option<f64>,
+}
+
+/// Assets a portfolio strategy trades and how the capital is split between them.
+///
+/// Each asset trades its share of the capital on its own, with its own position, pending orders
+/// and risk rules. The portfolio equity is the sum of them, marked on the merged timeline of the
+/// assets.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PortfolioSpec {
+    pub assets: Vec<String>,
+    /// Fraction of the capital given to each asset, an even split if not set. Capital left
+    /// unallocated stays in cash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allocation: Option<BTreeMap<String, f64>>,
+    /// How often the capital is brought back to the allocation, never if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rebalance: Option<Rebalance>,
+}
+
+impl PortfolioSpec {
+    /// Fraction of the capital given to each asset, in the order of `assets`.
+    pub fn weights(&self) -> Vec<f64> {
+        let even = 1.0 / self.assets.len() as f64;
+        self.assets
+            .iter()
+            .map(|asset| match &self.allocation {
+                Some(allocation) => allocation.get(asset).copied().unwrap_or(0.0),
+                None => even,
+            })
+            .collect()
+    }
+}
+
+/// Rebalancing periods, in UTC. Weeks start on Monday.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rebalance {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Rebalance {
+    /// Number of the period `timestamp` falls in, the capital is rebalanced when it changes.
+    pub fn period(&self, timestamp: i64) -> i64 {
+        let day = timestamp.div_euclid(crate::dataset::SECONDS_PER_DAY);
+        match self {
+            Rebalance::Daily => day,
+            // 1970-01-01 was a Thursday.
+            Rebalance::Weekly => (day + 3).div_euclid(7),
+            Rebalance::Monthly => DateTime::from_timestamp(timestamp, 0)
+                .map_or(0, |date| date.year() as i64 * 12 + date.month0() as i64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrategyContent {
+    pub meta: Meta,
+    pub actions: Vec<Action>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub risk: Option<Risk>,
+    /// Makes the strategy trade several assets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub portfolio: Option<PortfolioSpec>,
+    /// Named conditions, referenced from the actions and from each other with `{"ref": name}`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub definitions: BTreeMap<String, Cond>,
+}
+
+impl StrategyContent {
+    /// `cond` with the references to the definitions replaced by the conditions they name. Fails
+    /// past `MAX_EXPANDED_NODES` or `MAX_EXPANDED_DEPTH`.
+    pub fn expand(&self, cond: &Cond) -> Result<Cond, DefinitionError> {
+        let mut cond = cond.clone();
+        cond.expand(&mut Expansion {
+            definitions: &self.definitions,
+            stack: Vec::new(),
+            budget: MAX_EXPANDED_NODES,
+            depth: 0,
+        })?;
+        Ok(cond)
+    }
+
+    /// The strategy with its definitions expanded and every condition in its canonical form, see
+    /// `Cond::canonical`. Two strategies only written differently have the same canonical form,
+    /// which is what gets compared and hashed.
+    pub fn canonical(&self) -> StrategyContent {
+        let canonical = |cond: &Cond| {
+            self.expand(cond)
+                .unwrap_or_else(|_| cond.clone())
+                .canonical()
+        };
+        let mut strategy = self.clone();
+        strategy.definitions.clear();
+        for action in &mut strategy.actions {
+            action.cond = canonical(&action.cond);
+            if let Some(order) = &mut action.order {
+                match &mut order.order_type {
+                    OrderType::Market => {}
+                    OrderType::Limit { price } | OrderType::Stop { price } => {
+                        *price = price.canonical()
+                    }
+                    OrderType::StopLimit { stop, limit } => {
+                        *stop = stop.canonical();
+                        *limit = limit.canonical();
+                    }
+                }
+            }
+        }
+        strategy
+    }
+
+    /// Every indicator reference of the actions, of the definitions and of the risk rules, as
+    /// written.
+    pub fn references(&self) -> Vec<&str> {
+        let mut references = Vec::new();
+        for cond in self.definitions.values() {
+            cond.references(&mut references);
+        }
+        for action in &self.actions {
+            action.cond.references(&mut references);
+            if let Some(order) = &action.order {
+                match &order.order_type {
+                    OrderType::Market => {}
+                    OrderType::Limit { price } | OrderType::Stop { price } => {
+                        price.references(&mut references)
+                    }
+                    OrderType::StopLimit { stop, limit } => {
+                        stop.references(&mut references);
+                        limit.references(&mut references);
+                    }
+                }
+            }
+        }
+        if let Some(risk) = &self.risk {
+            for distance in [&risk.stop_loss, &risk.take_profit, &risk.trailing_stop]
+                .into_iter()
+                .flatten()
+            {
+                if let Distance::Atr { indicator, .. } = distance {
+                    references.push(indicator);
+                }
+            }
+        }
+        references
+    }
+}
@@ -0,0 +1,1153 @@
+//! Text form of the strategies, easier to write by hand than their JSON.
+//!
+//! ```text
+//! # Trend following, out on a stop.
+//! type spot
+//! risk {"stop_loss": {"pct": 5.0}}
+//!
+//! buy 0.8 when sma_10 crosses above sma_50 and rsi < 30
+//! sell 1 when close < sma_50 * 0.98 or not rsi(period=7) between 20 and 80 limit close tif 3
+//! ```
+//!
+//! A strategy is a list of statements, separated by whitespace only:
+//! - `type {spot|options|perp}` (spot if not set) and `max_entries {n}` set the meta;
+//! - `sizing`, `margin`, `options`, `risk` and `portfolio` take their section as JSON;
+//! - every other statement is an action: its type, its weight, optionally `on {asset}`, then
+//!   `when {condition}`, then optionally its order (`market`, `limit {price}`, `stop {price}` or
+//!   `stop {price} limit {price}`, followed by `tif {n}`) and its `sizing` and `legs` as JSON.
+//!
+//! Conditions combine comparisons (`<`, `>`, `<=`, `>=`, `==`, `!=`, `crosses above`,
+//! `crosses below` and `between .. and ..`) with `not`, `and` and `or`, in that order of
+//! precedence. Values are numbers, indicator references as in `Value`, indicator specs
+//! (`bb(period=20, dev=2, out="upper")`), arithmetic with `+`, `-`, `*`, `/` and the functions
+//! `abs(v)`, `min(a, b)`, `max(a, b)` and `lag(v, n)`. `#` starts a comment.
+//!
+//! `define {name} = {condition}` names a condition, which the actions and the other definitions
+//! then use by its name: `define uptrend = sma_10 > sma_50`, then `buy 1 when uptrend and rsi <
+//! 30`. `true` and `false` are the values 1 and 0.
+//!
+//! The trading state is read with `in_position`, a condition of its own, and the values
+//! `bars_since_entry`, `trades_today`, `pnl_pct`, `hour`, `weekday` and `bars_since(condition)`,
+//! as in `sell 1 when bars_since_entry >= 5 or pnl_pct < -2`.
+//!
+//! `format` writes a strategy back in this form, which parses to the same strategy.
+
+use std::{collections::BTreeMap, ops::Range};
+
+use serde::{Serialize, de::DeserializeOwned};
+use thiserror::Error;
+
+use crate::{
+    indicator::IndicatorSpec,
+    strategy::{
+        Action, Cond, Expr, Meta, Order, OrderType, StateVar, StrategyContent, StrategyType, Value,
+    },
+};
+
+/// Words that can not be read as an indicator.
+const KEYWORDS: &[&str] = &[
+    "and",
+    "or",
+    "not",
+    "crosses",
+    "between",
+    "when",
+    "on",
+    "market",
+    "limit",
+    "stop",
+    "tif",
+    "sizing",
+    "legs",
+    "in_position",
+    "define",
+    "true",
+    "false",
+];
+
+/// Syntax error of a strategy text, located by its byte range in the text, and by the line and
+/// column (both from 1) it starts at.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("line {line}, column {column}: {message}")]
+pub struct ParseError {
+    pub message: String,
+    pub span: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Word(String),
+    Str(String),
+    Symbol(&'static str),
+    Eof,
+}
+
+const SYMBOLS: &[&str] = &[
+    "<=", ">=", "==", "!=", "<", ">", "(", ")", ",", "=", "+", "-", "*", "/",
+];
+
+/// How deep conditions and values can nest, so deeply nested text fails instead of overflowing
+/// the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, span: Range<usize>, message: impl Into<String>) -> ParseError {
+        let before = &self.src[..span.start];
+        let line = before.matches('\n').count() + 1;
+        let column = before[before.rfind('\n').map_or(0, |i| i + 1)..]
+            .chars()
+            .count()
+            + 1;
+        ParseError {
+            message: message.into(),
+            span,
+            line,
+            column,
+        }
+    }
+
+    fn skip_blank(&mut self) {
+        loop {
+            let rest = &self.src[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if !trimmed.starts_with('#') {
+                return;
+            }
+            self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+
+    fn peek(&mut self) -> Result<(Token, Range<usize>), ParseError> {
+        self.skip_blank();
+        let start = self.pos;
+        let rest = &self.src[start..];
+        let Some(c) = rest.chars().next() else {
+            return Ok((Token::Eof, start..start));
+        };
+        let len_while = |from: usize, pred: fn(char) -> bool| {
+            rest[from..]
+                .find(|c: char| !pred(c))
+                .map_or(rest.len(), |i| from + i)
+        };
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = len_while(0, |c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+            if rest[end..].starts_with('@') {
+                end = len_while(end + 1, |c| c.is_ascii_alphanumeric());
+            }
+            if rest[end..].starts_with('[') {
+                let digits = len_while(end + 1, |c| c.is_ascii_digit());
+                if digits == end + 1 || !rest[digits..].starts_with(']') {
+                    return Err(self.error(start..start + digits, "expected a lag like '[1]'"));
+                }
+                end = digits + 1;
+            }
+            return Ok((Token::Word(rest[..end].to_string()), start..start + end));
+        }
+        if c.is_ascii_digit() {
+            let mut end = len_while(0, |c| c.is_ascii_digit());
+            if rest[end..].starts_with('.') {
+                end = len_while(end + 1, |c| c.is_ascii_digit());
+            }
+            if rest[end..].starts_with(['e', 'E']) {
+                let sign = usize::from(rest[end + 1..].starts_with(['+', '-']));
+                end = len_while(end + 1 + sign, |c| c.is_ascii_digit());
+            }
+            let span = start..start + end;
+            return match rest[..end].parse() {
+                Ok(number) => Ok((Token::Number(number), span)),
+                Err(_) => Err(self.error(span, "invalid number")),
+            };
+        }
+        if c == '"' {
+            return match rest[1..].find(['"', '\n']) {
+                Some(i) if rest[1 + i..].starts_with('"') => {
+                    Ok((Token::Str(rest[1..1 + i].to_string()), start..start + i + 2))
+                }
+                _ => Err(self.error(start..start + 1, "unterminated string")),
+            };
+        }
+        match SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            Some(symbol) => Ok((Token::Symbol(symbol), start..start + symbol.len())),
+            None => Err(self.error(
+                start..start + c.len_utf8(),
+                format!("unexpected character '{}'", c),
+            )),
+        }
+    }
+
+    fn next(&mut self) -> Result<(Token, Range<usize>), ParseError> {
+        let (token, span) = self.peek()?;
+        self.pos = span.end;
+        Ok((token, span))
+    }
+
+    /// Consume the next token if it is `word`.
+    fn eat_word(&mut self, word: &str) -> Result<bool, ParseError> {
+        let (token, span) = self.peek()?;
+        let found = matches!(token, Token::Word(w) if w == word);
+        if found {
+            self.pos = span.end;
+        }
+        Ok(found)
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> Result<bool, ParseError> {
+        let (token, span) = self.peek()?;
+        let found = matches!(token, Token::Symbol(s) if s == symbol);
+        if found {
+            self.pos = span.end;
+        }
+        Ok(found)
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), ParseError> {
+        let (_, span) = self.peek()?;
+        if !self.eat_word(word)? {
+            return Err(self.error(span, format!("expected '{}'", word)));
+        }
+        Ok(())
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), ParseError> {
+        let (_, span) = self.peek()?;
+        if !self.eat_symbol(symbol)? {
+            return Err(self.error(span, format!("expected '{}'", symbol)));
+        }
+        Ok(())
+    }
+
+    /// Run `parse` one nesting level deeper.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth == MAX_DEPTH {
+            let (_, span) = self.peek()?;
+            return Err(self.error(span, format!("nested more than {} levels deep", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn number(&mut self, what: &str) -> Result<f64, ParseError> {
+        match self.next()? {
+            (Token::Number(number), _) => Ok(number),
+            (_, span) => Err(self.error(span, format!("expected {}", what))),
+        }
+    }
+
+    fn integer(&mut self, what: &str) -> Result<u32, ParseError> {
+        match self.next()? {
+            (Token::Number(number), _)
+                if number.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&number) =>
+            {
+                Ok(number as u32)
+            }
+            (_, span) => Err(self.error(span, format!("expected {}, a whole number", what))),
+        }
+    }
+
+    fn word(&mut self, what: &str) -> Result<String, ParseError> {
+        match self.next()? {
+            (Token::Word(word), _) => Ok(word),
+            (_, span) => Err(self.error(span, format!("expected {}", what))),
+        }
+    }
+
+    /// A JSON value deserialized into `T`.
+    fn json<T: DeserializeOwned>(&mut self, what: &str) -> Result<T, ParseError> {
+        self.skip_blank();
+        let start = self.pos;
+        let mut values =
+            serde_json::Deserializer::from_str(&self.src[start..]).into_iter::<serde_json::Value>();
+        let value = match values.next() {
+            Some(Ok(value)) => value,
+            _ => {
+                let end = start + self.src[start..].chars().next().map_or(0, char::len_utf8);
+                return Err(self.error(start..end, format!("expected {} as JSON", what)));
+            }
+        };
+        self.pos = start + values.byte_offset();
+        serde_json::from_value(value)
+            .map_err(|e| self.error(start..self.pos, format!("invalid {}: {}", what, e)))
+    }
+
+    fn strategy(&mut self) -> Result<StrategyContent, ParseError> {
+        let mut strategy_type = None;
+        let mut meta_sizing = None;
+        let mut max_entries = None;
+        let mut margin = None;
+        let mut options = None;
+        let mut risk = None;
+        let mut portfolio = None;
+        let mut definitions = BTreeMap::new();
+        let mut actions = Vec::new();
+        let action_types = [
+            StrategyType::Spot,
+            StrategyType::Options,
+            StrategyType::Perp,
+        ]
+        .iter()
+        .flat_map(|t| t.valid_actions())
+        .collect::<Vec<_>>();
+
+        loop {
+            let (token, span) = self.next()?;
+            let statement = match token {
+                Token::Eof => break,
+                Token::Word(word) => word,
+                _ => return Err(self.error(span, "expected a statement")),
+            };
+            let set = match statement.as_str() {
+                "type" => {
+                    let (_, type_span) = self.peek()?;
+                    let name = self.word("the strategy type")?;
+                    let parsed = serde_json::from_value(serde_json::Value::String(name.clone()))
+                        .map_err(|_| {
+                            self.error(type_span, format!("unknown strategy type '{}'", name))
+                        })?;
+                    strategy_type.replace(parsed).is_some()
+                }
+                "max_entries" => max_entries
+                    .replace(self.integer("the number of entries")?)
+                    .is_some(),
+                "sizing" => meta_sizing.replace(self.json("sizing")?).is_some(),
+                "margin" => margin.replace(self.json("margin")?).is_some(),
+                "options" => options.replace(self.json("options")?).is_some(),
+                "risk" => risk.replace(self.json("risk")?).is_some(),
+                "portfolio" => portfolio.replace(self.json("portfolio")?).is_some(),
+                "define" => {
+                    let (_, name_span) = self.peek()?;
+                    let name = self.word("the name of the condition")?;
+                    if !is_name(&name) {
+                        return Err(self.error(name_span, format!("invalid name '{}'", name)));
+                    }
+                    self.expect_symbol("=")?;
+                    let cond = self.cond()?;
+                    if definitions.insert(name.clone(), cond).is_some() {
+                        return Err(self.error(name_span, format!("'{}' is already defined", name)));
+                    }
+                    continue;
+                }
+                action if action_types.contains(&action) => {
+                    actions.push(self.action(action.to_string())?);
+                    continue;
+                }
+                _ => {
+                    return Err(self.error(span, format!("unknown statement '{}'", statement)));
+                }
+            };
+            if set {
+                return Err(self.error(span, format!("'{}' is already set", statement)));
+            }
+        }
+
+        Ok(StrategyContent {
+            meta: Meta {
+                strategy_type: strategy_type.unwrap_or(StrategyType::Spot),
+                sizing: meta_sizing,
+                max_entries,
+                margin,
+                options,
+            },
+            actions,
+            risk,
+            portfolio,
+            definitions,
+        })
+    }
+
+    fn action(&mut self, action_type: String) -> Result<Action, ParseError> {
+        let w = self.number("the weight of the action")?;
+        let asset = if self.eat_word("on")? {
+            Some(self.word("an asset")?)
+        } else {
+            None
+        };
+        self.expect_word("when")?;
+        let cond = self.cond()?;
+
+        let order_type = if self.eat_word("market")? {
+            Some(OrderType::Market)
+        } else if self.eat_word("limit")? {
+            Some(OrderType::Limit {
+                price: self.value()?,
+            })
+        } else if self.eat_word("stop")? {
+            let stop = self.value()?;
+            if self.eat_word("limit")? {
+                Some(OrderType::StopLimit {
+                    stop,
+                    limit: self.value()?,
+                })
+            } else {
+                Some(OrderType::Stop { price: stop })
+            }
+        } else {
+            None
+        };
+        let tif = if self.eat_word("tif")? {
+            Some(self.integer("the time in force")?)
+        } else {
+            None
+        };
+        let order = match (order_type, tif) {
+            (None, None) => None,
+            (order_type, tif) => Some(Order {
+                order_type: order_type.unwrap_or(OrderType::Market),
+                tif,
+            }),
+        };
+
+        let mut action = Action {
+            action_type,
+            w,
+            cond,
+            order,
+            sizing: None,
+            asset,
+            legs: None,
+        };
+        loop {
+            let (_, span) = self.peek()?;
+            let set = if self.eat_word("sizing")? {
+                action.sizing.replace(self.json("sizing")?).is_some()
+            } else if self.eat_word("legs")? {
+                action.legs.replace(self.json("legs")?).is_some()
+            } else {
+                break;
+            };
+            if set {
+                return Err(self.error(span, "set twice for the same action"));
+            }
+        }
+        Ok(action)
+    }
+
+    fn cond(&mut self) -> Result<Cond, ParseError> {
+        let mut conds = vec![self.conjunction()?];
+        while self.eat_word("or")? {
+            conds.push(self.conjunction()?);
+        }
+        Ok(match conds.len() {
+            1 => conds.remove(0),
+            _ => Cond::Or { conds },
+        })
+    }
+
+    fn conjunction(&mut self) -> Result<Cond, ParseError> {
+        let mut conds = vec![self.unary()?];
+        while self.eat_word("and")? {
+            conds.push(self.unary()?);
+        }
+        Ok(match conds.len() {
+            1 => conds.remove(0),
+            _ => Cond::And { conds },
+        })
+    }
+
+    fn unary(&mut self) -> Result<Cond, ParseError> {
+        self.nested(|parser| {
+            if parser.eat_word("not")? {
+                return Ok(Cond::Not {
+                    cond: Box::new(parser.unary()?),
+                });
+            }
+            if parser.eat_word("in_position")? {
+                return Ok(Cond::InPosition);
+            }
+            // A parenthesis opens either a condition or a value, `(a + b) > c`.
+            let start = parser.pos;
+            if parser.eat_symbol("(")? {
+                if let Ok(cond) = parser.cond()
+                    && parser.eat_symbol(")")?
+                {
+                    return Ok(cond);
+                }
+                parser.pos = start;
+            }
+            parser.comparison()
+        })
+    }
+
+    fn comparison(&mut self) -> Result<Cond, ParseError> {
+        let l = Box::new(self.value()?);
+        // A name compared to nothing is a defined condition.
+        if let Value::Indicator(name) = l.as_ref()
+            && is_name(name)
+            && !matches!(
+                self.peek()?.0,
+                Token::Symbol("<" | ">" | "<=" | ">=" | "==" | "!=")
+            )
+            && !matches!(self.peek()?.0, Token::Word(w) if w == "crosses" || w == "between")
+        {
+            return Ok(Cond::Ref(name.clone()));
+        }
+        let (token, span) = self.next()?;
+        let cond = match token {
+            Token::Symbol("<") => Cond::LessThan {
+                l,
+                r: Box::new(self.value()?),
+            },
+            Token::Symbol(">") => Cond::GreaterThan {
+                l,
+                r: Box::new(self.value()?),
+            },
+            Token::Symbol("<=") => Cond::LessThanOrEqual {
+                l,
+                r: Box::new(self.value()?),
+            },
+            Token::Symbol(">=") => Cond::GreaterThanOrEqual {
+                l,
+                r: Box::new(self.value()?),
+            },
+            Token::Symbol("==") => Cond::Equal {
+                l,
+                r: Box::new(self.value()?),
+            },
+            Token::Symbol("!=") => Cond::NotEqual {
+                l,
+                r: Box::new(self.value()?),
+            },
+            Token::Word(word) if word == "crosses" => {
+                if self.eat_word("above")? {
+                    Cond::CrossesAbove {
+                        l,
+                        r: Box::new(self.value()?),
+                    }
+                } else if self.eat_word("below")? {
+                    Cond::CrossesBelow {
+                        l,
+                        r: Box::new(self.value()?),
+                    }
+                } else {
+                    let (_, span) = self.peek()?;
+                    return Err(self.error(span, "expected 'above' or 'below'"));
+                }
+            }
+            Token::Word(word) if word == "between" => {
+                let min = Box::new(self.value()?);
+                self.expect_word("and")?;
+                Cond::Between {
+                    val: l,
+                    min,
+                    max: Box::new(self.value()?),
+                }
+            }
+            _ => return Err(self.error(span, "expected a comparison")),
+        };
+        Ok(cond)
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        let mut value = self.term()?;
+        loop {
+            value = if self.eat_symbol("+")? {
+                Value::Expr(Box::new(Expr::Add {
+                    l: value,
+                    r: self.term()?,
+                }))
+            } else if self.eat_symbol("-")? {
+                Value::Expr(Box::new(Expr::Sub {
+                    l: value,
+                    r: self.term()?,
+                }))
+            } else {
+                return Ok(value);
+            };
+        }
+    }
+
+    fn term(&mut self) -> Result<Value, ParseError> {
+        let mut value = self.atom()?;
+        loop {
+            value = if self.eat_symbol("*")? {
+                Value::Expr(Box::new(Expr::Mul {
+                    l: value,
+                    r: self.atom()?,
+                }))
+            } else if self.eat_symbol("/")? {
+                Value::Expr(Box::new(Expr::Div {
+                    l: value,
+                    r: self.atom()?,
+                }))
+            } else {
+                return Ok(value);
+            };
+        }
+    }
+
+    fn atom(&mut self) -> Result<Value, ParseError> {
+        self.nested(|parser| {
+            let (token, span) = parser.next()?;
+            match token {
+                Token::Number(number) => Ok(Value::Number(number)),
+                Token::Word(word) if word == "true" || word == "false" => {
+                    Ok(Value::Boolean(word == "true"))
+                }
+                Token::Symbol("-") => Ok(Value::Number(-parser.number("a number")?)),
+                Token::Symbol("(") => {
+                    let value = parser.value()?;
+                    parser.expect_symbol(")")?;
+                    Ok(value)
+                }
+                Token::Word(word) if !KEYWORDS.contains(&word.as_str()) => {
+                    if let Some(var) = StateVar::ALL.into_iter().find(|var| var.name() == word) {
+                        Ok(Value::Expr(Box::new(Expr::State(var))))
+                    } else if parser.eat_symbol("(")? {
+                        parser.call(word, span)
+                    } else {
+                        Ok(Value::Indicator(word))
+                    }
+                }
+                _ => Err(parser.error(span, "expected a value")),
+            }
+        })
+    }
+
+    /// A function, or an indicator spec, whose opening parenthesis was consumed.
+    fn call(&mut self, name: String, span: Range<usize>) -> Result<Value, ParseError> {
+        let expr = match name.as_str() {
+            "abs" => Expr::Abs { val: self.value()? },
+            "bars_since" => Expr::BarsSince {
+                cond: Box::new(self.cond()?),
+            },
+            "min" | "max" => {
+                let l = self.value()?;
+                self.expect_symbol(",")?;
+                let r = self.value()?;
+                if name == "min" {
+                    Expr::Min { l, r }
+                } else {
+                    Expr::Max { l, r }
+                }
+            }
+            "lag" => {
+                let val = self.value()?;
+                self.expect_symbol(",")?;
+                Expr::Lag {
+                    val,
+                    n: self.integer("the number of bars")?,
+                }
+            }
+            _ => return self.spec(name, span),
+        };
+        self.expect_symbol(")")?;
+        Ok(Value::Expr(Box::new(expr)))
+    }
+
+    fn spec(&mut self, name: String, span: Range<usize>) -> Result<Value, ParseError> {
+        let mut fields = serde_json::Map::new();
+        fields.insert("ind".to_string(), serde_json::Value::String(name));
+        while !self.eat_symbol(")")? {
+            if fields.len() > 1 {
+                self.expect_symbol(",")?;
+            }
+            let key = self.word("a parameter")?;
+            self.expect_symbol("=")?;
+            let value = match self.next()? {
+                (Token::Number(n), _) if n.fract() == 0.0 && n >= 0.0 => {
+                    serde_json::Value::from(n as u64)
+                }
+                (Token::Number(n), _) => serde_json::Value::from(n),
+                (Token::Word(s) | Token::Str(s), _) => serde_json::Value::String(s),
+                (_, span) => return Err(self.error(span, "expected a parameter value")),
+            };
+            fields.insert(key, value);
+        }
+        let spec: IndicatorSpec = serde_json::from_value(serde_json::Value::Object(fields))
+            .map_err(|e| self.error(span.start..self.pos, format!("invalid indicator: {}", e)))?;
+        Ok(Value::Spec(spec))
+    }
+}
+
+/// Whether `word` can name a definition: not a reserved word, and without the qualifiers of an
+/// indicator reference.
+fn is_name(word: &str) -> bool {
+    !KEYWORDS.contains(&word)
+        && StateVar::ALL.iter().all(|var| var.name() != word)
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parse the text form of a strategy.
+///
+/// Only the syntax is checked here, the strategy still has to be validated.
+pub fn parse(text: &str) -> Result<StrategyContent, ParseError> {
+    Parser {
+        src: text,
+        pos: 0,
+        depth: 0,
+    }
+    .strategy()
+}
+
+/// Write `strategy` in its text form, one statement per line.
+pub fn format(strategy: &StrategyContent) -> String {
+    let meta = &strategy.meta;
+    let mut lines = vec![format!(
+        "type {}",
+        json(&meta.strategy_type).trim_matches('"')
+    )];
+    if let Some(max_entries) = meta.max_entries {
+        lines.push(format!("max_entries {}", max_entries));
+    }
+    let sections = [
+        ("sizing", meta.sizing.as_ref().map(json)),
+        ("margin", meta.margin.as_ref().map(json)),
+        ("options", meta.options.as_ref().map(json)),
+        ("risk", strategy.risk.as_ref().map(json)),
+        ("portfolio", strategy.portfolio.as_ref().map(json)),
+    ];
+    for (keyword, section) in sections {
+        if let Some(section) = section {
+            lines.push(format!("{} {}", keyword, section));
+        }
+    }
+    lines.push(String::new());
+
+    if !strategy.definitions.is_empty() {
+        for (name, cond) in &strategy.definitions {
+            let mut line = format!("define {} = ", name);
+            write_cond(cond, Context::Top, &mut line);
+            lines.push(line);
+        }
+        lines.push(String::new());
+    }
+
+    for action in &strategy.actions {
+        lines.push(format_action(action));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Write an action in its text form, a line of `format`.
+pub fn format_action(action: &Action) -> String {
+    let mut line = format!("{} {}", action.action_type, action.w);
+    if let Some(asset) = &action.asset {
+        line += &format!(" on {}", asset);
+    }
+    line += " when ";
+    write_cond(&action.cond, Context::Top, &mut line);
+    if let Some(order) = &action.order {
+        match &order.order_type {
+            OrderType::Market => line += " market",
+            OrderType::Limit { price } => {
+                line += " limit ";
+                write_value(price, Level::Sum, &mut line);
+            }
+            OrderType::Stop { price } => {
+                line += " stop ";
+                write_value(price, Level::Sum, &mut line);
+            }
+            OrderType::StopLimit { stop, limit } => {
+                line += " stop ";
+                write_value(stop, Level::Sum, &mut line);
+                line += " limit ";
+                write_value(limit, Level::Sum, &mut line);
+            }
+        }
+        if let Some(tif) = order.tif {
+            line += &format!(" tif {}", tif);
+        }
+    }
+    if let Some(sizing) = &action.sizing {
+        line += &format!(" sizing {}", json(sizing));
+    }
+    if let Some(legs) = &action.legs {
+        line += &format!(" legs {}", json(legs));
+    }
+    line
+}
+
+/// Write a condition in its text form.
+pub fn format_cond(cond: &Cond) -> String {
+    let mut out = String::new();
+    write_cond(cond, Context::Top, &mut out);
+    out
+}
+
+/// Write a value in its text form.
+pub fn format_value(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(value, Level::Sum, &mut out);
+    out
+}
+
+fn json<T: Serialize>(value: &T) -> String {
+    // Unwrap is fine, the strategy types always serialize.
+    serde_json::to_string(value).unwrap()
+}
+
+/// Where a condition is written, to know when it needs parentheses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Top,
+    Or,
+    And,
+    Not,
+}
+
+fn write_cond(cond: &Cond, context: Context, out: &mut String) {
+    let (conds, separator, own) = match cond {
+        Cond::InPosition => return *out += "in_position",
+        Cond::Ref(name) => return *out += name,
+        Cond::Or { conds } => (conds, " or ", Context::Or),
+        Cond::And { conds } => (conds, " and ", Context::And),
+        Cond::Not { cond } => {
+            *out += "not ";
+            return write_cond(cond, Context::Not, out);
+        }
+        Cond::Between { val, min, max } => {
+            write_value(val, Level::Sum, out);
+            *out += " between ";
+            write_value(min, Level::Sum, out);
+            *out += " and ";
+            write_value(max, Level::Sum, out);
+            return;
+        }
+        Cond::LessThan { l, r }
+        | Cond::GreaterThan { l, r }
+        | Cond::LessThanOrEqual { l, r }
+        | Cond::GreaterThanOrEqual { l, r }
+        | Cond::Equal { l, r }
+        | Cond::NotEqual { l, r }
+        | Cond::CrossesAbove { l, r }
+        | Cond::CrossesBelow { l, r } => {
+            let operator = match cond {
+                Cond::LessThan { .. } => "<",
+                Cond::GreaterThan { .. } => ">",
+                Cond::LessThanOrEqual { .. } => "<=",
+                Cond::GreaterThanOrEqual { .. } => ">=",
+                Cond::Equal { .. } => "==",
+                Cond::NotEqual { .. } => "!=",
+                Cond::CrossesAbove { .. } => "crosses above",
+                _ => "crosses below",
+            };
+            write_value(l, Level::Sum, out);
+            *out += &format!(" {} ", operator);
+            write_value(r, Level::Sum, out);
+            return;
+        }
+    };
+
+    // Nested `and` and `or` keep their parentheses so the text parses to the same tree.
+    let parenthesized = match own {
+        Context::Or => context != Context::Top,
+        _ => context != Context::Top && context != Context::Or,
+    };
+    if parenthesized {
+        *out += "(";
+    }
+    for (i, cond) in conds.iter().enumerate() {
+        if i > 0 {
+            *out += separator;
+        }
+        write_cond(cond, own, out);
+    }
+    if parenthesized {
+        *out += ")";
+    }
+}
+
+/// Precedence of a value, the lowest that can be written without parentheses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Sum,
+    Product,
+    Atom,
+}
+
+fn write_value(value: &Value, level: Level, out: &mut String) {
+    let expr = match value {
+        Value::Number(number) => return *out += &number.to_string(),
+        Value::Boolean(b) => return *out += &b.to_string(),
+        Value::Indicator(reference) => return *out += reference,
+        Value::Spec(spec) => return write_spec(spec, out),
+        Value::Expr(expr) => expr.as_ref(),
+    };
+    let (l, operator, r, own) = match expr {
+        Expr::Add { l, r } => (l, " + ", r, Level::Sum),
+        Expr::Sub { l, r } => (l, " - ", r, Level::Sum),
+        Expr::Mul { l, r } => (l, " * ", r, Level::Product),
+        Expr::Div { l, r } => (l, " / ", r, Level::Product),
+        Expr::Min { l, r } | Expr::Max { l, r } => {
+            *out += if matches!(expr, Expr::Min { .. }) {
+                "min("
+            } else {
+                "max("
+            };
+            write_value(l, Level::Sum, out);
+            *out += ", ";
+            write_value(r, Level::Sum, out);
+            return *out += ")";
+        }
+        Expr::Abs { val } => {
+            *out += "abs(";
+            write_value(val, Level::Sum, out);
+            return *out += ")";
+        }
+        Expr::Lag { val, n } => {
+            *out += "lag(";
+            write_value(val, Level::Sum, out);
+            return *out += &format!(", {})", n);
+        }
+        Expr::State(var) => return *out += var.name(),
+        Expr::BarsSince { cond } => {
+            *out += "bars_since(";
+            write_cond(cond, Context::Top, out);
+            return *out += ")";
+        }
+    };
+
+    if own < level {
+        *out += "(";
+    }
+    write_value(l, own, out);
+    *out += operator;
+    // The operators are left associative, the right operand binds tighter.
+    let right = if own == Level::Sum {
+        Level::Product
+    } else {
+        Level::Atom
+    };
+    write_value(r, right, out);
+    if own < level {
+        *out += ")";
+    }
+}
+
+fn write_spec(spec: &IndicatorSpec, out: &mut String) {
+    *out += &spec.ind;
+    *out += "(";
+    let fields = match serde_json::to_value(spec) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => unreachable!("an indicator spec serializes to an object"),
+    };
+    let params = fields
+        .iter()
+        .filter(|(key, _)| *key != "ind")
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>();
+    *out += &params.join(", ");
+    *out += ")";
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"
+            # Trend following.
+            type spot
+            risk {"stop_loss": {"pct": 5.0}}
+
+            buy 0.8 when sma_10 crosses above sma_50 and rsi < 30
+            sell 1 when close < sma_50 * 0.98 or not bb(period=20, dev=2, out="lower") between -1 and 2
+                limit close tif 3
+        "#;
+        let strategy = parse(text).unwrap();
+
+        let expected = json!({
+            "meta": { "type": "spot" },
+            "actions": [
+                {
+                    "type": "buy",
+                    "w": 0.8,
+                    "cond": { "and": { "conds": [
+                        { "xab": { "l": "sma_10", "r": "sma_50" } },
+                        { "lt": { "l": "rsi", "r": 30.0 } }
+                    ] } }
+                },
+                {
+                    "type": "sell",
+                    "w": 1.0,
+                    "cond": { "or": { "conds": [
+                        { "lt": { "l": "close", "r": { "mul": { "l": "sma_50", "r": 0.98 } } } },
+                        { "not": { "cond": { "bet": {
+                            "val": { "ind": "bb", "period": 20, "dev": 2.0, "out": "lower" },
+                            "min": -1.0,
+                            "max": 2.0
+                        } } } }
+                    ] } },
+                    "order": { "type": { "limit": { "price": "close" } }, "tif": 3 }
+                }
+            ],
+            "risk": { "stop_loss": { "pct": 5.0 } }
+        });
+        let expected: StrategyContent = serde_json::from_value(expected).unwrap();
+        assert_eq!(
+            serde_json::to_value(&strategy).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let strategy = json!({
+            "meta": { "type": "spot", "max_entries": 2, "sizing": { "mode": "equity" } },
+            "actions": [
+                {
+                    "type": "buy",
+                    "w": 0.5,
+                    "asset": "ETHUSDT",
+                    "cond": { "and": { "conds": [
+                        { "or": { "conds": [
+                            { "gt": { "l": "BTCUSDT.close", "r": "BTCUSDT.sma_200@1d[1]" } },
+                            { "and": { "conds": [
+                                { "ge": { "l": "rsi", "r": 50.0 } },
+                                { "neq": { "l": "volume", "r": 0.0 } }
+                            ] } }
+                        ] } },
+                        { "le": {
+                            "l": { "sub": {
+                                "l": "close",
+                                "r": { "sub": { "l": "open", "r": { "lag": { "val": "close", "n": 2 } } } }
+                            } },
+                            "r": { "div": {
+                                "l": { "abs": { "val": { "add": { "l": "high", "r": -1.5 } } } },
+                                "r": { "min": { "l": "low", "r": { "max": { "l": 1.0, "r": 2.0 } } } }
+                            } }
+                        } },
+                        { "not": { "cond": { "or": { "conds": [
+                            { "eq": { "l": { "ind": "rsi", "tf": "4h" }, "r": 1.0 } },
+                            { "xbe": { "l": "close@4h", "r": "sma_50@4h" } }
+                        ] } } } }
+                    ] } },
+                    "order": { "type": { "stop_limit": { "stop": "high", "limit": { "mul": { "l": "high", "r": 1.01 } } } } },
+                    "sizing": { "mode": "quote", "amount": 100.0 }
+                },
+                {
+                    "type": "sell",
+                    "w": 1.0,
+                    "cond": { "or": { "conds": [
+                        { "lt": { "l": "close", "r": "sma_50" } },
+                        { "not": { "cond": { "ref": "signal" } } },
+                        { "and": { "conds": [
+                            "in_position",
+                            { "ge": { "l": { "state": "bars_since_entry" }, "r": 5.0 } },
+                            { "le": {
+                                "l": { "bars_since": { "cond": { "xab": { "l": "close", "r": "sma_50" } } } },
+                                "r": 3.0
+                            } },
+                            { "bet": { "val": { "state": "hour" }, "min": 9.0, "max": 17.0 } }
+                        ] } }
+                    ] } },
+                    "order": { "type": "market", "tif": 2 }
+                }
+            ],
+            "portfolio": { "assets": ["BTCUSDT", "ETHUSDT"], "rebalance": "weekly" },
+            "definitions": {
+                "uptrend": { "gt": { "l": "sma_10", "r": "sma_50" } },
+                "signal": { "and": { "conds": [
+                    { "ref": "uptrend" },
+                    { "eq": { "l": "breakout", "r": true } }
+                ] } }
+            }
+        });
+        let strategy: StrategyContent = serde_json::from_value(strategy).unwrap();
+
+        let text = format(&strategy);
+        let parsed = parse(&text).unwrap_or_else(|e| panic!("{}\n{}", e, text));
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&strategy).unwrap(),
+            "{}",
+            text
+        );
+        assert_eq!(format(&parsed), text);
+        assert!(text.contains(
+            "buy 0.5 on ETHUSDT when (BTCUSDT.close > BTCUSDT.sma_200@1d[1] or rsi >= 50 and \
+             volume != 0) and close - (open - lag(close, 2)) <= abs(high + -1.5) / min(low, \
+             max(1, 2)) and not (rsi(tf=\"4h\") == 1 or close@4h crosses below sma_50@4h) stop \
+             high limit high * 1.01 sizing {\"mode\":\"quote\",\"amount\":100.0}"
+        ));
+        assert!(text.starts_with("type spot\nmax_entries 2\n"));
+        assert!(text.contains(
+            "\ndefine signal = uptrend and breakout == true\ndefine uptrend = sma_10 > sma_50\n"
+        ));
+        assert!(text.contains(
+            "sell 1 when close < sma_50 or not signal or in_position and bars_since_entry >= 5 and \
+             bars_since(close crosses above sma_50) <= 3 and hour between 9 and 17 market tif 2"
+        ));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| parse(text).unwrap_err();
+
+        let e = error("type spot\nbuy 0.5 when rsi < \n");
+        assert_eq!((e.line, e.column), (3, 1));
+        assert_eq!(e.message, "expected a value");
+
+        let e = error("buy 0.5 when rsi <> 30");
+        assert_eq!(e.span, 18..19);
+        assert_eq!(e.message, "expected a value");
+
+        let e = error("type spot\n  bye 0.5 when rsi < 30");
+        assert_eq!((e.line, e.column, e.span), (2, 3, 12..15));
+        assert_eq!(e.message, "unknown statement 'bye'");
+
+        let e = error("buy 0.5 when rsi(periods=3) > 1");
+        assert_eq!(e.span, 13..27);
+        assert!(e.message.starts_with("invalid indicator"));
+
+        let e = error("buy 0.5 when close crosses sma");
+        assert_eq!(e.message, "expected 'above' or 'below'");
+        let e = error("define a = rsi > 1\ndefine a = rsi < 1");
+        assert_eq!(
+            (e.message.as_str(), e.span.clone()),
+            ("'a' is already defined", 26..27)
+        );
+        let e = error("define hour = rsi > 1");
+        assert_eq!(e.message, "invalid name 'hour'");
+        let e = error("type spot\ntype perp");
+        assert_eq!(e.message, "'type' is already set");
+        let e = error("buy 0.5 rsi < 30");
+        assert_eq!(e.message, "expected 'when'");
+        let e = error("risk {\"stop_loss\": 5}");
+        assert!(e.message.starts_with("invalid risk"));
+        assert_eq!(e.span, 5..21);
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |open: &str, close: &str, depth: usize| {
+            format!(
+                "buy 1 when {}rsi < 30{}",
+                open.repeat(depth),
+                close.repeat(depth)
+            )
+        };
+
+        assert!(parse(&nested("(", ")", 60)).is_ok());
+        assert!(parse(&nested("not ", "", 60)).is_ok());
+        assert!(parse("buy 1 when rsi < (((((((((((1)))))))))))").is_ok());
+
+        for text in [
+            nested("(", ")", 5000),
+            nested("not ", "", 5000),
+            nested("abs(", ")", 5000),
+        ] {
+            let e = parse(&text).unwrap_err();
+            assert_eq!(e.message, "nested more than 64 levels deep");
+        }
+    }
+}
@@ -16,33 +16,80 @@ pub struct CreateStrategyRequest {
     pub content: StrategyContent,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StrategyText {
+    pub text: String,
+}
+
+/// A lint the backend found in a saved strategy.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lint {
+    pub kind: String,
+    #[serde(default)]
+    pub action: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct StrategyCreated {
+    #[serde(default)]
+    warnings: Vec<Lint>,
+}
+
+/// Read the error message out of a failed response.
+async fn response_error(r: gloo_net::http::Response) -> String {
+    match r.json::<ErrorResponse>().await {
+        Ok(e_msg) => e_msg.error,
+        Err(_) => format!("no error message: {}", r.status_text()),
+    }
+}
+
+/// Compile the text form of a strategy on the backend.
+async fn parse_text(text: String) -> Result<StrategyContent, String> {
+    let response = Request::post("/api/strategy/parse")
+        .json(&StrategyText { text })
+        .unwrap()
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status() != 200 {
+        return Err(response_error(response).await);
+    }
+    response
+        .json::<StrategyContent>()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Write a strategy in its text form on the backend.
+async fn format_content(content: StrategyContent) -> Result<String, String> {
+    let response = Request::post("/api/strategy/format")
+        .json(&content)
+        .unwrap()
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status() != 200 {
+        return Err(response_error(response).await);
+    }
+    let text = response
+        .json::<StrategyText>()
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(text.text)
+}
+
 // New Strategy Page
 #[function_component(NewStrategyPage)]
 pub fn new_strategy_page() -> Html {
-    let strategy_json = use_state(|| {
-        r#"
-        {
-          "meta": {
-            "type": "spot"
-          },
-          "actions": [
-            {
-              "type": "buy",
-              "w": 0.8,
-              "cond": {
-                "gt": {
-                  "l": "sma_10",
-                  "r": "sma_50"
-                }
-              }
-            }
-          ]
-        }"#
-        .to_string()
-    });
+    let strategy_json = use_state(|| "type spot\n\nbuy 0.8 when sma_10 > sma_50\n".to_string());
+    // The editor holds the text form unless switched to raw JSON.
+    let text_mode = use_state(|| true);
 
     let strategy_title = use_state(|| "Strategy1".to_string());
     let error = use_state(|| Option::<String>::None);
+    // Lints of the saved strategy, shown before leaving the page.
+    let warnings = use_state(Vec::<Lint>::new);
 
     let on_title_change = {
         let strategy_title = strategy_title.clone();
@@ -60,21 +107,60 @@ pub fn new_strategy_page() -> Html {
         })
     };
 
+    let on_toggle_mode = {
+        let strategy_json = strategy_json.clone();
+        let text_mode = text_mode.clone();
+        let error = error.clone();
+        Callback::from(move |_| {
+            let strategy_json = strategy_json.clone();
+            let text_mode = text_mode.clone();
+            let error = error.clone();
+            spawn_local(async move {
+                let source = (*strategy_json).clone();
+                let converted = if *text_mode {
+                    parse_text(source).await.map(|content| {
+                        serde_json::to_string_pretty(&content).expect("failed to encode json")
+                    })
+                } else {
+                    match serde_json::from_str::<StrategyContent>(&source) {
+                        Ok(content) => format_content(content).await,
+                        Err(e) => Err(format!("Invalid JSON: {}", e)),
+                    }
+                };
+                match converted {
+                    Ok(converted) => {
+                        strategy_json.set(converted);
+                        text_mode.set(!*text_mode);
+                        error.set(None);
+                    }
+                    Err(e) => error.set(Some(e)),
+                }
+            })
+        })
+    };
+
     let on_save = {
         let strategy_json = strategy_json.clone();
         let strategy_title = strategy_title.clone();
+        let text_mode = text_mode.clone();
         let error = error.clone();
+        let warnings = warnings.clone();
         Callback::from(move |_| {
-            // Validate JSON
             let strategy_title = strategy_title.clone();
             let error = error.clone();
-            let strategy_json = strategy_json.clone();
-            match serde_json::from_str::<StrategyContent>(&*strategy_json) {
-                Ok(content) => {
-                    spawn_local(async move {
-                        let strategy_title = strategy_title.clone();
-                        let error = error.clone();
-
+            let warnings = warnings.clone();
+            let source = (*strategy_json).clone();
+            let text_mode = *text_mode;
+            spawn_local(async move {
+                let content = if text_mode {
+                    parse_text(source).await
+                } else {
+                    // Validate JSON
+                    serde_json::from_str::<StrategyContent>(&source)
+                        .map_err(|e| format!("Invalid JSON: {}", e))
+                };
+                match content {
+                    Ok(content) => {
                         let content_msgpack =
                             to_vec_named(&content).expect("failed to encode message pack");
                         let payload_b64 = BASE64.encode(content_msgpack);
@@ -91,11 +177,21 @@ pub fn new_strategy_page() -> Html {
                         match response {
                             // We got a response, and it's OK.
                             Ok(r) if r.status() == 200 => {
-                                web_sys::window()
-                                    .unwrap()
-                                    .location()
-                                    .set_href("/app")
-                                    .unwrap();
+                                let lints = r
+                                    .json::<StrategyCreated>()
+                                    .await
+                                    .map(|created| created.warnings)
+                                    .unwrap_or_default();
+                                if lints.is_empty() {
+                                    web_sys::window()
+                                        .unwrap()
+                                        .location()
+                                        .set_href("/app")
+                                        .unwrap();
+                                } else {
+                                    error.set(None);
+                                    warnings.set(lints);
+                                }
                             }
                             // We got a response, but it's an error.
                             Ok(r) => {
@@ -119,18 +215,12 @@ pub fn new_strategy_page() -> Html {
                                 )));
                             }
                         }
-                    })
-                    /*
-                                        web_sys::window()
-                                            .unwrap()
-                                            .alert_with_message("Strategy saved! (Backend integration needed)")
-                                            .unwrap();
-                    */
+                    }
+                    Err(e) => {
+                        error.set(Some(e));
+                    }
                 }
-                Err(e) => {
-                    error.set(Some(format!("Invalid JSON: {}", e)));
-                }
-            }
+            })
         })
     };
 
@@ -154,6 +244,27 @@ pub fn new_strategy_page() -> Html {
                         html! {}
                     }}
 
+                    {if !warnings.is_empty() {
+                        html! {
+                            <div class="warning-message">
+                                <p>{"Strategy saved, but it may not do what you expect:"}</p>
+                                <ul>
+                                    {for warnings.iter().map(|lint| html! {
+                                        <li>
+                                            {match lint.action {
+                                                Some(i) => format!("Action {}: {}", i + 1, lint.message),
+                                                None => lint.message.clone(),
+                                            }}
+                                        </li>
+                                    })}
+                                </ul>
+                                <Link<Route> to={Route::App} classes="btn-secondary">{"Continue"}</Link<Route>>
+                            </div>
+                        }
+                    } else {
+                        html! {}
+                    }}
+
                     <div class="strategy-editor">
                         <div class="form-group">
                             <label for="strategy-name">{"Strategy Name"}</label>
@@ -167,7 +278,9 @@ pub fn new_strategy_page() -> Html {
                         </div>
 
                         <div class="form-group">
-                            <label for="strategy-json">{"Strategy Configuration (JSON)"}</label>
+                            <label for="strategy-json">
+                                {if *text_mode { "Strategy" } else { "Strategy Configuration (JSON)" }}
+                            </label>
                             <textarea
                                 id="strategy-json"
                                 class="json-editor"
@@ -178,6 +291,9 @@ pub fn new_strategy_page() -> Html {
 
                         <div class="editor-actions">
                             <button class="btn-primary" onclick={on_save}>{"Save Strategy"}</button>
+                            <button class="btn-secondary" onclick={on_toggle_mode}>
+                                {if *text_mode { "Edit as JSON" } else { "Edit as text" }}
+                            </button>
                             <Link<Route> to={Route::App} classes="btn-secondary">{"Cancel"}</Link<Route>>
                         </div>
                     </div>
@@ -32,22 +32,124 @@ pub struct Strategy {
 pub enum StrategyType {
     Spot,
     Options,
+    Perp,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Meta {
     #[serde(rename = "type")]
     pub strategy_type: StrategyType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sizing: Option<Sizing>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_entries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margin: Option<Margin>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<OptionsConfig>,
     //#[serde(flatten)]
     //pub extra: std::collections::HashMap<String, rmpv::Value>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Margin {
+    pub leverage: f64,
+    pub maintenance_margin: f64,
+    pub funding_rate: f64,
+    pub funding_interval: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OptionsConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iv: Option<f64>,
+    pub vol_lookback: u32,
+    pub risk_free_rate: f64,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Value {
     Number(f64),
-    //Boolean(bool),
+    Boolean(bool),
     Indicator(String),
+    Expr(Box<Expr>),
+    Spec(IndicatorSpec),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IndicatorSpec {
+    pub ind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fast: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dev: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub d: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tf: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Expr {
+    Add {
+        l: Value,
+        r: Value,
+    },
+    Sub {
+        l: Value,
+        r: Value,
+    },
+    Mul {
+        l: Value,
+        r: Value,
+    },
+    Div {
+        l: Value,
+        r: Value,
+    },
+    Min {
+        l: Value,
+        r: Value,
+    },
+    Max {
+        l: Value,
+        r: Value,
+    },
+    Abs {
+        val: Value,
+    },
+    Lag {
+        val: Value,
+        n: u32,
+    },
+    State(StateVar),
+    #[serde(rename = "bars_since")]
+    BarsSince {
+        cond: Box<Cond>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StateVar {
+    BarsSinceEntry,
+    TradesToday,
+    PnlPct,
+    Hour,
+    Weekday,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -108,6 +210,25 @@ pub enum Cond {
         l: Box<Value>,
         r: Box<Value>,
     },
+    #[serde(rename = "in_position")]
+    InPosition,
+    Ref(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType {
+    Market,
+    Limit { price: Value },
+    Stop { price: Value },
+    StopLimit { stop: Value, limit: Value },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Order {
+    #[serde(rename = "type")]
+    pub order_type: OrderType,
+    pub tif: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -116,12 +237,105 @@ pub struct Action {
     pub action_type: String,
     pub w: f64,
     pub cond: Cond,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sizing: Option<Sizing>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legs: Option<Vec<OptionLeg>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionKind {
+    Call,
+    Put,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OptionLeg {
+    #[serde(rename = "type")]
+    pub kind: OptionKind,
+    pub strike: f64,
+    pub expiry: u32,
+    pub quantity: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Sizing {
+    #[default]
+    Cash,
+    Equity,
+    Quote {
+        amount: f64,
+    },
+    Volatility {
+        target: f64,
+        lookback: u32,
+    },
+    Kelly {
+        fraction: f64,
+        cap: f64,
+        #[serde(default = "default_min_trades")]
+        min_trades: u32,
+    },
+}
+
+fn default_min_trades() -> u32 {
+    20
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Distance {
+    Pct(f64),
+    Atr { indicator: String, mult: f64 },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Risk {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_loss: Option<Distance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub take_profit: Option<Distance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trailing_stop: Option<Distance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_holding_bars: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_daily_loss: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StrategyContent {
     pub meta: Meta,
     pub actions: Vec<Action>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub risk: Option<Risk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub portfolio: Option<PortfolioSpec>,
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub definitions: std::collections::BTreeMap<String, Cond>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PortfolioSpec {
+    pub assets: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allocation: Option<std::collections::BTreeMap<String, f64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rebalance: Option<Rebalance>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rebalance {
+    Daily,
+    Weekly,
+    Monthly,
 }
 
 /* ==== Backtest structs ==== */